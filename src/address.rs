@@ -0,0 +1,377 @@
+// src/address.rs
+//! socat-style endpoint addresses, so any front-end can attach to any
+//! transport with one argument:
+//!
+//! ```text
+//! TCP:host:port                 connect over TCP
+//! TCP-LISTEN:port[,bind=host]   wait for one TCP connection
+//! UNIX-CONNECT:path             connect to a Unix socket
+//! UNIX-LISTEN:path[,mode=660]   wait for one connection on a Unix socket
+//! ABSTRACT-CONNECT:name         the same in Linux's abstract namespace
+//! ABSTRACT-LISTEN:name
+//! PIPE:read_fifo,write_fifo     a pair of named pipes
+//! FILE:/dev/ttyUSB0[,b115200][,raw]
+//!                               a serial port
+//! UDP:host:port[,bind=local]    datagrams to a known peer
+//! UDP-LISTEN:port[,bind=host]   datagrams from whoever sends first
+//! STDIO (or -)                  standard input and output
+//! EXEC:command[,pty]            a command run through the shell
+//! ```
+//!
+//! Keywords are not case sensitive. The older `<mode> <address>` pairs map
+//! onto these through [`Address::from_mode`].
+
+use crate::{
+    cancel::Cancel,
+    direct::Duplex,
+    exec::{Exec, ExecSpec},
+    net::{self, Stream, Timeouts},
+    pipe, serial,
+    state::ConnectionState,
+    udp::{self, UdpOptions},
+};
+#[cfg(unix)]
+use crate::unix::{self, UnixAddress};
+use std::{fmt, io, path::PathBuf};
+
+/// One end of a chat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// Connect to a `host:port` or `unix:` address, as taken by [`net::dial`].
+    Connect(String),
+    /// Listen on an address as taken by [`net::Listener::bind`] and take
+    /// the first connection.
+    Listen(String),
+    Pipe { read: PathBuf, write: PathBuf },
+    Serial { device: String, baud: u32 },
+    /// A spec as taken by [`udp::Endpoint::open`].
+    Udp(String),
+    Stdio,
+    Exec(ExecSpec),
+}
+
+/// An opened [`Address`].
+pub struct Opened {
+    pub transport: Transport,
+    /// Who is on the other end, when that can be told.
+    pub peer: Option<String>,
+}
+
+pub enum Transport {
+    /// A TCP or Unix socket connection, tied to the [`Cancel`] it was
+    /// opened with.
+    Stream(Stream),
+    Datagrams(udp::Endpoint),
+    Process(Exec),
+    Other(Duplex),
+}
+
+impl Transport {
+    pub fn into_duplex(self) -> io::Result<Duplex> {
+        match self {
+            Transport::Stream(stream) => Duplex::stream(stream),
+            Transport::Datagrams(endpoint) => Ok(endpoint.duplex()),
+            Transport::Process(exec) => Ok(exec.duplex()),
+            Transport::Other(duplex) => Ok(duplex),
+        }
+    }
+}
+
+impl Address {
+    /// Parses a socat-style address, see the module documentation.
+    pub fn parse(spec: &str) -> Result<Address, String> {
+        let spec = spec.trim();
+        if spec == "-" || spec.eq_ignore_ascii_case("STDIO") {
+            return Ok(Address::Stdio);
+        }
+        let (keyword, rest) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("expected KEYWORD:PARAMETERS, e.g. TCP:host:port, got {:?}", spec))?;
+        let keyword = keyword.to_ascii_uppercase();
+        if keyword == "EXEC" {
+            // Commands are taken whole but for a trailing `,pty`; they may
+            // well contain commas.
+            return ExecSpec::parse(rest).map(Address::Exec);
+        }
+        let mut parts = rest.split(',').map(str::trim);
+        let target = parts.next().unwrap_or_default();
+        if target.is_empty() {
+            return Err(format!("missing parameters after {}:", keyword));
+        }
+        let options: Vec<(&str, Option<&str>)> = parts
+            .filter(|option| !option.is_empty())
+            .map(|option| match option.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (option, None),
+            })
+            .collect();
+        let unknown = |option: &(&str, Option<&str>)| format!("{} does not take option {:?}", keyword, option.0);
+
+        match keyword.as_str() {
+            "TCP" | "TCP-CONNECT" => {
+                if let Some(option) = options.first() {
+                    return Err(unknown(option));
+                }
+                net::split_host_port(target)?;
+                Ok(Address::Connect(target.to_string()))
+            }
+            "TCP-LISTEN" => {
+                let port: u16 = target.parse().map_err(|_| format!("invalid port {:?}", target))?;
+                let mut host = "*".to_string();
+                for option in &options {
+                    match option {
+                        ("bind", Some(value)) => host = bracketed(value),
+                        // What std does anyway.
+                        ("reuseaddr", None) => {}
+                        _ => return Err(unknown(option)),
+                    }
+                }
+                Ok(Address::Listen(format!("{}:{}", host, port)))
+            }
+            "UNIX-CONNECT" | "ABSTRACT-CONNECT" => {
+                if let Some(option) = options.first() {
+                    return Err(unknown(option));
+                }
+                Ok(Address::Connect(unix_address(&keyword, target, None)?))
+            }
+            "UNIX-LISTEN" | "ABSTRACT-LISTEN" => {
+                let mut mode = None;
+                for option in &options {
+                    match option {
+                        ("mode", Some(value)) if keyword == "UNIX-LISTEN" => mode = Some(*value),
+                        _ => return Err(unknown(option)),
+                    }
+                }
+                Ok(Address::Listen(unix_address(&keyword, target, mode)?))
+            }
+            "PIPE" => {
+                let write = match options.as_slice() {
+                    [(write, None)] => write,
+                    _ => return Err(format!("PIPE needs two named pipes, PIPE:READ_PIPE,WRITE_PIPE, got {:?}", rest)),
+                };
+                Ok(Address::Pipe { read: PathBuf::from(target), write: PathBuf::from(write) })
+            }
+            "FILE" => {
+                let mut baud = serial::DEFAULT_BAUD;
+                for option in &options {
+                    match option {
+                        (flag, None) if flag.starts_with('b') && flag.len() > 1 => {
+                            baud = flag[1..].parse().map_err(|_| format!("invalid baud rate {:?}", flag))?;
+                        }
+                        // Serial ports are always opened raw, 8 bits, no echo.
+                        ("raw" | "cs8", None) | ("echo", Some("0")) => {}
+                        _ => return Err(unknown(option)),
+                    }
+                }
+                Ok(Address::Serial { device: target.to_string(), baud })
+            }
+            "UDP" | "UDP-CONNECT" => {
+                net::split_host_port(target)?;
+                let mut local = None;
+                for option in &options {
+                    match option {
+                        ("bind", Some(value)) => local = Some(*value),
+                        _ => return Err(unknown(option)),
+                    }
+                }
+                Ok(Address::Udp(match local {
+                    Some(local) => format!("{},{}", local, target),
+                    None => target.to_string(),
+                }))
+            }
+            "UDP-LISTEN" => {
+                let port: u16 = target.parse().map_err(|_| format!("invalid port {:?}", target))?;
+                let mut host = "*".to_string();
+                for option in &options {
+                    match option {
+                        ("bind", Some(value)) => host = bracketed(value),
+                        _ => return Err(unknown(option)),
+                    }
+                }
+                Ok(Address::Udp(format!("{}:{},", host, port)))
+            }
+            _ => Err(format!(
+                "unknown address type {:?}; expected TCP, TCP-LISTEN, UNIX-CONNECT, UNIX-LISTEN, PIPE, FILE, UDP, UDP-LISTEN, STDIO or EXEC",
+                keyword
+            )),
+        }
+    }
+
+    /// Maps the `<mode> <address>` pairs of the connection dialog and the
+    /// older command line onto an address.
+    pub fn from_mode(mode: &str, address: &str) -> Result<Address, String> {
+        match mode {
+            "server" => {
+                net::validate_address(address)?;
+                Ok(Address::Listen(address.to_string()))
+            }
+            "client" => {
+                net::validate_address(address)?;
+                Ok(Address::Connect(address.to_string()))
+            }
+            "serial" => {
+                let (device, baud) = serial::parse_spec(address)?;
+                Ok(Address::Serial { device, baud })
+            }
+            "fifo" => {
+                let (read, write) = pipe::parse_pair(address)?;
+                Ok(Address::Pipe { read, write })
+            }
+            "udp" => {
+                udp::parse_spec(address)?;
+                Ok(Address::Udp(address.to_string()))
+            }
+            _ => Err(format!("unknown mode {:?}", mode)),
+        }
+    }
+
+    /// The dialog mode this address corresponds to, or a short name for the
+    /// kinds the dialog does not offer.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Address::Connect(_) => "client",
+            Address::Listen(_) => "server",
+            Address::Pipe { .. } => "fifo",
+            Address::Serial { .. } => "serial",
+            Address::Udp(_) => "udp",
+            Address::Stdio => "stdio",
+            Address::Exec(_) => "exec",
+        }
+    }
+
+    /// Whether opening waits for the other side to come along.
+    pub fn is_listening(&self) -> bool {
+        match self {
+            Address::Listen(_) => true,
+            Address::Udp(spec) => matches!(udp::parse_spec(spec), Ok((_, None))),
+            _ => false,
+        }
+    }
+
+    /// Opens the endpoint, reporting progress through `report`. Listening
+    /// addresses wait for the first peer; all waiting ends when `cancel`
+    /// fires.
+    pub fn open(
+        &self,
+        timeouts: Timeouts,
+        udp_options: UdpOptions,
+        cancel: &Cancel,
+        report: &dyn Fn(ConnectionState),
+    ) -> io::Result<Opened> {
+        match self {
+            Address::Connect(address) => {
+                report(ConnectionState::Connecting { address: address.clone() });
+                let stream = net::dial(address, timeouts.connect, cancel)?;
+                cancel.watch(&stream)?;
+                let peer = Some(stream.peer().to_string());
+                Ok(Opened { transport: Transport::Stream(stream), peer })
+            }
+            Address::Listen(address) => {
+                report(ConnectionState::Binding { address: address.clone() });
+                let listener = net::Listener::bind(address)?;
+                report(ConnectionState::Listening { address: listener.describe() });
+                let (stream, peer) = listener.accept(cancel)?;
+                cancel.watch(&stream)?;
+                Ok(Opened { transport: Transport::Stream(stream), peer: Some(peer.to_string()) })
+            }
+            Address::Pipe { read, write } => {
+                Ok(Opened { transport: Transport::Other(pipe::open_pair(read, write)), peer: None })
+            }
+            Address::Serial { device, baud } => {
+                report(ConnectionState::Binding { address: device.clone() });
+                let port = serial::open(&format!("{},{}", device, baud), cancel)?;
+                Ok(Opened { transport: Transport::Other(port), peer: Some(device.clone()) })
+            }
+            Address::Udp(spec) => {
+                report(ConnectionState::Binding { address: spec.clone() });
+                let endpoint = udp::Endpoint::open(spec, udp_options, cancel)?;
+                let peer = endpoint.peer().map(|peer| peer.to_string());
+                Ok(Opened { transport: Transport::Datagrams(endpoint), peer })
+            }
+            Address::Stdio => Ok(Opened {
+                transport: Transport::Other(Duplex::new(io::stdin(), io::stdout())),
+                peer: Some("standard input/output".to_string()),
+            }),
+            Address::Exec(spec) => {
+                report(ConnectionState::Binding { address: self.to_string() });
+                let exec = Exec::spawn(spec, cancel)?;
+                Ok(Opened { transport: Transport::Process(exec), peer: Some(spec.command.clone()) })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    /// The socat form, which [`Address::parse`] reads back.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Address::Connect(address) | Address::Listen(address) if unix::is_unix(address) => {
+                let listening = matches!(self, Address::Listen(_));
+                let Ok(spec) = unix::parse(address) else { return write!(f, "{}", address) };
+                match (spec.address, listening) {
+                    (UnixAddress::Path(path), false) => write!(f, "UNIX-CONNECT:{}", path.display()),
+                    (UnixAddress::Path(path), true) => {
+                        write!(f, "UNIX-LISTEN:{}", path.display())?;
+                        if spec.mode != unix::DEFAULT_MODE {
+                            write!(f, ",mode={:o}", spec.mode)?;
+                        }
+                        Ok(())
+                    }
+                    (UnixAddress::Abstract(name), false) => write!(f, "ABSTRACT-CONNECT:{}", name),
+                    (UnixAddress::Abstract(name), true) => write!(f, "ABSTRACT-LISTEN:{}", name),
+                }
+            }
+            Address::Connect(address) => write!(f, "TCP:{}", address),
+            Address::Listen(address) => write_listen(f, "TCP-LISTEN", address),
+            Address::Pipe { read, write } => write!(f, "PIPE:{},{}", read.display(), write.display()),
+            Address::Serial { device, baud } => write!(f, "FILE:{},b{},raw", device, baud),
+            Address::Udp(spec) => match udp::parse_spec(spec) {
+                Ok((None, Some(peer))) => write!(f, "UDP:{}", peer),
+                Ok((Some(local), Some(peer))) => write!(f, "UDP:{},bind={}", peer, local),
+                Ok((Some(local), None)) => write_listen(f, "UDP-LISTEN", &local),
+                _ => write!(f, "UDP:{}", spec),
+            },
+            Address::Stdio => write!(f, "STDIO"),
+            Address::Exec(spec) => write!(f, "EXEC:{}", spec),
+        }
+    }
+}
+
+/// `KEYWORD:port`, with `,bind=host` unless listening on every interface.
+fn write_listen(f: &mut fmt::Formatter<'_>, keyword: &str, address: &str) -> fmt::Result {
+    match net::split_host_port(address) {
+        Ok(("" | "*", port)) => write!(f, "{}:{}", keyword, port),
+        Ok((host, port)) => write!(f, "{}:{},bind={}", keyword, port, host),
+        Err(_) => write!(f, "{}:{}", keyword, address),
+    }
+}
+
+/// Puts IPv6 literals in brackets, so a port can follow.
+fn bracketed(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// The `unix:` address for a `UNIX-*` or `ABSTRACT-*` address.
+#[cfg(unix)]
+fn unix_address(keyword: &str, target: &str, mode: Option<&str>) -> Result<String, String> {
+    let mut address = if keyword.starts_with("ABSTRACT") {
+        format!("{}@{}", unix::PREFIX, target)
+    } else {
+        format!("{}{}", unix::PREFIX, target)
+    };
+    if let Some(mode) = mode {
+        address.push_str(&format!(",mode={}", mode));
+    }
+    unix::parse(&address)?;
+    Ok(address)
+}
+
+#[cfg(not(unix))]
+fn unix_address(keyword: &str, _target: &str, _mode: Option<&str>) -> Result<String, String> {
+    Err(format!("{} needs a Unix system", keyword))
+}
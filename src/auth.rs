@@ -0,0 +1,373 @@
+// src/auth.rs
+//! Login checks for the `multi_chat` server: an optional shared room
+//! password, an optional file of per-user salted password hashes, a list of
+//! reserved names and a per-address limit on failed attempts.
+//!
+//! The login handshake is not encrypted: the password crosses the network
+//! as typed, so rooms with one belong on trusted networks or behind a tunnel.
+
+use crate::{cli::Args, hex};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const PBKDF2_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+pub const MAX_USERNAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    InvalidName,
+    ReservedName,
+    NameInUse,
+    BadCredentials,
+    TooManyAttempts,
+    Banned,
+    TooManyConnections,
+    RoomFull,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            AuthError::InvalidName => "invalid username",
+            AuthError::ReservedName => "username is reserved",
+            AuthError::NameInUse => "username already in use",
+            AuthError::BadCredentials => "wrong username or password",
+            AuthError::TooManyAttempts => "too many failed attempts, try again later",
+            AuthError::Banned => "banned from this room",
+            AuthError::TooManyConnections => "too many connections from your address",
+            AuthError::RoomFull => "the room is full",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+#[derive(Debug, Clone)]
+struct StoredPassword {
+    salt: Vec<u8>,
+    rounds: u32,
+    hash: Vec<u8>,
+}
+
+impl StoredPassword {
+    fn new(password: &str) -> io::Result<Self> {
+        let mut salt = vec![0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+        let hash = derive(password, &salt, PBKDF2_ROUNDS);
+        Ok(StoredPassword { salt, rounds: PBKDF2_ROUNDS, hash })
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        constant_time_eq(&derive(password, &self.salt, self.rounds), &self.hash)
+    }
+}
+
+fn derive(password: &str, salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut out = vec![0u8; HASH_LEN];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut out);
+    out
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Per-user credentials stored one per line as `name:salt:rounds:hash`,
+/// with salt and hash hex encoded. Lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct UserDb {
+    users: HashMap<String, StoredPassword>,
+}
+
+impl UserDb {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut db = UserDb::default();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: malformed user entry", path.display(), number + 1),
+                )
+            };
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 4 {
+                return Err(invalid());
+            }
+            let stored = StoredPassword {
+                salt: hex::decode(fields[1]).ok_or_else(invalid)?,
+                rounds: fields[2].parse().map_err(|_| invalid())?,
+                hash: hex::decode(fields[3]).ok_or_else(invalid)?,
+            };
+            db.users.insert(fields[0].to_string(), stored);
+        }
+        Ok(db)
+    }
+
+    /// Adds or replaces `username` in the file at `path`, creating it if needed.
+    pub fn add_user(path: &Path, username: &str, password: &str) -> io::Result<()> {
+        if validate_username(username).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid username"));
+        }
+        let existing = if path.exists() { fs::read_to_string(path)? } else { String::new() };
+        let stored = StoredPassword::new(password)?;
+
+        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        for line in existing.lines() {
+            if line.split(':').next() != Some(username) {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        writeln!(
+            file,
+            "{}:{}:{}:{}",
+            username,
+            hex::encode(&stored.salt),
+            stored.rounds,
+            hex::encode(&stored.hash)
+        )?;
+        file.flush()
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        match self.users.get(username) {
+            Some(stored) => stored.verify(password),
+            None => {
+                // Spend the same effort for unknown users so timing does not reveal them.
+                derive(password, &[0u8; SALT_LEN], PBKDF2_ROUNDS);
+                false
+            }
+        }
+    }
+}
+
+/// Rejects empty, overlong and whitespace/control-containing names.
+pub fn validate_username(username: &str) -> Result<(), AuthError> {
+    if username.is_empty()
+        || username.chars().count() > MAX_USERNAME_LEN
+        || username.chars().any(|c| c.is_whitespace() || c.is_control() || c == ':')
+    {
+        return Err(AuthError::InvalidName);
+    }
+    Ok(())
+}
+
+/// Tracks failed logins per address and locks an address out for a while
+/// once it exceeds `max_failures` within `window`. Local peers, which have
+/// no address, are counted together under `None`.
+#[derive(Debug)]
+pub struct LoginLimiter {
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    attempts: HashMap<Option<IpAddr>, Attempts>,
+}
+
+#[derive(Debug)]
+struct Attempts {
+    failures: u32,
+    first_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl LoginLimiter {
+    pub fn new(max_failures: u32, window: Duration, lockout: Duration) -> Self {
+        LoginLimiter {
+            max_failures,
+            window,
+            lockout,
+            attempts: HashMap::new(),
+        }
+    }
+
+    pub fn is_locked(&mut self, address: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        match self.attempts.get(&address) {
+            Some(Attempts { locked_until: Some(until), .. }) if *until > now => true,
+            Some(Attempts { locked_until: Some(_), .. }) => {
+                self.attempts.remove(&address);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Counts a failure from `address`, and forgets addresses whose failures
+    /// and lockout are over, so addresses that never come back do not pile up.
+    pub fn record_failure(&mut self, address: Option<IpAddr>) {
+        let now = Instant::now();
+        let window = self.window;
+        self.attempts.retain(|_, attempts| match attempts.locked_until {
+            Some(until) => until > now,
+            None => now.duration_since(attempts.first_failure) <= window,
+        });
+        let attempts = self.attempts.entry(address).or_insert(Attempts {
+            failures: 0,
+            first_failure: now,
+            locked_until: None,
+        });
+        if now.duration_since(attempts.first_failure) > self.window {
+            attempts.failures = 0;
+            attempts.first_failure = now;
+        }
+        attempts.failures += 1;
+        if attempts.failures >= self.max_failures {
+            attempts.locked_until = Some(now + self.lockout);
+        }
+    }
+
+    pub fn record_success(&mut self, address: Option<IpAddr>) {
+        self.attempts.remove(&address);
+    }
+
+    /// Addresses whose failures are remembered.
+    pub fn tracked(&self) -> usize {
+        self.attempts.len()
+    }
+}
+
+impl Default for LoginLimiter {
+    fn default() -> Self {
+        LoginLimiter::new(5, Duration::from_secs(60), Duration::from_secs(300))
+    }
+}
+
+/// Server-side login policy.
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    room_password: Option<String>,
+    users: Option<UserDb>,
+    reserved: Vec<String>,
+    limiter: Mutex<LoginLimiter>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Authenticator::default()
+    }
+
+    /// Requires every client to know `password`.
+    pub fn with_room_password(mut self, password: &str) -> Self {
+        self.room_password = Some(password.to_string());
+        self
+    }
+
+    /// Only lets in users listed in the credentials file at `path`.
+    pub fn with_users_file(mut self, path: &Path) -> io::Result<Self> {
+        self.users = Some(UserDb::load(path)?);
+        Ok(self)
+    }
+
+    /// Names nobody may log in as, compared case-insensitively.
+    pub fn with_reserved<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.reserved.extend(names.into_iter().map(|n| n.to_lowercase()));
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: LoginLimiter) -> Self {
+        self.limiter = Mutex::new(limiter);
+        self
+    }
+
+    pub fn is_reserved(&self, username: &str) -> bool {
+        self.reserved.contains(&username.to_lowercase())
+    }
+
+    /// Checks a login attempt from `address`, `None` for local peers. Wrong
+    /// passwords count towards the address's rate limit; a mistyped or
+    /// reserved name does not.
+    pub fn authenticate(&self, address: Option<IpAddr>, username: &str, password: &str) -> Result<(), AuthError> {
+        validate_username(username)?;
+        if self.is_reserved(username) {
+            return Err(AuthError::ReservedName);
+        }
+        let mut limiter = self.limiter.lock().unwrap();
+        if limiter.is_locked(address) {
+            return Err(AuthError::TooManyAttempts);
+        }
+        let result = self.check_password(username, password);
+        match result {
+            Ok(()) => limiter.record_success(address),
+            Err(_) => limiter.record_failure(address),
+        }
+        result
+    }
+
+    /// Per-user credentials take precedence over the shared room password.
+    fn check_password(&self, username: &str, password: &str) -> Result<(), AuthError> {
+        let ok = match (&self.users, &self.room_password) {
+            (Some(users), _) => users.verify(username, password),
+            (None, Some(expected)) => constant_time_eq(expected.as_bytes(), password.as_bytes()),
+            (None, None) => true,
+        };
+        if ok { Ok(()) } else { Err(AuthError::BadCredentials) }
+    }
+
+    /// True if clients must present a password at all.
+    pub fn requires_password(&self) -> bool {
+        self.room_password.is_some() || self.users.is_some()
+    }
+}
+
+/// Environment variable holding the password when `--password` is not
+/// given, which keeps it out of the process list.
+pub const PASSWORD_VAR: &str = "MULTI_CHAT_PASSWORD";
+
+/// The password given as `--password=SECRET`, asked for on `input` for a
+/// bare `--password`, or taken from `env` (normally [`PASSWORD_VAR`]);
+/// empty if there is none.
+pub fn password_from(args: &Args, env: Option<String>, input: &mut impl BufRead) -> io::Result<String> {
+    if let Some(password) = args.value("password") {
+        return Ok(password.to_string());
+    }
+    if !args.flag("password") {
+        return Ok(env.unwrap_or_default());
+    }
+    print!("Password: ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    without_echo(|| input.read_line(&mut line))?;
+    println!();
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Runs `read` with the terminal on stdin not echoing what is typed, if
+/// stdin is a terminal.
+#[cfg(unix)]
+fn without_echo<T>(read: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    // SAFETY: termios is plain data, filled in by tcgetattr before use.
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: `saved` is a valid termios for the call to fill in.
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+        return read();
+    }
+    let mut quiet = saved;
+    quiet.c_lflag &= !libc::ECHO;
+    // SAFETY: both are valid termios structures for stdin.
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) };
+    let result = read();
+    // SAFETY: as above, putting back what tcgetattr returned.
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+    result
+}
+
+#[cfg(not(unix))]
+fn without_echo<T>(read: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    read()
+}
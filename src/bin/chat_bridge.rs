@@ -0,0 +1,352 @@
+// src/bin/chat_bridge.rs
+use fltk::{
+    app,
+    prelude::*,
+    window::Window,
+    input::Input,
+    button::Button,
+    text::{TextDisplay, TextBuffer},
+    group::Flex,
+    frame::Frame,
+    enums::{Align, Color, Event, Font, FrameType, Key},
+};
+use socat_chat::{
+    address::Address,
+    bridge::{Bridge, BridgeEvent, Direction, LineEnding, Mirror},
+    cancel::Cancel,
+    capture::{Flow, Recorder},
+    cli::Args,
+    net::Timeouts,
+    settings::WindowGeometry,
+    state::ConnectionState,
+    udp::UdpOptions,
+};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// One of the two ends, by index: A is 0, B is 1.
+type Side = usize;
+
+enum Message {
+    State(Side, ConnectionState),
+    Traffic(String),
+    Error(String),
+}
+
+struct ChatBridge {
+    window: Window,
+    input: Input,
+    send_buttons: [Button; 2],
+    display_buffer: TextBuffer,
+    status_labels: [Frame; 2],
+    cancel_button: Button,
+    addresses: [Address; 2],
+    line_ending: LineEnding,
+    timeouts: Timeouts,
+    udp_options: UdpOptions,
+    capture: Option<Recorder>,
+    cancel: Cancel,
+}
+
+impl ChatBridge {
+    fn new(
+        addresses: [Address; 2],
+        line_ending: LineEnding,
+        timeouts: Timeouts,
+        udp_options: UdpOptions,
+        capture: Option<Recorder>,
+    ) -> Self {
+        let _app = app::App::default().with_scheme(app::Scheme::Gtk);
+        let title = format!("Chat Bridge - {} <-> {}", addresses[0], addresses[1]);
+        let geometry = WindowGeometry::load_or("chat_bridge", WindowGeometry { x: 100, y: 100, w: 600, h: 400 });
+        let mut window = Window::new(geometry.x, geometry.y, geometry.w, geometry.h, &*title);
+
+        let mut layout = Flex::default_fill().column();
+        layout.set_margin(10);
+        layout.set_pad(10);
+
+        // One status line per end, with a way out while they connect
+        let mut status_row = Flex::default().row();
+        let status_column = Flex::default().column();
+        let status_labels = [0, 1].map(|side| {
+            let mut label = Frame::default().with_label(&Self::status_text(side, &addresses[side], &ConnectionState::Idle));
+            label.set_align(Align::Left | Align::Inside);
+            label.set_label_color(Color::Red);
+            label
+        });
+        status_column.end();
+        let mut cancel_button = Button::default().with_label("Cancel");
+        cancel_button.deactivate();
+        status_row.fixed(&cancel_button, 70);
+        status_row.end();
+        layout.fixed(&status_row, 50);
+
+        // The traffic, in a fixed-width font so byte dumps line up
+        let display_buffer = TextBuffer::default();
+        let mut text_display = TextDisplay::default();
+        text_display.set_buffer(display_buffer.clone());
+        text_display.set_frame(FrameType::FlatBox);
+        text_display.set_color(Color::White);
+        text_display.set_text_font(Font::Courier);
+
+        // Enter sends to A, Shift+Enter to B.
+        let mut input_row = Flex::default().row();
+        let input = Input::default();
+        let send_buttons = ["To A", "To B"].map(|label| {
+            let mut button = Button::default().with_label(label);
+            button.deactivate();
+            button
+        });
+        for button in &send_buttons {
+            input_row.fixed(button, 60);
+        }
+        input_row.end();
+        layout.fixed(&input_row, 30);
+
+        layout.end();
+        window.end();
+        window.resizable(&layout);
+        window.size_range(400, 200, 0, 0);
+        window.show();
+
+        ChatBridge {
+            window,
+            input,
+            send_buttons,
+            display_buffer,
+            status_labels,
+            cancel_button,
+            addresses,
+            line_ending,
+            timeouts,
+            udp_options,
+            capture,
+            cancel: Cancel::new(),
+        }
+    }
+
+    fn status_text(side: Side, address: &Address, state: &ConnectionState) -> String {
+        format!("{}: {} - {}", ['A', 'B'][side], address, state)
+    }
+
+    /// Opens both ends at once, so either may be the one waiting for a
+    /// peer, and starts relaying when both are up.
+    fn connect(&mut self, sender: app::Sender<Message>, bridge: Arc<Mutex<Option<Bridge>>>) {
+        let cancel = self.cancel.clone();
+        self.cancel_button.set_callback(move |_| cancel.cancel());
+        self.cancel_button.activate();
+
+        let (timeouts, udp_options) = (self.timeouts, self.udp_options);
+        let opening: Vec<_> = [0, 1]
+            .map(|side| {
+                let address = self.addresses[side].clone();
+                let (cancel, sender) = (self.cancel.clone(), sender.clone());
+                let worker = cancel.worker();
+                thread::spawn(move || {
+                    let _worker = worker;
+                    let report = |state| sender.send(Message::State(side, state));
+                    let opened = address
+                        .open(timeouts, udp_options, &cancel, &report)
+                        .and_then(|opened| Ok((opened.transport.into_duplex()?, opened.peer)));
+                    match opened {
+                        Ok((duplex, peer)) => {
+                            report(ConnectionState::Connected { peer });
+                            Some(duplex)
+                        }
+                        Err(e) => {
+                            report(ConnectionState::failed(e));
+                            // Waiting for the other end makes no sense now.
+                            cancel.cancel();
+                            None
+                        }
+                    }
+                })
+            })
+            .into();
+
+        let capture = self.capture.clone();
+        let cancel = self.cancel.clone();
+        let worker = cancel.worker();
+        thread::spawn(move || {
+            let _worker = worker;
+            let mut ends = opening.into_iter().map(|opening| opening.join().ok().flatten());
+            let (Some(Some(a)), Some(Some(b))) = (ends.next(), ends.next()) else { return };
+            let (relay, events) = Bridge::start(a, b, &cancel);
+            *bridge.lock().unwrap() = Some(relay);
+            sender.send(Message::Traffic("Relaying between A and B\n".to_string()));
+
+            let mut mirror = Mirror::default();
+            while !cancel.is_cancelled() {
+                let Ok(event) = events.recv_timeout(Duration::from_millis(100)) else { continue };
+                if let (Some(recorder), BridgeEvent::Relayed { direction, bytes } | BridgeEvent::Injected { direction, bytes }) =
+                    (&capture, &event)
+                {
+                    recorder.record(Self::flow(*direction), bytes);
+                }
+                match event {
+                    BridgeEvent::Relayed { direction, bytes } => {
+                        sender.send(Message::Traffic(mirror.render(direction, false, &bytes)))
+                    }
+                    BridgeEvent::Injected { direction, bytes } => {
+                        sender.send(Message::Traffic(mirror.render(direction, true, &bytes)))
+                    }
+                    BridgeEvent::Closed { direction, error } => {
+                        let side = if direction == Direction::AtoB { 0 } else { 1 };
+                        let reason = error.unwrap_or_else(|| "closed".to_string());
+                        sender.send(Message::State(side, ConnectionState::failed(reason)));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Captures are seen from B's side: what comes from A is `in`.
+    fn flow(direction: Direction) -> Flow {
+        match direction {
+            Direction::AtoB => Flow::In,
+            Direction::BtoA => Flow::Out,
+        }
+    }
+
+    fn inject(bridge: &Mutex<Option<Bridge>>, direction: Direction, text: &str, line_ending: LineEnding) -> Result<(), String> {
+        let bridge = bridge.lock().unwrap();
+        let bridge = bridge.as_ref().ok_or("not relaying yet")?;
+        let bytes = format!("{}{}", text, line_ending.as_str());
+        bridge
+            .inject(direction, bytes.as_bytes())
+            .map_err(|e| format!("sending to {}: {}", direction.target(), e))
+    }
+
+    fn run(&mut self) {
+        let (sender, receiver) = app::channel::<Message>();
+        let bridge = Arc::new(Mutex::new(None));
+        self.connect(sender.clone(), Arc::clone(&bridge));
+
+        // Typed lines go to A or B, as if they came from the other end.
+        for (side, direction) in [(0, Direction::BtoA), (1, Direction::AtoB)] {
+            let (mut input, bridge, errors, line_ending) =
+                (self.input.clone(), Arc::clone(&bridge), sender.clone(), self.line_ending);
+            self.send_buttons[side].set_callback(move |_| {
+                match Self::inject(&bridge, direction, &input.value(), line_ending) {
+                    Ok(()) => input.set_value(""),
+                    Err(e) => errors.send(Message::Error(format!("Error {}\n", e))),
+                }
+            });
+        }
+        let (line_ending, errors) = (self.line_ending, sender);
+        self.input.handle(move |i, ev| {
+            if ev == Event::KeyDown && app::event_key() == Key::Enter {
+                let direction = if app::is_event_shift() { Direction::AtoB } else { Direction::BtoA };
+                match Self::inject(&bridge, direction, &i.value(), line_ending) {
+                    Ok(()) => i.set_value(""),
+                    Err(e) => errors.send(Message::Error(format!("Error {}\n", e))),
+                }
+                true
+            } else {
+                false
+            }
+        });
+
+        let mut up = [false, false];
+        while self.window.shown() {
+            if let Some(msg) = receiver.recv() {
+                match msg {
+                    Message::Traffic(text) | Message::Error(text) => self.display_buffer.append(&text),
+                    Message::State(side, state) => {
+                        up[side] = state.is_connected();
+                        self.show_state(side, &state);
+                        if up == [true, true] {
+                            self.cancel_button.deactivate();
+                            self.send_buttons.iter_mut().for_each(|button| button.activate());
+                        } else if !state.is_pending() {
+                            self.send_buttons.iter_mut().for_each(|button| button.deactivate());
+                        }
+                    }
+                }
+            }
+            app::wait();
+        }
+        // Unblock and wait for the relay threads before exiting.
+        if !self.cancel.shutdown(Duration::from_secs(2)) {
+            println!("Relay threads did not stop in time");
+        }
+        self.save_geometry();
+    }
+
+    fn show_state(&mut self, side: Side, state: &ConnectionState) {
+        let label = &mut self.status_labels[side];
+        label.set_label(&Self::status_text(side, &self.addresses[side], state));
+        label.set_label_color(if state.is_connected() {
+            Color::Green
+        } else if state.is_pending() {
+            Color::Yellow
+        } else {
+            Color::Red
+        });
+    }
+
+    fn save_geometry(&self) {
+        let geometry = WindowGeometry {
+            x: self.window.x(),
+            y: self.window.y(),
+            w: self.window.w(),
+            h: self.window.h(),
+        };
+        if let Err(e) = geometry.save("chat_bridge") {
+            println!("Could not save window position: {}", e);
+        }
+    }
+}
+
+fn usage() {
+    println!("Usage: cargo run --bin chat_bridge <ADDRESS_A> <ADDRESS_B> [options]");
+    println!("\nRelays bytes between A and B and shows the traffic. Lines typed in the");
+    println!("window go to A on Enter (or with 'To A') and to B on Shift+Enter (or 'To B').");
+    println!("\nExamples:");
+    println!("  Share a serial console: cargo run --bin chat_bridge FILE:/dev/ttyUSB0,b115200,raw TCP-LISTEN:7000");
+    println!("  Watch a TCP service:    cargo run --bin chat_bridge TCP-LISTEN:8080 TCP:10.0.0.2:80");
+    println!("\nAddresses: TCP:host:port, TCP-LISTEN:port, UNIX-CONNECT:path, UNIX-LISTEN:path,");
+    println!("PIPE:read_pipe,write_pipe, FILE:device,bBAUD, UDP:host:port, UDP-LISTEN:port, STDIO, EXEC:command");
+    println!("\nOptions:");
+    println!("  --eol=lf|crlf|cr|none    Line ending added to typed lines (default: lf)");
+    println!("  --connect-timeout=SECS   Give up connecting after SECS (default: 10)");
+    println!("  --capture=FILE           Record the traffic, A>B as in and B>A as out (pcap if FILE");
+    println!("                           ends in .pcap); play it back with chat_replay");
+}
+
+fn main() {
+    let args = Args::from_env();
+    if args.positional.len() != 2 {
+        usage();
+        return;
+    }
+    let addresses = match (Address::parse(&args.positional[0]), Address::parse(&args.positional[1])) {
+        (Ok(a), Ok(b)) => [a, b],
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let options = || -> Result<_, String> {
+        let capture = args
+            .value("capture")
+            .map(|path| Recorder::create(Path::new(path)))
+            .transpose()
+            .map_err(|e| format!("Error creating capture file: {}", e))?;
+        Ok((args.parse_or("eol", LineEnding::Lf)?, Timeouts::from_args(&args)?, UdpOptions::from_args(&args)?, capture))
+    };
+    let (line_ending, timeouts, udp_options, capture) = match options() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut bridge = ChatBridge::new(addresses, line_ending, timeouts, udp_options, capture);
+    bridge.run();
+}
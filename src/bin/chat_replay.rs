@@ -0,0 +1,105 @@
+// src/bin/chat_replay.rs
+use socat_chat::{
+    address::Address,
+    cancel::Cancel,
+    capture::{self, Flow, Speed},
+    cli::Args,
+    net::Timeouts,
+    state::ConnectionState,
+    udp::UdpOptions,
+};
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+struct Options {
+    flow: Flow,
+    speed: Speed,
+    wait: Duration,
+    timeouts: Timeouts,
+    udp_options: UdpOptions,
+}
+
+fn replay(path: &Path, address: &Address, options: Options) -> io::Result<()> {
+    let records = capture::read(path)?;
+    let cancel = Cancel::new();
+    let report = |state: ConnectionState| println!("{}: {}", address, state);
+    let opened = address.open(options.timeouts, options.udp_options, &cancel, &report)?;
+    let (mut reader, mut writer) = opened.transport.into_duplex()?.into_parts();
+
+    // Replies are shown as they arrive, while the capture plays.
+    let worker = cancel.worker();
+    thread::spawn(move || {
+        let _worker = worker;
+        let mut buf = [0u8; 4096];
+        while let Ok(len) = reader.read(&mut buf) {
+            if len == 0 {
+                break;
+            }
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&buf[..len]).and_then(|_| stdout.flush());
+        }
+    });
+
+    let played = capture::replay(&records, options.flow, options.speed, &mut writer, &cancel)?;
+    println!("Replayed {} of {} records", played, records.len());
+    thread::sleep(options.wait);
+    if !cancel.shutdown(Duration::from_secs(1)) {
+        println!("Reader thread did not stop in time");
+    }
+    Ok(())
+}
+
+fn usage() {
+    println!("Usage: cargo run --bin chat_replay <CAPTURE> <ADDRESS> [options]");
+    println!("\nSends the recorded bytes of one direction of a capture (made with --capture=FILE");
+    println!("in network_chat, multi_chat or chat_bridge) to ADDRESS, keeping the pauses between");
+    println!("them, and prints what the endpoint sends back.");
+    println!("\nExamples:");
+    println!("  cargo run --bin chat_replay session.cap TCP:localhost:8080");
+    println!("  cargo run --bin chat_replay session.cap FILE:/dev/ttyUSB0,b115200,raw --speed=10");
+    println!("\nOptions:");
+    println!("  --direction=out|in       Which records to send (default: out, what was sent)");
+    println!("  --speed=FACTOR|max       Play FACTOR times faster, or without pauses (default: 1)");
+    println!("  --wait=SECS              Keep printing replies for SECS afterwards (default: 1)");
+    println!("  --connect-timeout=SECS   Give up connecting after SECS (default: 10)");
+    println!("\nThe capture format is described in src/capture.rs; pcap captures cannot be replayed.");
+}
+
+fn main() {
+    let args = Args::from_env();
+    if args.positional.len() != 2 {
+        usage();
+        return;
+    }
+    let address = match Address::parse(&args.positional[1]) {
+        Ok(address) => address,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let options = || -> Result<_, String> {
+        Ok(Options {
+            flow: args.parse_or("direction", Flow::Out)?,
+            speed: args.parse_or("speed", Speed::Factor(1.0))?,
+            wait: Duration::from_secs_f64(args.parse_or("wait", 1.0f64)?.max(0.0)),
+            timeouts: Timeouts::from_args(&args)?,
+            udp_options: UdpOptions::from_args(&args)?,
+        })
+    };
+    let options = match options() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = replay(Path::new(&args.positional[0]), &address, options) {
+        println!("Replay failed: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,101 @@
+// src/bin/chat_script.rs
+use socat_chat::{
+    address::Address,
+    cancel::Cancel,
+    cli::Args,
+    net::Timeouts,
+    script::{Runner, Script, ScriptError, ScriptEvent},
+    state::ConnectionState,
+    udp::UdpOptions,
+};
+use std::{
+    io::{self, Write},
+    path::Path,
+    process,
+    time::Duration,
+};
+
+fn run(script: &Script, address: &Address, timeouts: Timeouts, udp_options: UdpOptions, quiet: bool) -> Result<(), ScriptError> {
+    let cancel = Cancel::new();
+    let report = |state: ConnectionState| {
+        if !quiet {
+            println!("{}: {}", address, state);
+        }
+    };
+    let opened = address.open(timeouts, udp_options, &cancel, &report)?;
+    let (reader, writer) = opened.transport.into_duplex()?.into_parts();
+    let mut runner = Runner::new(reader, writer, &cancel);
+
+    // Received bytes are shown as they are, sent ones on lines of their own.
+    let mut show = |event: ScriptEvent| {
+        if quiet {
+            return;
+        }
+        let mut stdout = io::stdout();
+        let _ = match event {
+            ScriptEvent::Received(bytes) => stdout.write_all(&bytes),
+            ScriptEvent::Sent(bytes) => writeln!(stdout, "\n>>> {}", String::from_utf8_lossy(&bytes).escape_debug()),
+            ScriptEvent::Matched { .. } => Ok(()),
+        };
+        let _ = stdout.flush();
+    };
+    let result = runner.run(script, &mut show);
+    if !cancel.shutdown(Duration::from_secs(1)) && !quiet {
+        println!("Reader thread did not stop in time");
+    }
+    result
+}
+
+fn usage() {
+    println!("Usage: cargo run --bin chat_script <SCRIPT> <ADDRESS> [options]");
+    println!("\nRuns an expect-style script against ADDRESS without a window and exits with");
+    println!("0 when it completes, 2 when an expect timed out, 3 when a fail pattern showed up");
+    println!("or the connection closed, and 1 for any other error.");
+    println!("\nScript commands, one per line (# starts a comment):");
+    println!("  send \"text\"                      Send the text and the line ending");
+    println!("  expect \"text\"|/regex/ [timeout 5s] [fail \"text\"|/regex/]");
+    println!("                                   Wait for the text to arrive");
+    println!("  sleep 500ms                      Pause");
+    println!("  timeout 2s                       Default wait of later expects (default: 10s)");
+    println!("  eol lf|crlf|cr|none              Line ending of send (default: lf)");
+    println!("  loop [N] ... end                 Repeat N times, or until an expect fails");
+    println!("\nExamples:");
+    println!("  cargo run --bin chat_script modem.txt FILE:/dev/ttyUSB0,b115200,raw");
+    println!("  cargo run --bin chat_script smoke.txt TCP:localhost:8080 --quiet");
+    println!("\nOptions:");
+    println!("  --quiet                  Only report errors");
+    println!("  --connect-timeout=SECS   Give up connecting after SECS (default: 10)");
+}
+
+fn main() {
+    let args = Args::from_env();
+    if args.positional.len() != 2 {
+        usage();
+        return;
+    }
+    let script = match Script::load(Path::new(&args.positional[0])) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let address = match Address::parse(&args.positional[1]) {
+        Ok(address) => address,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let (timeouts, udp_options) = match (Timeouts::from_args(&args), UdpOptions::from_args(&args)) {
+        (Ok(timeouts), Ok(udp_options)) => (timeouts, udp_options),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = run(&script, &address, timeouts, udp_options, args.flag("quiet")) {
+        println!("\nScript failed: {}", e);
+        process::exit(e.exit_code());
+    }
+}
@@ -3,79 +3,312 @@ use fltk::{
     app,
     prelude::*,
     window::Window,
-    input::Input,
+    input::MultilineInput,
     button::Button,
     text::{TextDisplay, TextBuffer},
-    group::Pack,
+    group::{Flex, Tile},
     frame::Frame,
-    enums::{Color, FrameType, Event, Key},
+    browser::HoldBrowser,
+    enums::{Align, CallbackTrigger, Color, Font, FrameType, Event, Key},
+};
+use crossbeam_channel::Receiver;
+use socat_chat::{
+    auth::{self, Authenticator, UserDb, PASSWORD_VAR},
+    bot::Bot,
+    cancel::Cancel,
+    capture::Recorder,
+    cli::Args,
+    client::{self, ChatClient, ClientEvent},
+    dialog::{self, DialogOptions},
+    discovery::{self, Announcement, Announcer, Browser, DiscoveryConfig},
+    display::format_block,
+    heartbeat::Heartbeat,
+    history::{format_clock, History},
+    limits::Limits,
+    moderation::{self, AuditLog, BanList, Moderation},
+    net::{self, Timeouts},
+    outbox::Outbox,
+    presence::{IdleTimer, Roster, TypingNotifier, IDLE_AFTER},
+    protocol::Presence,
+    receipts::{Edit, Receipts},
+    server::{ChatServer, ServerConfig, ServerEvent},
+    settings::WindowGeometry,
+    state::ConnectionState,
 };
 use std::{
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
+    cell::{Cell, RefCell},
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
     sync::{Arc, Mutex},
-    collections::HashMap,
 };
 
 #[derive(Debug)]
 enum Message {
     UpdateDisplay(String),
     Error(String),
-    UserList(String),
+    UserList(Vec<String>),
+    State(ConnectionState),
+    Typing { from: String, typing: bool },
+    Presence { user: String, presence: Presence },
+    /// The message input changed; true if it is now empty.
+    Edited(bool),
+    /// Once a second, to notice pauses and idleness.
+    Tick,
+    /// Someone else's post, to report reading once we have seen it.
+    Received { id: u64, from: String },
+    Delivered(u64),
+    Read { id: u64, by: String },
+    /// A ping to the server came back after this long.
+    RoundTrip(Duration),
+    /// An operator threw us out; no reconnecting after that.
+    Kicked(String),
 }
 
 struct MultiChat {
     app: app::App,
     window: Window,
-    input: Input,
+    input: MultilineInput,
     send_button: Button,
     text_display: TextDisplay,
     display_buffer: TextBuffer,
     status_label: Frame,
+    /// What the status bar shows, with the latest ping time while connected.
+    state: ConnectionState,
+    round_trip: Option<Duration>,
+    cancel_button: Button,
     users_label: Frame,
-    stream: Option<TcpStream>,
+    users_list: HoldBrowser,
+    typing_label: Frame,
+    roster: Roster,
+    typing: TypingNotifier,
+    idle: IdleTimer,
+    /// When the window last saw a key or the mouse.
+    last_input: Rc<Cell<Instant>>,
+    outgoing: Rc<RefCell<Outgoing>>,
+    link: Option<Link>,
     username: String,
+    config: ChatConfig,
+    cancel: Cancel,
+}
+
+/// Settings taken from the command line.
+struct ChatConfig {
+    history: HistoryConfig,
+    auth: Arc<Authenticator>,
+    /// Password the client presents to the server.
+    password: String,
+    timeouts: Timeouts,
+    /// Records a client's frames, with `--capture=FILE`.
+    capture: Option<Recorder>,
+    /// Answers chat messages, with `--bot=FILE`.
+    bot: Option<Arc<Bot>>,
+    /// How long without input before we show as idle.
+    idle_after: Duration,
+    /// Whether others learn when we read their messages.
+    read_receipts: bool,
+    /// Whether a client logs in again when the connection drops.
+    reconnect: bool,
+    /// Client messages not acknowledged yet, possibly saved with `--outbox=FILE`.
+    outbox: Outbox,
+    /// Pings and keepalive, to notice a peer that vanished.
+    heartbeat: Heartbeat,
+    /// A server's bans, operators and audit log.
+    moderation: Moderation,
+    /// What a server lets each client send, and how many connect.
+    limits: Limits,
+    /// Where a server announces itself, unless `--no-announce` was given.
+    announce: Option<DiscoveryConfig>,
+}
+
+/// How much history the server keeps and replays to new clients.
+struct HistoryConfig {
+    room: String,
+    capacity: usize,
+    replay: usize,
+    dir: Option<PathBuf>,
+}
+
+impl HistoryConfig {
+    fn open(&self) -> io::Result<History> {
+        match &self.dir {
+            Some(dir) => History::open(self.capacity, &History::room_path(dir, &self.room)),
+            None => Ok(History::new(self.capacity)),
+        }
+    }
+}
+
+/// Our messages on their way out, shared with the send callbacks.
+struct Outgoing {
+    /// Markers after our messages in the log.
+    receipts: Receipts,
+    /// A client's posts until the server acknowledges them.
+    outbox: Outbox,
+    /// Whether posts can be written now; otherwise they wait in the outbox.
+    online: bool,
+}
+
+/// Where messages typed into the window are delivered.
+#[derive(Clone)]
+enum Link {
+    Client(ChatClient),
+    Server(Box<ChatServer>),
+}
+
+impl Link {
+    fn send(&self, text: &str) -> io::Result<()> {
+        match self {
+            Link::Client(client) => client.send(text),
+            Link::Server(server) => {
+                server.send(text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends one of our messages as post `id`. The server is the room, so
+    /// its posts need no acknowledgement.
+    fn post(&self, id: u64, text: &str) -> io::Result<()> {
+        match self {
+            Link::Client(client) => client.post(id, text),
+            Link::Server(server) => {
+                server.post(id, text);
+                Ok(())
+            }
+        }
+    }
+
+    fn read(&self, id: u64, author: &str) -> io::Result<()> {
+        match self {
+            Link::Client(client) => client.read(id, author),
+            Link::Server(server) => {
+                server.read(id, author);
+                Ok(())
+            }
+        }
+    }
+
+    fn set_typing(&self, typing: bool) -> io::Result<()> {
+        match self {
+            Link::Client(client) => client.set_typing(typing),
+            Link::Server(server) => {
+                server.set_typing(typing);
+                Ok(())
+            }
+        }
+    }
+
+    fn set_presence(&self, presence: Presence) -> io::Result<()> {
+        match self {
+            Link::Client(client) => client.set_presence(presence),
+            Link::Server(server) => {
+                server.set_presence(presence);
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs a slash command. The server answers a client's later, if it
+    /// refuses it.
+    fn command(&self, line: &str) -> Result<(), String> {
+        match self {
+            Link::Client(client) => client.command(line).map_err(|e| format!("Error sending command: {}", e)),
+            Link::Server(server) => server.command(line),
+        }
+    }
 }
 
-type ClientMap = Arc<Mutex<HashMap<String, TcpStream>>>;
+/// Event stream of whichever side of the connection we are.
+enum Events {
+    Client(Receiver<ClientEvent>),
+    Server(Receiver<ServerEvent>),
+}
 
 impl MultiChat {
-    fn new(mode: String, username: String) -> Self {
+    fn new(mode: String, username: String, mut config: ChatConfig) -> Self {
         println!("Creating new MultiChat instance: mode={}, username={}", mode, username);
         let app = app::App::default().with_scheme(app::Scheme::Gtk);
-        
+
         let title = format!("Multi Chat - {} - {}", mode, username);
-        let mut window = Window::new(100, 100, 400, 450, &*title);
-        
-        let mut pack = Pack::new(10, 10, 380, 430, "");
-        pack.set_spacing(10);
-        
-        let mut status_label = Frame::new(0, 0, 380, 30, "Status: Connecting...");
+        let geometry = WindowGeometry::load_or("multi_chat", WindowGeometry { x: 100, y: 100, w: 520, h: 450 });
+        let mut window = Window::new(geometry.x, geometry.y, geometry.w, geometry.h, &*title);
+
+        let mut layout = Flex::default_fill().column();
+        layout.set_margin(10);
+        layout.set_pad(10);
+
+        // Status and user count share the top row. Cancel aborts a connection
+        // attempt, or stops the server while it is running.
+        let mut status_row = Flex::default().row();
+        let mut status_label = Frame::default().with_label(&ConnectionState::Idle.status_line());
         status_label.set_label_color(Color::Red);
-        
-        let mut users_label = Frame::new(200, 0, 180, 30, "Users: 0");
+        let mut cancel_button = Button::default().with_label("Cancel");
+        cancel_button.deactivate();
+        status_row.fixed(&cancel_button, 80);
+        let mut users_label = Frame::default().with_label("Users: 0");
         users_label.set_label_color(Color::Blue);
-        
+        status_row.fixed(&users_label, 120);
+        status_row.end();
+        layout.fixed(&status_row, 30);
+
+        // Message log and user list side by side; the divider can be dragged.
+        // Tile children must cover it exactly, so size them for the initial
+        // window and let the Flex scale everything from there.
+        let (tile_x, tile_y) = (10, 50);
+        let (tile_w, tile_h) = (geometry.w - 20, geometry.h - 156);
+        let side_w = 120.min(tile_w / 2);
+        let mut tile = Tile::new(tile_x, tile_y, tile_w, tile_h, None);
+
         let display_buffer = TextBuffer::default();
-        let mut text_display = TextDisplay::new(0, 0, 380, 200, "");
+        let mut text_display = TextDisplay::new(tile_x, tile_y, tile_w - side_w, tile_h, None);
         text_display.set_buffer(display_buffer.clone());
         text_display.set_frame(FrameType::FlatBox);
         text_display.set_color(Color::White);
-        
-        let mut input = Input::new(0, 0, 300, 30, "");
-        let mut send_button = Button::new(300, 0, 80, 30, "Send");
+
+        let users_list = HoldBrowser::new(tile_x + tile_w - side_w, tile_y, side_w, tile_h, None);
+        tile.size_range_by_child(&users_list, 60, 40, 0, 0);
+        tile.size_range_by_child(&text_display, 120, 40, 0, 0);
+        tile.end();
+
+        // Who is typing, under the log.
+        let mut typing_label = Frame::default();
+        typing_label.set_align(Align::Left | Align::Inside);
+        typing_label.set_label_font(Font::HelveticaItalic);
+        typing_label.set_label_size(12);
+        typing_label.set_label_color(Color::Dark3);
+        layout.fixed(&typing_label, 16);
+
+        // Input row stays at the bottom. Enter sends, Shift+Enter adds a line.
+        let mut input_row = Flex::default().row();
+        let input = MultilineInput::default();
+        let mut send_button = Button::default().with_label("Send");
         send_button.set_color(Color::from_rgb(50, 50, 255));
         send_button.set_label_color(Color::White);
         send_button.deactivate();
-        
-        pack.end();
+        input_row.fixed(&send_button, 80);
+        input_row.end();
+        layout.fixed(&input_row, 60);
+
+        layout.end();
         window.end();
+        window.resizable(&layout);
+        window.size_range(300, 250, 0, 0);
+
+        // Any key or mouse activity in the window means we are not idle.
+        let last_input = Rc::new(Cell::new(Instant::now()));
+        let window_input = Rc::clone(&last_input);
+        window.handle(move |_, ev| {
+            if matches!(ev, Event::Push | Event::KeyDown | Event::MouseWheel | Event::Move | Event::Focus) {
+                window_input.set(Instant::now());
+            }
+            false
+        });
         window.show();
-        
+
         println!("Window created successfully");
-        
+
         MultiChat {
             app,
             window,
@@ -84,146 +317,189 @@ impl MultiChat {
             text_display,
             display_buffer,
             status_label,
+            state: ConnectionState::Idle,
+            round_trip: None,
+            cancel_button,
             users_label,
-            stream: None,
+            users_list,
+            typing_label,
+            roster: Roster::default(),
+            typing: TypingNotifier::default(),
+            idle: IdleTimer::new(config.idle_after),
+            last_input,
+            outgoing: Rc::new(RefCell::new(Outgoing {
+                receipts: Receipts::new(),
+                outbox: std::mem::take(&mut config.outbox),
+                online: true,
+            })),
+            link: None,
             username,
+            config,
+            cancel: Cancel::new(),
         }
     }
 
-    fn handle_initial_connection(stream: &mut TcpStream, sender: app::Sender<Message>) -> std::io::Result<()> {
-        println!("Handling initial connection setup");
-        
-        let mut username_buffer = Vec::new();
-        let mut byte_buffer = [0u8; 1];
-        
-        loop {
-            match stream.read(&mut byte_buffer) {
-                Ok(1) => {
-                    if byte_buffer[0] == b'\n' {
-                        break;
-                    }
-                    username_buffer.push(byte_buffer[0]);
-                }
-                _ => break,
+    /// Lets the bot answer a chat message, on a thread of its own since a
+    /// rule may run a command.
+    fn answer(bot: &Option<(Arc<Bot>, Link)>, from: &str, text: &str, sender: &app::Sender<Message>) {
+        let Some((bot, link)) = bot.clone() else { return };
+        let (from, text, sender) = (from.to_string(), text.to_string(), sender.clone());
+        thread::spawn(move || {
+            let Some(reply) = bot.respond(&from, &text) else { return };
+            match link.send(&reply) {
+                Ok(()) => sender.send(Message::UpdateDisplay(format_block("Bot: ", &reply))),
+                Err(e) => sender.send(Message::Error(format!("Error sending bot reply: {}\n", e))),
             }
-        }
-        
-        if let Ok(name) = String::from_utf8(username_buffer) {
-            println!("Client username (cleaned): {}", name.trim());
-            let msg = format!("User {} joined the chat\n", name.trim());
-            sender.send(Message::UpdateDisplay(msg));
-            sender.send(Message::UserList(format!("Users: 1")));
-        }
-        
-        Ok(())
+        });
     }
-    
-    fn start_message_receiver(mut stream: TcpStream, sender: app::Sender<Message>) {
+
+    /// A chat message from someone else, as a display message. Whoever
+    /// sent it is no longer typing.
+    fn chat(bot: &Option<(Arc<Bot>, Link)>, from: String, text: String, sender: &app::Sender<Message>) -> Message {
+        Self::answer(bot, &from, &text, sender);
+        sender.send(Message::Typing { from: from.clone(), typing: false });
+        Message::UpdateDisplay(format_block(&format!("{}: ", from), &text))
+    }
+
+    /// Forwards engine events to the UI thread as display messages.
+    fn forward_events(events: Events, sender: app::Sender<Message>, bot: Option<(Arc<Bot>, Link)>) {
         println!("Starting message receiver");
-        thread::spawn(move || {
-            let mut buffer = [0u8; 1024];
-            loop {
-                match stream.read(&mut buffer) {
-                    Ok(n) if n > 0 => {
-                        if let Ok(message) = String::from_utf8(buffer[..n].to_vec()) {
-                            let trimmed = message.trim();
-                            if !trimmed.is_empty() {
-                                println!("Receiver got message: {}", trimmed);
-                                sender.send(Message::UpdateDisplay(format!("Other: {}\n", trimmed)));
-                            }
+        thread::spawn(move || match events {
+            Events::Server(events) => {
+                for event in events {
+                    sender.send(match event {
+                        ServerEvent::Chat { from, text } => Self::chat(&bot, from, text, &sender),
+                        ServerEvent::Post { id, from, text } => {
+                            sender.send(Message::Received { id, from: from.clone() });
+                            Self::chat(&bot, from, text, &sender)
                         }
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        thread::sleep(Duration::from_millis(50));
-                    }
-                    Err(e) => {
-                        sender.send(Message::Error(format!("Error reading: {}\n", e)));
-                        thread::sleep(Duration::from_secs(1));
-                    }
-                    _ => thread::sleep(Duration::from_millis(50)),
+                        ServerEvent::Read { id, by } => Message::Read { id, by },
+                        ServerEvent::Notice(text) => Message::UpdateDisplay(format!("*** {}\n", text)),
+                        ServerEvent::Users(users) => Message::UserList(users),
+                        ServerEvent::Typing { from, typing } => Message::Typing { from, typing },
+                        ServerEvent::Presence { user, presence } => Message::Presence { user, presence },
+                        ServerEvent::Stopped => {
+                            sender.send(Message::UpdateDisplay("*** Server stopped\n".to_string()));
+                            sender.send(Message::State(ConnectionState::Idle));
+                            return;
+                        }
+                    });
+                }
+            }
+            Events::Client(events) => {
+                for event in events {
+                    sender.send(match event {
+                        ClientEvent::Chat { from, text } => Self::chat(&bot, from, text, &sender),
+                        ClientEvent::Post { id, from, text } => {
+                            sender.send(Message::Received { id, from: from.clone() });
+                            Self::chat(&bot, from, text, &sender)
+                        }
+                        ClientEvent::Delivered(id) => Message::Delivered(id),
+                        ClientEvent::Read { id, by } => Message::Read { id, by },
+                        ClientEvent::RoundTrip(round_trip) => Message::RoundTrip(round_trip),
+                        ClientEvent::Kicked(reason) => Message::Kicked(reason),
+                        ClientEvent::History { timestamp, from, text } => {
+                            let prefix = format!("[history {}] {}: ", format_clock(timestamp), from);
+                            Message::UpdateDisplay(format_block(&prefix, &text))
+                        }
+                        ClientEvent::System(text) => Message::UpdateDisplay(format!("*** {}\n", text)),
+                        ClientEvent::Users(users) => Message::UserList(users),
+                        ClientEvent::Typing { from, typing } => Message::Typing { from, typing },
+                        ClientEvent::Presence { user, presence } => Message::Presence { user, presence },
+                        ClientEvent::Disconnected(reason) => {
+                            let reason = reason.unwrap_or_else(|| "disconnected from server".to_string());
+                            sender.send(Message::Error(format!("Connection lost: {}\n", reason)));
+                            Message::State(ConnectionState::Failed { reason })
+                        }
+                    });
                 }
             }
         });
     }
-    
-    fn connect(&mut self, mode: String, address: String) {
+
+    fn connect(&mut self, mode: String, address: String, sender: app::Sender<Message>, receiver: &app::Receiver<Message>) {
         println!("Starting connection: mode={}, address={}", mode, address);
-        let (sender, receiver) = app::channel::<Message>();
-        let stream_container = Arc::new(Mutex::new(None));
-        let stream_container_clone = Arc::clone(&stream_container);
+        let link_container = Arc::new(Mutex::new(None));
+        let link_container_clone = Arc::clone(&link_container);
         let username = self.username.clone();
+        let history = self.config.history.open();
+        let replay = self.config.history.replay;
+        let auth = Arc::clone(&self.config.auth);
+        let password = self.config.password.clone();
+        let timeouts = self.config.timeouts;
+        let capture = self.config.capture.clone();
+        let bot = self.config.bot.clone();
+        let heartbeat = self.config.heartbeat;
+        let moderation = std::mem::take(&mut self.config.moderation);
+        let limits = self.config.limits;
+        let announce = self.config.announce;
+        let room = self.config.history.room.clone();
+        let cancel = self.cancel.clone();
+        let worker = cancel.worker();
+
+        let button_cancel = self.cancel.clone();
+        self.cancel_button.set_callback(move |_| button_cancel.cancel());
+        self.cancel_button.activate();
 
         thread::spawn(move || {
+            let _worker = worker;
             let result = match mode.as_str() {
                 "server" => {
                     println!("Starting server on {}", address);
-                    sender.send(Message::UpdateDisplay("Starting server...\n".to_string()));
-                    
-                    match TcpListener::bind(&address) {
-                        Ok(listener) => {
+                    sender.send(Message::State(ConnectionState::Binding { address: address.clone() }));
+                    history
+                        .and_then(|history| {
+                            let handshake_timeout = timeouts.handshake;
+                            let config = ServerConfig {
+                                username,
+                                history,
+                                replay,
+                                auth,
+                                handshake_timeout,
+                                heartbeat,
+                                moderation,
+                                limits,
+                            };
+                            ChatServer::bind(&address, config)
+                        })
+                        .map(|(server, events)| {
                             println!("Server bound to address successfully");
+                            let running = server.clone();
+                            cancel.on_cancel(move || running.shutdown());
                             sender.send(Message::UpdateDisplay("Server started, waiting for clients...\n".to_string()));
-                            match listener.accept() {
-                                Ok((mut stream, addr)) => {
-                                    println!("Client connected from: {}", addr);
-                                    
-                                    match stream.set_nonblocking(true) {
-                                        Ok(_) => {
-                                            match stream.try_clone() {
-                                                Ok(receiver_stream) => {
-                                                    let sender_clone = sender.clone();
-                                                    Self::start_message_receiver(receiver_stream, sender_clone);
-                                                    
-                                                    match Self::handle_initial_connection(&mut stream, sender.clone()) {
-                                                        Ok(_) => Ok(stream),
-                                                        Err(e) => Err(e)
-                                                    }
-                                                }
-                                                Err(e) => Err(e)
-                                            }
-                                        }
-                                        Err(e) => Err(e)
-                                    }
-                                }
-                                Err(e) => Err(e)
+                            if let Some(config) = announce {
+                                Self::announce(&server, room, config, &cancel);
                             }
-                        }
-                        Err(e) => Err(e)
-                    }
+                            let state = ConnectionState::Listening { address: server.listening_on().to_string() };
+                            (Link::Server(Box::new(server)), Events::Server(events), state)
+                        })
                 }
                 "client" => {
                     println!("Starting client connection to {}", address);
-                    sender.send(Message::UpdateDisplay(format!("Connecting to {}...\n", address)));
-                    match TcpStream::connect(&address) {
-                        Ok(mut stream) => {
-                            println!("Client connected successfully");
-                            writeln!(stream, "{}", username).unwrap();
-                            stream.flush().unwrap();
-                            
-                            sender.send(Message::UpdateDisplay("Connected successfully\n".to_string()));
-                            Ok(stream)
-                        }
-                        Err(e) => {
-                            println!("Error connecting to server: {}", e);
-                            Err(e)
-                        }
-                    }
+                    sender.send(Message::State(ConnectionState::Connecting { address: address.clone() }));
+                    net::dial(&address, timeouts.connect, &cancel).and_then(|stream| {
+                        let peer = Some(stream.peer().to_string());
+                        cancel.watch(&stream)?;
+                        stream.set_read_timeout(Some(timeouts.handshake))?;
+                        sender.send(Message::State(ConnectionState::Handshaking));
+                        let (client, events) = ChatClient::login_with(stream, &username, &password, capture, heartbeat)?;
+                        Ok((Link::Client(client), Events::Client(events), ConnectionState::Connected { peer }))
+                    })
                 }
-                _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid mode"))
+                _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid mode"))
             };
 
             match result {
-                Ok(stream) => {
-                    println!("Setting stream to non-blocking mode");
-                    if let Ok(_) = stream.set_nonblocking(true) {
-                        let mut lock = stream_container_clone.lock().unwrap();
-                        *lock = Some(stream);
-                        sender.send(Message::UpdateDisplay("Connected successfully\n".to_string()));
-                    }
+                Ok((link, events, state)) => {
+                    let bot = bot.map(|bot| (bot, link.clone()));
+                    *link_container_clone.lock().unwrap() = Some(link);
+                    // Only sent once the link is stored, so the UI can take it.
+                    sender.send(Message::State(state));
+                    Self::forward_events(events, sender, bot);
                 }
                 Err(e) => {
                     println!("Connection error: {}", e);
-                    sender.send(Message::Error(format!("Connection error: {}\n", e)));
+                    sender.send(Message::State(ConnectionState::failed(e)));
                 }
             }
         });
@@ -233,23 +509,31 @@ impl MultiChat {
             if let Some(msg) = receiver.recv() {
                 println!("Received message: {:?}", msg);
                 match msg {
-                    Message::UpdateDisplay(text) => {
+                    Message::UpdateDisplay(text) | Message::Error(text) => {
                         self.display_buffer.append(&text);
-                        self.status_label.set_label("Status: Connected");
-                        self.status_label.set_label_color(Color::Green);
-                        self.send_button.activate();
-                        if text.contains("Connected successfully") {
-                            println!("Connection successful, breaking connect loop");
-                            break;
-                        }
                     }
-                    Message::Error(text) => {
-                        self.display_buffer.append(&text);
-                        self.status_label.set_label("Status: Error");
-                        self.status_label.set_label_color(Color::Red);
+                    Message::Kicked(reason) => self.display_buffer.append(&format!("*** {}\n", reason)),
+                    Message::UserList(users) => {
+                        self.roster.set_users(users);
+                        self.show_users();
                     }
-                    Message::UserList(text) => {
-                        self.users_label.set_label(&text);
+                    Message::Typing { .. } | Message::Presence { .. } => self.show_presence(msg),
+                    Message::Edited(_) | Message::Tick => {}
+                    Message::Received { .. } | Message::Delivered(_) | Message::Read { .. } => {}
+                    Message::RoundTrip(_) => {}
+                    Message::State(state) => {
+                        self.show_state(&state);
+                        match state {
+                            ConnectionState::Connected { .. } | ConnectionState::Listening { .. } => {
+                                println!("Connection ready, leaving connect loop");
+                                break;
+                            }
+                            ConnectionState::Failed { reason } => {
+                                self.display_buffer.append(&format!("Connection error: {}\n", reason));
+                                break;
+                            }
+                            _ => {}
+                        }
                     }
                 }
                 app::flush();
@@ -257,140 +541,234 @@ impl MultiChat {
             app::wait();
         }
 
-        let mut lock = stream_container.lock().unwrap();
-        if let Some(stream) = lock.take() {
-            println!("Taking ownership of stream");
-            self.stream = Some(stream);
+        let mut lock = link_container.lock().unwrap();
+        if let Some(link) = lock.take() {
+            println!("Taking ownership of link");
+            self.link = Some(link);
+        }
+    }
+
+    /// Lets clients on the local network find `server`. Unix socket servers
+    /// are not announced.
+    fn announce(server: &ChatServer, room: String, config: DiscoveryConfig, cancel: &Cancel) {
+        let Some(port) = server.local_addr().map(|addr| addr.port()) else { return };
+        let server = server.clone();
+        let host = discovery::host_name();
+        let started = Announcer::start(config, move || Announcement {
+            room: room.clone(),
+            host: host.clone(),
+            port,
+            users: server.users().len(),
+        });
+        match started {
+            Ok(announcer) => {
+                println!("Announcing the room on {}", config.group);
+                cancel.on_cancel(move || announcer.stop());
+            }
+            Err(e) => println!("Could not announce the room: {}", e),
         }
     }
-    
+
+    fn send_input(
+        link: &Link,
+        input: &mut MultilineInput,
+        display_buffer: &mut TextBuffer,
+        outgoing: &RefCell<Outgoing>,
+        sender: &app::Sender<Message>,
+    ) {
+        let message = input.value();
+        println!("Sending message: {}", message);
+        if message.is_empty() {
+            return;
+        }
+        // A leading slash makes a command; a doubled one sends a plain slash.
+        let message = match message.strip_prefix('/') {
+            Some(text) if text.starts_with('/') => text.to_string(),
+            Some(_) => {
+                if let Err(e) = link.command(&message) {
+                    display_buffer.append(&format!("*** {}\n", e));
+                }
+                input.set_value("");
+                sender.send(Message::Edited(true));
+                return;
+            }
+            None => message,
+        };
+        let mut outgoing = outgoing.borrow_mut();
+        let Outgoing { receipts, outbox, online } = &mut *outgoing;
+        let id = receipts.next_id();
+        // The server is the room, so only a client has to wait for acknowledgements.
+        let client = matches!(link, Link::Client(_));
+        if client {
+            if let Err(e) = outbox.push(id, &message) {
+                println!("Error saving the outbox: {}", e);
+            }
+        }
+        let sent = *online
+            && match link.post(id, &message) {
+                Ok(_) => true,
+                Err(e) => {
+                    // The reader notices the connection is gone and reconnects.
+                    println!("Error writing to stream, queueing the message: {}", e);
+                    false
+                }
+            };
+
+        let block = format_block("Me: ", &message);
+        let at = display_buffer.length() as usize;
+        if sent {
+            println!("Message sent successfully");
+            display_buffer.append(&receipts.track(id, &block, at));
+        } else {
+            display_buffer.append(&receipts.track_queued(id, &block, at));
+        }
+        if !client {
+            Self::apply(display_buffer, receipts.delivered(id));
+        }
+        input.set_value("");
+        // Clearing the input from code does not run its callback.
+        sender.send(Message::Edited(true));
+        app::flush();
+    }
+
+    /// Sends everything in the outbox again, oldest first, once connected.
+    fn flush_outbox(&mut self, link: &Link) {
+        let outgoing = Rc::clone(&self.outgoing);
+        let mut outgoing = outgoing.borrow_mut();
+        outgoing.online = true;
+        let Outgoing { receipts, outbox, online } = &mut *outgoing;
+        if !outbox.is_empty() {
+            println!("Sending {} queued messages", outbox.len());
+        }
+        for queued in outbox.queued() {
+            // Left over from an earlier run, so not in the log yet.
+            if receipts.delivery(queued.id).is_none() {
+                let at = self.display_buffer.length() as usize;
+                let block = format_block("Me: ", &queued.text);
+                self.display_buffer.append(&receipts.track_queued(queued.id, &block, at));
+            }
+            if let Err(e) = link.post(queued.id, &queued.text) {
+                println!("Error sending queued message: {}", e);
+                *online = false;
+                return;
+            }
+            Self::apply(&mut self.display_buffer, receipts.resent(queued.id));
+        }
+    }
+
+    /// The server got post `id`, or someone even read it.
+    fn acknowledged(&mut self, id: u64) {
+        if let Err(e) = self.outgoing.borrow_mut().outbox.acknowledged(id) {
+            println!("Error saving the outbox: {}", e);
+        }
+    }
+
+    /// Logs `client` in again after the connection dropped, waiting longer
+    /// after each failed attempt, until it works or is cancelled.
+    fn reconnect(&self, client: ChatClient, address: String, sender: app::Sender<Message>) {
+        let password = self.config.password.clone();
+        let timeouts = self.config.timeouts;
+        let bot = self.config.bot.clone().map(|bot| (bot, Link::Client(client.clone())));
+        let cancel = self.cancel.clone();
+        let worker = cancel.worker();
+        thread::spawn(move || {
+            let _worker = worker;
+            for attempt in 1.. {
+                sender.send(Message::State(ConnectionState::Reconnecting { attempt }));
+                if cancel.sleep(client::reconnect_delay(attempt)).is_err() {
+                    break;
+                }
+                let result = net::dial(&address, timeouts.connect, &cancel).and_then(|stream| {
+                    let peer = Some(stream.peer().to_string());
+                    cancel.watch(&stream)?;
+                    stream.set_read_timeout(Some(timeouts.handshake))?;
+                    Ok((client.resume(stream, &password)?, peer))
+                });
+                match result {
+                    Ok((events, peer)) => {
+                        sender.send(Message::UpdateDisplay("*** Reconnected\n".to_string()));
+                        sender.send(Message::State(ConnectionState::Connected { peer }));
+                        Self::forward_events(Events::Client(events), sender, bot);
+                        return;
+                    }
+                    Err(e) if cancel.is_cancelled() => {
+                        println!("Reconnecting cancelled: {}", e);
+                        break;
+                    }
+                    Err(e) => println!("Reconnection attempt {} failed: {}", attempt, e),
+                }
+            }
+            sender.send(Message::State(ConnectionState::failed("reconnecting cancelled")));
+        });
+    }
+
+    /// Updates a marker after one of our messages.
+    fn apply(display_buffer: &mut TextBuffer, edit: Option<Edit>) {
+        if let Some(edit) = edit {
+            display_buffer.replace(edit.start as i32, edit.end as i32, &edit.text);
+        }
+    }
+
     fn run(&mut self, mode: String, address: String) {
-        self.connect(mode, address);
-        
-        if let Some(stream) = self.stream.as_ref() {
+        let (sender, receiver) = app::channel::<Message>();
+        self.connect(mode, address.clone(), sender.clone(), &receiver);
+
+        if let Some(link) = self.link.clone() {
             println!("Setting up message handling");
-            let stream_write = stream.try_clone().expect("Failed to clone stream");
+            self.flush_outbox(&link);
             let mut input = self.input.clone();
             let mut display_buffer = self.display_buffer.clone();
-            let username = self.username.clone();
-            
-            let stream_write = Arc::new(Mutex::new(stream_write));
-            let stream_write_clone = stream_write.clone();
-            
+            let button_link = link.clone();
+            let button_sender = sender.clone();
+            let button_outgoing = Rc::clone(&self.outgoing);
+
             println!("Setting up send button callback");
             self.send_button.set_callback(move |_| {
-                let message = input.value();
-                println!("Send button clicked, message: {}", message);
-                if !message.is_empty() {
-                    if let Ok(mut stream) = stream_write.lock() {
-                        let full_message = format!("{}: {}", username, message);
-                        println!("Attempting to send: {}", full_message);
-                        match writeln!(&mut *stream, "{}", full_message) {
-                            Ok(_) => {
-                                match stream.flush() {
-                                    Ok(_) => {
-                                        println!("Message sent successfully");
-                                        display_buffer.append(&format!("Me: {}\n", message));
-                                        input.set_value("");
-                                    }
-                                    Err(e) => {
-                                        println!("Error flushing stream: {}", e);
-                                        display_buffer.append(&format!("Error flushing: {}\n", e));
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                println!("Error writing to stream: {}", e);
-                                display_buffer.append(&format!("Error sending: {}\n", e));
-                            }
-                        }
-                    } else {
-                        println!("Failed to acquire stream lock");
-                    }
-                }
-                app::flush();
+                Self::send_input(&button_link, &mut input, &mut display_buffer, &button_outgoing, &button_sender);
             });
-            
+
             let mut input = self.input.clone();
             let mut display_buffer = self.display_buffer.clone();
-            let username = self.username.clone();
-            
+            let key_sender = sender.clone();
+            let key_link = link.clone();
+            let key_outgoing = Rc::clone(&self.outgoing);
+
             println!("Setting up Enter key handler");
             input.handle(move |i, ev| {
                 if ev == Event::KeyDown && app::event_key() == Key::Enter {
-                    let message = i.value();
-                    println!("Enter pressed, message: {}", message);
-                    if !message.is_empty() {
-                        if let Ok(mut stream) = stream_write_clone.lock() {
-                            let full_message = format!("{}: {}", username, message);
-                            println!("Attempting to send: {}", full_message);
-                            match writeln!(&mut *stream, "{}", full_message) {
-                                Ok(_) => {
-                                    match stream.flush() {
-                                        Ok(_) => {
-                                            println!("Message sent successfully");
-                                            display_buffer.append(&format!("Me: {}\n", message));
-                                            i.set_value("");
-                                        }
-                                        Err(e) => {
-                                            println!("Error flushing stream: {}", e);
-                                            display_buffer.append(&format!("Error flushing: {}\n", e));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    println!("Error writing to stream: {}", e);
-                                    display_buffer.append(&format!("Error sending: {}\n", e));
-                                }
-                            }
-                        } else {
-                            println!("Failed to acquire stream lock");
-                        }
+                    if app::is_event_shift() {
+                        return false;
                     }
-                    app::flush();
+                    Self::send_input(&key_link, i, &mut display_buffer, &key_outgoing, &key_sender);
                     true
                 } else {
                     false
                 }
             });
-            
-            let mut stream_read = stream.try_clone().expect("Failed to clone stream");
-            let (sender, receiver) = app::channel::<Message>();
-            
-            println!("Starting message receiving thread");
-            thread::spawn(move || {
-                let mut buffer = [0u8; 1024];
-                loop {
-                    match stream_read.read(&mut buffer) {
-                        Ok(n) if n > 0 => {
-                            println!("Received raw data, bytes: {}", n);
-                            if let Ok(message) = String::from_utf8(buffer[..n].to_vec()) {
-                                let trimmed = message.trim();
-                                if !trimmed.is_empty() {
-                                    println!("Processing message: {}", trimmed);
-                                    sender.send(Message::UpdateDisplay(format!("Other: {}\n", trimmed)));
-                                } else {
-                                    println!("Skipping empty message");
-                                }
-                            } else {
-                                println!("Failed to convert received data to UTF-8");
-                            }
-                        }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            thread::sleep(Duration::from_secs(1));
-                        }
-                        _ => {
-                            thread::sleep(Duration::from_millis(50));
-                        }
-                    }
-                }
+
+            // Typing notices follow the edits of the input.
+            let edit_sender = sender.clone();
+            let edit_input = Rc::clone(&self.last_input);
+            input.set_trigger(CallbackTrigger::Changed);
+            input.set_callback(move |i| {
+                edit_input.set(Instant::now());
+                edit_sender.send(Message::Edited(i.value().is_empty()));
+            });
+
+            let tick_sender = sender.clone();
+            app::add_timeout3(1.0, move |handle| {
+                tick_sender.send(Message::Tick);
+                app::repeat_timeout3(1.0, handle);
             });
 
             println!("Starting UI message handling loop");
             let mut display_buffer = self.display_buffer.clone();
             while self.window.shown() {
                 if let Some(msg) = receiver.recv() {
-                    println!("Received UI message: {:?}", msg);
+                    // Ticks come every second and edits with every keystroke.
+                    if !matches!(msg, Message::Tick | Message::Edited(_)) {
+                        println!("Received UI message: {:?}", msg);
+                    }
                     match msg {
                         Message::UpdateDisplay(text) => {
                             println!("Updating display with: {}", text);
@@ -400,9 +778,52 @@ impl MultiChat {
                             println!("Error received: {}", text);
                             display_buffer.append(&text);
                         }
-                        Message::UserList(text) => {
-                            println!("Updating user list: {}", text);
-                            self.users_label.set_label(&text);
+                        Message::UserList(users) => {
+                            println!("Updating user list: {:?}", users);
+                            self.roster.set_users(users);
+                            self.show_users();
+                        }
+                        Message::State(state) => {
+                            if state.is_failed() {
+                                self.went_offline(&mut display_buffer);
+                                if let Link::Client(client) = &link {
+                                    if self.config.reconnect && !self.cancel.is_cancelled() {
+                                        self.reconnect(client.clone(), address.clone(), sender.clone());
+                                    }
+                                }
+                            }
+                            if state.is_connected() {
+                                self.flush_outbox(&link);
+                            }
+                            self.show_state(&state);
+                        }
+                        Message::Received { id, from } => {
+                            if self.config.read_receipts {
+                                self.outgoing.borrow_mut().receipts.received(id, &from, Instant::now());
+                            }
+                        }
+                        Message::Delivered(id) => {
+                            self.acknowledged(id);
+                            Self::apply(&mut display_buffer, self.outgoing.borrow_mut().receipts.delivered(id));
+                        }
+                        Message::Read { id, by } => {
+                            self.acknowledged(id);
+                            Self::apply(&mut display_buffer, self.outgoing.borrow_mut().receipts.read(id, &by));
+                        }
+                        Message::Typing { .. } | Message::Presence { .. } => self.show_presence(msg),
+                        Message::Edited(empty) => {
+                            if let Some(typing) = self.typing.edited(empty, Instant::now()) {
+                                Self::tell_typing(&link, typing);
+                            }
+                        }
+                        Message::Tick => self.tick(&link),
+                        Message::RoundTrip(round_trip) => {
+                            self.round_trip = Some(round_trip);
+                            self.show_status();
+                        }
+                        Message::Kicked(reason) => {
+                            display_buffer.append(&format!("*** {}\n", reason));
+                            self.config.reconnect = false;
                         }
                     }
                 }
@@ -414,25 +835,404 @@ impl MultiChat {
                 app::wait();
             }
         }
+        // Stop the server or drop the connection, and let the threads finish.
+        if !self.cancel.shutdown(Duration::from_secs(2)) {
+            println!("Connection threads did not stop in time");
+        }
+        self.save_geometry();
+    }
+
+    /// Holds back further posts and flags those never acknowledged.
+    fn went_offline(&mut self, display_buffer: &mut TextBuffer) {
+        let mut outgoing = self.outgoing.borrow_mut();
+        outgoing.online = false;
+        for edit in outgoing.receipts.disconnected() {
+            Self::apply(display_buffer, Some(edit));
+        }
+    }
+
+    fn show_state(&mut self, state: &ConnectionState) {
+        // A running server is "listening" for as long as it runs. While
+        // reconnecting, messages can still be written; they are queued.
+        let usable = matches!(
+            state,
+            ConnectionState::Connected { .. } | ConnectionState::Listening { .. } | ConnectionState::Reconnecting { .. }
+        );
+        if usable {
+            self.send_button.activate();
+        } else {
+            self.send_button.deactivate();
+        }
+        if state.is_pending() || usable {
+            self.cancel_button.activate();
+        } else {
+            self.cancel_button.deactivate();
+        }
+        self.state = state.clone();
+        self.round_trip = None;
+        self.show_status();
+        self.status_label.set_label_color(if state.is_failed() {
+            Color::Red
+        } else if state.is_pending() {
+            Color::Yellow
+        } else {
+            Color::Green
+        });
+    }
+
+    /// The status line, e.g. `Status: Connected to 10.0.0.2:8080 (ping 23 ms)`.
+    fn show_status(&mut self) {
+        let line = match self.round_trip {
+            Some(round_trip) if self.state.is_connected() => {
+                format!("{} (ping {} ms)", self.state.status_line(), round_trip.as_millis())
+            }
+            _ => self.state.status_line(),
+        };
+        self.status_label.set_label(&line);
+    }
+
+    fn show_users(&mut self) {
+        let users = self.roster.users();
+        self.users_label.set_label(&format!("Users: {}", users.len()));
+        self.users_list.clear();
+        for user in users {
+            self.users_list.add(&self.roster.label(user));
+        }
+        self.typing_label.set_label(&self.roster.typing_line());
     }
+
+    /// Applies a typing or presence change of someone else to the roster.
+    fn show_presence(&mut self, msg: Message) {
+        match msg {
+            Message::Typing { from, typing } => self.roster.set_typing(&from, typing, Instant::now()),
+            Message::Presence { user, presence } => self.roster.set_presence(&user, presence),
+            _ => return,
+        }
+        self.show_users();
+    }
+
+    /// Ends typing after a pause, notices idleness and drops stale notices.
+    fn tick(&mut self, link: &Link) {
+        let now = Instant::now();
+        if let Some(typing) = self.typing.tick(now) {
+            Self::tell_typing(link, typing);
+        }
+        if self.config.read_receipts {
+            for (id, author) in self.outgoing.borrow_mut().receipts.seen(self.last_input.get()) {
+                if let Err(e) = link.read(id, &author) {
+                    println!("Error sending read receipt: {}", e);
+                }
+            }
+        }
+        let mut changed = self.roster.expire(now);
+        if let Some(presence) = self.idle.update(self.last_input.get(), now) {
+            if let Err(e) = link.set_presence(presence) {
+                println!("Error sending presence: {}", e);
+            }
+            let username = self.username.clone();
+            self.roster.set_presence(&username, presence);
+            changed = true;
+        }
+        if changed {
+            self.show_users();
+        }
+    }
+
+    fn tell_typing(link: &Link, typing: bool) {
+        if let Err(e) = link.set_typing(typing) {
+            println!("Error sending typing notice: {}", e);
+        }
+    }
+
+    fn save_geometry(&self) {
+        let geometry = WindowGeometry {
+            x: self.window.x(),
+            y: self.window.y(),
+            w: self.window.w(),
+            h: self.window.h(),
+        };
+        if let Err(e) = geometry.save("multi_chat") {
+            println!("Could not save window position: {}", e);
+        }
+    }
+}
+
+fn build_authenticator(args: &Args, username: &str, room_password: Option<&str>) -> io::Result<Authenticator> {
+    let mut reserved = vec![username.to_string()];
+    if let Some(names) = args.value("reserved") {
+        reserved.extend(names.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()));
+    }
+
+    let mut auth = Authenticator::new().with_reserved(reserved);
+    if let Some(path) = args.value("users") {
+        auth = auth.with_users_file(Path::new(path))?;
+    } else if let Some(password) = room_password.filter(|p| !p.is_empty()) {
+        auth = auth.with_room_password(password);
+    }
+    Ok(auth)
+}
+
+const DIALOG: DialogOptions = DialogOptions {
+    title: "Multi Chat - Connect",
+    name: "multi_chat",
+    modes: &["server", "client"],
+    ask_username: true,
+    ask_password: true,
+    offer_e2e: false,
+    discovery: Some(DiscoveryConfig::DEFAULT),
+};
+
+/// Prints the servers announcing themselves within `wait`.
+fn discover(config: DiscoveryConfig, wait: Duration) -> io::Result<()> {
+    let mut browser = Browser::open(config)?;
+    println!("Looking for servers on {} for {} s...", config.group, wait.as_secs());
+    browser.poll(wait)?;
+    let servers = browser.servers();
+    if servers.is_empty() {
+        println!("No servers found.");
+    }
+    for server in servers {
+        println!("{}", server.label());
+    }
+    Ok(())
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    
-    if args.len() != 4 {
-        println!("Usage: cargo run --bin multi_chat <mode> <address> <username>");
-        println!("\nExamples:");
-        println!("  Server: cargo run --bin multi_chat server 0.0.0.0:8080 ServerUser");
-        println!("  Client: cargo run --bin multi_chat client 192.168.0.108:8080 Alice");
+    let args = Args::from_env();
+
+    let discovery = match DiscoveryConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    if args.positional.first().map(String::as_str) == Some("discover") {
+        let wait = match args.parse_or("wait", 5) {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        if let Err(e) = discover(discovery, wait) {
+            println!("Error looking for servers: {}", e);
+        }
         return;
     }
-    
-    let mode = args[1].clone();
-    let address = args[2].clone();
-    let username = args[3].clone();
-    
+
+    // Started without arguments, e.g. from a file manager: ask instead.
+    let (mode, address, username, password) = match args.positional.len() {
+        0 => match dialog::ask(&DialogOptions { discovery: Some(discovery), ..DIALOG }) {
+            Some(choice) => (choice.profile.mode, choice.profile.address, choice.profile.username, choice.password),
+            None => return,
+        },
+        3 => match auth::password_from(&args, std::env::var(PASSWORD_VAR).ok(), &mut io::stdin().lock()) {
+            Ok(password) => (args.positional[0].clone(), args.positional[1].clone(), args.positional[2].clone(), password),
+            Err(e) => {
+                println!("Error reading the password: {}", e);
+                return;
+            }
+        },
+        _ => {
+            print_usage();
+            return;
+        }
+    };
+
+    if mode == "adduser" {
+        let path = Path::new(&address);
+        if password.is_empty() {
+            println!("adduser needs --password, --password=SECRET or {}", PASSWORD_VAR);
+            return;
+        }
+        match UserDb::add_user(path, &username, &password) {
+            Ok(_) => println!("Stored credentials for {} in {}", username, path.display()),
+            Err(e) => println!("Error updating {}: {}", path.display(), e),
+        }
+        return;
+    }
+
+    let history = match (args.parse_or("history", 100), args.parse_or("replay", 20)) {
+        (Ok(capacity), Ok(replay)) => HistoryConfig {
+            room: args.value("room").unwrap_or("lobby").to_string(),
+            capacity,
+            replay,
+            dir: args.value("history-dir").map(PathBuf::from),
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let auth = match build_authenticator(&args, &username, Some(&password)) {
+        Ok(auth) => auth,
+        Err(e) => {
+            println!("Error loading credentials: {}", e);
+            return;
+        }
+    };
+
+    let timeouts = match Timeouts::from_args(&args) {
+        Ok(timeouts) => timeouts,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let capture = match args.value("capture").map(|path| Recorder::create(Path::new(path))).transpose() {
+        Ok(capture) => capture,
+        Err(e) => {
+            println!("Error creating capture file: {}", e);
+            return;
+        }
+    };
+
+    let bot = match args.value("bot").map(|path| Bot::load(Path::new(path))).transpose() {
+        Ok(bot) => bot.map(Arc::new),
+        Err(e) => {
+            println!("Error loading bot rules: {}", e);
+            return;
+        }
+    };
+
+    let idle_after = match args.parse_or("idle-after", IDLE_AFTER.as_secs()) {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let outbox = match args.value("outbox").map(|path| Outbox::open(Path::new(path))).transpose() {
+        Ok(outbox) => outbox.unwrap_or_default(),
+        Err(e) => {
+            println!("Error loading the outbox: {}", e);
+            return;
+        }
+    };
+
+    let heartbeat = match Heartbeat::from_args(&args) {
+        Ok(heartbeat) => heartbeat,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut moderation = Moderation::new();
+    if let Some(path) = args.value("bans") {
+        match BanList::open(Path::new(path)) {
+            Ok(bans) => moderation = moderation.with_bans(bans),
+            Err(e) => {
+                println!("Error loading the ban list: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(path) = args.value("audit-log") {
+        match AuditLog::open(Path::new(path)) {
+            Ok(audit) => moderation = moderation.with_audit(audit),
+            Err(e) => {
+                println!("Error opening the audit log: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(names) = args.value("ops") {
+        moderation = moderation.with_operators(names.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()));
+    }
+
+    let limits = match Limits::from_args(&args) {
+        Ok(limits) => limits,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let config = ChatConfig {
+        history,
+        auth: Arc::new(auth),
+        password,
+        timeouts,
+        capture,
+        bot,
+        idle_after,
+        read_receipts: !args.flag("no-read-receipts"),
+        reconnect: !args.flag("no-reconnect"),
+        outbox,
+        heartbeat,
+        moderation,
+        limits,
+        announce: (!args.flag("no-announce")).then_some(discovery),
+    };
+
     println!("Starting Multi Chat with mode={}, address={}, username={}", mode, address, username);
-    let mut chat = MultiChat::new(mode.clone(), username);
+    let mut chat = MultiChat::new(mode.clone(), username, config);
     chat.run(mode, address);
-}
\ No newline at end of file
+}
+
+fn print_usage() {
+    println!("Usage: cargo run --bin multi_chat [<mode> <address> <username> [options]]");
+    println!("\nWithout arguments a connection dialog is shown, listing the servers");
+    println!("announced on the local network.");
+    println!("\nExamples:");
+    println!("  Server: cargo run --bin multi_chat server 0.0.0.0:8080 ServerUser");
+    println!("  Client: cargo run --bin multi_chat client 192.168.0.108:8080 Alice");
+    println!("  IPv6:   cargo run --bin multi_chat server [::]:8080 ServerUser");
+    println!("  Unix:   cargo run --bin multi_chat server unix:/tmp/chat.sock,mode=660 ServerUser");
+    println!("  Find servers: cargo run --bin multi_chat discover [--wait=SECS]");
+    println!("  Add a user: cargo run --bin multi_chat adduser users.txt Alice --password");
+    println!("\nServer options:");
+    println!("  --room=NAME          Room name used for the history file (default: lobby)");
+    println!("  --history=N          Messages kept in memory (default: 100)");
+    println!("  --replay=N           Messages replayed to new clients (default: 20)");
+    println!("  --history-dir=DIR    Persist history to DIR/<room>.history");
+    println!("  --password=SECRET    Shared room password clients must present (see below)");
+    println!("  --users=FILE         Only admit users listed in FILE (see adduser)");
+    println!("  --reserved=A,B       Extra names nobody may use (the server's own name always is)");
+    println!("  --handshake-timeout=SECS  Drop clients that have not logged in after SECS (default: 10)");
+    println!("  --no-announce        Do not announce the room on the local network");
+    println!("  --ops=A,B            Users who may run the commands below, besides the server's user");
+    println!("  --bans=FILE          Keep the ban list in FILE, one 'user NAME' or 'ip ADDRESS' per line");
+    println!("  --audit-log=FILE     Append every command carried out to FILE");
+    println!("  --max-rate=N         Frames a client may send per second, 0 for any (default: 10)");
+    println!("  --max-bandwidth=N    Bytes a client may send per second, 0 for any (default: 32768)");
+    println!("  --max-message=N      Drop clients sending a line longer than N bytes (default: 65536)");
+    println!("  --max-per-ip=N       Connections allowed from one address, 0 for any (default: 10)");
+    println!("  --max-clients=N      Connections allowed in total, 0 for any (default: 200)");
+    println!("  --send-timeout=SECS  Drop clients that stop reading for SECS (default: 10)");
+    println!("\nDiscovery options:");
+    println!("  --discovery-group=ADDR:PORT  Multicast group for announcements (default: {})", discovery::DEFAULT_GROUP);
+    println!("  --discovery-interface=IP     Interface to announce and listen on");
+    println!("\nClient options:");
+    println!("  --password=SECRET    Room or user password (see below)");
+    println!("  --connect-timeout=SECS    Give up connecting after SECS (default: 10)");
+    println!("  --handshake-timeout=SECS  Give up on the login after SECS (default: 10)");
+    println!("  --capture=FILE       Record the frames sent and received (pcap if FILE ends in .pcap)");
+    println!("  --no-reconnect       Give up when the connection drops instead of logging in again");
+    println!("  --outbox=FILE        Keep unsent messages in FILE, to send them after a restart");
+    println!("\nServer and client options:");
+    println!("  --bot=FILE           Answer messages by the rules in FILE, one per line:");
+    println!("                         <regex> => reply <text>    ($from, $1... are filled in)");
+    println!("                         <regex> => run <command>   (message on stdin, output is sent)");
+    println!("  --idle-after=SECS    Show as idle after SECS without input (default: {})", IDLE_AFTER.as_secs());
+    println!("  --no-read-receipts   Do not tell others when their messages were read");
+    println!("  --ping-interval=SECS Ping the other side every SECS, 0 for never (default: 15)");
+    println!("  --ping-timeout=SECS  Drop a connection silent for SECS (default: 45)");
+    println!("  --keepalive=IDLE[,INTERVAL[,COUNT]]  TCP keepalive timing in seconds (default: 60,10,5)");
+    println!("  --no-keepalive       Turn TCP keepalive off");
+    println!("\nPasswords:");
+    println!("  A bare --password asks for it instead, and without --password it is taken");
+    println!("  from ${} if set, which keeps it out of the process list. The login", PASSWORD_VAR);
+    println!("  is not encrypted: the password crosses the network as typed, so only use");
+    println!("  one on a trusted network or through a tunnel such as ssh -L.");
+    println!("\nCommands, typed into the message box by operators (// sends a plain /):");
+    for name in ["kick", "ban", "unban", "mute", "unmute", "topic", "op", "deop"] {
+        println!("  {}", moderation::usage(name).unwrap_or_default());
+    }
+}
@@ -3,16 +3,33 @@ use fltk::{
     app,
     prelude::*,
     window::Window,
-    input::Input,
+    input::MultilineInput,
     button::Button,
-    text::{TextDisplay, TextBuffer},
-    group::Pack,
+    text::{TextDisplay, TextBuffer, StyleTableEntry},
+    group::Flex,
     frame::Frame,
-    enums::{Color, FrameType, Event, Key},
+    enums::{Color, Font, FrameType, Event, Key},
+};
+use socat_chat::{
+    address::{Address, Opened, Transport},
+    cancel::Cancel,
+    bot::Bot,
+    capture::{self, Recorder},
+    cli::Args,
+    dialog::{self, DialogOptions},
+    direct::{self, DirectSender, Duplex},
+    display::format_block,
+    e2e::{self, E2eError, Session},
+    exec::{self, Exec},
+    heartbeat::Heartbeat,
+    net::{self, Stream, Timeouts},
+    settings::WindowGeometry,
+    state::ConnectionState,
+    udp::{self, UdpOptions},
 };
 use std::{
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
+    io::{self, Read, Write},
+    path::Path,
     thread,
     time::Duration,
     sync::{Arc, Mutex},
@@ -21,48 +38,135 @@ use std::{
 enum Message {
     UpdateDisplay(String),
     Error(String),
+    State(ConnectionState),
+    RoundTrip(Duration),
+}
+
+/// An opened transport, secured if asked to.
+struct Connection {
+    duplex: Duplex,
+    session: Option<Session>,
+    peer: Option<String>,
+    /// The TCP or Unix socket beneath, to keep the heartbeat on.
+    socket: Option<Stream>,
+}
+
+/// The chat log with its styles: plain text, and errors in red.
+#[derive(Clone)]
+struct Log {
+    text: TextBuffer,
+    styles: TextBuffer,
+}
+
+impl Log {
+    const PLAIN: char = 'A';
+    const ERROR: char = 'B';
+
+    fn new(display: &mut TextDisplay) -> Self {
+        let log = Log { text: TextBuffer::default(), styles: TextBuffer::default() };
+        display.set_buffer(log.text.clone());
+        let entry = |color| StyleTableEntry { color, font: Font::Helvetica, size: 14 };
+        display.set_highlight_data(log.styles.clone(), vec![entry(Color::Black), entry(Color::Red)]);
+        log
+    }
+
+    fn append(&mut self, text: &str) {
+        self.push(text, Log::PLAIN);
+    }
+
+    fn append_error(&mut self, text: &str) {
+        self.push(text, Log::ERROR);
+    }
+
+    /// One style byte per byte of text, as the display expects.
+    fn push(&mut self, text: &str, style: char) {
+        self.text.append(text);
+        self.styles.append(&style.to_string().repeat(text.len()));
+    }
 }
 
 struct NetworkChat {
     app: app::App,  // Keep app instance alive
     window: Window,
-    input: Input,
+    input: MultilineInput,
     send_button: Button,
     text_display: TextDisplay,
-    display_buffer: TextBuffer,
+    log: Log,
     status_label: Frame,
-    stream: Option<TcpStream>,
+    cancel_button: Button,
+    restart_button: Button,
+    stream: Option<Duplex>,
+    socket: Option<Stream>,
+    e2e: bool,
+    session: Option<Session>,
+    state: ConnectionState,
+    round_trip: Option<Duration>,
+    timeouts: Timeouts,
+    heartbeat: Heartbeat,
+    udp_options: UdpOptions,
+    capture: Option<Recorder>,
+    bot: Option<Arc<Bot>>,
+    cancel: Cancel,
 }
 
 impl NetworkChat {
-    fn new(mode: String, _address: String) -> Self {
+    fn new(
+        mode: &str,
+        e2e: bool,
+        timeouts: Timeouts,
+        heartbeat: Heartbeat,
+        udp_options: UdpOptions,
+        capture: Option<Recorder>,
+        bot: Option<Arc<Bot>>,
+    ) -> Self {
         let app = app::App::default().with_scheme(app::Scheme::Gtk);
         
         // Create the window title string first
-        let title = format!("Network Chat - {}", mode);
-        let mut window = Window::new(100, 100, 400, 350, &*title);
+        let title = if e2e {
+            format!("Network Chat - {} (E2E)", mode)
+        } else {
+            format!("Network Chat - {}", mode)
+        };
+        let geometry = WindowGeometry::load_or("network_chat", WindowGeometry { x: 100, y: 100, w: 400, h: 350 });
+        let mut window = Window::new(geometry.x, geometry.y, geometry.w, geometry.h, &*title);
         
-        let mut pack = Pack::new(10, 10, 380, 330, "");
-        pack.set_spacing(10);
+        let mut layout = Flex::default_fill().column();
+        layout.set_margin(10);
+        layout.set_pad(10);
         
-        // Status label at the top
-        let mut status_label = Frame::new(0, 0, 380, 30, "Status: Connecting...");
+        // Status label at the top, with a way out while connecting
+        let mut status_row = Flex::default().row();
+        let mut status_label = Frame::default().with_label(&ConnectionState::Idle.status_line());
         status_label.set_label_color(Color::Red);
+        let mut cancel_button = Button::default().with_label("Cancel");
+        cancel_button.deactivate();
+        status_row.fixed(&cancel_button, 70);
+        // Only for local programs, which may be started again.
+        let mut restart_button = Button::default().with_label("Restart");
+        restart_button.hide();
+        status_row.fixed(&restart_button, 70);
+        status_row.end();
+        layout.fixed(&status_row, 30);
         
-        // Message display area
-        let display_buffer = TextBuffer::default();
-        let mut text_display = TextDisplay::new(0, 0, 380, 200, "");
-        text_display.set_buffer(display_buffer.clone());
+        // Message display area, grows with the window
+        let mut text_display = TextDisplay::default();
+        let log = Log::new(&mut text_display);
         text_display.set_frame(FrameType::FlatBox);
         text_display.set_color(Color::White);
         
-        // Input area
-        let input = Input::new(0, 0, 300, 30, "");
-        let mut send_button = Button::new(310, 0, 70, 30, "Send");
+        // Input area, pinned to the bottom. Enter sends, Shift+Enter adds a line.
+        let mut input_row = Flex::default().row();
+        let input = MultilineInput::default();
+        let mut send_button = Button::default().with_label("Send");
         send_button.deactivate(); // Disabled until connected
+        input_row.fixed(&send_button, 70);
+        input_row.end();
+        layout.fixed(&input_row, 60);
         
-        pack.end();
+        layout.end();
         window.end();
+        window.resizable(&layout);
+        window.size_range(300, 200, 0, 0);
         window.show();
         
         NetworkChat {
@@ -71,113 +175,90 @@ impl NetworkChat {
             input,
             send_button,
             text_display,
-            display_buffer,
+            log,
             status_label,
+            cancel_button,
+            restart_button,
             stream: None,
+            socket: None,
+            e2e,
+            session: None,
+            state: ConnectionState::Idle,
+            round_trip: None,
+            timeouts,
+            heartbeat,
+            udp_options,
+            capture,
+            bot,
+            cancel: Cancel::new(),
         }
     }
     
-    fn connect(&mut self, mode: String, address: String) {
-        let (sender, receiver) = app::channel::<String>();
+    fn connect(&mut self, address: Address) {
+        let (sender, receiver) = app::channel::<Message>();
         let stream_container = Arc::new(Mutex::new(None));
         let stream_container_clone = Arc::clone(&stream_container);
-        let address_clone = address.clone();
+        let session_container = Arc::new(Mutex::new(None));
+        let session_container_clone = Arc::clone(&session_container);
+        let socket_container = Arc::new(Mutex::new(None));
+        let socket_container_clone = Arc::clone(&socket_container);
+        let process_container = Arc::new(Mutex::new(None));
+        let process_container_clone = Arc::clone(&process_container);
+        let process_sender = sender.clone();
+        let use_e2e = self.e2e;
+        let timeouts = self.timeouts;
+        let udp_options = self.udp_options;
+        let capture = self.capture.clone();
+        let cancel = self.cancel.clone();
+        let worker = cancel.worker();
+
+        let button_cancel = self.cancel.clone();
+        self.cancel_button.set_callback(move |_| button_cancel.cancel());
+        self.cancel_button.activate();
 
         thread::spawn(move || {
-            let result = match mode.as_str() {
-                "server" => {
-                    println!("Starting server on {}", address_clone);
-                    sender.send("WAIT".to_string());
-                    match TcpListener::bind(&address_clone) {
-                        Ok(listener) => {
-                            sender.send("SERVER_STARTED".to_string());
-                            match listener.accept() {
-                                Ok((stream, addr)) => {
-                                    println!("Client connected from: {}", addr);
-                                    sender.send(format!("CLIENT_CONNECTED:{}", addr));
-                                    Ok(stream)
-                                }
-                                Err(e) => {
-                                    sender.send(format!("ERROR:{}", e));
-                                    Err(e)
-                                }
+            let _worker = worker;
+            let process = &process_container_clone;
+            match Self::open_transport(&address, use_e2e, timeouts, udp_options, &cancel, &sender, process) {
+                Ok(Connection { duplex: stream, session, peer, socket }) => {
+                    // Records what goes over the wire, after any encryption.
+                    let stream = match capture {
+                        Some(recorder) => {
+                            if let Some(peer) = peer.as_deref().and_then(|peer| peer.parse().ok()) {
+                                recorder.set_peer(peer);
                             }
+                            capture::tap(stream, recorder)
                         }
-                        Err(e) => {
-                            sender.send(format!("ERROR:{}", e));
-                            Err(e)
-                        }
-                    }
-                }
-                "client" => {
-                    sender.send("CONNECTING".to_string());
-                    match TcpStream::connect(&address_clone) {
-                        Ok(stream) => {
-                            println!("Client connected successfully");
-                            sender.send("CONNECTED".to_string());
-                            Ok(stream)
-                        }
-                        Err(e) => {
-                            sender.send(format!("ERROR:{}", e));
-                            Err(e)
-                        }
-                    }
-                }
-                _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid mode")),
-            };
-
-            match result {
-                Ok(stream) => {
-                    if let Ok(_) = stream.set_nonblocking(true) {
-                        let mut lock = stream_container_clone.lock().unwrap();
-                        *lock = Some(stream);
-                        sender.send("SUCCESS".to_string());
-                    }
-                }
-                Err(e) => {
-                    sender.send(format!("ERROR:{}", e));
+                        None => stream,
+                    };
+                    *session_container_clone.lock().unwrap() = session;
+                    *socket_container_clone.lock().unwrap() = socket;
+                    *stream_container_clone.lock().unwrap() = Some(stream);
+                    // Only sent once the stream is stored, so the UI can take it.
+                    sender.send(Message::State(ConnectionState::Connected { peer }));
                 }
+                Err(e) => sender.send(Message::State(ConnectionState::failed(e))),
             }
         });
 
         // Handle UI updates in the main thread
         while self.window.shown() {
             if let Some(msg) = receiver.recv() {
-                match msg.as_str() {
-                    "WAIT" => {
-                        self.status_label.set_label("Status: Waiting for client...");
-                        self.status_label.set_label_color(Color::Yellow);
-                    }
-                    "SERVER_STARTED" => {
-                        self.display_buffer.append("Server started, waiting for connection...\n");
-                    }
-                    "CONNECTING" => {
-                        self.status_label.set_label("Status: Connecting to server...");
-                        self.status_label.set_label_color(Color::Yellow);
-                        self.display_buffer.append(&format!("Connecting to {}...\n", address));
-                    }
-                    "CONNECTED" | "SUCCESS" => {
-                        self.status_label.set_label("Status: Connected");
-                        self.status_label.set_label_color(Color::Green);
-                        self.send_button.activate();
-                        break;
-                    }
-                    msg if msg.starts_with("CLIENT_CONNECTED:") => {
-                        let addr = msg.split(':').nth(1).unwrap_or("unknown");
-                        self.display_buffer.append(&format!("Client connected from: {}\n", addr));
-                        self.status_label.set_label("Status: Connected");
-                        self.status_label.set_label_color(Color::Green);
-                        self.send_button.activate();
-                        break;
-                    }
-                    msg if msg.starts_with("ERROR:") => {
-                        let error = msg.split(':').nth(1).unwrap_or("Unknown error");
-                        self.status_label.set_label(&format!("Status: Connection failed - {}", error));
-                        self.status_label.set_label_color(Color::Red);
-                        self.display_buffer.append(&format!("Connection error: {}\n", error));
-                        break;
+                match msg {
+                    Message::UpdateDisplay(text) => self.log.append(&text),
+                    Message::Error(text) => self.log.append_error(&text),
+                    Message::RoundTrip(_) => {}
+                    Message::State(state) => {
+                        self.show_state(&state);
+                        if let ConnectionState::Failed { reason } = &state {
+                            self.log.append_error(&format!("Connection error: {}\n", reason));
+                            break;
+                        }
+                        if state.is_connected() {
+                            self.send_button.activate();
+                            break;
+                        }
                     }
-                    _ => {}
                 }
                 app::flush();
             }
@@ -188,35 +269,224 @@ impl NetworkChat {
         if let Some(stream) = lock.take() {
             self.stream = Some(stream);
         }
+        self.session = session_container.lock().unwrap().take();
+        self.socket = socket_container.lock().unwrap().take();
+        let process = process_container.lock().unwrap().take();
+        if let Some(exec) = process {
+            self.attach_process(exec, process_sender);
+        }
+    }
+
+    /// Shows what a local program writes to standard error and when it
+    /// exits, and offers to start it again.
+    fn attach_process(&mut self, exec: Exec, sender: app::Sender<Message>) {
+        let errors = sender.clone();
+        exec.on_stderr(move |line| errors.send(Message::Error(format!("{}\n", line))));
+        let exits = sender.clone();
+        exec.on_exit(move |status| {
+            exits.send(Message::State(ConnectionState::Exited { status: exec::describe_exit(status) }))
+        });
+
+        let mut log = self.log.clone();
+        self.restart_button.set_callback(move |_| match exec.restart() {
+            Ok(()) => {
+                log.append(&format!("Restarted {}\n", exec.spec().command));
+                sender.send(Message::State(ConnectionState::Connected { peer: Some(exec.spec().command.clone()) }));
+            }
+            Err(e) => log.append_error(&format!("Could not restart: {}\n", e)),
+        });
+        self.restart_button.show();
+        if let Some(row) = self.restart_button.parent().and_then(|row| Flex::from_dyn_widget(&row)) {
+            row.layout();
+        }
+    }
+
+    /// Opens `address` and secures the connection, reporting progress on
+    /// `sender`. A local program is also left in `process`.
+    fn open_transport(
+        address: &Address,
+        use_e2e: bool,
+        timeouts: Timeouts,
+        udp_options: UdpOptions,
+        cancel: &Cancel,
+        sender: &app::Sender<Message>,
+        process: &Mutex<Option<Exec>>,
+    ) -> io::Result<Connection> {
+        match address {
+            Address::Listen(listen) => println!("Starting server on {}", listen),
+            Address::Pipe { read, write } => sender.send(Message::UpdateDisplay(format!(
+                "Reading from {}, writing to {}\n",
+                read.display(),
+                write.display()
+            ))),
+            _ => {}
+        }
+        let report = |state: ConnectionState| {
+            let note = match &state {
+                ConnectionState::Listening { .. } if address.kind() == "server" => {
+                    Some("Server started, waiting for connection...\n".to_string())
+                }
+                ConnectionState::Connecting { address } => Some(format!("Connecting to {}...\n", address)),
+                _ => None,
+            };
+            sender.send(Message::State(state));
+            if let Some(note) = note {
+                sender.send(Message::UpdateDisplay(note));
+            }
+        };
+        let Opened { transport, peer } = address.open(timeouts, udp_options, cancel, &report)?;
+        match transport {
+            Transport::Stream(stream) => {
+                if let (Address::Listen(_), Some(peer)) = (address, &peer) {
+                    println!("Client connected from: {}", peer);
+                    sender.send(Message::UpdateDisplay(format!("Client connected from: {}\n", peer)));
+                } else {
+                    println!("Client connected successfully");
+                }
+                Self::secure_stream(stream, peer, use_e2e, timeouts.handshake, sender)
+            }
+            Transport::Datagrams(endpoint) => {
+                let lost = sender.clone();
+                endpoint.on_lost(move |seq| {
+                    lost.send(Message::Error(format!("Message #{} was not acknowledged by the peer\n", seq)))
+                });
+                let local = endpoint.local_addr()?;
+                sender.send(Message::UpdateDisplay(match endpoint.peer() {
+                    Some(peer) => format!("Sending datagrams to {} from {}\n", peer, local),
+                    None => format!("Waiting for the first datagram on {}...\n", local),
+                }));
+                let mut datagrams = endpoint.duplex();
+                let session = Self::secure(&mut datagrams, use_e2e, sender)?;
+                let peer = endpoint.peer().map(|peer| peer.to_string());
+                Ok(Connection { duplex: datagrams, session, peer, socket: None })
+            }
+            Transport::Process(exec) => {
+                let mut duplex = exec.duplex();
+                let session = Self::secure(&mut duplex, use_e2e, sender)?;
+                *process.lock().unwrap() = Some(exec);
+                Ok(Connection { duplex, session, peer, socket: None })
+            }
+            Transport::Other(mut duplex) => {
+                let session = Self::secure(&mut duplex, use_e2e, sender)?;
+                Ok(Connection { duplex, session, peer, socket: None })
+            }
+        }
+    }
+
+    /// Runs the optional key exchange on a fresh TCP or Unix socket
+    /// connection, within the handshake timeout.
+    fn secure_stream(
+        mut stream: Stream,
+        peer: Option<String>,
+        use_e2e: bool,
+        handshake_timeout: Duration,
+        sender: &app::Sender<Message>,
+    ) -> io::Result<Connection> {
+        stream.set_read_timeout(Some(handshake_timeout))?;
+        let session = Self::secure(&mut stream, use_e2e, sender).map_err(|e| net::timed_out("key exchange", e))?;
+        stream.set_read_timeout(None)?;
+        let socket = stream.try_clone()?;
+        Ok(Connection { duplex: Duplex::stream(stream)?, session, peer, socket: Some(socket) })
+    }
+
+    fn secure<S: Read + Write>(stream: &mut S, use_e2e: bool, sender: &app::Sender<Message>) -> io::Result<Option<Session>> {
+        if !use_e2e {
+            return Ok(None);
+        }
+        sender.send(Message::State(ConnectionState::Handshaking));
+        let session = e2e::handshake(stream).map_err(|e| match e {
+            E2eError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        })?;
+        sender.send(Message::UpdateDisplay(format!(
+            "End-to-end encryption enabled.\nFingerprint: {}\nCompare it with your peer over another channel; if it differs, someone is intercepting the chat.\n",
+            session.fingerprint
+        )));
+        Ok(Some(session))
+    }
+
+    fn show_state(&mut self, state: &ConnectionState) {
+        if state.is_pending() {
+            self.cancel_button.activate();
+        } else {
+            self.cancel_button.deactivate();
+        }
+        if !state.is_connected() {
+            self.round_trip = None;
+        }
+        self.state = state.clone();
+        self.show_status();
+    }
+
+    /// The state, with the last round trip measured while connected.
+    fn show_status(&mut self) {
+        let state = &self.state;
+        let mut label = state.status_line();
+        if state.is_connected() && self.e2e {
+            label.push_str(" (end-to-end encrypted)");
+        }
+        if let Some(round_trip) = self.round_trip {
+            label.push_str(&format!(" (ping {} ms)", round_trip.as_millis()));
+        }
+        self.status_label.set_label(&label);
+        self.status_label.set_label_color(if state.is_connected() {
+            Color::Green
+        } else if state.is_failed() || matches!(state, ConnectionState::Exited { .. }) {
+            Color::Red
+        } else {
+            Color::Yellow
+        });
+    }
+
+    fn send_line(chat_sender: &Mutex<DirectSender>, message: &str) -> io::Result<()> {
+        chat_sender.lock().unwrap().send(message)
+    }
+
+    /// Lets the bot answer a message, on a thread of its own since a rule
+    /// may run a command.
+    fn answer(bot: &Arc<Bot>, chat_sender: &Arc<Mutex<DirectSender>>, message: &str, sender: &app::Sender<Message>) {
+        let (bot, chat_sender, message, sender) = (Arc::clone(bot), Arc::clone(chat_sender), message.to_string(), sender.clone());
+        thread::spawn(move || {
+            let Some(reply) = bot.respond("Other", &message) else { return };
+            match Self::send_line(&chat_sender, &reply) {
+                Ok(()) => sender.send(Message::UpdateDisplay(format_block("Bot: ", &reply))),
+                Err(e) => sender.send(Message::Error(format!("Error sending bot reply: {}\n", e))),
+            }
+        });
     }
     
-    fn run(&mut self, mode: String, address: String) {
-        self.connect(mode, address);
+    fn run(&mut self, address: Address) {
+        self.connect(address);
         
         // Set up callbacks only if we have a stream
-        if let Some(stream) = self.stream.as_ref() {
-            let stream_write = stream.try_clone().expect("Failed to clone stream");
+        if let Some(stream) = self.stream.take() {
+            let (chat_sender, mut chat_receiver) = direct::split_duplex(stream, self.session.take());
             let mut input = self.input.clone();
-            let mut display_buffer = self.display_buffer.clone();
+            let mut log = self.log.clone();
             
-            let stream_write = Arc::new(Mutex::new(stream_write));
-            let stream_write_clone = stream_write.clone();
+            let chat_sender = Arc::new(Mutex::new(chat_sender));
+            let chat_sender_clone = chat_sender.clone();
+            let (sender, receiver) = app::channel::<Message>();
+            if let Some(socket) = self.socket.take() {
+                let round_trips = sender.clone();
+                let measured = move |round_trip| round_trips.send(Message::RoundTrip(round_trip));
+                if let Err(e) = direct::keep_alive(&self.heartbeat, socket, &chat_sender, &mut chat_receiver, measured) {
+                    self.log.append_error(&format!("Error pinging: {}\n", e));
+                }
+            }
+            let bot = self.bot.clone().map(|bot| (bot, chat_sender.clone()));
             
             // Set up send button callback
             self.send_button.set_callback(move |_| {
                 let message = input.value();
                 if !message.is_empty() {
-                    if let Ok(mut stream) = stream_write.lock() {
-                        match writeln!(&mut *stream, "{}", message) {
-                            Ok(_) => {
-                                if let Ok(_) = stream.flush() {
-                                    display_buffer.append(&format!("Me: {}\n", message));
-                                    input.set_value("");
-                                }
-                            }
-                            Err(e) => {
-                                display_buffer.append(&format!("Error sending: {}\n", e));
-                            }
+                    match Self::send_line(&chat_sender, &message) {
+                        Ok(_) => {
+                            log.append(&format_block("Me: ", &message));
+                            input.set_value("");
+                        }
+                        Err(e) => {
+                            log.append_error(&format!("Error sending: {}\n", e));
                         }
                     }
                 }
@@ -224,23 +494,22 @@ impl NetworkChat {
             
             // Set up Enter key handler
             let mut input = self.input.clone();
-            let mut display_buffer = self.display_buffer.clone();
+            let mut log = self.log.clone();
             
             input.handle(move |i, ev| {
                 if ev == Event::KeyDown && app::event_key() == Key::Enter {
+                    if app::is_event_shift() {
+                        return false;
+                    }
                     let message = i.value();
                     if !message.is_empty() {
-                        if let Ok(mut stream) = stream_write_clone.lock() {
-                            match writeln!(&mut *stream, "{}", message) {
-                                Ok(_) => {
-                                    if let Ok(_) = stream.flush() {
-                                        display_buffer.append(&format!("Me: {}\n", message));
-                                        i.set_value("");
-                                    }
-                                }
-                                Err(e) => {
-                                    display_buffer.append(&format!("Error sending: {}\n", e));
-                                }
+                        match Self::send_line(&chat_sender_clone, &message) {
+                            Ok(_) => {
+                                log.append(&format_block("Me: ", &message));
+                                i.set_value("");
+                            }
+                            Err(e) => {
+                                log.append_error(&format!("Error sending: {}\n", e));
                             }
                         }
                     }
@@ -250,42 +519,57 @@ impl NetworkChat {
                 }
             });
             
-            // Set up message receiving thread with a channel for UI updates
-            let mut stream_read = stream.try_clone().expect("Failed to clone stream");
-            let (sender, receiver) = app::channel::<Message>();
+            // Set up message receiving thread, reporting through the channel for UI updates
+            let worker = self.cancel.worker();
+            let heartbeat = self.heartbeat;
             
             thread::spawn(move || {
-                let mut buffer = [0u8; 1024];
-                loop {
-                    match stream_read.read(&mut buffer) {
-                        Ok(n) if n > 0 => {
-                            if let Ok(message) = String::from_utf8(buffer[..n].to_vec()) {
-                                sender.send(Message::UpdateDisplay(format!("Other: {}", message)));
+                let _worker = worker;
+                while let Some(message) = chat_receiver.recv() {
+                    match message {
+                        Ok(message) => {
+                            sender.send(Message::UpdateDisplay(format_block("Other: ", &message)));
+                            if let Some((bot, chat_sender)) = &bot {
+                                Self::answer(bot, chat_sender, &message, &sender);
                             }
                         }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            thread::sleep(Duration::from_millis(50));
-                        }
-                        Err(e) => {
+                        Err(E2eError::Io(e)) => {
+                            let e = heartbeat.explain(e);
                             sender.send(Message::Error(format!("Error reading: {}\n", e)));
-                            thread::sleep(Duration::from_secs(1));
+                            sender.send(Message::State(ConnectionState::failed(e)));
+                            return;
                         }
-                        _ => thread::sleep(Duration::from_millis(50)),
+                        Err(e) => sender.send(Message::Error(format!("Dropped message: {}\n", e))),
                     }
                 }
+                sender.send(Message::Error("Connection closed\n".to_string()));
+                sender.send(Message::State(ConnectionState::failed("connection closed by peer")));
             });
 
             // Handle received messages in the main thread
-            let mut display_buffer = self.display_buffer.clone();
+            let mut log = self.log.clone();
             while self.window.shown() {
                 // Handle received messages
                 if let Some(msg) = receiver.recv() {
                     match msg {
                         Message::UpdateDisplay(text) => {
-                            display_buffer.append(&text);
+                            log.append(&text);
                         }
                         Message::Error(text) => {
-                            display_buffer.append(&text);
+                            log.append_error(&text);
+                        }
+                        Message::State(state) => {
+                            // A restarted program can be talked to again.
+                            if state.is_connected() {
+                                self.send_button.activate();
+                            } else if !state.is_pending() {
+                                self.send_button.deactivate();
+                            }
+                            self.show_state(&state);
+                        }
+                        Message::RoundTrip(round_trip) => {
+                            self.round_trip = Some(round_trip);
+                            self.show_status();
                         }
                     }
                 }
@@ -296,41 +580,133 @@ impl NetworkChat {
                 app::wait();
             }
         }
+        // Unblock and wait for the connection threads before exiting.
+        if !self.cancel.shutdown(Duration::from_secs(2)) {
+            println!("Connection threads did not stop in time");
+        }
+        self.save_geometry();
     }
-}
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
     
-    if args.len() != 3 {
-        println!("Usage: cargo run --bin network_chat <mode> <address>");
-        println!("\nExamples:");
-        println!("  Server: cargo run --bin network_chat server 0.0.0.0:8080");
-        println!("  Client: cargo run --bin network_chat client 192.168.0.108:8080");
-        return;
+    fn save_geometry(&self) {
+        let geometry = WindowGeometry {
+            x: self.window.x(),
+            y: self.window.y(),
+            w: self.window.w(),
+            h: self.window.h(),
+        };
+        if let Err(e) = geometry.save("network_chat") {
+            println!("Could not save window position: {}", e);
+        }
     }
-    
-    let mode = args[1].clone();
-    let address = args[2].clone();
-    
-    let mut chat = NetworkChat::new(mode.clone(), address.clone());
-    chat.run(mode, address);
 }
+
+const DIALOG: DialogOptions = DialogOptions {
+    title: "Network Chat - Connect",
+    name: "network_chat",
+    modes: &["server", "client", "serial", "fifo", "udp"],
+    ask_username: false,
+    ask_password: false,
+    offer_e2e: true,
+    discovery: None,
+};
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let args = Args::from_env();
     
-    if args.len() != 4 {
-        println!("Usage: cargo run --bin multi_chat <mode> <address> <username>");
-        println!("\nExamples:");
-        println!("  Server: cargo run --bin multi_chat server 0.0.0.0:8080 ServerUser");
-        println!("  Client: cargo run --bin multi_chat client 192.168.0.108:8080 Alice");
-        return;
-    }
+    let timeouts = match Timeouts::from_args(&args) {
+        Ok(timeouts) => timeouts,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     
-    let mode = args[1].clone();
-    let address = args[2].clone();
-    let username = args[3].clone();
+    let heartbeat = match Heartbeat::from_args(&args) {
+        Ok(heartbeat) => heartbeat,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let udp_options = match UdpOptions::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let capture = match args.value("capture").map(|path| Recorder::create(Path::new(path))).transpose() {
+        Ok(capture) => capture,
+        Err(e) => {
+            println!("Error creating capture file: {}", e);
+            return;
+        }
+    };
+
+    let bot = match args.value("bot").map(|path| Bot::load(Path::new(path))).transpose() {
+        Ok(bot) => bot.map(Arc::new),
+        Err(e) => {
+            println!("Error loading bot rules: {}", e);
+            return;
+        }
+    };
+
+    // Started without arguments, e.g. from a file manager: ask instead.
+    let (address, e2e) = match args.positional.len() {
+        0 => match dialog::ask(&DIALOG) {
+            Some(choice) => (Address::from_mode(&choice.profile.mode, &choice.profile.address), choice.profile.e2e),
+            None => return,
+        },
+        1 => (Address::parse(&args.positional[0]), args.flag("e2e")),
+        2 => (Address::from_mode(&args.positional[0], &args.positional[1]), args.flag("e2e")),
+        _ => {
+            println!("Usage: cargo run --bin network_chat [<mode> <address> | <socat-address>] [options]");
+            println!("\nWithout arguments a connection dialog is shown.");
+            println!("\nExamples:");
+            println!("  Server: cargo run --bin network_chat server 0.0.0.0:8080");
+            println!("  Client: cargo run --bin network_chat client 192.168.0.108:8080");
+            println!("  IPv6:   cargo run --bin network_chat client [::1]:8080");
+            println!("  Unix:   cargo run --bin network_chat server unix:/tmp/chat.sock,mode=660");
+            println!("  Serial: cargo run --bin network_chat serial /dev/ttyUSB0,115200");
+            println!("  FIFO:   cargo run --bin network_chat fifo /tmp/pipe1,/tmp/pipe2");
+            println!("  UDP:    cargo run --bin network_chat udp *:9000,   (waits for a peer)");
+            println!("          cargo run --bin network_chat udp 192.168.0.108:9000 --ack");
+            println!("\nHosts may be names; IPv6 addresses go in brackets and *:PORT listens on all interfaces.");
+            println!("unix:PATH uses a Unix socket (mode= sets its permissions, default 600); unix:@NAME an abstract one.");
+            println!("\nA single socat-style address works too:");
+            println!("  TCP:host:port, TCP-LISTEN:port[,bind=host], UNIX-CONNECT:path, UNIX-LISTEN:path[,mode=660],");
+            println!("  PIPE:read_pipe,write_pipe, FILE:/dev/ttyUSB0,b115200,raw, UDP:host:port, UDP-LISTEN:port,");
+            println!("  STDIO, EXEC:command[,pty]");
+            println!("  e.g. cargo run --bin network_chat TCP-LISTEN:8080 --e2e");
+            println!("       cargo run --bin network_chat 'EXEC:python3 -i,pty'   (chat with a local program)");
+            println!("\nOptions:");
+            println!("  --e2e                    End-to-end encrypt messages (both sides must enable it)");
+            println!("  --connect-timeout=SECS   Give up connecting after SECS (default: 10)");
+            println!("  --handshake-timeout=SECS Give up on the key exchange after SECS (default: 10)");
+            println!("  --ping-interval=SECS     TCP/Unix with --e2e: ping every SECS, 0 for never (default: 15)");
+            println!("  --ping-timeout=SECS      TCP/Unix with --e2e: drop a peer silent for SECS (default: 45)");
+            println!("  --keepalive=IDLE[,INTERVAL[,COUNT]]  TCP keepalive timing in seconds (default: 60,10,5)");
+            println!("  --no-keepalive           Turn TCP keepalive off");
+            println!("  --ack                    UDP: have messages acknowledged, resending lost ones");
+            println!("  --mtu=BYTES              UDP: path MTU limiting the message size (default: {})", udp::DEFAULT_MTU);
+            println!("  --capture=FILE           Record the traffic with timestamps (pcap if FILE ends in .pcap);");
+            println!("                           play it back with chat_replay");
+            println!("  --bot=FILE               Answer messages by the rules in FILE, one per line:");
+            println!("                             <regex> => reply <text>    ($1... are filled in)");
+            println!("                             <regex> => run <command>   (message on stdin, output is sent)");
+            return;
+        }
+    };
     
-    let mut chat = MultiChat::new(mode.clone(), username);
-    chat.run(mode, address);
-}
\ No newline at end of file
+    let address = match address {
+        Ok(address) => address,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut chat = NetworkChat::new(address.kind(), e2e, timeouts, heartbeat, udp_options, capture, bot);
+    chat.run(address);
+}
@@ -6,11 +6,13 @@ use fltk::{
     input::Input,
     button::Button,
     text::{TextDisplay, TextBuffer},
-    group::Pack,
+    group::Flex,
+    frame::Frame,
+    enums::Color,
 };
+use socat_chat::{pipe, settings::WindowGeometry, state::ConnectionState};
 use std::{
-    fs::OpenOptions,
-    io::{Read, Write},
+    path::PathBuf,
     thread,
     time::Duration,
 };
@@ -21,25 +23,39 @@ struct SimpleChatApp {
     send_button: Button,
     text_display: TextDisplay,
     display_buffer: TextBuffer,
+    status_label: Frame,
 }
 
 impl SimpleChatApp {
     fn new(name: &str) -> Self {
         let _app = app::App::default();
-        let mut window = Window::new(100, 100, 400, 300, format!("Chat - {}", name).as_str());
+        let geometry = WindowGeometry::load_or("simple_chat", WindowGeometry { x: 100, y: 100, w: 400, h: 300 });
+        let mut window = Window::new(geometry.x, geometry.y, geometry.w, geometry.h, format!("Chat - {}", name).as_str());
         
-        let mut pack = Pack::new(10, 10, 380, 280, "");
-        pack.set_spacing(10);
+        // The message log takes whatever space the input row leaves
+        let mut layout = Flex::default_fill().column();
+        layout.set_margin(10);
+        layout.set_pad(10);
+        
+        let mut status_label = Frame::default().with_label(&ConnectionState::Idle.status_line());
+        status_label.set_label_color(Color::Red);
+        layout.fixed(&status_label, 30);
         
         let display_buffer = TextBuffer::default();
-        let mut text_display = TextDisplay::new(0, 0, 380, 200, "");
+        let mut text_display = TextDisplay::default();
         text_display.set_buffer(display_buffer.clone());
         
-        let input = Input::new(0, 0, 300, 30, "");
-        let send_button = Button::new(310, 0, 70, 30, "Send");
+        let mut input_row = Flex::default().row();
+        let input = Input::default();
+        let send_button = Button::default().with_label("Send");
+        input_row.fixed(&send_button, 70);
+        input_row.end();
+        layout.fixed(&input_row, 30);
         
-        pack.end();
+        layout.end();
         window.end();
+        window.resizable(&layout);
+        window.size_range(250, 150, 0, 0);
         window.show();
         
         SimpleChatApp {
@@ -48,6 +64,7 @@ impl SimpleChatApp {
             send_button,
             text_display,
             display_buffer,
+            status_label,
         }
     }
     
@@ -56,46 +73,85 @@ impl SimpleChatApp {
         println!("Read from: {}", read_pipe);
         println!("Write to: {}", write_pipe);
 
+        let (sender, receiver) = app::channel::<ConnectionState>();
+        
         // Set up write pipe
-        let write_pipe = write_pipe.to_string();
+        let write_pipe = PathBuf::from(write_pipe);
         let mut input = self.input.clone();
         let mut display_buffer = self.display_buffer.clone();
+        let status = sender.clone();
         
         self.send_button.set_callback(move |_| {
             let message = input.value();
-            if !message.is_empty() {
-                if let Ok(mut file) = OpenOptions::new().write(true).open(&write_pipe) {
-                    if let Ok(_) = writeln!(file, "{}", message) {
-                        display_buffer.append(&format!("Me: {}\n", message));
-                        input.set_value("");
-                    }
+            if message.is_empty() {
+                return;
+            }
+            match pipe::send(&write_pipe, &message) {
+                Ok(_) => {
+                    display_buffer.append(&format!("Me: {}\n", message));
+                    input.set_value("");
+                    status.send(ConnectionState::Connected { peer: None });
                 }
+                Err(e) => status.send(ConnectionState::failed(format!("{}: {}", write_pipe.display(), e))),
             }
         });
         
         // Set up read pipe
-        let read_pipe = read_pipe.to_string();
+        let read_pipe = PathBuf::from(read_pipe);
         let mut display_buffer = self.display_buffer.clone();
         
         thread::spawn(move || {
-            let mut buffer = [0u8; 1024];
+            // Opening the pipe blocks until the other side writes to it.
+            let mut state = ConnectionState::Listening { address: read_pipe.display().to_string() };
+            sender.send(state.clone());
             loop {
-                if let Ok(mut file) = OpenOptions::new().read(true).open(&read_pipe) {
-                    if let Ok(n) = file.read(&mut buffer) {
-                        if n > 0 {
-                            if let Ok(message) = String::from_utf8(buffer[..n].to_vec()) {
-                                display_buffer.append(&format!("Other: {}", message));
-                            }
+                let next = match pipe::receive(&read_pipe) {
+                    Ok(messages) => {
+                        for message in messages {
+                            display_buffer.append(&format!("Other: {}\n", message));
                         }
+                        ConnectionState::Connected { peer: None }
                     }
+                    Err(e) => ConnectionState::failed(format!("{}: {}", read_pipe.display(), e)),
+                };
+                if next != state {
+                    state = next;
+                    sender.send(state.clone());
                 }
                 thread::sleep(Duration::from_millis(100));
             }
         });
         
         while self.window.shown() {
+            if let Some(state) = receiver.recv() {
+                self.show_state(&state);
+            }
             app::wait();
         }
+        self.save_geometry();
+    }
+    
+    fn show_state(&mut self, state: &ConnectionState) {
+        self.status_label.set_label(&state.status_line());
+        self.status_label.set_label_color(if state.is_failed() {
+            Color::Red
+        } else if state.is_pending() {
+            Color::Yellow
+        } else {
+            Color::Green
+        });
+    }
+    
+    fn save_geometry(&self) {
+        let geometry = WindowGeometry {
+            x: self.window.x(),
+            y: self.window.y(),
+            w: self.window.w(),
+            h: self.window.h(),
+        };
+        if let Err(e) = geometry.save("simple_chat") {
+            println!("Could not save window position: {}", e);
+        }
     }
 }
 
@@ -0,0 +1,209 @@
+// src/bot.rs
+//! Automatic replies to incoming chat messages, configured in a rules file
+//! (`--bot=FILE` in `network_chat` and `multi_chat`).
+//!
+//! Each rule is one line, a regular expression and what to do when a
+//! message matches it:
+//!
+//! ```text
+//! # Blank lines and lines starting with # are ignored.
+//! (?i)^ping$ => reply pong, $from
+//! ^!deploy (\w+)$ => reply deploying $1...
+//! ^!build (?P<branch>\S+)$ => run ./build-status.sh
+//! ```
+//!
+//! `reply` sends the text, with `$from` replaced by the sender, `$1`,
+//! `${2}` or `${name}` by the groups of the match, `$0` by the whole match
+//! and `$$` by a dollar sign. `run` starts the command with the shell,
+//! writes the message to its standard input and sends what it prints, if
+//! anything. Nothing from the message is put into the command line; the
+//! command gets it as `CHAT_FROM`, `CHAT_MATCH_1`, `CHAT_MATCH_2`, ... in
+//! its environment instead.
+//!
+//! Only the first matching rule applies. Messages are whole lines, so a
+//! prompt that does not end its line (a serial `login: `, say) never
+//! matches; `chat_script` handles those.
+
+use crate::exec;
+use regex::{Captures, Regex};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process::Stdio,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a `run` command may take before it is killed.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Reply(String),
+    Run(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: Regex,
+    pub action: Action,
+}
+
+impl Rule {
+    /// Parses `<regex> => reply <text>` or `<regex> => run <command>`.
+    pub fn parse(line: &str) -> Result<Rule, String> {
+        let (pattern, action) = line
+            .split_once(" => ")
+            .ok_or_else(|| format!("expected '<regex> => reply <text>' or '<regex> => run <command>', got {:?}", line))?;
+        let pattern = Regex::new(pattern).map_err(|e| format!("invalid regex {:?}: {}", pattern, e))?;
+        let action = match action.split_once(' ') {
+            Some(("reply", text)) => Action::Reply(text.to_string()),
+            Some(("run", command)) if !command.trim().is_empty() => Action::Run(command.to_string()),
+            _ => return Err(format!("unknown action {:?}, expected reply <text> or run <command>", action)),
+        };
+        Ok(Rule { pattern, action })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Bot {
+    rules: Vec<Rule>,
+    timeout: Option<Duration>,
+}
+
+impl Bot {
+    pub fn parse(text: &str) -> Result<Bot, String> {
+        let rules = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(n, line)| Rule::parse(line.trim()).map_err(|e| format!("line {}: {}", n + 1, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Bot { rules, timeout: None })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Bot> {
+        let text = fs::read_to_string(path)?;
+        Bot::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Overrides [`COMMAND_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// What to send in answer to `text` from `from`, if anything. Runs the
+    /// command of a `run` rule to completion, so call it off the UI thread.
+    pub fn respond(&self, from: &str, text: &str) -> Option<String> {
+        let (rule, captures) = self
+            .rules
+            .iter()
+            .find_map(|rule| rule.pattern.captures(text).map(|captures| (rule, captures)))?;
+        let reply = match &rule.action {
+            Action::Reply(template) => expand(template, from, &captures),
+            Action::Run(command) => match run(command, from, text, &captures, self.timeout.unwrap_or(COMMAND_TIMEOUT)) {
+                Ok(output) => output,
+                Err(e) => {
+                    println!("Bot command {:?} failed: {}", command, e);
+                    return None;
+                }
+            },
+        };
+        Some(reply).filter(|reply| !reply.is_empty())
+    }
+}
+
+/// Fills `$from`, `$N`, `${N}`, `${name}` and `$$` into `template`.
+fn expand(template: &str, from: &str, captures: &Captures) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, after)) => (name, after),
+                None => ("", rest),
+            }
+        } else if rest.starts_with('$') {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        } else if let Some(after) = rest.strip_prefix("from") {
+            ("from", after)
+        } else {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (&rest[..digits], &rest[digits..])
+        };
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        let value = match name.parse::<usize>() {
+            _ if name == "from" => Some(from),
+            Ok(index) => captures.get(index).map(|m| m.as_str()),
+            Err(_) => captures.name(name).map(|m| m.as_str()),
+        };
+        out.push_str(value.unwrap_or_default());
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Runs `command` with the message on standard input and returns what it
+/// printed, without the trailing newline.
+fn run(command: &str, from: &str, text: &str, captures: &Captures, timeout: Duration) -> io::Result<String> {
+    let mut shell = exec::shell(command);
+    shell.env("CHAT_FROM", from);
+    for (index, group) in captures.iter().enumerate().skip(1) {
+        shell.env(format!("CHAT_MATCH_{}", index), group.map(|m| m.as_str()).unwrap_or_default());
+    }
+    // A group of its own, so a timeout also ends whatever it started.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let mut child = shell.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+
+    // Fed and drained on their own threads, so neither side can block the other.
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let input = format!("{}\n", text);
+    thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            #[cfg(unix)]
+            // SAFETY: a plain system call on the child's own group, which
+            // has not been waited for yet.
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("still running after {} s", timeout.as_secs_f64())));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    if !status.success() {
+        println!("Bot command {:?} {}", command, exec::describe_exit(status));
+    }
+    let output = output.join().map_err(|_| io::Error::other("output reader panicked"))??;
+    Ok(String::from_utf8_lossy(&output).trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,205 @@
+// src/bridge.rs
+//! Relaying bytes between two endpoints, as socat does, while showing the
+//! traffic and letting an operator type into either direction. Used by
+//! `chat_bridge`, e.g. to share a lab serial console over the network.
+//!
+//! The two ends are called A and B. Bytes are passed on unchanged; only
+//! the [`Mirror`] shown to the operator is made readable.
+
+use crate::{cancel::Cancel, direct::Duplex};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Which way bytes travel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    AtoB,
+    BtoA,
+}
+
+impl Direction {
+    /// The end bytes going this way come from.
+    pub fn source(self) -> char {
+        match self {
+            Direction::AtoB => 'A',
+            Direction::BtoA => 'B',
+        }
+    }
+
+    /// The end bytes going this way are written to.
+    pub fn target(self) -> char {
+        match self {
+            Direction::AtoB => 'B',
+            Direction::BtoA => 'A',
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}>{}", self.source(), self.target())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeEvent {
+    /// Bytes relayed from one end to the other.
+    Relayed { direction: Direction, bytes: Vec<u8> },
+    /// Bytes the operator sent.
+    Injected { direction: Direction, bytes: Vec<u8> },
+    /// Reading from one end stopped; carries the error unless it closed
+    /// cleanly. Nothing more goes `direction`.
+    Closed { direction: Direction, error: Option<String> },
+}
+
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A running relay between two ends.
+pub struct Bridge {
+    /// Writing halves of A and B.
+    writers: [Writer; 2],
+    events: Sender<BridgeEvent>,
+}
+
+impl Bridge {
+    /// Starts relaying both ways. Events describe the traffic; the relay
+    /// threads stop once `cancel` fires and the ends' reads return.
+    pub fn start(a: Duplex, b: Duplex, cancel: &Cancel) -> (Bridge, Receiver<BridgeEvent>) {
+        let (events, receiver) = unbounded();
+        let (a_reader, a_writer) = a.into_parts();
+        let (b_reader, b_writer) = b.into_parts();
+        let writers: [Writer; 2] = [Arc::new(Mutex::new(a_writer)), Arc::new(Mutex::new(b_writer))];
+        let bridge = Bridge { writers, events };
+        bridge.relay(Direction::AtoB, a_reader, cancel);
+        bridge.relay(Direction::BtoA, b_reader, cancel);
+        (bridge, receiver)
+    }
+
+    fn writer(&self, direction: Direction) -> &Writer {
+        match direction {
+            Direction::AtoB => &self.writers[1],
+            Direction::BtoA => &self.writers[0],
+        }
+    }
+
+    fn relay(&self, direction: Direction, mut reader: Box<dyn Read + Send>, cancel: &Cancel) {
+        let writer = Arc::clone(self.writer(direction));
+        let events = self.events.clone();
+        let worker = cancel.worker();
+        thread::spawn(move || {
+            let _worker = worker;
+            let mut buf = [0u8; 4096];
+            let error = loop {
+                let len = match reader.read(&mut buf) {
+                    Ok(0) => break None,
+                    Ok(len) => len,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => break None,
+                    Err(e) => break Some(e.to_string()),
+                };
+                let mut writer = writer.lock().unwrap();
+                if let Err(e) = writer.write_all(&buf[..len]).and_then(|_| writer.flush()) {
+                    break Some(format!("writing to {}: {}", direction.target(), e));
+                }
+                drop(writer);
+                let _ = events.send(BridgeEvent::Relayed { direction, bytes: buf[..len].to_vec() });
+            };
+            let _ = events.send(BridgeEvent::Closed { direction, error });
+        });
+    }
+
+    /// Writes `bytes` to the target end of `direction` as if they had come
+    /// from the other end.
+    pub fn inject(&self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer(direction).lock().unwrap();
+        writer.write_all(bytes)?;
+        writer.flush()?;
+        let _ = self.events.send(BridgeEvent::Injected { direction, bytes: bytes.to_vec() });
+        Ok(())
+    }
+}
+
+/// What the operator types gets this appended before it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+    None,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+            LineEnding::None => "",
+        }
+    }
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = String;
+
+    /// `lf`, `crlf`, `cr` or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            "cr" => Ok(LineEnding::Cr),
+            "none" => Ok(LineEnding::None),
+            _ => Err(format!("unknown line ending {:?}, expected lf, crlf, cr or none", s)),
+        }
+    }
+}
+
+/// Turns relayed bytes into readable text: every line starts with the
+/// direction it travelled, e.g. `A>B `, and bytes that are not printable
+/// are shown as `\xNN`. Chunks may end mid-line; the next chunk going the
+/// same way continues the line.
+#[derive(Debug, Default)]
+pub struct Mirror {
+    /// Direction of the line being written, if it is not finished.
+    open: Option<Direction>,
+    /// Whether that line holds injected bytes.
+    injected: bool,
+}
+
+impl Mirror {
+    pub fn render(&mut self, direction: Direction, injected: bool, bytes: &[u8]) -> String {
+        let mut out = String::new();
+        if self.open.is_some() && (self.open != Some(direction) || self.injected != injected) {
+            out.push('\n');
+            self.open = None;
+        }
+        for chunk in bytes.split_inclusive(|&b| b == b'\n') {
+            if self.open.is_none() {
+                out.push_str(&format!("{}{} ", direction, if injected { "*" } else { "" }));
+                self.open = Some(direction);
+                self.injected = injected;
+            }
+            let (line, newline) = match chunk.strip_suffix(b"\n") {
+                Some(line) => (line, true),
+                None => (chunk, false),
+            };
+            // A CR before the newline is just the line ending.
+            let line = if newline { line.strip_suffix(b"\r").unwrap_or(line) } else { line };
+            for &byte in line {
+                match byte {
+                    b' '..=b'~' | b'\t' => out.push(byte as char),
+                    _ => out.push_str(&format!("\\x{:02x}", byte)),
+                }
+            }
+            if newline {
+                out.push('\n');
+                self.open = None;
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,121 @@
+// src/cancel.rs
+//! Cancelling connection attempts and stopping a connection's threads,
+//! e.g. from a Cancel button or when the window is closed.
+
+use crate::net::Stream;
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use std::{
+    io,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// How often [`Cancel::sleep`] checks the flag.
+const POLL: Duration = Duration::from_millis(50);
+
+/// Shared cancellation flag. Cloning gives another handle to the same flag.
+#[derive(Clone)]
+pub struct Cancel {
+    cancelled: Arc<AtomicBool>,
+    hooks: Arc<Mutex<Vec<Hook>>>,
+    workers: Arc<Mutex<Option<Sender<()>>>>,
+    finished: Receiver<()>,
+}
+
+/// Held by a thread for as long as it runs, so [`Cancel::shutdown`] can
+/// wait for it.
+pub struct Worker {
+    _alive: Option<Sender<()>>,
+}
+
+impl Default for Cancel {
+    fn default() -> Self {
+        Cancel::new()
+    }
+}
+
+impl Cancel {
+    pub fn new() -> Self {
+        let (workers, finished) = bounded(0);
+        Cancel {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            hooks: Arc::new(Mutex::new(Vec::new())),
+            workers: Arc::new(Mutex::new(Some(workers))),
+            finished,
+        }
+    }
+
+    /// Sets the flag and runs everything registered with [`on_cancel`](Self::on_cancel).
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let hooks: Vec<Hook> = self.hooks.lock().unwrap().drain(..).collect();
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// `Err(Interrupted)` once cancelled, for use with `?` in loops.
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits for `duration`, or returns `Err(Interrupted)` as soon as cancelled.
+    pub fn sleep(&self, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(left.min(POLL));
+        }
+    }
+
+    /// Runs `hook` on cancel, or right away if that already happened.
+    pub fn on_cancel(&self, hook: impl FnOnce() + Send + 'static) {
+        let mut hooks = self.hooks.lock().unwrap();
+        if self.is_cancelled() {
+            drop(hooks);
+            hook();
+        } else {
+            hooks.push(Box::new(hook));
+        }
+    }
+
+    /// Shuts `stream` down on cancel, which wakes up any thread blocked on it.
+    pub fn watch(&self, stream: &Stream) -> io::Result<()> {
+        let stream = stream.try_clone()?;
+        self.on_cancel(move || {
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+        Ok(())
+    }
+
+    pub fn worker(&self) -> Worker {
+        Worker { _alive: self.workers.lock().unwrap().clone() }
+    }
+
+    /// Cancels and waits up to `timeout` for every [`Worker`] to be dropped.
+    /// Returns whether they all finished in time.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.cancel();
+        self.workers.lock().unwrap().take();
+        !matches!(self.finished.recv_timeout(timeout), Err(RecvTimeoutError::Timeout))
+    }
+}
@@ -0,0 +1,371 @@
+// src/capture.rs
+//! Recording a session's traffic to a file and playing it back, for
+//! reproducing protocol problems.
+//!
+//! A capture is a text file with one record per chunk of bytes sent or
+//! received:
+//!
+//! ```text
+//! # socat_chat capture 1
+//! 1718000000.123456 out 68656c6c6f0a
+//! 1718000000.250000 in 68690a
+//! ```
+//!
+//! Each record is the time in seconds since the Unix epoch (with
+//! microseconds), `out` for bytes we sent or `in` for bytes we received,
+//! and the bytes in lowercase hex, separated by tabs (shown as spaces
+//! above). Lines starting with `#` are comments. Chunks are recorded as
+//! they were written or read, so a line of chat may span several records.
+//! `network_chat` records what goes over the wire, after any encryption;
+//! `multi_chat` clients record frames with the login password blanked;
+//! `chat_bridge` records bytes from A as `in` and bytes to A as `out`.
+//!
+//! Captures can also be written as pcap files, which Wireshark and tcpdump
+//! read as a single TCP connection. The IP and TCP headers are made up
+//! apart from the peer's address, when it is known.
+
+use crate::{cancel::Cancel, direct::Duplex, hex};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const HEADER: &str = "# socat_chat capture 1";
+
+/// Which way a chunk went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Received from the peer.
+    In,
+    /// Sent to the peer.
+    Out,
+}
+
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Flow::In => "in",
+            Flow::Out => "out",
+        })
+    }
+}
+
+impl std::str::FromStr for Flow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Flow::In),
+            "out" => Ok(Flow::Out),
+            _ => Err(format!("unknown direction {:?}, expected in or out", s)),
+        }
+    }
+}
+
+/// One chunk of a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Since the Unix epoch.
+    pub time: Duration,
+    pub flow: Flow,
+    pub bytes: Vec<u8>,
+}
+
+impl Record {
+    pub fn encode(&self) -> String {
+        format!("{}.{:06}\t{}\t{}", self.time.as_secs(), self.time.subsec_micros(), self.flow, hex::encode(&self.bytes))
+    }
+
+    pub fn decode(line: &str) -> Result<Record, String> {
+        let fields: Vec<&str> = line.trim_end().split('\t').collect();
+        let [time, flow, bytes] = fields[..] else { return Err(format!("expected 3 fields, got {:?}", line)) };
+        let (secs, micros) = time.split_once('.').unwrap_or((time, "0"));
+        let secs: u64 = secs.parse().map_err(|_| format!("invalid time {:?}", time))?;
+        let micros: u32 = format!("{:0<6}", micros)[..6].parse().map_err(|_| format!("invalid time {:?}", time))?;
+        Ok(Record {
+            time: Duration::new(secs, micros * 1000),
+            flow: flow.parse()?,
+            bytes: hex::decode(bytes).ok_or_else(|| format!("invalid hex {:?}", bytes))?,
+        })
+    }
+}
+
+/// Reads a capture written in the text format.
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let text = fs::read_to_string(path)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| {
+            Record::decode(line).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), n + 1, e))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Pcap,
+}
+
+impl Format {
+    /// Pcap for `.pcap` files, text for anything else.
+    pub fn for_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("pcap") => Format::Pcap,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// Writes records to a capture file. Cloning gives another handle to the
+/// same file.
+#[derive(Clone)]
+pub struct Recorder {
+    sink: Arc<Mutex<Sink>>,
+}
+
+enum Sink {
+    Text(BufWriter<File>),
+    Pcap(Pcap),
+    /// Writing failed; the error was reported once.
+    Broken,
+}
+
+impl Recorder {
+    /// Creates (or truncates) `path`, in the format its name suggests.
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let sink = match Format::for_path(path) {
+            Format::Text => {
+                writeln!(file, "{}", HEADER)?;
+                file.flush()?;
+                Sink::Text(file)
+            }
+            Format::Pcap => Sink::Pcap(Pcap::new(file)?),
+        };
+        println!("Capturing traffic to {}", path.display());
+        Ok(Recorder { sink: Arc::new(Mutex::new(sink)) })
+    }
+
+    /// The peer's address, for the headers of a pcap capture; a made-up
+    /// one is used until this is called. Ignored for text captures.
+    pub fn set_peer(&self, peer: SocketAddrV4) {
+        if let Sink::Pcap(pcap) = &mut *self.sink.lock().unwrap() {
+            pcap.endpoints[1] = peer;
+        }
+    }
+
+    /// Records `bytes` as having gone `flow` just now.
+    pub fn record(&self, flow: Flow, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let record = Record { time, flow, bytes: bytes.to_vec() };
+        let mut sink = self.sink.lock().unwrap();
+        let written = match &mut *sink {
+            Sink::Text(file) => writeln!(file, "{}", record.encode()).and_then(|_| file.flush()),
+            Sink::Pcap(pcap) => pcap.write(&record),
+            Sink::Broken => return,
+        };
+        if let Err(e) = written {
+            println!("Capture stopped: {}", e);
+            *sink = Sink::Broken;
+        }
+    }
+}
+
+/// Records everything read from and written to `duplex`.
+pub fn tap(duplex: Duplex, recorder: Recorder) -> Duplex {
+    let (reader, writer) = duplex.into_parts();
+    Duplex::new(
+        TapReader { inner: reader, recorder: recorder.clone() },
+        TapWriter { inner: writer, recorder },
+    )
+}
+
+struct TapReader {
+    inner: Box<dyn Read + Send>,
+    recorder: Recorder,
+}
+
+impl Read for TapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.recorder.record(Flow::In, &buf[..len]);
+        Ok(len)
+    }
+}
+
+struct TapWriter {
+    inner: Box<dyn Write + Send>,
+    recorder: Recorder,
+}
+
+impl Write for TapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.recorder.record(Flow::Out, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A classic pcap file of raw IPv4 packets (link type 101).
+struct Pcap {
+    file: BufWriter<File>,
+    /// Local and peer address.
+    endpoints: [SocketAddrV4; 2],
+    /// Next sequence number from the local and the peer side.
+    seq: [u32; 2],
+}
+
+/// Room for the IP and TCP headers in a packet of at most 64 KiB.
+const MAX_SEGMENT: usize = 65_535 - 40;
+
+impl Pcap {
+    fn new(mut file: BufWriter<File>) -> io::Result<Pcap> {
+        file.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        file.write_all(&0i32.to_le_bytes())?; // UTC
+        file.write_all(&0u32.to_le_bytes())?; // accuracy
+        file.write_all(&65_535u32.to_le_bytes())?; // snapshot length
+        file.write_all(&101u32.to_le_bytes())?; // LINKTYPE_RAW
+        file.flush()?;
+        let endpoints = [
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50_000),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8080),
+        ];
+        Ok(Pcap { file, endpoints, seq: [1, 1] })
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let from = match record.flow {
+            Flow::Out => 0,
+            Flow::In => 1,
+        };
+        for segment in record.bytes.chunks(MAX_SEGMENT) {
+            let packet = self.packet(from, segment);
+            self.file.write_all(&(record.time.as_secs() as u32).to_le_bytes())?;
+            self.file.write_all(&record.time.subsec_micros().to_le_bytes())?;
+            self.file.write_all(&(packet.len() as u32).to_le_bytes())?;
+            self.file.write_all(&(packet.len() as u32).to_le_bytes())?;
+            self.file.write_all(&packet)?;
+            self.seq[from] = self.seq[from].wrapping_add(segment.len() as u32);
+        }
+        self.file.flush()
+    }
+
+    /// An IPv4 packet carrying `payload` in a TCP segment from side `from`.
+    fn packet(&self, from: usize, payload: &[u8]) -> Vec<u8> {
+        let (source, target) = (self.endpoints[from], self.endpoints[1 - from]);
+        let total = 40 + payload.len();
+        let mut packet = Vec::with_capacity(total);
+        // IPv4 header: version 4, 20 bytes, no options, don't fragment, TTL 64.
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&(total as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        packet.extend_from_slice(&source.ip().octets());
+        packet.extend_from_slice(&target.ip().octets());
+        let ip_checksum = checksum(&packet[..20], 0);
+        packet[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+        // TCP header: 20 bytes, PSH and ACK set.
+        packet.extend_from_slice(&source.port().to_be_bytes());
+        packet.extend_from_slice(&target.port().to_be_bytes());
+        packet.extend_from_slice(&self.seq[from].to_be_bytes());
+        packet.extend_from_slice(&self.seq[1 - from].to_be_bytes());
+        packet.extend_from_slice(&[0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        packet.extend_from_slice(payload);
+
+        let tcp_len = (20 + payload.len()) as u32;
+        let mut pseudo = 0u32;
+        for word in [source.ip().octets(), target.ip().octets()] {
+            pseudo += u32::from(u16::from_be_bytes([word[0], word[1]])) + u32::from(u16::from_be_bytes([word[2], word[3]]));
+        }
+        pseudo += 6 + tcp_len;
+        let tcp_checksum = checksum(&packet[20..], pseudo);
+        packet[36..38].copy_from_slice(&tcp_checksum.to_be_bytes());
+        packet
+    }
+}
+
+/// The Internet checksum (RFC 1071) of `data`, starting from `sum`.
+fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    for pair in data.chunks(2) {
+        let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+        sum += u32::from(word);
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// How fast to play a capture back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// The original timing divided by this factor: 1 is real time, 10 ten
+    /// times faster.
+    Factor(f64),
+    /// No pauses at all.
+    Max,
+}
+
+impl std::str::FromStr for Speed {
+    type Err = String;
+
+    /// A factor such as `1`, `2.5` or `10x`, or `max`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(Speed::Max);
+        }
+        match s.trim_end_matches('x').parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(Speed::Factor(factor)),
+            _ => Err(format!("invalid speed {:?}, expected a factor like 2 or 10x, or max", s)),
+        }
+    }
+}
+
+/// Writes the `flow` records of `records` to `writer`, keeping the gaps
+/// between them as recorded (scaled by `speed`). Returns how many records
+/// were played; stops early with `Interrupted` once `cancel` fires.
+pub fn replay(
+    records: &[Record],
+    flow: Flow,
+    speed: Speed,
+    writer: &mut dyn Write,
+    cancel: &Cancel,
+) -> io::Result<usize> {
+    let start = Instant::now();
+    let mut first = None;
+    let mut played = 0;
+    for record in records.iter().filter(|record| record.flow == flow) {
+        let first = *first.get_or_insert(record.time);
+        if let Speed::Factor(factor) = speed {
+            let due = start + (record.time.saturating_sub(first)).div_f64(factor);
+            while let Some(left) = due.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+                cancel.check()?;
+                thread::sleep(left.min(Duration::from_millis(50)));
+            }
+        }
+        cancel.check()?;
+        writer.write_all(&record.bytes)?;
+        writer.flush()?;
+        played += 1;
+    }
+    Ok(played)
+}
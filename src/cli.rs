@@ -0,0 +1,55 @@
+// src/cli.rs
+//! Minimal command line handling shared by the binaries: positional
+//! arguments plus optional `--name=value` settings and `--flag` switches.
+
+use std::{collections::HashMap, str::FromStr};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub positional: Vec<String>,
+    options: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut parsed = Args::default();
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(option) if !option.is_empty() => match option.split_once('=') {
+                    Some((name, value)) => {
+                        parsed.options.insert(name.to_string(), Some(value.to_string()));
+                    }
+                    None => {
+                        parsed.options.insert(option.to_string(), None);
+                    }
+                },
+                _ => parsed.positional.push(arg),
+            }
+        }
+        parsed
+    }
+
+    /// Arguments of the current process, without the program name.
+    pub fn from_env() -> Self {
+        Args::parse(std::env::args().skip(1))
+    }
+
+    /// True if `--name` was given, with or without a value.
+    pub fn flag(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|v| v.as_deref())
+    }
+
+    /// Value of `--name=...` parsed as `T`, or `default` when absent.
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.value(name) {
+            Some(raw) => raw
+                .parse()
+                .map_err(|_| format!("invalid value for --{}: {}", name, raw)),
+            None => Ok(default),
+        }
+    }
+}
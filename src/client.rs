@@ -0,0 +1,272 @@
+// src/client.rs
+//! Client side of the `multi_chat` protocol: logs in to a [`ChatServer`]
+//! and turns incoming frames into [`ClientEvent`]s.
+//!
+//! [`ChatServer`]: crate::server::ChatServer
+
+use crate::{
+    cancel::Cancel,
+    capture::{Flow, Recorder},
+    heartbeat::{Heartbeat, Pinger},
+    net::{self, Stream, Timeouts},
+    protocol::{Frame, Presence},
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Longest pause between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Chat { from: String, text: String },
+    /// A message replayed from before we joined, `timestamp` in unix seconds.
+    History { timestamp: u64, from: String, text: String },
+    System(String),
+    Users(Vec<String>),
+    /// Someone started or stopped typing.
+    Typing { from: String, typing: bool },
+    /// Someone became active or idle.
+    Presence { user: String, presence: Presence },
+    /// A chat message whose author would like to know we read it; see
+    /// [`ChatClient::read`].
+    Post { id: u64, from: String, text: String },
+    /// Our post `id` reached the room.
+    Delivered(u64),
+    /// `by` read our post `id`.
+    Read { id: u64, by: String },
+    /// How long our latest ping took to come back.
+    RoundTrip(Duration),
+    /// An operator threw us out, saying why; the connection ends next.
+    Kicked(String),
+    /// The connection ended; carries the error if it did not close cleanly.
+    Disconnected(Option<String>),
+}
+
+/// A logged-in connection to a room. Cloning shares the connection.
+#[derive(Clone)]
+pub struct ChatClient {
+    username: String,
+    stream: Arc<Mutex<Stream>>,
+    recorder: Option<Recorder>,
+    heartbeat: Heartbeat,
+}
+
+impl ChatClient {
+    /// Connects to `address` and logs in. Events start flowing on the
+    /// returned channel right after the server's `WELCOME`.
+    pub fn connect(address: &str, username: &str, password: &str) -> io::Result<(ChatClient, Receiver<ClientEvent>)> {
+        let stream = net::dial(address, Timeouts::default().connect, &Cancel::new())?;
+        println!("Client connected successfully");
+        ChatClient::login(stream, username, password)
+    }
+
+    /// Runs the login handshake over an already connected stream. A read
+    /// timeout set on `stream` bounds the handshake and is cleared afterwards.
+    pub fn login(stream: Stream, username: &str, password: &str) -> io::Result<(ChatClient, Receiver<ClientEvent>)> {
+        ChatClient::login_recorded(stream, username, password, None)
+    }
+
+    /// Like [`ChatClient::login`], also recording every frame sent and
+    /// received to `recorder`. The password is blanked in the capture.
+    pub fn login_recorded(
+        stream: Stream,
+        username: &str,
+        password: &str,
+        recorder: Option<Recorder>,
+    ) -> io::Result<(ChatClient, Receiver<ClientEvent>)> {
+        ChatClient::login_with(stream, username, password, recorder, Heartbeat::default())
+    }
+
+    /// Like [`ChatClient::login_recorded`], pinging the server and giving up
+    /// on a silent one as `heartbeat` says.
+    pub fn login_with(
+        stream: Stream,
+        username: &str,
+        password: &str,
+        recorder: Option<Recorder>,
+        heartbeat: Heartbeat,
+    ) -> io::Result<(ChatClient, Receiver<ClientEvent>)> {
+        let (stream, reader) = handshake(stream, username, password, &recorder, &heartbeat)?;
+        let client = ChatClient {
+            username: username.to_string(),
+            stream: Arc::new(Mutex::new(stream)),
+            recorder,
+            heartbeat,
+        };
+        let events = client.start_receiving(reader);
+        Ok((client, events))
+    }
+
+    /// Logs in again over `stream` after the connection was lost. Every
+    /// clone of this client writes to the new connection from then on;
+    /// its events arrive on the returned channel.
+    pub fn resume(&self, stream: Stream, password: &str) -> io::Result<Receiver<ClientEvent>> {
+        let (stream, reader) = handshake(stream, &self.username, password, &self.recorder, &self.heartbeat)?;
+        *self.stream.lock().unwrap() = stream;
+        Ok(self.start_receiving(reader))
+    }
+
+    /// Starts the threads of a connection that just logged in: one turning
+    /// frames into events, and one pinging while the first one runs.
+    fn start_receiving(&self, reader: BufReader<Stream>) -> Receiver<ClientEvent> {
+        let (events, receiver) = unbounded();
+        let pinging = self.clone();
+        let pinger = self.heartbeat.start(move |frame| pinging.write_frame(frame));
+        let client = self.clone();
+        thread::spawn(move || client.receive(reader, events, pinger));
+        receiver
+    }
+
+    fn receive(&self, reader: BufReader<Stream>, events: Sender<ClientEvent>, pinger: Option<Pinger>) {
+        println!("Starting message receiver");
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    // After a timeout the socket is still open; close it.
+                    self.disconnect();
+                    let reason = self.heartbeat.explain(e).to_string();
+                    let _ = events.send(ClientEvent::Disconnected(Some(reason)));
+                    return;
+                }
+            };
+            if let Some(recorder) = &self.recorder {
+                recorder.record(Flow::In, format!("{}\n", line).as_bytes());
+            }
+            let event = match Frame::decode(&line) {
+                Ok(Frame::Chat { from, text }) => ClientEvent::Chat { from, text },
+                Ok(Frame::History { timestamp, from, text }) => ClientEvent::History { timestamp, from, text },
+                Ok(Frame::System(text)) => ClientEvent::System(text),
+                Ok(Frame::Users(users)) => ClientEvent::Users(users),
+                Ok(Frame::Typing { from, typing }) => ClientEvent::Typing { from, typing },
+                Ok(Frame::Presence { user, presence }) => ClientEvent::Presence { user, presence },
+                Ok(Frame::Post { id, from, text }) => ClientEvent::Post { id, from, text },
+                Ok(Frame::Ack { id }) => ClientEvent::Delivered(id),
+                Ok(Frame::Read { id, user }) => ClientEvent::Read { id, by: user },
+                Ok(Frame::Kicked(reason)) => ClientEvent::Kicked(reason),
+                Ok(Frame::Ping(token)) => {
+                    if let Err(e) = self.write_frame(&Frame::Pong(token)) {
+                        println!("Error answering ping: {}", e);
+                    }
+                    continue;
+                }
+                Ok(Frame::Pong(token)) => match &pinger {
+                    Some(pinger) => ClientEvent::RoundTrip(pinger.round_trip(token)),
+                    None => continue,
+                },
+                Ok(other) => {
+                    println!("Ignoring unexpected frame from server: {:?}", other);
+                    continue;
+                }
+                Err(e) => {
+                    println!("Invalid frame from server: {}", e);
+                    continue;
+                }
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(ClientEvent::Disconnected(None));
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn send(&self, text: &str) -> io::Result<()> {
+        self.write_frame(&Frame::Chat { from: self.username.clone(), text: text.to_string() })
+    }
+
+    /// Sends a chat message as post `id`. The server acknowledges it with
+    /// [`ClientEvent::Delivered`]; readers may follow with [`ClientEvent::Read`].
+    pub fn post(&self, id: u64, text: &str) -> io::Result<()> {
+        self.write_frame(&Frame::Post { id, from: self.username.clone(), text: text.to_string() })
+    }
+
+    /// Tells `author` we read their post `id`.
+    pub fn read(&self, id: u64, author: &str) -> io::Result<()> {
+        self.write_frame(&Frame::Read { id, user: author.to_string() })
+    }
+
+    /// Tells the room whether we are typing.
+    pub fn set_typing(&self, typing: bool) -> io::Result<()> {
+        self.write_frame(&Frame::Typing { from: self.username.clone(), typing })
+    }
+
+    /// Tells the room whether we are active or idle.
+    pub fn set_presence(&self, presence: Presence) -> io::Result<()> {
+        self.write_frame(&Frame::Presence { user: self.username.clone(), presence })
+    }
+
+    /// Sends a slash command such as `/kick bob` for the server to carry
+    /// out. A refusal comes back as a [`ClientEvent::System`] message.
+    pub fn command(&self, line: &str) -> io::Result<()> {
+        self.write_frame(&Frame::Command(line.to_string()))
+    }
+
+    fn write_frame(&self, frame: &Frame) -> io::Result<()> {
+        let line = format!("{}\n", frame.encode());
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(line.as_bytes())?;
+        stream.flush()?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Flow::Out, line.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Closes the connection; the event channel reports `Disconnected`.
+    pub fn disconnect(&self) {
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+/// How long to wait before reconnection attempt `attempt` (from 1): one
+/// second, doubling up to half a minute.
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    MAX_RECONNECT_DELAY.min(Duration::from_secs(1 << attempt.saturating_sub(1).min(5)))
+}
+
+/// Sends `HELLO` and waits for the verdict. Returns the stream and a
+/// reader over it.
+fn handshake(
+    mut stream: Stream,
+    username: &str,
+    password: &str,
+    recorder: &Option<Recorder>,
+    heartbeat: &Heartbeat,
+) -> io::Result<(Stream, BufReader<Stream>)> {
+    let hello = Frame::Hello { username: username.to_string(), password: password.to_string() };
+    writeln!(stream, "{}", hello.encode())?;
+    stream.flush()?;
+    if let Some(recorder) = recorder {
+        let blanked = Frame::Hello { username: username.to_string(), password: String::new() };
+        recorder.record(Flow::Out, format!("{}\n", blanked.encode()).as_bytes());
+    }
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| net::timed_out("login", e))?;
+    if let Some(recorder) = recorder {
+        recorder.record(Flow::In, line.as_bytes());
+    }
+    match Frame::decode(&line) {
+        Ok(Frame::Welcome) => heartbeat.apply(&stream)?,
+        Ok(Frame::Rejected(reason)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("login rejected: {}", reason),
+            ))
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected reply to login")),
+    }
+    Ok((stream, reader))
+}
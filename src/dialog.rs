@@ -0,0 +1,320 @@
+// src/dialog.rs
+//! Startup dialog for picking how to connect, shown when a binary is
+//! launched without command-line arguments (e.g. by double-clicking it).
+//!
+//! This is the one FLTK-based module in the library; the chat windows
+//! themselves live in the binaries.
+//!
+//! There are no TLS options: nothing in this crate speaks TLS, so the
+//! dialog offers only what the binaries can do. A room password (see
+//! [`crate::auth`]) is sent unencrypted.
+
+use crate::{
+    discovery::{Browser, DiscoveredServer, DiscoveryConfig},
+    net,
+    settings::{ConnectionProfile, RecentConnections},
+    udp,
+};
+use fltk::{
+    app,
+    browser::HoldBrowser,
+    button::{Button, CheckButton},
+    dialog,
+    enums::{Align, Color},
+    frame::Frame,
+    group::Flex,
+    input::{Input, SecretInput},
+    menu::Choice,
+    prelude::*,
+    window::Window,
+};
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+/// What a binary lets the user choose.
+#[derive(Clone, Copy)]
+pub struct DialogOptions {
+    pub title: &'static str,
+    /// Name the recent connections are stored under.
+    pub name: &'static str,
+    pub modes: &'static [&'static str],
+    pub ask_username: bool,
+    pub ask_password: bool,
+    pub offer_e2e: bool,
+    /// Where to look for servers announcing themselves, if anywhere.
+    pub discovery: Option<DiscoveryConfig>,
+}
+
+pub struct ConnectionChoice {
+    pub profile: ConnectionProfile,
+    pub password: String,
+}
+
+/// Example address for each mode, shown under the address field.
+pub fn address_hint(mode: &str) -> &'static str {
+    match mode {
+        "server" => "Address to listen on, e.g. *:8080, [::1]:8080 or unix:/tmp/chat.sock",
+        "client" => "Server to connect to, e.g. 192.168.0.108:8080, chat.lan:8080 or unix:/tmp/chat.sock",
+        "serial" => "Device and baud rate, e.g. /dev/ttyUSB0,115200",
+        "fifo" => "Read and write pipe, e.g. /tmp/pipe1,/tmp/pipe2",
+        "udp" => "Peer, e.g. 192.168.0.108:9000; or *:9000, to wait for one",
+        _ => "",
+    }
+}
+
+#[derive(Clone)]
+struct Fields {
+    mode: Choice,
+    address: Input,
+    hint: Frame,
+    username: Input,
+    password: SecretInput,
+    e2e: CheckButton,
+}
+
+impl Fields {
+    fn mode(&self) -> String {
+        self.mode.choice().unwrap_or_default()
+    }
+
+    fn fill(&mut self, profile: &ConnectionProfile) {
+        let index = self.mode.find_index(&profile.mode);
+        if index >= 0 {
+            self.mode.set_value(index);
+        }
+        self.address.set_value(&profile.address);
+        self.username.set_value(&profile.username);
+        self.e2e.set_checked(profile.e2e);
+        self.show_hint();
+    }
+
+    /// Switches to client mode and fills in a discovered server's address.
+    fn pick_server(&mut self, address: &str) {
+        let index = self.mode.find_index("client");
+        if index >= 0 {
+            self.mode.set_value(index);
+        }
+        self.address.set_value(address);
+        self.show_hint();
+    }
+
+    fn show_hint(&mut self) {
+        self.hint.set_label(address_hint(&self.mode()));
+    }
+
+    fn choice(&self, options: &DialogOptions) -> Result<ConnectionChoice, String> {
+        let profile = ConnectionProfile {
+            mode: self.mode(),
+            address: self.address.value().trim().to_string(),
+            username: self.username.value().trim().to_string(),
+            e2e: options.offer_e2e && self.e2e.is_checked(),
+        };
+        if profile.address.is_empty() {
+            return Err("Please enter an address.".to_string());
+        }
+        if matches!(profile.mode.as_str(), "server" | "client") {
+            net::validate_address(&profile.address).map_err(|e| format!("Invalid address: {}", e))?;
+        }
+        if profile.mode == "udp" {
+            udp::parse_spec(&profile.address).map_err(|e| format!("Invalid address: {}", e))?;
+        }
+        if options.ask_username && profile.username.is_empty() {
+            return Err("Please enter a username.".to_string());
+        }
+        Ok(ConnectionChoice { profile, password: self.password.value() })
+    }
+}
+
+/// Labelled row in the dialog's column.
+fn row(column: &mut Flex, label: &str) -> Flex {
+    let mut row = Flex::default().row();
+    let mut caption = Frame::default().with_label(label);
+    caption.set_align(Align::Left | Align::Inside);
+    row.fixed(&caption, 90);
+    column.fixed(&row, 30);
+    row
+}
+
+/// Shows the dialog and blocks until the user connects or gives up.
+/// The chosen connection is added to the recent list.
+pub fn ask(options: &DialogOptions) -> Option<ConnectionChoice> {
+    let mut recent = RecentConnections::load(options.name);
+
+    let height = if options.discovery.is_some() { 560 } else { 420 };
+    let mut window = Window::default().with_size(440, height).with_label(options.title);
+    let mut column = Flex::default_fill().column();
+    column.set_margin(10);
+    column.set_pad(8);
+
+    let mode_row = row(&mut column, "Mode:");
+    let mut mode = Choice::default();
+    for name in options.modes {
+        mode.add_choice(name);
+    }
+    mode.set_value(0);
+    mode_row.end();
+
+    let address_row = row(&mut column, "Address:");
+    let address = Input::default();
+    address_row.end();
+
+    let mut hint = Frame::default();
+    hint.set_align(Align::Left | Align::Inside);
+    hint.set_label_color(Color::Dark3);
+    hint.set_label_size(12);
+    column.fixed(&hint, 20);
+
+    // Fields a binary does not use stay hidden; Flex leaves them out.
+    let mut username_row = row(&mut column, "Username:");
+    let username = Input::default();
+    username_row.end();
+    if !options.ask_username {
+        username_row.hide();
+    }
+
+    let mut password_row = row(&mut column, "Password:");
+    let password = SecretInput::default();
+    password_row.end();
+    if !options.ask_password {
+        password_row.hide();
+    }
+
+    let mut e2e = CheckButton::default().with_label("End-to-end encryption");
+    column.fixed(&e2e, 25);
+    if !options.offer_e2e {
+        e2e.hide();
+    }
+
+    // Servers found on the network; picking one fills in the address.
+    let mut found_label = Frame::default().with_label("Servers on this network:");
+    found_label.set_align(Align::Left | Align::Inside);
+    column.fixed(&found_label, 20);
+    let mut found_list = HoldBrowser::default();
+    let browser = options.discovery.and_then(|config| match Browser::open(config) {
+        Ok(browser) => Some(browser),
+        Err(e) => {
+            println!("Not looking for servers: {}", e);
+            None
+        }
+    });
+    if browser.is_none() {
+        found_label.hide();
+        found_list.hide();
+    }
+
+    let mut recent_label = Frame::default().with_label("Recent connections:");
+    recent_label.set_align(Align::Left | Align::Inside);
+    column.fixed(&recent_label, 20);
+    let mut recent_list = HoldBrowser::default();
+    for entry in &recent.entries {
+        recent_list.add(&entry.label());
+    }
+
+    let mut buttons = Flex::default().row();
+    Frame::default();
+    let mut cancel = Button::default().with_label("Cancel");
+    let mut connect = Button::default().with_label("Connect");
+    buttons.fixed(&cancel, 90);
+    buttons.fixed(&connect, 90);
+    buttons.end();
+    column.fixed(&buttons, 30);
+
+    column.end();
+    window.end();
+    window.make_modal(true);
+    window.make_resizable(true);
+    window.show();
+
+    let mut fields = Fields { mode, address, hint, username, password, e2e };
+    match recent.entries.first() {
+        Some(last) => fields.fill(last),
+        None => fields.show_hint(),
+    }
+
+    let chosen: Rc<RefCell<Option<ConnectionChoice>>> = Rc::new(RefCell::new(None));
+
+    let mut on_mode = fields.clone();
+    fields.mode.set_callback(move |_| on_mode.show_hint());
+
+    let submit = {
+        let fields = fields.clone();
+        let chosen = Rc::clone(&chosen);
+        let window = window.clone();
+        let options = *options;
+        move || match fields.choice(&options) {
+            Ok(choice) => {
+                *chosen.borrow_mut() = Some(choice);
+                window.clone().hide();
+            }
+            Err(problem) => dialog::alert_default(&problem),
+        }
+    };
+    let submit = Rc::new(submit);
+
+    let on_connect = Rc::clone(&submit);
+    connect.set_callback(move |_| on_connect());
+
+    let mut on_cancel = window.clone();
+    cancel.set_callback(move |_| on_cancel.hide());
+
+    let entries = recent.entries.clone();
+    let mut on_pick = fields.clone();
+    let on_double_click = Rc::clone(&submit);
+    recent_list.set_callback(move |list| {
+        let line = list.value();
+        if line < 1 {
+            return;
+        }
+        on_pick.fill(&entries[line as usize - 1]);
+        if app::event_clicks() {
+            on_double_click();
+        }
+    });
+
+    let found: Rc<RefCell<Vec<DiscoveredServer>>> = Rc::new(RefCell::new(Vec::new()));
+    let found_servers = Rc::clone(&found);
+    let mut on_found = fields.clone();
+    let on_found_double_click = Rc::clone(&submit);
+    found_list.set_callback(move |list| {
+        let line = list.value();
+        if line < 1 {
+            return;
+        }
+        if let Some(server) = found_servers.borrow().get(line as usize - 1) {
+            on_found.pick_server(&server.address.to_string());
+        }
+        if app::event_clicks() {
+            on_found_double_click();
+        }
+    });
+
+    match browser {
+        Some(mut browser) => {
+            while window.shown() {
+                app::wait_for(0.25).ok();
+                match browser.poll(Duration::ZERO) {
+                    Ok(true) => {
+                        *found.borrow_mut() = browser.servers();
+                        found_list.clear();
+                        for server in found.borrow().iter() {
+                            found_list.add(&server.label());
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => println!("Error looking for servers: {}", e),
+                }
+            }
+        }
+        None => {
+            while window.shown() {
+                app::wait();
+            }
+        }
+    }
+
+    let choice = chosen.borrow_mut().take()?;
+    recent.remember(choice.profile.clone());
+    if let Err(e) = recent.save(options.name) {
+        println!("Could not save recent connections: {}", e);
+    }
+    Some(choice)
+}
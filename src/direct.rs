@@ -0,0 +1,224 @@
+// src/direct.rs
+//! One-to-one chat over a single byte stream, as used by `network_chat`.
+//!
+//! Without encryption lines go over as they are, so the other end may just
+//! as well be socat or netcat; a multi-line message goes as one line each.
+//! With an [`e2e::Session`] each line is a sealed message instead, which
+//! keeps multi-line messages whole.
+//!
+//! Between sealed lines, lines starting with a backslash are [`Control`]
+//! lines, which sealed lines, being hex, never are. Unsealed chats have no
+//! control lines, since the other end may not know them.
+
+use crate::{
+    e2e::{self, E2eError, Opener, Sealer},
+    heartbeat::Heartbeat,
+    net::Stream,
+    protocol::Frame,
+};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Both directions of a transport, kept as separate halves so they can be
+/// used from different threads. TCP, serial ports and FIFO pairs all end up
+/// as one of these.
+pub struct Duplex {
+    reader: Box<dyn Read + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Duplex {
+    pub fn new(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Self {
+        Duplex { reader: Box::new(reader), writer: Box::new(writer) }
+    }
+
+    pub fn tcp(stream: TcpStream) -> io::Result<Self> {
+        Ok(Duplex::new(stream.try_clone()?, stream))
+    }
+
+    /// A TCP or Unix socket connection.
+    pub fn stream(stream: Stream) -> io::Result<Self> {
+        Ok(Duplex::new(stream.try_clone()?, stream))
+    }
+
+    /// The reading and writing halves, for relaying raw bytes.
+    pub fn into_parts(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// What the two ends exchange besides messages, to notice a peer that
+/// vanished. Control lines carry no chat, so they are never sealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Asks for a [`Control::Pong`] with the same token.
+    Ping(u64),
+    Pong(u64),
+}
+
+impl Control {
+    pub fn encode(&self) -> String {
+        match self {
+            Control::Ping(token) => format!("\\ping {}", token),
+            Control::Pong(token) => format!("\\pong {}", token),
+        }
+    }
+
+    /// `None` unless `line` is a control line.
+    pub fn decode(line: &str) -> Option<Control> {
+        let (kind, token) = line.strip_prefix('\\')?.split_once(' ')?;
+        let token = token.parse().ok()?;
+        match kind {
+            "ping" => Some(Control::Ping(token)),
+            "pong" => Some(Control::Pong(token)),
+            _ => None,
+        }
+    }
+}
+
+/// Writing half of a direct chat.
+pub struct DirectSender {
+    writer: Box<dyn Write + Send>,
+    sealer: Option<Sealer>,
+}
+
+impl DirectSender {
+    pub fn send(&mut self, message: &str) -> io::Result<()> {
+        match self.sealer.as_mut() {
+            Some(sealer) => writeln!(self.writer, "{}", sealer.seal(message))?,
+            None => {
+                for line in message.split('\n') {
+                    writeln!(self.writer, "{}", line)?;
+                }
+            }
+        }
+        self.writer.flush()
+    }
+
+    /// Sends a control line, which only a sealed peer takes as one.
+    pub fn control(&mut self, control: Control) -> io::Result<()> {
+        writeln!(self.writer, "{}", control.encode())?;
+        self.writer.flush()
+    }
+}
+
+/// Reading half of a direct chat.
+pub struct DirectReceiver {
+    reader: BufReader<Box<dyn Read + Send>>,
+    opener: Option<Opener>,
+    on_control: Option<Box<dyn FnMut(Control) + Send>>,
+}
+
+impl DirectReceiver {
+    /// Calls `handler` with each control line received in a sealed chat.
+    /// Without a handler they are dropped.
+    pub fn on_control(&mut self, handler: impl FnMut(Control) + Send + 'static) {
+        self.on_control = Some(Box::new(handler));
+    }
+
+    /// Blocks for the next message. `None` once the peer closed the connection.
+    /// Unsealed, every line is a message; sealed, blank lines and control
+    /// lines are skipped.
+    pub fn recv(&mut self) -> Option<Result<String, E2eError>> {
+        loop {
+            let mut line = String::new();
+            if let Err(e) = self.reader.read_line(&mut line) {
+                return Some(Err(E2eError::Io(e)));
+            }
+            if line.is_empty() {
+                return None;
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            let Some(opener) = self.opener.as_mut() else { return Some(Ok(line.to_string())) };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(control) = Control::decode(line) {
+                if let Some(handler) = &mut self.on_control {
+                    handler(control);
+                }
+                continue;
+            }
+            return Some(opener.open(line));
+        }
+    }
+}
+
+/// Splits a connected stream into its two halves, using `session` for
+/// encryption when one was negotiated with [`e2e::handshake`].
+pub fn split(stream: TcpStream, session: Option<e2e::Session>) -> io::Result<(DirectSender, DirectReceiver)> {
+    Ok(split_duplex(Duplex::tcp(stream)?, session))
+}
+
+/// Like [`split`], for any transport.
+pub fn split_duplex(duplex: Duplex, session: Option<e2e::Session>) -> (DirectSender, DirectReceiver) {
+    let (sealer, opener) = match session {
+        Some(session) => (Some(session.sealer), Some(session.opener)),
+        None => (None, None),
+    };
+    (
+        DirectSender { writer: duplex.writer, sealer },
+        DirectReceiver { reader: BufReader::new(duplex.reader), opener, on_control: None },
+    )
+}
+
+/// Keeps up `heartbeat` on a direct chat over `socket`. TCP keepalive is
+/// set either way, but pings need `network_chat` on the other end, which
+/// only a sealed chat is sure of. There the peer's pings are answered and,
+/// unless pings are off, it is pinged every interval and given up once
+/// silent for the timeout. Each round trip measured goes to `round_trip`.
+pub fn keep_alive(
+    heartbeat: &Heartbeat,
+    socket: Stream,
+    sender: &Arc<Mutex<DirectSender>>,
+    receiver: &mut DirectReceiver,
+    mut round_trip: impl FnMut(Duration) + Send + 'static,
+) -> io::Result<()> {
+    if receiver.opener.is_none() {
+        if let Err(e) = socket.set_keepalive(heartbeat.keepalive) {
+            println!("Could not set TCP keepalive: {}", e);
+        }
+        return Ok(());
+    }
+    heartbeat.apply(&socket)?;
+    let pinging = Arc::clone(sender);
+    let pinger = heartbeat.start(move |frame| match frame {
+        Frame::Ping(token) => pinging.lock().unwrap().control(Control::Ping(*token)),
+        _ => Ok(()),
+    });
+
+    let sender = Arc::clone(sender);
+    receiver.on_control(move |control| match control {
+        Control::Ping(token) => {
+            if let Err(e) = sender.lock().unwrap().control(Control::Pong(token)) {
+                println!("Could not answer a ping: {}", e);
+            }
+        }
+        Control::Pong(token) => {
+            if let Some(pinger) = &pinger {
+                round_trip(pinger.round_trip(token));
+            }
+        }
+    });
+    Ok(())
+}
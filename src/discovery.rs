@@ -0,0 +1,312 @@
+// src/discovery.rs
+//! Finding `multi_chat` servers on the local network.
+//!
+//! A running server multicasts a short [`Announcement`] every few seconds;
+//! a [`Browser`] listens for them and keeps a list of the servers heard
+//! recently. Each announcement is one datagram holding one tab-separated
+//! line:
+//!
+//! ```text
+//! socat_chat-announce <room> <host> <port> <users>
+//! ```
+//!
+//! The server's address is taken from where the datagram came from, so
+//! the announcement only carries the port.
+
+use crate::{
+    cli::Args,
+    protocol::{escape, unescape},
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// An organisation-local multicast group (RFC 2365) and an unassigned port.
+pub const DEFAULT_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 77), 48077);
+
+const MAGIC: &str = "socat_chat-announce";
+
+/// Announcements a server may miss before browsers forget it.
+const MISSED_ANNOUNCEMENTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    pub group: SocketAddrV4,
+    /// Interface to announce and listen on; unspecified lets the system pick.
+    pub interface: Ipv4Addr,
+    /// Time between two announcements of a server.
+    pub interval: Duration,
+}
+
+impl DiscoveryConfig {
+    pub const DEFAULT: DiscoveryConfig = DiscoveryConfig {
+        group: DEFAULT_GROUP,
+        interface: Ipv4Addr::UNSPECIFIED,
+        interval: Duration::from_secs(2),
+    };
+
+    /// Reads `--discovery-group=ADDR:PORT` and `--discovery-interface=IP`.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        Ok(DiscoveryConfig {
+            group: args.parse_or("discovery-group", DEFAULT_GROUP)?,
+            interface: args.parse_or("discovery-interface", Ipv4Addr::UNSPECIFIED)?,
+            ..DiscoveryConfig::DEFAULT
+        })
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig::DEFAULT
+    }
+}
+
+/// What a server tells the network about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub room: String,
+    /// Name of the machine the server runs on, for display only.
+    pub host: String,
+    pub port: u16,
+    /// People in the room, the operator included.
+    pub users: usize,
+}
+
+impl Announcement {
+    pub fn encode(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            MAGIC,
+            escape(&self.room),
+            escape(&self.host),
+            self.port,
+            self.users
+        )
+    }
+
+    /// `None` for anything that is not an announcement, so unrelated
+    /// traffic on the group is ignored.
+    pub fn decode(datagram: &str) -> Option<Announcement> {
+        let fields: Vec<&str> = datagram.trim_end().split('\t').collect();
+        let [MAGIC, room, host, port, users] = fields[..] else { return None };
+        Some(Announcement {
+            room: unescape(room),
+            host: unescape(host),
+            port: port.parse().ok()?,
+            users: users.parse().ok()?,
+        })
+    }
+}
+
+/// Name of this machine, as shown to people browsing for servers.
+pub fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Sends a server's announcements until stopped.
+pub struct Announcer {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Announcer {
+    /// Starts announcing on a background thread. `announcement` is asked
+    /// for a fresh one each time, so the user count stays current.
+    pub fn start(
+        config: DiscoveryConfig,
+        announcement: impl Fn() -> Announcement + Send + 'static,
+    ) -> io::Result<Announcer> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        // Announcements are for the local network only.
+        socket.set_multicast_ttl_v4(1)?;
+        socket.set_multicast_loop_v4(true)?;
+        if !config.interface.is_unspecified() {
+            sys::set_multicast_interface(&socket, config.interface)?;
+        }
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&stopped);
+        thread::spawn(move || {
+            while !stopping.load(Ordering::SeqCst) {
+                if let Err(e) = socket.send_to(announcement().encode().as_bytes(), config.group) {
+                    println!("Error announcing on {}: {}", config.group, e);
+                }
+                thread::sleep(config.interval);
+            }
+        });
+        Ok(Announcer { stopped })
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A server heard from recently.
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    /// Where to connect to.
+    pub address: SocketAddr,
+    pub announcement: Announcement,
+    pub last_seen: Instant,
+}
+
+impl DiscoveredServer {
+    /// One line for a list of servers.
+    pub fn label(&self) -> String {
+        let a = &self.announcement;
+        format!(
+            "{} on {} ({}), {} {}",
+            a.room,
+            a.host,
+            self.address,
+            a.users,
+            if a.users == 1 { "user" } else { "users" }
+        )
+    }
+}
+
+/// Listens for announcements and remembers who sent them.
+pub struct Browser {
+    socket: UdpSocket,
+    config: DiscoveryConfig,
+    servers: HashMap<SocketAddr, DiscoveredServer>,
+}
+
+impl Browser {
+    /// Joins the group. Several browsers on one machine can do so at once.
+    pub fn open(config: DiscoveryConfig) -> io::Result<Browser> {
+        let socket = sys::bind_shared(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.group.port()))?;
+        socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+        Ok(Browser { socket, config, servers: HashMap::new() })
+    }
+
+    /// Takes in announcements for up to `wait` (zero only takes what has
+    /// arrived already) and forgets servers that went quiet. Returns
+    /// whether the list changed.
+    pub fn poll(&mut self, wait: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + wait;
+        let mut changed = false;
+        let mut datagram = [0u8; 1500];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                self.socket.set_nonblocking(true)?;
+            } else {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(Some(left))?;
+            }
+            let (len, from) = match self.socket.recv_from(&mut datagram) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            };
+            let Some(announcement) = std::str::from_utf8(&datagram[..len]).ok().and_then(Announcement::decode)
+            else {
+                continue;
+            };
+            let address = SocketAddr::new(from.ip(), announcement.port);
+            let previous = self.servers.insert(
+                address,
+                DiscoveredServer { address, announcement: announcement.clone(), last_seen: Instant::now() },
+            );
+            changed |= previous.is_none_or(|previous| previous.announcement != announcement);
+        }
+        let cutoff = self.config.interval * MISSED_ANNOUNCEMENTS;
+        let before = self.servers.len();
+        self.servers.retain(|_, server| server.last_seen.elapsed() < cutoff);
+        Ok(changed || self.servers.len() != before)
+    }
+
+    /// The servers heard from, by room name and then address.
+    pub fn servers(&self) -> Vec<DiscoveredServer> {
+        let mut servers: Vec<DiscoveredServer> = self.servers.values().cloned().collect();
+        servers.sort_by(|a, b| (&a.announcement.room, a.address).cmp(&(&b.announcement.room, b.address)));
+        servers
+    }
+}
+
+/// Socket options the standard library does not offer.
+#[cfg(unix)]
+mod sys {
+    use std::{
+        io, mem,
+        net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+        os::fd::{AsRawFd, FromRawFd},
+    };
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_option<T>(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+        let len = mem::size_of::<T>() as libc::socklen_t;
+        // SAFETY: `value` points to `len` readable bytes for the whole call.
+        check(unsafe { libc::setsockopt(socket.as_raw_fd(), level, name, value as *const T as *const _, len) })
+    }
+
+    fn in_addr(ip: Ipv4Addr) -> libc::in_addr {
+        libc::in_addr { s_addr: u32::from(ip).to_be() }
+    }
+
+    /// Binds with `SO_REUSEADDR` and `SO_REUSEPORT` set, so that more than
+    /// one process can listen to the group.
+    pub fn bind_shared(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+        // SAFETY: a fresh descriptor, owned by the socket from here on.
+        let socket = unsafe {
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+            check(fd)?;
+            UdpSocket::from_raw_fd(fd)
+        };
+        let on: libc::c_int = 1;
+        set_option(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR, &on)?;
+        set_option(&socket, libc::SOL_SOCKET, libc::SO_REUSEPORT, &on)?;
+
+        // SAFETY: all-zero is a valid sockaddr_in; the fields that matter
+        // are filled in below.
+        let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+        sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+        sockaddr.sin_port = addr.port().to_be();
+        sockaddr.sin_addr = in_addr(*addr.ip());
+        let len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        // SAFETY: `sockaddr` is a sockaddr_in of `len` bytes.
+        check(unsafe { libc::bind(socket.as_raw_fd(), &sockaddr as *const _ as *const libc::sockaddr, len) })?;
+        Ok(socket)
+    }
+
+    pub fn set_multicast_interface(socket: &UdpSocket, interface: Ipv4Addr) -> io::Result<()> {
+        set_option(socket, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &in_addr(interface))
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::{
+        io,
+        net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    };
+
+    /// Without `SO_REUSEADDR` only one browser per machine can listen.
+    pub fn bind_shared(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr)
+    }
+
+    pub fn set_multicast_interface(_socket: &UdpSocket, _interface: Ipv4Addr) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "choosing the discovery interface needs a Unix system"))
+    }
+}
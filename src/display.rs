@@ -0,0 +1,18 @@
+// src/display.rs
+//! Helpers for rendering messages in the chat log.
+
+/// Formats `text` after `prefix` as one block: continuation lines of a
+/// multi-line message are indented to line up under the first one.
+pub fn format_block(prefix: &str, text: &str) -> String {
+    let indent = " ".repeat(prefix.chars().count());
+    let mut out = String::from(prefix);
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            out.push_str(&indent);
+        }
+        out.push_str(line.trim_end_matches('\r'));
+    }
+    out.push('\n');
+    out
+}
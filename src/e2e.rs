@@ -0,0 +1,199 @@
+// src/e2e.rs
+//! Optional end-to-end encryption for the one-to-one `network_chat`.
+//!
+//! Both peers send a fresh X25519 public key, derive one ChaCha20-Poly1305
+//! key per direction from the shared secret with HKDF-SHA256, and display a
+//! fingerprint of the two public keys. The exchange is authenticated by the
+//! users comparing that fingerprint out of band: a relay that substituted its
+//! own keys would cause the two windows to show different fingerprints.
+//!
+//! After the handshake every message travels as one line of hex:
+//! an 8-byte big-endian sequence number followed by the ciphertext. The
+//! sequence number is the nonce and must strictly increase, so replayed or
+//! reordered lines are rejected.
+
+use crate::hex;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const HELLO_PREFIX: &str = "E2E-HELLO ";
+const KEY_LABEL_LOW_TO_HIGH: &[u8] = b"socat_chat e2e low->high";
+const KEY_LABEL_HIGH_TO_LOW: &[u8] = b"socat_chat e2e high->low";
+
+#[derive(Debug)]
+pub enum E2eError {
+    Io(io::Error),
+    Handshake(String),
+    Malformed,
+    Replayed,
+    Decrypt,
+}
+
+impl fmt::Display for E2eError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            E2eError::Io(e) => write!(f, "{}", e),
+            E2eError::Handshake(reason) => write!(f, "key exchange failed: {}", reason),
+            E2eError::Malformed => write!(f, "malformed encrypted message"),
+            E2eError::Replayed => write!(f, "replayed or reordered message rejected"),
+            E2eError::Decrypt => write!(f, "message failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for E2eError {}
+
+impl From<io::Error> for E2eError {
+    fn from(e: io::Error) -> Self {
+        E2eError::Io(e)
+    }
+}
+
+/// Keys for one established conversation.
+pub struct Session {
+    pub sealer: Sealer,
+    pub opener: Opener,
+    /// Fingerprint of both public keys, identical on both ends if nobody
+    /// tampered with the exchange.
+    pub fingerprint: String,
+}
+
+/// Encrypts outgoing messages.
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    sequence: u64,
+}
+
+impl Sealer {
+    /// Encrypts `text` into a single line (without the trailing newline).
+    pub fn seal(&mut self, text: &str) -> String {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce(sequence), text.as_bytes())
+            .expect("ChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+        let mut frame = sequence.to_be_bytes().to_vec();
+        frame.extend_from_slice(&ciphertext);
+        hex::encode(&frame)
+    }
+}
+
+/// Decrypts and authenticates incoming messages.
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    next_sequence: u64,
+}
+
+impl Opener {
+    pub fn open(&mut self, line: &str) -> Result<String, E2eError> {
+        let frame = hex::decode(line.trim()).ok_or(E2eError::Malformed)?;
+        if frame.len() < 8 {
+            return Err(E2eError::Malformed);
+        }
+        let (sequence, ciphertext) = frame.split_at(8);
+        let sequence = u64::from_be_bytes(sequence.try_into().unwrap());
+        if sequence < self.next_sequence {
+            return Err(E2eError::Replayed);
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(&nonce(sequence), ciphertext)
+            .map_err(|_| E2eError::Decrypt)?;
+        self.next_sequence = sequence + 1;
+        String::from_utf8(plaintext).map_err(|_| E2eError::Malformed)
+    }
+}
+
+fn nonce(sequence: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[4..].copy_from_slice(&sequence.to_be_bytes());
+    Nonce::from(bytes)
+}
+
+/// Runs the key exchange over `stream`, which must be in blocking mode.
+/// Reads byte by byte so no encrypted data after the handshake is consumed.
+pub fn handshake<S: Read + Write>(stream: &mut S) -> Result<Session, E2eError> {
+    let mut secret_bytes = [0u8; 32];
+    getrandom::getrandom(&mut secret_bytes).map_err(|e| E2eError::Handshake(e.to_string()))?;
+    let secret = StaticSecret::from(secret_bytes);
+    let public = PublicKey::from(&secret);
+
+    writeln!(stream, "{}{}", HELLO_PREFIX, hex::encode(public.as_bytes()))?;
+    stream.flush()?;
+
+    let line = read_line(stream)?;
+    let peer_bytes = line
+        .trim()
+        .strip_prefix(HELLO_PREFIX)
+        .and_then(hex::decode)
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| E2eError::Handshake("peer did not send a valid public key".to_string()))?;
+    let peer = PublicKey::from(peer_bytes);
+    if peer.as_bytes() == public.as_bytes() {
+        return Err(E2eError::Handshake("peer echoed our own key".to_string()));
+    }
+
+    let shared = secret.diffie_hellman(&peer);
+    if !shared.was_contributory() {
+        return Err(E2eError::Handshake("peer sent a low-order key".to_string()));
+    }
+
+    let we_are_low = public.as_bytes() < peer.as_bytes();
+    let (low, high) = if we_are_low { (&public, &peer) } else { (&peer, &public) };
+    let mut transcript = low.as_bytes().to_vec();
+    transcript.extend_from_slice(high.as_bytes());
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&transcript), shared.as_bytes());
+    let low_to_high = derive_cipher(&hkdf, KEY_LABEL_LOW_TO_HIGH);
+    let high_to_low = derive_cipher(&hkdf, KEY_LABEL_HIGH_TO_LOW);
+    let (send, receive) = if we_are_low { (low_to_high, high_to_low) } else { (high_to_low, low_to_high) };
+
+    Ok(Session {
+        sealer: Sealer { cipher: send, sequence: 0 },
+        opener: Opener { cipher: receive, next_sequence: 0 },
+        fingerprint: fingerprint(&transcript),
+    })
+}
+
+fn derive_cipher(hkdf: &Hkdf<Sha256>, label: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    hkdf.expand(label, &mut key).expect("32 bytes is a valid HKDF-SHA256 output length");
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Groups of four hex digits over the first 16 bytes of SHA-256(transcript).
+fn fingerprint(transcript: &[u8]) -> String {
+    let digest = Sha256::digest(transcript);
+    hex::encode(&digest[..16])
+        .as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn read_line<S: Read>(stream: &mut S) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        match stream.read(&mut byte)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during key exchange")),
+            _ if byte[0] == b'\n' => break,
+            _ => line.push(byte[0]),
+        }
+        if line.len() > 256 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "key exchange line too long"));
+        }
+    }
+    String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "key exchange line is not UTF-8"))
+}
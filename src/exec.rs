@@ -0,0 +1,419 @@
+// src/exec.rs
+//! Local programs as chat peers: a REPL, a device shell or a bot script.
+//!
+//! The command runs through the shell; its standard input and output are
+//! the chat, and what it writes to standard error is reported line by line
+//! through [`Exec::on_stderr`]. With a pseudo-terminal (`pty`, Unix only)
+//! programs behave as they would in a terminal, prompts and line buffering
+//! included, but their errors arrive mixed into the output.
+//!
+//! A program that exited can be [restarted](Exec::restart); the chat goes
+//! on with the new process over the same [`Duplex`].
+
+use crate::{cancel::Cancel, direct::Duplex};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How often waiting threads look at their [`Cancel`] or the child.
+const POLL: Duration = Duration::from_millis(50);
+
+/// A command and how to run it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecSpec {
+    /// A shell command line.
+    pub command: String,
+    /// Run the command on a pseudo-terminal instead of pipes.
+    pub pty: bool,
+}
+
+impl ExecSpec {
+    /// Parses `command[,pty]`. Only a trailing `,pty` is taken as an
+    /// option; other commas belong to the command.
+    pub fn parse(spec: &str) -> Result<ExecSpec, String> {
+        let spec = spec.trim();
+        let (command, pty) = match spec.rsplit_once(',') {
+            Some((command, "pty")) => (command.trim(), true),
+            _ => (spec, false),
+        };
+        if command.is_empty() {
+            return Err("EXEC needs a command".to_string());
+        }
+        Ok(ExecSpec { command: command.to_string(), pty })
+    }
+}
+
+impl fmt::Display for ExecSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.command, if self.pty { ",pty" } else { "" })
+    }
+}
+
+/// How a process ended, e.g. `exited with status 1`.
+pub fn describe_exit(status: ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exited with status {}", code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("was killed by signal {}", signal);
+        }
+    }
+    format!("ended ({})", status)
+}
+
+type StderrHook = Box<dyn Fn(String) + Send>;
+type ExitHook = Box<dyn Fn(ExitStatus) + Send>;
+
+/// A running (or exited) command.
+#[derive(Clone)]
+pub struct Exec {
+    shared: Arc<Shared>,
+    cancel: Cancel,
+}
+
+struct Shared {
+    spec: ExecSpec,
+    /// Output of every generation of the child, in order.
+    output: Sender<Vec<u8>>,
+    received: Receiver<Vec<u8>>,
+    process: Mutex<Process>,
+    hooks: Mutex<Hooks>,
+}
+
+struct Process {
+    /// Counts restarts, so threads of a replaced child stay quiet.
+    generation: u64,
+    child: Child,
+    stdin: Option<Box<dyn Write + Send>>,
+    exit: Option<ExitStatus>,
+}
+
+#[derive(Default)]
+struct Hooks {
+    on_stderr: Option<StderrHook>,
+    /// Lines written before anyone asked for them.
+    stderr: Vec<String>,
+    on_exit: Option<ExitHook>,
+}
+
+impl Exec {
+    /// Starts `spec`. The child is killed once `cancel` fires.
+    pub fn spawn(spec: &ExecSpec, cancel: &Cancel) -> io::Result<Exec> {
+        let (output, received) = unbounded();
+        let (process, pipes) = start(spec, 0)?;
+        let shared = Arc::new(Shared {
+            spec: spec.clone(),
+            output,
+            received,
+            process: Mutex::new(process),
+            hooks: Mutex::new(Hooks::default()),
+        });
+        shared.pump(0, pipes);
+        let killed = Arc::clone(&shared);
+        cancel.on_cancel(move || killed.kill());
+        Ok(Exec { shared, cancel: cancel.clone() })
+    }
+
+    pub fn spec(&self) -> &ExecSpec {
+        &self.shared.spec
+    }
+
+    /// The child's process id.
+    pub fn id(&self) -> u32 {
+        self.shared.process.lock().unwrap().child.id()
+    }
+
+    /// How the current child ended, or `None` while it runs.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.shared.process.lock().unwrap().exit
+    }
+
+    /// Called with every line the command writes to standard error,
+    /// starting with those written so far.
+    pub fn on_stderr(&self, hook: impl Fn(String) + Send + 'static) {
+        let mut hooks = self.shared.hooks.lock().unwrap();
+        for line in hooks.stderr.drain(..) {
+            hook(line);
+        }
+        hooks.on_stderr = Some(Box::new(hook));
+    }
+
+    /// Called whenever the command exits on its own or is killed, but not
+    /// for a child replaced by [`restart`](Self::restart). Called right
+    /// away if the current child has exited already.
+    pub fn on_exit(&self, hook: impl Fn(ExitStatus) + Send + 'static) {
+        let exit = self.shared.process.lock().unwrap().exit;
+        let mut hooks = self.shared.hooks.lock().unwrap();
+        if let Some(status) = exit {
+            hook(status);
+        }
+        hooks.on_exit = Some(Box::new(hook));
+    }
+
+    /// Kills the command if it is still running and starts it again.
+    pub fn restart(&self) -> io::Result<()> {
+        let mut process = self.shared.process.lock().unwrap();
+        if process.exit.is_none() {
+            process.kill();
+            let _ = process.child.wait();
+        }
+        let generation = process.generation + 1;
+        let (fresh, pipes) = start(&self.shared.spec, generation)?;
+        *process = fresh;
+        drop(process);
+        println!("Restarted {}", self.shared.spec.command);
+        self.shared.pump(generation, pipes);
+        Ok(())
+    }
+
+    pub fn kill(&self) {
+        self.shared.kill();
+    }
+
+    /// The chat over the command's input and output. Reading carries on
+    /// across restarts and ends only when cancelled.
+    pub fn duplex(&self) -> Duplex {
+        let reader = OutputReader { shared: Arc::clone(&self.shared), cancel: self.cancel.clone(), buffer: Vec::new() };
+        Duplex::new(reader, InputWriter { shared: Arc::clone(&self.shared) })
+    }
+}
+
+/// What a fresh child reads from.
+struct Pipes {
+    stdout: Box<dyn Read + Send>,
+    stderr: Option<Box<dyn Read + Send>>,
+}
+
+/// `command` run by the platform's shell.
+pub(crate) fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    shell
+}
+
+fn start(spec: &ExecSpec, generation: u64) -> io::Result<(Process, Pipes)> {
+    let mut command = shell(&spec.command);
+    let (child, stdin, pipes) = if spec.pty {
+        pty::spawn(&mut command)?
+    } else {
+        // A group of its own, so whatever the command starts goes with it.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdin: Box<dyn Write + Send> = Box::new(child.stdin.take().expect("piped stdin"));
+        let stdout: Box<dyn Read + Send> = Box::new(child.stdout.take().expect("piped stdout"));
+        let stderr: Box<dyn Read + Send> = Box::new(child.stderr.take().expect("piped stderr"));
+        (child, stdin, Pipes { stdout, stderr: Some(stderr) })
+    };
+    println!("Started {} (pid {})", spec.command, child.id());
+    Ok((Process { generation, child, stdin: Some(stdin), exit: None }, pipes))
+}
+
+impl Shared {
+    /// Moves the output of child `generation` to the channel and its errors
+    /// to the hook, and reports its exit.
+    fn pump(self: &Arc<Self>, generation: u64, pipes: Pipes) {
+        if let Some(stderr) = pipes.stderr {
+            let shared = Arc::clone(self);
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    let Ok(line) = line else { break };
+                    let mut hooks = shared.hooks.lock().unwrap();
+                    match hooks.on_stderr.as_ref() {
+                        Some(hook) => hook(line),
+                        None => hooks.stderr.push(line),
+                    }
+                }
+            });
+        }
+        let shared = Arc::clone(self);
+        let mut stdout = pipes.stdout;
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            // A pseudo-terminal reports EIO once the child is gone.
+            while let Ok(len @ 1..) = stdout.read(&mut buf) {
+                if shared.output.send(buf[..len].to_vec()).is_err() {
+                    return;
+                }
+            }
+            shared.wait(generation);
+        });
+    }
+
+    /// Waits for child `generation` to exit and reports it, unless it was
+    /// replaced in the meantime.
+    fn wait(&self, generation: u64) {
+        let status = loop {
+            let mut process = self.process.lock().unwrap();
+            if process.generation != generation || process.exit.is_some() {
+                return;
+            }
+            match process.child.try_wait() {
+                Ok(Some(status)) => {
+                    process.exit = Some(status);
+                    process.stdin = None;
+                    break status;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    println!("Error waiting for {}: {}", self.spec.command, e);
+                    return;
+                }
+            }
+            drop(process);
+            thread::sleep(POLL);
+        };
+        println!("{} {}", self.spec.command, describe_exit(status));
+        if let Some(hook) = self.hooks.lock().unwrap().on_exit.as_ref() {
+            hook(status);
+        }
+    }
+
+    fn kill(&self) {
+        let mut process = self.process.lock().unwrap();
+        if process.exit.is_none() {
+            process.kill();
+        }
+    }
+}
+
+impl Process {
+    /// Kills the child and the rest of its process group, which would
+    /// otherwise keep its output open. Only while it has not been waited
+    /// for, so the id is still its own.
+    fn kill(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: a plain system call on the child's own group.
+        unsafe {
+            libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL);
+        }
+        let _ = self.child.kill();
+    }
+}
+
+struct OutputReader {
+    shared: Arc<Shared>,
+    cancel: Cancel,
+    buffer: Vec<u8>,
+}
+
+impl Read for OutputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer.is_empty() {
+            match self.shared.received.recv_timeout(POLL) {
+                Ok(bytes) => self.buffer = bytes,
+                Err(RecvTimeoutError::Timeout) => self.cancel.check()?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buffer.len());
+        buf[..len].copy_from_slice(&self.buffer[..len]);
+        self.buffer.drain(..len);
+        Ok(len)
+    }
+}
+
+struct InputWriter {
+    shared: Arc<Shared>,
+}
+
+impl InputWriter {
+    fn with_stdin<T>(&self, f: impl FnOnce(&mut Box<dyn Write + Send>) -> io::Result<T>) -> io::Result<T> {
+        let mut process = self.shared.process.lock().unwrap();
+        match process.stdin.as_mut() {
+            Some(stdin) => f(stdin),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "the command is not running")),
+        }
+    }
+}
+
+impl Write for InputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_stdin(|stdin| stdin.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_stdin(|stdin| stdin.flush())
+    }
+}
+
+#[cfg(unix)]
+mod pty {
+    use super::Pipes;
+    use std::{
+        fs::File,
+        io::{self, Read, Write},
+        mem,
+        os::{
+            fd::{AsRawFd, FromRawFd},
+            unix::process::CommandExt,
+        },
+        process::{Child, Command, Stdio},
+        ptr,
+    };
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Starts `command` in a new session with a new pseudo-terminal as its
+    /// controlling terminal and standard input, output and error. Echo is turned off,
+    /// so what we send is not read back as output.
+    pub fn spawn(command: &mut Command) -> io::Result<(Child, Box<dyn Write + Send>, Pipes)> {
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: the out-pointers are valid; no name, settings or size.
+        check(unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), ptr::null()) })?;
+        // SAFETY: openpty handed us both descriptors.
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        // SAFETY: all-zero is a valid termios to be filled in by tcgetattr.
+        let mut settings: libc::termios = unsafe { mem::zeroed() };
+        // SAFETY: `settings` is a termios for the whole call.
+        check(unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut settings) })?;
+        settings.c_lflag &= !libc::ECHO;
+        // SAFETY: as above.
+        check(unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &settings) })?;
+
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: only async-signal-safe calls between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                check(libc::setsid())?;
+                check(libc::ioctl(0, libc::TIOCSCTTY as _, 0))?;
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        let stdout: Box<dyn Read + Send> = Box::new(master.try_clone()?);
+        Ok((child, Box::new(master), Pipes { stdout, stderr: None }))
+    }
+}
+
+#[cfg(not(unix))]
+mod pty {
+    use super::Pipes;
+    use std::{
+        io::{self, Write},
+        process::{Child, Command},
+    };
+
+    pub fn spawn(_command: &mut Command) -> io::Result<(Child, Box<dyn Write + Send>, Pipes)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals need a Unix system"))
+    }
+}
@@ -0,0 +1,116 @@
+// src/heartbeat.rs
+//! Noticing a peer that vanished without closing the connection, e.g. a
+//! suspended laptop or a pulled cable. Both sides ping every
+//! [`Heartbeat::interval`] and answer pings, so a live connection is never
+//! quiet for long; one that stays quiet for [`Heartbeat::timeout`] is given
+//! up. TCP keepalive backs this up in the kernel.
+//!
+//! `multi_chat` sends pings as frames; `network_chat` as the control lines
+//! of [`crate::direct`], and only in sealed chats, see
+//! [`crate::direct::keep_alive`].
+
+use crate::{
+    cli::Args,
+    net::{Keepalive, Stream},
+    protocol::Frame,
+};
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Time between pings; zero turns them off, and with them the timeout.
+    pub interval: Duration,
+    /// How long the peer may stay silent.
+    pub timeout: Duration,
+    pub keepalive: Option<Keepalive>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+            keepalive: Some(Keepalive::default()),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Reads `--ping-interval=SECS`, `--ping-timeout=SECS` and the options of
+    /// [`Keepalive::from_args`].
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let defaults = Heartbeat::default();
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(args.parse_or("ping-interval", defaults.interval.as_secs())?),
+            timeout: Duration::from_secs(args.parse_or("ping-timeout", defaults.timeout.as_secs())?),
+            keepalive: Keepalive::from_args(args)?,
+        };
+        if heartbeat.enabled() && heartbeat.timeout <= heartbeat.interval {
+            return Err("--ping-timeout must be longer than --ping-interval".to_string());
+        }
+        Ok(heartbeat)
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// Prepares a stream that just logged in: reads give up after the
+    /// timeout, and TCP keepalive is set.
+    pub fn apply(&self, stream: &Stream) -> io::Result<()> {
+        stream.set_read_timeout(self.enabled().then_some(self.timeout))?;
+        if let Err(e) = stream.set_keepalive(self.keepalive) {
+            println!("Could not set TCP keepalive: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Pings through `send` every interval, until the returned [`Pinger`]
+    /// is dropped or `send` fails. `None` when pings are off.
+    pub fn start(&self, mut send: impl FnMut(&Frame) -> io::Result<()> + Send + 'static) -> Option<Pinger> {
+        if !self.enabled() {
+            return None;
+        }
+        let (stop, stopped) = bounded::<()>(0);
+        let started = Instant::now();
+        let interval = self.interval;
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(e) = send(&Frame::Ping(started.elapsed().as_millis() as u64)) {
+                    println!("Stopped pinging: {}", e);
+                    return;
+                }
+            }
+        });
+        Some(Pinger { started, _stop: stop })
+    }
+
+    /// Makes the error of a read that timed out say why.
+    pub fn explain(&self, e: io::Error) -> io::Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no sign of life for {:?}", self.timeout),
+            ),
+            _ => e,
+        }
+    }
+}
+
+/// Sends pings until dropped. Each ping carries the milliseconds since the
+/// pinger started, which the pong echoes back.
+pub struct Pinger {
+    started: Instant,
+    _stop: Sender<()>,
+}
+
+impl Pinger {
+    /// The round trip of the ping that `token` came back from.
+    pub fn round_trip(&self, token: u64) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(token))
+    }
+}
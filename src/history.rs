@@ -0,0 +1,173 @@
+// src/history.rs
+//! Bounded message history kept by the `multi_chat` server so late joiners
+//! can be brought up to date.
+
+use crate::protocol::{escape, unescape};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    pub from: String,
+    pub text: String,
+}
+
+impl HistoryEntry {
+    pub fn now(from: &str, text: &str) -> Self {
+        HistoryEntry {
+            timestamp: unix_now(),
+            from: from.to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// Ring buffer of the most recent messages of one room, optionally mirrored
+/// to a file so history survives a server restart. New entries are appended
+/// to the file, which is cut back to the last `capacity` entries on opening
+/// and whenever it has grown to twice that.
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    store: Option<Store>,
+}
+
+/// The file a history is mirrored to.
+struct Store {
+    path: PathBuf,
+    file: File,
+    /// Entries in the file, including those no longer remembered.
+    lines: usize,
+}
+
+impl History {
+    /// In-memory history holding at most `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            store: None,
+        }
+    }
+
+    /// History backed by the file at `path`. The last `capacity` entries
+    /// already in the file are loaded and the rest dropped from it; new
+    /// entries are appended to it.
+    pub fn open(capacity: usize, path: &Path) -> io::Result<Self> {
+        let mut history = History::new(capacity);
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines() {
+                if let Some(entry) = parse_line(&line?) {
+                    history.remember(entry);
+                }
+            }
+        }
+
+        history.compact(path)?;
+        Ok(history)
+    }
+
+    /// Rewrites the file at `path` to hold just the entries remembered,
+    /// through a temporary one like the outbox, and appends to it from then on.
+    fn compact(&mut self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        for entry in &self.entries {
+            write_entry(&mut file, entry)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        self.store = Some(Store { path: path.to_path_buf(), file, lines: self.entries.len() });
+        Ok(())
+    }
+
+    /// Path of the history file for `room` inside `dir`.
+    pub fn room_path(dir: &Path, room: &str) -> PathBuf {
+        let name: String = room
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        dir.join(format!("{}.history", name))
+    }
+
+    /// Records `entry`, evicting the oldest one when the buffer is full.
+    pub fn push(&mut self, entry: HistoryEntry) -> io::Result<()> {
+        let mut full = None;
+        if let Some(store) = self.store.as_mut() {
+            write_entry(&mut store.file, &entry)?;
+            store.file.flush()?;
+            store.lines += 1;
+            if store.lines >= 2 * self.capacity.max(1) {
+                full = Some(store.path.clone());
+            }
+        }
+        self.remember(entry);
+        match full {
+            Some(path) => self.compact(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// The last `n` entries, oldest first.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().skip(self.entries.len().saturating_sub(n))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn remember(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+fn write_entry(file: &mut File, entry: &HistoryEntry) -> io::Result<()> {
+    writeln!(file, "{}\t{}\t{}", entry.timestamp, escape(&entry.from), escape(&entry.text))
+}
+
+fn parse_line(line: &str) -> Option<HistoryEntry> {
+    let mut fields = line.splitn(3, '\t');
+    let timestamp = fields.next()?.parse().ok()?;
+    let from = unescape(fields.next()?);
+    let text = unescape(fields.next()?);
+    Some(HistoryEntry { timestamp, from, text })
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a unix timestamp as `HH:MM` (UTC) for display next to replayed messages.
+pub fn format_clock(timestamp: u64) -> String {
+    let seconds_of_day = timestamp % 86_400;
+    format!("{:02}:{:02}", seconds_of_day / 3600, (seconds_of_day % 3600) / 60)
+}
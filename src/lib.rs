@@ -0,0 +1,38 @@
+// src/lib.rs
+//! Shared pieces used by the chat binaries. Everything except [`dialog`]
+//! is UI-independent.
+
+pub mod address;
+pub mod auth;
+pub mod bot;
+pub mod bridge;
+pub mod cancel;
+pub mod capture;
+pub mod cli;
+pub mod dialog;
+pub mod discovery;
+pub mod client;
+pub mod direct;
+pub mod display;
+pub mod e2e;
+pub mod exec;
+pub mod heartbeat;
+pub mod history;
+pub mod limits;
+pub mod moderation;
+pub mod net;
+pub mod outbox;
+pub mod pipe;
+pub mod presence;
+pub mod protocol;
+pub mod receipts;
+pub mod script;
+pub mod serial;
+pub mod server;
+pub mod settings;
+pub mod state;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
+
+mod hex;
@@ -0,0 +1,196 @@
+// src/limits.rs
+//! What one `multi_chat` client may cost the server: how fast it may send,
+//! how long a line may be, how many connections may come from one address
+//! and from everywhere, and how long the server waits for a client that
+//! stopped reading.
+
+use crate::{auth::AuthError, cli::Args};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Read},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Frames a client may send per second, on average; 0 for no limit.
+    pub messages_per_second: u32,
+    /// Bytes a client may send per second, on average; 0 for no limit.
+    pub bytes_per_second: u64,
+    /// Longest frame accepted, in bytes without the newline.
+    pub max_message: usize,
+    /// Connections open at once from one address; 0 for no limit.
+    pub max_per_address: usize,
+    /// Connections open at once in total; 0 for no limit.
+    pub max_connections: usize,
+    /// How long a write to a client may take before it is dropped.
+    pub send_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            messages_per_second: 10,
+            bytes_per_second: 32 * 1024,
+            max_message: 64 * 1024,
+            max_per_address: 10,
+            max_connections: 200,
+            send_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Limits {
+    /// Reads `--max-rate=MSGS`, `--max-bandwidth=BYTES`, `--max-message=BYTES`,
+    /// `--max-per-ip=N`, `--max-clients=N` and `--send-timeout=SECS`.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let defaults = Limits::default();
+        let limits = Limits {
+            messages_per_second: args.parse_or("max-rate", defaults.messages_per_second)?,
+            bytes_per_second: args.parse_or("max-bandwidth", defaults.bytes_per_second)?,
+            max_message: args.parse_or("max-message", defaults.max_message)?,
+            max_per_address: args.parse_or("max-per-ip", defaults.max_per_address)?,
+            max_connections: args.parse_or("max-clients", defaults.max_connections)?,
+            send_timeout: Duration::from_secs(args.parse_or("send-timeout", defaults.send_timeout.as_secs())?),
+        };
+        if limits.max_message == 0 {
+            return Err("--max-message must be at least 1".to_string());
+        }
+        if limits.send_timeout.is_zero() {
+            return Err("--send-timeout must be at least 1".to_string());
+        }
+        Ok(limits)
+    }
+
+    /// The pair of buckets one connection's frames are taken from.
+    pub fn throttle(&self) -> Throttle {
+        Throttle {
+            messages: TokenBucket::new(f64::from(self.messages_per_second)),
+            bytes: TokenBucket::new(self.bytes_per_second as f64),
+        }
+    }
+}
+
+/// Refills at `rate` per second, holding at most two seconds' worth, so a
+/// short burst goes through and a steady flood is slowed to the rate.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// A full bucket; a `rate` of zero never runs dry.
+    pub fn new(rate: f64) -> Self {
+        TokenBucket { rate, tokens: 2.0 * rate, last: None }
+    }
+
+    /// Takes `amount` at `now` and returns how long the taker should wait
+    /// to stay within the rate; zero while the bucket holds enough.
+    pub fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        if self.rate == 0.0 {
+            return Duration::ZERO;
+        }
+        if let Some(last) = self.last {
+            let refill = now.saturating_duration_since(last).as_secs_f64() * self.rate;
+            self.tokens = (self.tokens + refill).min(2.0 * self.rate);
+        }
+        self.last = Some(now);
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Rate limits of one connection, by frame count and by size.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Throttle {
+    /// Accounts for a frame of `len` bytes read at `now`; returns how long to
+    /// wait before reading the next one.
+    pub fn frame(&mut self, len: usize, now: Instant) -> Duration {
+        self.messages.take(1.0, now).max(self.bytes.take(len as f64, now))
+    }
+}
+
+/// Reads a line like [`BufRead::read_line`], failing with `InvalidData`
+/// instead of reading on once it exceeds `max` bytes without its newline.
+pub fn read_line_limited(reader: &mut impl BufRead, max: usize, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(max as u64 + 1).read_line(line)?;
+    if read > max && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message longer than {} bytes", max),
+        ));
+    }
+    Ok(read)
+}
+
+/// Counts open connections by address. Each admitted one holds a
+/// [`Connection`] until it closes.
+#[derive(Debug, Clone)]
+pub struct Connections {
+    max_per_address: usize,
+    max_total: usize,
+    open: Arc<Mutex<HashMap<Option<IpAddr>, usize>>>,
+}
+
+impl Connections {
+    pub fn new(limits: &Limits) -> Self {
+        Connections {
+            max_per_address: limits.max_per_address,
+            max_total: limits.max_connections,
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Lets a connection from `address` in, unless that makes too many.
+    /// Peers without an address, on a Unix socket, only count towards the
+    /// total: who may connect there is up to the socket file's permissions.
+    pub fn admit(&self, address: Option<IpAddr>) -> Result<Connection, AuthError> {
+        let mut open = self.open.lock().unwrap();
+        if self.max_total > 0 && open.values().sum::<usize>() >= self.max_total {
+            return Err(AuthError::RoomFull);
+        }
+        let count = open.entry(address).or_default();
+        if address.is_some() && self.max_per_address > 0 && *count >= self.max_per_address {
+            return Err(AuthError::TooManyConnections);
+        }
+        *count += 1;
+        Ok(Connection { address, open: Arc::clone(&self.open) })
+    }
+
+    /// Connections open now, from everywhere.
+    pub fn count(&self) -> usize {
+        self.open.lock().unwrap().values().sum()
+    }
+}
+
+/// An admitted connection; dropping it frees its place.
+#[derive(Debug)]
+pub struct Connection {
+    address: Option<IpAddr>,
+    open: Arc<Mutex<HashMap<Option<IpAddr>, usize>>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.address) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.address);
+            }
+        }
+    }
+}
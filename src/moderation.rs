@@ -0,0 +1,353 @@
+// src/moderation.rs
+//! Keeping order in a `multi_chat` room. Operators type slash commands
+//! (`/kick`, `/ban`, `/mute`, `/topic`, `/op`) which the server carries out
+//! and announces. Bans may be saved to a file so they outlast a restart, and
+//! every command carried out goes to an audit log.
+
+use crate::history::unix_now;
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// Who a ban is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    User(String),
+    Address(IpAddr),
+}
+
+impl Target {
+    /// An IP address if `s` is one, a user name otherwise.
+    pub fn parse(s: &str) -> Target {
+        match s.parse() {
+            Ok(ip) => Target::Address(ip),
+            Err(_) => Target::User(s.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::User(name) => f.write_str(name),
+            Target::Address(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Kick { user: String, reason: Option<String> },
+    Ban { target: Target, reason: Option<String> },
+    Unban(Target),
+    Mute(String),
+    Unmute(String),
+    /// Sets the topic; an empty one clears it.
+    Topic(String),
+    Op(String),
+    Deop(String),
+}
+
+impl Command {
+    /// Parses a line as typed, e.g. `/kick bob flooding the room`.
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let Some(rest) = line.trim().strip_prefix('/') else {
+            return Err("commands start with /".to_string());
+        };
+        let (name, args) = split_word(rest);
+        let (first, more) = split_word(args);
+        let misused = || Err(format!("usage: {}", usage(name).unwrap_or_default()));
+        let reason = (!more.is_empty()).then(|| more.to_string());
+        let command = match name {
+            "topic" => return Ok(Command::Topic(args.to_string())),
+            _ if usage(name).is_none() => return Err(format!("unknown command /{}", name)),
+            _ if first.is_empty() => return misused(),
+            "kick" => Command::Kick { user: first.to_string(), reason },
+            "ban" => Command::Ban { target: Target::parse(first), reason },
+            _ if reason.is_some() => return misused(),
+            "unban" => Command::Unban(Target::parse(first)),
+            "mute" => Command::Mute(first.to_string()),
+            "unmute" => Command::Unmute(first.to_string()),
+            "op" => Command::Op(first.to_string()),
+            "deop" => Command::Deop(first.to_string()),
+            _ => unreachable!("every command has a usage"),
+        };
+        Ok(command)
+    }
+
+    /// The user the command acts on, if it is about one.
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Command::Kick { user, .. } | Command::Mute(user) | Command::Unmute(user) => Some(user),
+            Command::Op(user) | Command::Deop(user) => Some(user),
+            Command::Ban { target: Target::User(user), .. } | Command::Unban(Target::User(user)) => Some(user),
+            _ => None,
+        }
+    }
+
+    /// What the room is told once `by` carried the command out.
+    pub fn announcement(&self, by: &str) -> String {
+        let because = |reason: &Option<String>| reason.as_ref().map(|r| format!(" ({})", r)).unwrap_or_default();
+        match self {
+            Command::Kick { user, reason } => format!("{} kicked {}{}", by, user, because(reason)),
+            Command::Ban { target, reason } => format!("{} banned {}{}", by, target, because(reason)),
+            Command::Unban(target) => format!("{} lifted the ban on {}", by, target),
+            Command::Mute(user) => format!("{} muted {}", by, user),
+            Command::Unmute(user) => format!("{} unmuted {}", by, user),
+            Command::Topic(topic) if topic.is_empty() => format!("{} cleared the topic", by),
+            Command::Topic(topic) => format!("{} set the topic: {}", by, topic),
+            Command::Op(user) => format!("{} made {} an operator", by, user),
+            Command::Deop(user) => format!("{} removed {} as an operator", by, user),
+        }
+    }
+}
+
+/// The command as it would be typed.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let with_reason = |f: &mut fmt::Formatter<'_>, reason: &Option<String>| match reason {
+            Some(reason) => write!(f, " {}", reason),
+            None => Ok(()),
+        };
+        match self {
+            Command::Kick { user, reason } => {
+                write!(f, "/kick {}", user)?;
+                with_reason(f, reason)
+            }
+            Command::Ban { target, reason } => {
+                write!(f, "/ban {}", target)?;
+                with_reason(f, reason)
+            }
+            Command::Unban(target) => write!(f, "/unban {}", target),
+            Command::Mute(user) => write!(f, "/mute {}", user),
+            Command::Unmute(user) => write!(f, "/unmute {}", user),
+            Command::Topic(topic) if topic.is_empty() => f.write_str("/topic"),
+            Command::Topic(topic) => write!(f, "/topic {}", topic),
+            Command::Op(user) => write!(f, "/op {}", user),
+            Command::Deop(user) => write!(f, "/deop {}", user),
+        }
+    }
+}
+
+/// How command `name` is used; `None` if there is no such command.
+pub fn usage(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "kick" => "/kick <user> [reason]",
+        "ban" => "/ban <user|ip> [reason]",
+        "unban" => "/unban <user|ip>",
+        "mute" => "/mute <user>",
+        "unmute" => "/unmute <user>",
+        "topic" => "/topic [text]",
+        "op" => "/op <user>",
+        "deop" => "/deop <user>",
+        _ => return None,
+    })
+}
+
+/// Splits off the first word of `s`; both parts come back trimmed.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.trim().split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s.trim(), ""),
+    }
+}
+
+/// Users and addresses kept out of the room. Optionally saved to a file
+/// holding one `user NAME` or `ip ADDRESS` line per ban.
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: Vec<Target>,
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    /// A ban list kept in memory only.
+    pub fn new() -> Self {
+        BanList::default()
+    }
+
+    /// A ban list saved to `path`, starting with the bans already there.
+    /// Lines starting with `#` are ignored.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut list = BanList { bans: Vec::new(), path: Some(path.to_path_buf()) };
+        if !path.exists() {
+            return Ok(list);
+        }
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let ban = match line.split_once(' ') {
+                Some(("user", name)) if !name.trim().is_empty() => Target::User(name.trim().to_string()),
+                Some(("ip", ip)) => ip.trim().parse().map(Target::Address).map_err(|_| malformed(path, number))?,
+                _ => return Err(malformed(path, number)),
+            };
+            list.bans.push(ban);
+        }
+        Ok(list)
+    }
+
+    /// Adds `target`; returns whether it was not banned already.
+    pub fn ban(&mut self, target: Target) -> io::Result<bool> {
+        if self.bans.contains(&target) {
+            return Ok(false);
+        }
+        self.bans.push(target);
+        self.save().map(|_| true)
+    }
+
+    /// Removes `target`; returns whether it was banned.
+    pub fn unban(&mut self, target: &Target) -> io::Result<bool> {
+        let before = self.bans.len();
+        self.bans.retain(|ban| ban != target);
+        if self.bans.len() == before {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    /// True if either the name or the address is banned. Unix socket peers
+    /// have no address, so only their name can be.
+    pub fn is_banned(&self, username: &str, ip: Option<IpAddr>) -> bool {
+        self.bans.iter().any(|ban| match ban {
+            Target::User(name) => name == username,
+            Target::Address(address) => Some(*address) == ip,
+        })
+    }
+
+    pub fn bans(&self) -> &[Target] {
+        &self.bans
+    }
+
+    /// Rewrites the file through a temporary one, like the outbox.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        for ban in &self.bans {
+            match ban {
+                Target::User(name) => writeln!(file, "user {}", name)?,
+                Target::Address(ip) => writeln!(file, "ip {}", ip)?,
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
+
+fn malformed(path: &Path, number: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: malformed ban entry", path.display(), number + 1))
+}
+
+/// Record of the commands carried out, one `timestamp<TAB>operator<TAB>command`
+/// line each with the timestamp in unix seconds. Without a file it is only
+/// printed.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<File>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(AuditLog { file: Some(OpenOptions::new().create(true).append(true).open(path)?) })
+    }
+
+    pub fn record(&mut self, by: &str, command: &Command) -> io::Result<()> {
+        println!("Audit: {} ran {}", by, command);
+        let Some(file) = &mut self.file else { return Ok(()) };
+        writeln!(file, "{}\t{}\t{}", unix_now(), by, command)?;
+        file.flush()
+    }
+}
+
+/// Everything the server enforces besides logins: bans, operators, who is
+/// muted and the topic. Operator rights and mutes last while the server
+/// runs, across reconnects.
+#[derive(Debug, Default)]
+pub struct Moderation {
+    bans: BanList,
+    audit: AuditLog,
+    operators: HashSet<String>,
+    muted: HashSet<String>,
+    topic: Option<String>,
+}
+
+impl Moderation {
+    pub fn new() -> Self {
+        Moderation::default()
+    }
+
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Users who are operators from the start.
+    pub fn with_operators<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.operators.extend(names);
+        self
+    }
+
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    pub fn audit(&mut self) -> &mut AuditLog {
+        &mut self.audit
+    }
+
+    pub fn is_operator(&self, user: &str) -> bool {
+        self.operators.contains(user)
+    }
+
+    /// Grants or takes operator rights; returns whether anything changed.
+    pub fn set_operator(&mut self, user: &str, operator: bool) -> bool {
+        if operator {
+            self.operators.insert(user.to_string())
+        } else {
+            self.operators.remove(user)
+        }
+    }
+
+    pub fn is_muted(&self, user: &str) -> bool {
+        self.muted.contains(user)
+    }
+
+    /// Mutes or unmutes `user`; returns whether anything changed.
+    pub fn set_muted(&mut self, user: &str, muted: bool) -> bool {
+        if muted {
+            self.muted.insert(user.to_string())
+        } else {
+            self.muted.remove(user)
+        }
+    }
+
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
+    /// Sets the topic, or clears it when `topic` is empty.
+    pub fn set_topic(&mut self, topic: &str) {
+        self.topic = (!topic.is_empty()).then(|| topic.to_string());
+    }
+}
@@ -0,0 +1,562 @@
+// src/net.rs
+//! Socket helpers shared by the network binaries: address parsing,
+//! connecting with a timeout, and listening and accepting in a way that can
+//! be cancelled. IPv4 and IPv6 are handled alike, and wherever an address
+//! is taken a `unix:` one works too (see [`crate::unix`]).
+
+#[cfg(unix)]
+use crate::unix::{self, Credentials};
+use crate::{cancel::Cancel, cli::Args};
+use crossbeam_channel::{bounded, unbounded, RecvTimeoutError};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+/// How often blocking waits look at their [`Cancel`].
+const POLL: Duration = Duration::from_millis(50);
+
+/// How long a connection attempt gets before the next address is tried in
+/// parallel (RFC 8305 suggests 250ms).
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Limit for establishing the TCP connection.
+    pub connect: Duration,
+    /// Limit for the login or key exchange that follows.
+    pub handshake: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts { connect: Duration::from_secs(10), handshake: Duration::from_secs(10) }
+    }
+}
+
+impl Timeouts {
+    /// Reads `--connect-timeout=SECS` and `--handshake-timeout=SECS`.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let defaults = Timeouts::default();
+        Ok(Timeouts {
+            connect: Duration::from_secs(args.parse_or("connect-timeout", defaults.connect.as_secs())?),
+            handshake: Duration::from_secs(args.parse_or("handshake-timeout", defaults.handshake.as_secs())?),
+        })
+    }
+}
+
+/// TCP keepalive probing, so the kernel notices a peer that vanished
+/// even while the connection is quiet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Quiet time before the first probe.
+    pub idle: Duration,
+    /// Time between unanswered probes.
+    pub interval: Duration,
+    /// Unanswered probes before the connection is dropped.
+    pub retries: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive { idle: Duration::from_secs(60), interval: Duration::from_secs(10), retries: 5 }
+    }
+}
+
+impl Keepalive {
+    /// Reads `--keepalive=IDLE[,INTERVAL[,RETRIES]]` (seconds) or
+    /// `--no-keepalive`; the defaults apply otherwise.
+    pub fn from_args(args: &Args) -> Result<Option<Self>, String> {
+        if args.flag("no-keepalive") {
+            return Ok(None);
+        }
+        let Some(raw) = args.value("keepalive") else { return Ok(Some(Keepalive::default())) };
+        let invalid = || format!("invalid value for --keepalive: {}", raw);
+        let fields: Vec<u64> = raw.split(',').map(|f| f.trim().parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+        let mut keepalive = Keepalive::default();
+        match fields.as_slice() {
+            [idle, rest @ ..] if rest.len() <= 2 && *idle > 0 => {
+                keepalive.idle = Duration::from_secs(*idle);
+                if let Some(interval) = rest.first() {
+                    keepalive.interval = Duration::from_secs((*interval).max(1));
+                }
+                if let Some(retries) = rest.get(1) {
+                    keepalive.retries = u32::try_from(*retries).map_err(|_| invalid())?.max(1);
+                }
+                Ok(Some(keepalive))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Splits `host:port`. IPv6 literals go in brackets (`[::1]:8080`); the
+/// brackets are removed from the returned host. An empty host or `*`
+/// means every interface when listening.
+pub fn split_host_port(address: &str) -> Result<(&str, u16), String> {
+    let (host, port) = if let Some(rest) = address.strip_prefix('[') {
+        let (host, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("missing ']' in {:?}", address))?;
+        let port = after
+            .strip_prefix(':')
+            .ok_or_else(|| format!("missing port in {:?}, expected [host]:port", address))?;
+        (host, port)
+    } else {
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| format!("missing port in {:?}, expected host:port", address))?;
+        if host.contains(':') {
+            return Err(format!("IPv6 addresses need brackets, e.g. [{}]:{}", host, port));
+        }
+        (host, port)
+    };
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| format!("invalid port {:?} in {:?}", port, address))?;
+    Ok((host, port))
+}
+
+/// Checks that `address` looks like `host:port` or `unix:path` before
+/// anything is bound or resolved, so mistakes get a readable message.
+pub fn validate_address(address: &str) -> Result<(), String> {
+    #[cfg(unix)]
+    if unix::is_unix(address) {
+        return unix::parse(address).map(|_| ());
+    }
+    split_host_port(address).map(|_| ())
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Resolves `address` to every address it stands for, in resolver order.
+/// An empty host or `*` gives the IPv6 and IPv4 wildcard addresses.
+pub fn resolve(address: &str) -> io::Result<Vec<SocketAddr>> {
+    let (host, port) = split_host_port(address).map_err(invalid_input)?;
+    let mut addrs: Vec<SocketAddr> = match host {
+        "" | "*" => vec![
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+        ],
+        host => (host, port).to_socket_addrs()?.collect(),
+    };
+    let mut seen = Vec::new();
+    addrs.retain(|addr| {
+        let new = !seen.contains(addr);
+        seen.push(*addr);
+        new
+    });
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", host)));
+    }
+    Ok(addrs)
+}
+
+/// The address as people expect to read it: IPv4 peers that reached an
+/// IPv6 socket are shown as plain IPv4.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// A connected stream socket of either kind.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    /// Turns TCP keepalive on with these settings, or off. Unix sockets
+    /// have no such thing and are left alone.
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => sys::set_keepalive(stream, keepalive),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    /// The other end, as far as it can be told.
+    pub fn peer(&self) -> Peer {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map(|addr| Peer::Tcp(canonical(addr))).unwrap_or(Peer::Unknown),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Peer::Local(unix::peer_credentials(stream)),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Who a [`Stream`] is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// Across a Unix socket, with the peer's credentials where available.
+    #[cfg(unix)]
+    Local(Option<Credentials>),
+    Unknown,
+}
+
+impl Peer {
+    /// The network address, which only TCP peers have. Address bans, the
+    /// per-address connection limit and login lockouts go by it.
+    pub fn address(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Tcp(addr) => Some(addr.ip()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Peer::Local(Some(credentials)) => write!(f, "local user {}", credentials),
+            #[cfg(unix)]
+            Peer::Local(None) => write!(f, "local socket"),
+            Peer::Unknown => write!(f, "unknown peer"),
+        }
+    }
+}
+
+/// One or more listening sockets for a single address, e.g. both the IPv6
+/// and the IPv4 socket of a host name or of `*:8080`, or a Unix socket.
+pub struct Listener {
+    sockets: Vec<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<unix::Bound>,
+}
+
+impl Listener {
+    /// Binds every address `address` resolves to. Succeeds if at least one
+    /// could be bound; with port 0 they all share the first one's port.
+    pub fn bind(address: &str) -> io::Result<Listener> {
+        #[cfg(unix)]
+        if unix::is_unix(address) {
+            let bound = unix::bind(address)?;
+            bound.listener.set_nonblocking(true)?;
+            return Ok(Listener { sockets: Vec::new(), unix: Some(bound) });
+        }
+        let mut sockets: Vec<TcpListener> = Vec::new();
+        let mut last_error = None;
+        for mut addr in resolve(address)? {
+            if let Some(first) = sockets.first() {
+                addr.set_port(first.local_addr()?.port());
+            }
+            match TcpListener::bind(addr) {
+                Ok(socket) => {
+                    socket.set_nonblocking(true)?;
+                    sockets.push(socket);
+                }
+                // A dual-stack IPv6 socket already covers IPv4, so the
+                // second bind failing is expected.
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse && !sockets.is_empty() => {}
+                Err(e) if !sockets.is_empty() => println!("Not listening on {}: {}", addr, e),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        if sockets.is_empty() {
+            return Err(last_error.unwrap_or_else(|| io::Error::other("nothing to bind")));
+        }
+        Ok(Listener {
+            sockets,
+            #[cfg(unix)]
+            unix: None,
+        })
+    }
+
+    /// The TCP addresses listened on; none for a Unix socket.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets.iter().filter_map(|socket| socket.local_addr().ok()).collect()
+    }
+
+    /// All local addresses, comma separated, for status messages.
+    pub fn describe(&self) -> String {
+        #[cfg(unix)]
+        if let Some(bound) = &self.unix {
+            return bound.address.to_string();
+        }
+        self.local_addrs().iter().map(|addr| addr.to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    /// A pending connection on any of the sockets, if there is one. The
+    /// returned stream is blocking.
+    pub fn try_accept(&self) -> io::Result<Option<(Stream, Peer)>> {
+        #[cfg(unix)]
+        if let Some(bound) = &self.unix {
+            return match bound.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    let stream = Stream::Unix(stream);
+                    let peer = stream.peer();
+                    Ok(Some((stream, peer)))
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+        for socket in &self.sockets {
+            match socket.accept() {
+                Ok((stream, addr)) => {
+                    stream.set_nonblocking(false)?;
+                    return Ok(Some((Stream::Tcp(stream), Peer::Tcp(canonical(addr)))));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for one connection until `cancel` fires.
+    pub fn accept(&self, cancel: &Cancel) -> io::Result<(Stream, Peer)> {
+        loop {
+            cancel.check()?;
+            match self.try_accept()? {
+                Some(accepted) => return Ok(accepted),
+                None => thread::sleep(POLL),
+            }
+        }
+    }
+}
+
+/// Connects to `address`, trying every address it resolves to in the
+/// happy-eyeballs fashion: attempts start [`ATTEMPT_DELAY`] apart,
+/// alternating between IPv6 and IPv4, and the first to succeed wins. Each
+/// attempt gives up after `timeout`; the whole thing as soon as `cancel`
+/// fires.
+pub fn connect(address: &str, timeout: Duration, cancel: &Cancel) -> io::Result<TcpStream> {
+    if let ("" | "*", _) = split_host_port(address).map_err(invalid_input)? {
+        return Err(invalid_input(format!("no host to connect to in {:?}", address)));
+    }
+    let address = address.to_string();
+    let (done, result) = bounded(1);
+    // Resolving and connecting cannot be interrupted, so they run on their
+    // own thread, which is simply abandoned when cancelled.
+    thread::spawn(move || {
+        let _ = done.send(resolve(&address).and_then(|addrs| connect_to(addrs, timeout)));
+    });
+    loop {
+        cancel.check()?;
+        match result.recv_timeout(POLL) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("connection attempt failed"))
+            }
+        }
+    }
+}
+
+/// Like [`connect`], but `address` may also be a `unix:` one.
+pub fn dial(address: &str, timeout: Duration, cancel: &Cancel) -> io::Result<Stream> {
+    cancel.check()?;
+    #[cfg(unix)]
+    if unix::is_unix(address) {
+        return unix::connect(address).map(Stream::Unix);
+    }
+    connect(address, timeout, cancel).map(Stream::Tcp)
+}
+
+/// The happy-eyeballs part of [`connect`], for addresses already resolved.
+pub fn connect_to(addrs: Vec<SocketAddr>, timeout: Duration) -> io::Result<TcpStream> {
+    let (finished, results) = unbounded();
+    let mut pending = 0;
+    let mut last_error = None;
+    let mut handle = |result: io::Result<TcpStream>, pending: &mut usize| {
+        *pending -= 1;
+        match result {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                last_error = Some(e);
+                None
+            }
+        }
+    };
+
+    for addr in interleave_families(addrs) {
+        let finished = finished.clone();
+        thread::spawn(move || {
+            let _ = finished.send(TcpStream::connect_timeout(&addr, timeout));
+        });
+        pending += 1;
+        // Give this attempt a head start; a quick failure starts the next
+        // one right away.
+        if let Ok(result) = results.recv_timeout(ATTEMPT_DELAY) {
+            if let Some(stream) = handle(result, &mut pending) {
+                return Ok(stream);
+            }
+        }
+    }
+    while pending > 0 {
+        match results.recv() {
+            Ok(result) => {
+                if let Some(stream) = handle(result, &mut pending) {
+                    return Ok(stream);
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
+
+/// Reorders addresses to alternate between families, starting with the
+/// family of the first one, as RFC 8305 recommends.
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_v6 = addrs.first().is_some_and(|addr| addr.is_ipv6());
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|addr| addr.is_ipv6() == first_is_v6);
+    preferred.reverse();
+    other.reverse();
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Socket options the standard library does not offer.
+#[cfg(unix)]
+mod sys {
+    use super::Keepalive;
+    use std::{io, mem, net::TcpStream, os::fd::AsRawFd};
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const KEEPALIVE_IDLE: libc::c_int = libc::TCP_KEEPALIVE;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    const KEEPALIVE_IDLE: libc::c_int = libc::TCP_KEEPIDLE;
+
+    fn set_option(stream: &TcpStream, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+        let len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `value` is a c_int of `len` bytes that outlives the call.
+        let result = unsafe { libc::setsockopt(stream.as_raw_fd(), level, name, &value as *const _ as *const _, len) };
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn seconds(duration: std::time::Duration) -> libc::c_int {
+        duration.as_secs().clamp(1, libc::c_int::MAX as u64) as libc::c_int
+    }
+
+    pub fn set_keepalive(stream: &TcpStream, keepalive: Option<Keepalive>) -> io::Result<()> {
+        let Some(keepalive) = keepalive else {
+            return set_option(stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 0);
+        };
+        set_option(stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+        set_option(stream, libc::IPPROTO_TCP, KEEPALIVE_IDLE, seconds(keepalive.idle))?;
+        set_option(stream, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, seconds(keepalive.interval))?;
+        let retries = keepalive.retries.min(libc::c_int::MAX as u32) as libc::c_int;
+        set_option(stream, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, retries)
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::Keepalive;
+    use std::{io, net::TcpStream};
+
+    pub fn set_keepalive(_stream: &TcpStream, keepalive: Option<Keepalive>) -> io::Result<()> {
+        match keepalive {
+            None => Ok(()),
+            Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "TCP keepalive settings need a Unix system")),
+        }
+    }
+}
+
+/// Maps the error a read timeout produces to a readable one.
+pub fn timed_out(what: &str, e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what))
+        }
+        _ => e,
+    }
+}
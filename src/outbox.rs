@@ -0,0 +1,90 @@
+// src/outbox.rs
+//! Messages on their way out of `multi_chat`. Each post stays queued until
+//! the server acknowledges it, so whatever was typed while offline, or was
+//! in flight when the connection dropped, can be sent again in order after
+//! a reconnect. Optionally the queue is saved to a file to survive a restart.
+
+use crate::protocol::{escape, unescape};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Queued {
+    pub id: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: Vec<Queued>,
+    path: Option<PathBuf>,
+}
+
+impl Outbox {
+    /// An outbox kept in memory only.
+    pub fn new() -> Self {
+        Outbox::default()
+    }
+
+    /// An outbox saved to `path`, starting with whatever an earlier run left
+    /// there. The file holds one `id<TAB>text` line per message.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut outbox = Outbox { queue: Vec::new(), path: Some(path.to_path_buf()) };
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                let Some((id, text)) = line.split_once('\t') else { continue };
+                match id.parse() {
+                    Ok(id) => outbox.queue.push(Queued { id, text: unescape(text) }),
+                    Err(_) => println!("Skipping bad outbox line: {}", line),
+                }
+            }
+        }
+        Ok(outbox)
+    }
+
+    /// Queues post `id`, after everything already queued.
+    pub fn push(&mut self, id: u64, text: &str) -> io::Result<()> {
+        self.queue.push(Queued { id, text: text.to_string() });
+        self.save()
+    }
+
+    /// The server acknowledged post `id`; returns whether it was queued.
+    pub fn acknowledged(&mut self, id: u64) -> io::Result<bool> {
+        let before = self.queue.len();
+        self.queue.retain(|queued| queued.id != id);
+        if self.queue.len() == before {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    /// Everything not acknowledged yet, oldest first.
+    pub fn queued(&self) -> &[Queued] {
+        &self.queue
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Rewrites the file through a temporary one, so a crash leaves either
+    /// the old queue or the new one.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        for queued in &self.queue {
+            writeln!(file, "{}\t{}", queued.id, escape(&queued.text))?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
@@ -0,0 +1,94 @@
+// src/pipe.rs
+//! Chat over a pair of named pipes (FIFOs), as used by `simple_chat`.
+//!
+//! The sender opens the pipe, writes one message and closes it again, so
+//! each open on the reading side yields whatever was written until the
+//! writer closed.
+
+use crate::direct::Duplex;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Writes `message` as one line to the pipe at `path`. Blocks until a
+/// reader has the pipe open.
+pub fn send(path: &Path, message: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    writeln!(file, "{}", message)?;
+    file.flush()
+}
+
+/// Opens the pipe at `path` and reads until every writer has closed it.
+/// Returns the lines received, without their line endings.
+pub fn receive(path: &Path) -> io::Result<Vec<String>> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// Parses `read_pipe,write_pipe`, the two FIFOs of one side of a chat.
+pub fn parse_pair(spec: &str) -> Result<(PathBuf, PathBuf), String> {
+    match spec.split_once(',') {
+        Some((read, write)) if !read.trim().is_empty() && !write.trim().is_empty() => {
+            Ok((PathBuf::from(read.trim()), PathBuf::from(write.trim())))
+        }
+        _ => Err(format!("expected READ_PIPE,WRITE_PIPE, got {:?}", spec)),
+    }
+}
+
+/// Streams over a FIFO pair in the same open-write-close fashion as
+/// [`send`] and [`receive`], so either end can be `simple_chat`.
+pub fn open_pair(read_path: &Path, write_path: &Path) -> Duplex {
+    Duplex::new(
+        FifoReader { path: read_path.to_path_buf(), file: None },
+        FifoWriter { path: write_path.to_path_buf(), pending: Vec::new() },
+    )
+}
+
+/// Reads a FIFO across writers: when one writer closes, waits for the next.
+struct FifoReader {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Read for FifoReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let file = match self.file.as_mut() {
+                Some(file) => file,
+                None => self.file.insert(OpenOptions::new().read(true).open(&self.path)?),
+            };
+            match file.read(buf)? {
+                0 => self.file = None,
+                n => return Ok(n),
+            }
+        }
+    }
+}
+
+/// Collects writes and delivers them on flush, opening the FIFO only for
+/// that moment.
+struct FifoWriter {
+    path: PathBuf,
+    pending: Vec<u8>,
+}
+
+impl Write for FifoWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.write_all(&self.pending)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+// src/presence.rs
+//! Who is around in a `multi_chat` room: typing notifications, idle
+//! status and the roster shown next to the log. Everything here is driven
+//! by the caller's clock, so the window only has to feed it events and
+//! tick it now and then.
+
+use crate::protocol::Presence;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// Typing stops counting after this long without a keystroke.
+pub const TYPING_PAUSE: Duration = Duration::from_secs(3);
+
+/// While typing goes on, peers are reminded this often, so a notice whose
+/// end got lost does not stay up forever.
+pub const TYPING_REFRESH: Duration = Duration::from_secs(5);
+
+/// A typing notice not refreshed for this long is dropped.
+pub const TYPING_EXPIRY: Duration = Duration::from_secs(12);
+
+/// How long without input before we count as idle.
+pub const IDLE_AFTER: Duration = Duration::from_secs(300);
+
+/// Turns edits of the message input into as few typing notifications as
+/// possible. Each method returns the notification to send, if any.
+#[derive(Debug, Default)]
+pub struct TypingNotifier {
+    /// When we last said we were typing, while we are.
+    announced: Option<Instant>,
+    last_edit: Option<Instant>,
+}
+
+impl TypingNotifier {
+    /// The input changed; `empty` if it was cleared.
+    pub fn edited(&mut self, empty: bool, now: Instant) -> Option<bool> {
+        if empty {
+            return self.stop();
+        }
+        self.last_edit = Some(now);
+        match self.announced {
+            Some(announced) if now.duration_since(announced) < TYPING_REFRESH => None,
+            _ => {
+                self.announced = Some(now);
+                Some(true)
+            }
+        }
+    }
+
+    /// The message was sent.
+    pub fn sent(&mut self) -> Option<bool> {
+        self.stop()
+    }
+
+    /// Call every second or so; stops typing after a pause.
+    pub fn tick(&mut self, now: Instant) -> Option<bool> {
+        match self.last_edit {
+            Some(last_edit) if now.duration_since(last_edit) >= TYPING_PAUSE => self.stop(),
+            _ => None,
+        }
+    }
+
+    fn stop(&mut self) -> Option<bool> {
+        self.last_edit = None;
+        self.announced.take().map(|_| false)
+    }
+}
+
+/// Decides when we are idle from the time of the last input.
+#[derive(Debug)]
+pub struct IdleTimer {
+    after: Duration,
+    presence: Presence,
+}
+
+impl IdleTimer {
+    pub fn new(after: Duration) -> Self {
+        IdleTimer { after, presence: Presence::Active }
+    }
+
+    /// Our presence if it changed since the last call.
+    pub fn update(&mut self, last_input: Instant, now: Instant) -> Option<Presence> {
+        let presence = if now.duration_since(last_input) >= self.after { Presence::Idle } else { Presence::Active };
+        (presence != self.presence).then(|| {
+            self.presence = presence;
+            presence
+        })
+    }
+}
+
+/// The room's users with their presence and who is typing.
+#[derive(Debug, Default)]
+pub struct Roster {
+    users: Vec<String>,
+    idle: HashSet<String>,
+    /// Who is typing, since when it was last said.
+    typing: HashMap<String, Instant>,
+}
+
+impl Roster {
+    /// A new user list; forgets about anyone who left.
+    pub fn set_users(&mut self, users: Vec<String>) {
+        self.typing.retain(|user, _| users.contains(user));
+        self.users = users;
+    }
+
+    pub fn users(&self) -> &[String] {
+        &self.users
+    }
+
+    pub fn set_presence(&mut self, user: &str, presence: Presence) {
+        match presence {
+            Presence::Active => self.idle.remove(user),
+            Presence::Idle => self.idle.insert(user.to_string()),
+        };
+    }
+
+    pub fn set_typing(&mut self, user: &str, typing: bool, now: Instant) {
+        if typing {
+            self.typing.insert(user.to_string(), now);
+        } else {
+            self.typing.remove(user);
+        }
+    }
+
+    /// A message from `user` ends their typing.
+    pub fn spoke(&mut self, user: &str) {
+        self.typing.remove(user);
+    }
+
+    /// Drops stale typing notices; true if any were.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let before = self.typing.len();
+        self.typing.retain(|_, since| now.duration_since(*since) < TYPING_EXPIRY);
+        self.typing.len() != before
+    }
+
+    /// How `user` is shown in the user list.
+    pub fn label(&self, user: &str) -> String {
+        if self.typing.contains_key(user) {
+            format!("{} (typing…)", user)
+        } else if self.idle.contains(user) {
+            format!("{} (idle)", user)
+        } else {
+            user.to_string()
+        }
+    }
+
+    /// E.g. "Alice is typing…", or empty when nobody is.
+    pub fn typing_line(&self) -> String {
+        let mut names: Vec<&str> = self
+            .users
+            .iter()
+            .filter(|user| self.typing.contains_key(*user))
+            .map(String::as_str)
+            .collect();
+        names.sort();
+        match names.as_slice() {
+            [] => String::new(),
+            [one] => format!("{} is typing…", one),
+            [first, second] => format!("{} and {} are typing…", first, second),
+            [first, second, third] => format!("{}, {} and {} are typing…", first, second, third),
+            _ => "Several people are typing…".to_string(),
+        }
+    }
+}
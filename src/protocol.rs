@@ -0,0 +1,222 @@
+// src/protocol.rs
+//! Line-based wire format spoken between the `multi_chat` server and its clients.
+//!
+//! Every frame is a single line: a kind tag followed by tab-separated fields.
+//! Tabs, newlines and backslashes inside fields are escaped so a field can
+//! never break the framing.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// First frame sent by a client: the name it wants and an optional password.
+    Hello { username: String, password: String },
+    /// The server accepted the `Hello`.
+    Welcome,
+    /// The server refused the `Hello`; it closes the connection afterwards.
+    Rejected(String),
+    /// A chat message from `from`.
+    Chat { from: String, text: String },
+    /// A message replayed from the server's history, `timestamp` in unix seconds.
+    History { timestamp: u64, from: String, text: String },
+    /// Informational text generated by the server (joins, leaves, ...).
+    System(String),
+    /// The full list of users currently in the room.
+    Users(Vec<String>),
+    /// `from` started or stopped typing. Clients send their own name; the
+    /// server relays it with the name the sender logged in with.
+    Typing { from: String, typing: bool },
+    /// `user` became active or idle, relayed like [`Frame::Typing`].
+    Presence { user: String, presence: Presence },
+    /// A chat message its author wants receipts for; `id` is chosen by the
+    /// author and unique among their messages. Relayed like [`Frame::Typing`].
+    Post { id: u64, from: String, text: String },
+    /// Server to author: the post `id` reached the room.
+    Ack { id: u64 },
+    /// A post was read. Clients name the post's author in `user`; the
+    /// server passes it on to that author with the reader's name instead.
+    Read { id: u64, user: String },
+    /// Asks the other side for a [`Frame::Pong`] with the same token. Either
+    /// side may send it.
+    Ping(u64),
+    Pong(u64),
+    /// Client to server: a slash command such as `/kick bob`, as typed.
+    Command(String),
+    /// Server to client: why it is being disconnected by an operator.
+    Kicked(String),
+}
+
+/// Whether someone is at their keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Presence {
+    #[default]
+    Active,
+    /// Nothing was typed or clicked for a while.
+    Idle,
+}
+
+impl Presence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Presence::Active => "active",
+            Presence::Idle => "idle",
+        }
+    }
+}
+
+impl std::str::FromStr for Presence {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Presence::Active),
+            "idle" => Ok(Presence::Idle),
+            _ => Err(ProtocolError::InvalidField("presence")),
+        }
+    }
+}
+
+impl Frame {
+    /// Encodes the frame as a single line, without the trailing newline.
+    pub fn encode(&self) -> String {
+        match self {
+            Frame::Hello { username, password } => {
+                format!("HELLO\t{}\t{}", escape(username), escape(password))
+            }
+            Frame::Welcome => "WELCOME".to_string(),
+            Frame::Rejected(reason) => format!("REJECT\t{}", escape(reason)),
+            Frame::Chat { from, text } => format!("MSG\t{}\t{}", escape(from), escape(text)),
+            Frame::History { timestamp, from, text } => {
+                format!("HIST\t{}\t{}\t{}", timestamp, escape(from), escape(text))
+            }
+            Frame::System(text) => format!("SYS\t{}", escape(text)),
+            Frame::Users(users) => {
+                let fields: Vec<String> = users.iter().map(|u| escape(u)).collect();
+                format!("USERS\t{}", fields.join("\t"))
+            }
+            Frame::Typing { from, typing } => format!("TYPING\t{}\t{}", escape(from), u8::from(*typing)),
+            Frame::Presence { user, presence } => format!("PRESENCE\t{}\t{}", escape(user), presence.as_str()),
+            Frame::Post { id, from, text } => format!("POST\t{}\t{}\t{}", id, escape(from), escape(text)),
+            Frame::Ack { id } => format!("ACK\t{}", id),
+            Frame::Read { id, user } => format!("READ\t{}\t{}", id, escape(user)),
+            Frame::Ping(token) => format!("PING\t{}", token),
+            Frame::Pong(token) => format!("PONG\t{}", token),
+            Frame::Command(line) => format!("CMD\t{}", escape(line)),
+            Frame::Kicked(reason) => format!("KICKED\t{}", escape(reason)),
+        }
+    }
+
+    /// Parses a single line produced by [`Frame::encode`].
+    pub fn decode(line: &str) -> Result<Frame, ProtocolError> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let mut fields = line.split('\t');
+        let kind = fields.next().unwrap_or_default();
+        let mut next = |name: &'static str| {
+            fields
+                .next()
+                .map(unescape)
+                .ok_or(ProtocolError::MissingField(name))
+        };
+
+        match kind {
+            "HELLO" => Ok(Frame::Hello { username: next("username")?, password: next("password")? }),
+            "WELCOME" => Ok(Frame::Welcome),
+            "REJECT" => Ok(Frame::Rejected(next("reason")?)),
+            "MSG" => Ok(Frame::Chat { from: next("from")?, text: next("text")? }),
+            "HIST" => {
+                let timestamp = next("timestamp")?
+                    .parse()
+                    .map_err(|_| ProtocolError::InvalidField("timestamp"))?;
+                Ok(Frame::History { timestamp, from: next("from")?, text: next("text")? })
+            }
+            "SYS" => Ok(Frame::System(next("text")?)),
+            "USERS" => Ok(Frame::Users(
+                line.split('\t').skip(1).filter(|u| !u.is_empty()).map(unescape).collect(),
+            )),
+            "TYPING" => {
+                let from = next("from")?;
+                let typing = match next("typing")?.as_str() {
+                    "1" => true,
+                    "0" => false,
+                    _ => return Err(ProtocolError::InvalidField("typing")),
+                };
+                Ok(Frame::Typing { from, typing })
+            }
+            "PRESENCE" => Ok(Frame::Presence { user: next("user")?, presence: next("presence")?.parse()? }),
+            "POST" => Ok(Frame::Post { id: parse_id(next("id")?)?, from: next("from")?, text: next("text")? }),
+            "ACK" => Ok(Frame::Ack { id: parse_id(next("id")?)? }),
+            "READ" => Ok(Frame::Read { id: parse_id(next("id")?)?, user: next("user")? }),
+            "PING" => Ok(Frame::Ping(parse_token(next("token")?)?)),
+            "PONG" => Ok(Frame::Pong(parse_token(next("token")?)?)),
+            "CMD" => Ok(Frame::Command(next("command")?)),
+            "KICKED" => Ok(Frame::Kicked(next("reason")?)),
+            other => Err(ProtocolError::UnknownKind(other.to_string())),
+        }
+    }
+}
+
+fn parse_id(field: String) -> Result<u64, ProtocolError> {
+    field.parse().map_err(|_| ProtocolError::InvalidField("id"))
+}
+
+fn parse_token(field: String) -> Result<u64, ProtocolError> {
+    field.parse().map_err(|_| ProtocolError::InvalidField("token"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    UnknownKind(String),
+    MissingField(&'static str),
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::UnknownKind(kind) => write!(f, "unknown frame kind '{}'", kind),
+            ProtocolError::MissingField(name) => write!(f, "missing field '{}'", name),
+            ProtocolError::InvalidField(name) => write!(f, "invalid field '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Escapes backslashes, tabs and line breaks so `field` fits in one frame field.
+pub fn escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reverses [`escape`]. Unknown escape sequences are kept verbatim.
+pub fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
@@ -0,0 +1,177 @@
+// src/receipts.rs
+//! Delivery and read receipts in `multi_chat`. [`Receipts`] hands out the
+//! ids of our posts, keeps the marker after each of them in the message log
+//! up to date, and holds on to other people's posts until we have seen them.
+//!
+//! The log is only ever appended to, so a marker is found again by its byte
+//! offset; changing one shifts the markers after it.
+
+use std::{
+    collections::BTreeMap,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How far one of our posts got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// Waiting for the connection to come back.
+    Queued,
+    /// Written to the connection, not acknowledged yet.
+    Pending,
+    /// The server passed it on to the room.
+    Delivered,
+    /// Read by these users, in the order they said so.
+    Read(Vec<String>),
+    /// The connection was lost before the server acknowledged it.
+    Unacknowledged,
+}
+
+impl Delivery {
+    /// What is shown after the message.
+    pub fn marker(&self) -> String {
+        match self {
+            Delivery::Queued => "… queued".to_string(),
+            Delivery::Pending => "…".to_string(),
+            Delivery::Delivered => "✓".to_string(),
+            Delivery::Read(readers) => format!("✓✓ {}", readers.join(", ")),
+            Delivery::Unacknowledged => "✗ not delivered".to_string(),
+        }
+    }
+}
+
+/// Replace bytes `start..end` of the log with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    delivery: Delivery,
+    /// Where the marker starts in the log, and its length in bytes.
+    start: usize,
+    len: usize,
+}
+
+#[derive(Debug)]
+pub struct Receipts {
+    next_id: u64,
+    sent: BTreeMap<u64, Tracked>,
+    /// Posts of others not seen yet: id, author and when it arrived.
+    unread: Vec<(u64, String, Instant)>,
+}
+
+impl Default for Receipts {
+    fn default() -> Self {
+        Receipts::new()
+    }
+}
+
+impl Receipts {
+    /// Ids start at the current time in milliseconds, so a restarted window
+    /// does not reuse the ids of its earlier posts.
+    pub fn new() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Receipts { next_id: now.as_millis() as u64, sent: BTreeMap::new(), unread: Vec::new() }
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Adds the pending marker to `block`, our post `id` as formatted for
+    /// the log, which is about to be appended at byte `at`. Returns the text
+    /// to append.
+    pub fn track(&mut self, id: u64, block: &str, at: usize) -> String {
+        self.track_as(id, block, at, Delivery::Pending)
+    }
+
+    /// Like [`Receipts::track`], for a post that could not be sent yet.
+    pub fn track_queued(&mut self, id: u64, block: &str, at: usize) -> String {
+        self.track_as(id, block, at, Delivery::Queued)
+    }
+
+    fn track_as(&mut self, id: u64, block: &str, at: usize, delivery: Delivery) -> String {
+        let line = block.strip_suffix('\n').unwrap_or(block);
+        let marker = delivery.marker();
+        let tracked = Tracked { delivery, start: at + line.len() + 1, len: marker.len() };
+        self.sent.insert(id, tracked);
+        format!("{} {}\n", line, marker)
+    }
+
+    pub fn delivery(&self, id: u64) -> Option<&Delivery> {
+        self.sent.get(&id).map(|tracked| &tracked.delivery)
+    }
+
+    /// The server acknowledged post `id`.
+    pub fn delivered(&mut self, id: u64) -> Option<Edit> {
+        match self.delivery(id)? {
+            Delivery::Queued | Delivery::Pending | Delivery::Unacknowledged => self.update(id, Delivery::Delivered),
+            _ => None,
+        }
+    }
+
+    /// Post `id` was written to the connection after waiting for it, or
+    /// again after a reconnect.
+    pub fn resent(&mut self, id: u64) -> Option<Edit> {
+        match self.delivery(id)? {
+            Delivery::Queued | Delivery::Unacknowledged => self.update(id, Delivery::Pending),
+            _ => None,
+        }
+    }
+
+    /// `by` read post `id`. Reading implies delivery, so this also covers a
+    /// lost acknowledgement.
+    pub fn read(&mut self, id: u64, by: &str) -> Option<Edit> {
+        let mut readers = match self.delivery(id)? {
+            Delivery::Read(readers) if readers.iter().any(|reader| reader == by) => return None,
+            Delivery::Read(readers) => readers.clone(),
+            _ => Vec::new(),
+        };
+        readers.push(by.to_string());
+        self.update(id, Delivery::Read(readers))
+    }
+
+    /// The connection was lost: flags every post still pending.
+    pub fn disconnected(&mut self) -> Vec<Edit> {
+        let pending: Vec<u64> = self
+            .sent
+            .iter()
+            .filter(|(_, tracked)| tracked.delivery == Delivery::Pending)
+            .map(|(id, _)| *id)
+            .collect();
+        pending.into_iter().filter_map(|id| self.update(id, Delivery::Unacknowledged)).collect()
+    }
+
+    /// Someone else's post arrived at `now`.
+    pub fn received(&mut self, id: u64, from: &str, now: Instant) {
+        self.unread.push((id, from.to_string(), now));
+    }
+
+    /// The posts that arrived before `last_input`, which we take to mean
+    /// they were seen, as (id, author). They are not returned again.
+    pub fn seen(&mut self, last_input: Instant) -> Vec<(u64, String)> {
+        let (seen, unread): (Vec<_>, Vec<_>) =
+            self.unread.drain(..).partition(|(_, _, arrived)| *arrived < last_input);
+        self.unread = unread;
+        seen.into_iter().map(|(id, from, _)| (id, from)).collect()
+    }
+
+    fn update(&mut self, id: u64, delivery: Delivery) -> Option<Edit> {
+        let text = delivery.marker();
+        let tracked = self.sent.get_mut(&id)?;
+        let edit = Edit { start: tracked.start, end: tracked.start + tracked.len, text };
+        let old_len = tracked.len;
+        tracked.len = edit.text.len();
+        tracked.delivery = delivery;
+        for other in self.sent.values_mut() {
+            if other.start > edit.start {
+                other.start = other.start + edit.text.len() - old_len;
+            }
+        }
+        Some(edit)
+    }
+}
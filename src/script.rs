@@ -0,0 +1,504 @@
+// src/script.rs
+//! Expect-style automation scripts run against an endpoint by
+//! `chat_script`, e.g. to log in to a device on a serial port or to test
+//! a chat server:
+//!
+//! ```text
+//! # Wake the modem up and check it answers.
+//! eol cr
+//! timeout 2s
+//! loop 3
+//!     send "AT"
+//!     expect "OK" fail "ERROR"
+//! end
+//! send "ATI"
+//! expect /Model: (\w+)/ timeout 5s
+//! sleep 500ms
+//! ```
+//!
+//! - `send "text"` writes the text and the line ending.
+//! - `expect "text"` or `expect /regex/` waits until what was received
+//!   since the last match contains it. `timeout 5s` changes how long it
+//!   waits, and `fail "text"` (or `/regex/`) gives up as soon as that
+//!   shows up first.
+//! - `sleep 500ms` pauses; durations are in `ms`, `s` or `m`.
+//! - `timeout 2s` sets how long later expects wait (default 10s).
+//! - `eol lf|crlf|cr|none` sets the line ending `send` adds (default lf).
+//! - `loop N` ... `end` repeats N times; a bare `loop` repeats until an
+//!   expect fails.
+//!
+//! Strings understand `\r`, `\n`, `\t`, `\\`, `\"` and `\xNN`; `#` starts
+//! a comment outside of them.
+
+use crate::{bridge::LineEnding, cancel::Cancel};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use regex::bytes::Regex;
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long an `expect` waits unless the script says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Text(Vec<u8>),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Where the first match ends in `data`, if there is one.
+    fn find_end(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Pattern::Text(text) if text.is_empty() => Some(0),
+            Pattern::Text(text) => data
+                .windows(text.len())
+                .position(|window| window == text.as_slice())
+                .map(|start| start + text.len()),
+            Pattern::Regex(regex) => regex.find(data).map(|m| m.end()),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Text(text) => write!(f, "{:?}", String::from_utf8_lossy(text)),
+            Pattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    Send(Vec<u8>),
+    Expect { pattern: Pattern, timeout: Option<Duration>, fail: Option<Pattern> },
+    Sleep(Duration),
+    Timeout(Duration),
+    Eol(LineEnding),
+    Loop { count: Option<u32>, body: Vec<Line> },
+}
+
+/// A step and the script line it came from.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub number: usize,
+    pub step: Step,
+}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(Vec<u8>),
+    Regex(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => f.write_str(word),
+            Token::Text(text) => write!(f, "{:?}", String::from_utf8_lossy(text)),
+            Token::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => break,
+            '"' => {
+                chars.next();
+                let mut text = Vec::new();
+                loop {
+                    let c = match chars.next().ok_or("unterminated string")? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or("unterminated string")? {
+                            'r' => '\r',
+                            'n' => '\n',
+                            't' => '\t',
+                            'x' => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\x{}", hex))?;
+                                text.push(byte);
+                                continue;
+                            }
+                            c @ ('\\' | '"') => c,
+                            c => return Err(format!("unknown escape \\{}", c)),
+                        },
+                        c => c,
+                    };
+                    text.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                tokens.push(Token::Text(text));
+            }
+            '/' => {
+                chars.next();
+                let mut regex = String::new();
+                loop {
+                    match chars.next().ok_or("unterminated regex")? {
+                        '/' => break,
+                        '\\' if chars.peek() == Some(&'/') => regex.push(chars.next().unwrap_or('/')),
+                        c => regex.push(c),
+                    }
+                }
+                tokens.push(Token::Regex(regex));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '#' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses `500ms`, `2s`, `1.5s` or `1m`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {:?}, expected e.g. 500ms, 2s or 1m", text);
+    let (number, scale) = if let Some(number) = text.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = text.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = text.strip_suffix('m') {
+        (number, 60.0)
+    } else {
+        return Err(invalid());
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(Duration::from_secs_f64(value * scale)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_pattern(token: Option<Token>, what: &str) -> Result<Pattern, String> {
+    match token {
+        Some(Token::Text(text)) => Ok(Pattern::Text(text)),
+        Some(Token::Regex(regex)) => {
+            Regex::new(&regex).map(Pattern::Regex).map_err(|e| format!("invalid regex /{}/: {}", regex, e))
+        }
+        Some(other) => Err(format!("{} takes a \"string\" or /regex/, got {}", what, other)),
+        None => Err(format!("{} takes a \"string\" or /regex/", what)),
+    }
+}
+
+fn word(token: Option<Token>, what: &str) -> Result<String, String> {
+    match token {
+        Some(Token::Word(word)) => Ok(word),
+        _ => Err(format!("{} is missing its value", what)),
+    }
+}
+
+/// The lines of the loop being read.
+fn innermost(blocks: &mut [(usize, Option<u32>, Vec<Line>)]) -> &mut Vec<Line> {
+    let last = blocks.len() - 1;
+    &mut blocks[last].2
+}
+
+/// One line of a script, before loops are put together.
+enum Parsed {
+    Blank,
+    Step(Step),
+    LoopStart(Option<u32>),
+    LoopEnd,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, String> {
+        // The loops being read, innermost last, each with its first line
+        // and count; the outermost is the script itself.
+        let mut blocks: Vec<(usize, Option<u32>, Vec<Line>)> = vec![(0, None, Vec::new())];
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            match Self::parse_line(line).map_err(|e| format!("line {}: {}", number, e))? {
+                Parsed::Blank => {}
+                Parsed::Step(step) => innermost(&mut blocks).push(Line { number, step }),
+                Parsed::LoopStart(count) => blocks.push((number, count, Vec::new())),
+                Parsed::LoopEnd => {
+                    if blocks.len() == 1 {
+                        return Err(format!("line {}: end without loop", number));
+                    }
+                    let (start, count, body) = blocks.pop().unwrap_or_default();
+                    innermost(&mut blocks).push(Line { number: start, step: Step::Loop { count, body } });
+                }
+            }
+        }
+        let (start, _, lines) = blocks.pop().unwrap_or_default();
+        if !blocks.is_empty() {
+            return Err(format!("line {}: loop without end", start));
+        }
+        Ok(Script { lines })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Script> {
+        let text = fs::read_to_string(path)?;
+        Script::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    fn parse_line(line: &str) -> Result<Parsed, String> {
+        let mut tokens = tokenize(line)?.into_iter();
+        let Some(command) = tokens.next() else { return Ok(Parsed::Blank) };
+        let Token::Word(command) = command else { return Err(format!("expected a command, got {}", command)) };
+        let parsed = match command.as_str() {
+            "send" => match tokens.next() {
+                Some(Token::Text(text)) => Parsed::Step(Step::Send(text)),
+                _ => return Err("send takes a \"string\"".to_string()),
+            },
+            "expect" => {
+                let pattern = parse_pattern(tokens.next(), "expect")?;
+                let (mut timeout, mut fail) = (None, None);
+                while let Some(option) = tokens.next() {
+                    match option {
+                        Token::Word(option) if option == "timeout" => {
+                            timeout = Some(parse_duration(&word(tokens.next(), "timeout")?)?)
+                        }
+                        Token::Word(option) if option == "fail" => fail = Some(parse_pattern(tokens.next(), "fail")?),
+                        other => return Err(format!("unknown expect option {}, expected timeout or fail", other)),
+                    }
+                }
+                Parsed::Step(Step::Expect { pattern, timeout, fail })
+            }
+            "sleep" => Parsed::Step(Step::Sleep(parse_duration(&word(tokens.next(), "sleep")?)?)),
+            "timeout" => Parsed::Step(Step::Timeout(parse_duration(&word(tokens.next(), "timeout")?)?)),
+            "eol" => Parsed::Step(Step::Eol(word(tokens.next(), "eol")?.parse()?)),
+            "loop" => match tokens.next() {
+                None => Parsed::LoopStart(None),
+                Some(Token::Word(count)) => {
+                    Parsed::LoopStart(Some(count.parse().map_err(|_| format!("invalid loop count {:?}", count))?))
+                }
+                Some(other) => return Err(format!("invalid loop count {}", other)),
+            },
+            "end" => Parsed::LoopEnd,
+            _ => return Err(format!("unknown command {:?}", command)),
+        };
+        match tokens.next() {
+            None => Ok(parsed),
+            Some(extra) => Err(format!("unexpected {} after {}", extra, command)),
+        }
+    }
+}
+
+/// Why a script stopped early.
+#[derive(Debug)]
+pub enum ScriptError {
+    /// An expect ran out of time; carries what had been received.
+    Timeout { line: usize, pattern: String, received: String },
+    /// The `fail` pattern of an expect showed up.
+    Mismatch { line: usize, pattern: String, received: String },
+    /// The endpoint closed while an expect was waiting.
+    Closed { line: usize, pattern: String },
+    Io(io::Error),
+}
+
+impl ScriptError {
+    /// 2 for timeouts, 3 for mismatches and closed connections, 1 for
+    /// anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ScriptError::Timeout { .. } => 2,
+            ScriptError::Mismatch { .. } | ScriptError::Closed { .. } => 3,
+            ScriptError::Io(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Timeout { line, pattern, received } => {
+                write!(f, "line {}: timed out waiting for {}, received {:?}", line, pattern, received)
+            }
+            ScriptError::Mismatch { line, pattern, received } => {
+                write!(f, "line {}: got {} instead, received {:?}", line, pattern, received)
+            }
+            ScriptError::Closed { line, pattern } => {
+                write!(f, "line {}: connection closed while waiting for {}", line, pattern)
+            }
+            ScriptError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self {
+        ScriptError::Io(e)
+    }
+}
+
+/// What a running script does, for showing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptEvent {
+    Sent(Vec<u8>),
+    Received(Vec<u8>),
+    Matched { line: usize, pattern: String },
+}
+
+/// Runs scripts against one endpoint, remembering what was received but
+/// not yet matched between them.
+pub struct Runner {
+    writer: Box<dyn Write + Send>,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    /// Received since the last match.
+    pending: Vec<u8>,
+    closed: bool,
+    timeout: Duration,
+    eol: LineEnding,
+    cancel: Cancel,
+}
+
+impl Runner {
+    /// Starts reading from `reader` in the background; that stops once
+    /// `cancel` fires and the read returns.
+    pub fn new(reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>, cancel: &Cancel) -> Runner {
+        let (chunks, received) = unbounded();
+        let worker = cancel.worker();
+        let mut reader = reader;
+        thread::spawn(move || {
+            let _worker = worker;
+            let mut buf = [0u8; 4096];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => Ok(buf[..len].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => break,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if chunks.send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Runner {
+            writer,
+            chunks: received,
+            pending: Vec::new(),
+            closed: false,
+            timeout: DEFAULT_TIMEOUT,
+            eol: LineEnding::Lf,
+            cancel: cancel.clone(),
+        }
+    }
+
+    pub fn run(&mut self, script: &Script, on_event: &mut dyn FnMut(ScriptEvent)) -> Result<(), ScriptError> {
+        self.run_lines(&script.lines, on_event)
+    }
+
+    fn run_lines(&mut self, lines: &[Line], on_event: &mut dyn FnMut(ScriptEvent)) -> Result<(), ScriptError> {
+        for line in lines {
+            self.cancel.check()?;
+            match &line.step {
+                Step::Send(text) => {
+                    let bytes = [text.as_slice(), self.eol.as_str().as_bytes()].concat();
+                    self.writer.write_all(&bytes)?;
+                    self.writer.flush()?;
+                    on_event(ScriptEvent::Sent(bytes));
+                }
+                Step::Expect { pattern, timeout, fail } => {
+                    self.expect(line.number, pattern, timeout.unwrap_or(self.timeout), fail.as_ref(), on_event)?
+                }
+                Step::Sleep(duration) => self.pause(*duration, on_event)?,
+                Step::Timeout(timeout) => self.timeout = *timeout,
+                Step::Eol(eol) => self.eol = *eol,
+                Step::Loop { count: Some(count), body } => {
+                    for _ in 0..*count {
+                        self.run_lines(body, on_event)?;
+                    }
+                }
+                Step::Loop { count: None, body } => loop {
+                    self.run_lines(body, on_event)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn expect(
+        &mut self,
+        line: usize,
+        pattern: &Pattern,
+        timeout: Duration,
+        fail: Option<&Pattern>,
+        on_event: &mut dyn FnMut(ScriptEvent),
+    ) -> Result<(), ScriptError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let found = pattern.find_end(&self.pending);
+            let failed = fail.and_then(|fail| fail.find_end(&self.pending));
+            match (found, failed) {
+                (found, Some(failed)) if found.is_none_or(|found| failed < found) => {
+                    let received = String::from_utf8_lossy(&self.pending).into_owned();
+                    return Err(ScriptError::Mismatch { line, pattern: fail.map(|f| f.to_string()).unwrap_or_default(), received });
+                }
+                (Some(end), _) => {
+                    self.pending.drain(..end);
+                    on_event(ScriptEvent::Matched { line, pattern: pattern.to_string() });
+                    return Ok(());
+                }
+                _ => {}
+            }
+            if self.closed {
+                return Err(ScriptError::Closed { line, pattern: pattern.to_string() });
+            }
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                let received = String::from_utf8_lossy(&self.pending).into_owned();
+                return Err(ScriptError::Timeout { line, pattern: pattern.to_string(), received });
+            };
+            self.receive(left, on_event)?;
+        }
+    }
+
+    /// Waits up to `duration`, collecting what arrives meanwhile.
+    fn pause(&mut self, duration: Duration, on_event: &mut dyn FnMut(ScriptEvent)) -> Result<(), ScriptError> {
+        let deadline = Instant::now() + duration;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+            if self.closed {
+                self.cancel.check()?;
+                thread::sleep(left.min(Duration::from_millis(50)));
+            } else {
+                self.receive(left, on_event)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes one chunk, waiting at most `wait` (in short steps, to notice
+    /// cancellation).
+    fn receive(&mut self, wait: Duration, on_event: &mut dyn FnMut(ScriptEvent)) -> Result<(), ScriptError> {
+        self.cancel.check()?;
+        match self.chunks.recv_timeout(wait.min(Duration::from_millis(50))) {
+            Ok(Ok(chunk)) => {
+                self.pending.extend_from_slice(&chunk);
+                on_event(ScriptEvent::Received(chunk));
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => self.closed = true,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+// src/serial.rs
+//! Serial ports as a chat transport.
+
+use crate::{cancel::Cancel, direct::Duplex};
+use serialport::SerialPort;
+use std::{
+    io::{self, Read},
+    time::Duration,
+};
+
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// How long one read waits before trying again; bounds how quickly a
+/// cancel is noticed.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Parses `device[,baud]`, e.g. `/dev/ttyUSB0,9600`. A `b` before the baud
+/// rate is accepted too, as in `/dev/ttyUSB0,b9600`.
+pub fn parse_spec(spec: &str) -> Result<(String, u32), String> {
+    let (device, baud) = match spec.split_once(',') {
+        Some((device, baud)) => {
+            let baud = baud.trim().trim_start_matches('b');
+            let baud = baud.parse().map_err(|_| format!("invalid baud rate: {}", baud))?;
+            (device.trim(), baud)
+        }
+        None => (spec.trim(), DEFAULT_BAUD),
+    };
+    if device.is_empty() {
+        return Err("missing serial device".to_string());
+    }
+    Ok((device.to_string(), baud))
+}
+
+/// Opens the port described by `spec` (see [`parse_spec`]). Reading stops
+/// once `cancel` fires.
+pub fn open(spec: &str, cancel: &Cancel) -> io::Result<Duplex> {
+    let (device, baud) = parse_spec(spec).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    println!("Opening serial port {} at {} baud", device, baud);
+    let port = serialport::new(&device, baud).timeout(READ_TIMEOUT).open()?;
+    let reader = PatientReader { port: port.try_clone()?, cancel: cancel.clone() };
+    Ok(Duplex::new(reader, port))
+}
+
+/// Keeps reading through timeouts, so a quiet line is not mistaken for a
+/// broken one.
+struct PatientReader {
+    port: Box<dyn SerialPort>,
+    cancel: Cancel,
+}
+
+impl Read for PatientReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.port.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => self.cancel.check()?,
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,626 @@
+// src/server.rs
+//! The `multi_chat` room server: accepts clients, runs the login handshake,
+//! replays history and relays messages between everyone in the room.
+//!
+//! The server knows nothing about the UI. Whatever the local operator should
+//! see is reported as [`ServerEvent`]s on a channel.
+
+use crate::{
+    auth::{AuthError, Authenticator},
+    heartbeat::Heartbeat,
+    history::{History, HistoryEntry},
+    limits::{self, Connections, Limits},
+    moderation::{Command, Moderation, Target},
+    net::{self, Peer, Stream},
+    protocol::{Frame, Presence},
+};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufReader, Write},
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// What the server reports to the operator's window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A client sent a chat message.
+    Chat { from: String, text: String },
+    /// Someone joined or left, already phrased for display.
+    Notice(String),
+    /// The room's user list changed.
+    Users(Vec<String>),
+    /// A client started or stopped typing.
+    Typing { from: String, typing: bool },
+    /// A client became active or idle.
+    Presence { user: String, presence: Presence },
+    /// A client sent a chat message that wants receipts; see [`ChatServer::read`].
+    Post { id: u64, from: String, text: String },
+    /// `by` read the operator's post `id`.
+    Read { id: u64, by: String },
+    /// [`ChatServer::shutdown`] finished; no more events follow.
+    Stopped,
+}
+
+pub struct ServerConfig {
+    /// Name of the operator, who takes part in the room as a user.
+    pub username: String,
+    pub history: History,
+    /// How many history entries are replayed to a new client.
+    pub replay: usize,
+    pub auth: Arc<Authenticator>,
+    /// How long a new connection may take to log in.
+    pub handshake_timeout: Duration,
+    /// Pinging clients and dropping silent ones.
+    pub heartbeat: Heartbeat,
+    /// Bans, operators, mutes and the topic.
+    pub moderation: Moderation,
+    /// What each client may send, and how many may connect.
+    pub limits: Limits,
+}
+
+/// How often the accept loop checks whether the server was shut down.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// How many post ids are remembered per user to spot a post sent again
+/// after a reconnect.
+const REMEMBERED_POSTS: usize = 256;
+
+/// How many lines may wait for a client before it counts as too slow and
+/// is disconnected.
+const OUTGOING_QUEUE: usize = 1024;
+
+type ClientMap = Arc<Mutex<HashMap<String, Client>>>;
+
+/// A logged-in client. What is sent to it is queued for a thread of its
+/// own to write, so a client that stopped reading holds up nobody else.
+struct Client {
+    stream: Stream,
+    outgoing: Sender<String>,
+}
+
+impl Client {
+    /// Queues `line` for the client `name`; one that does not keep up is
+    /// disconnected.
+    fn send(&self, name: &str, line: String) {
+        match self.outgoing.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                println!("{} is not keeping up, disconnecting", name);
+                let _ = self.stream.shutdown(Shutdown::Both);
+            }
+            // The writer gave up already; the receiving thread cleans up.
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// Handle to a running server. Cloning it is cheap.
+#[derive(Clone)]
+pub struct ChatServer {
+    username: String,
+    local_addrs: Vec<SocketAddr>,
+    listening_on: String,
+    clients: ClientMap,
+    /// Everyone who is not [`Presence::Active`], the operator included.
+    idle: Arc<Mutex<HashMap<String, Presence>>>,
+    /// The latest post ids of each user, kept across their reconnects.
+    posts: Arc<Mutex<HashMap<String, VecDeque<u64>>>>,
+    history: Arc<Mutex<History>>,
+    replay: usize,
+    auth: Arc<Authenticator>,
+    handshake_timeout: Duration,
+    heartbeat: Heartbeat,
+    moderation: Arc<Mutex<Moderation>>,
+    limits: Limits,
+    connections: Connections,
+    stopped: Arc<AtomicBool>,
+    events: Sender<ServerEvent>,
+}
+
+impl ChatServer {
+    /// Binds `address` and starts accepting clients on a background thread.
+    pub fn bind(address: &str, config: ServerConfig) -> io::Result<(ChatServer, Receiver<ServerEvent>)> {
+        let listener = net::Listener::bind(address)?;
+        let (events, receiver) = unbounded();
+        let server = ChatServer {
+            username: config.username,
+            local_addrs: listener.local_addrs(),
+            listening_on: listener.describe(),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            posts: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(Mutex::new(config.history)),
+            replay: config.replay,
+            auth: config.auth,
+            handshake_timeout: config.handshake_timeout,
+            heartbeat: config.heartbeat,
+            moderation: Arc::new(Mutex::new(config.moderation)),
+            limits: config.limits,
+            connections: Connections::new(&config.limits),
+            stopped: Arc::new(AtomicBool::new(false)),
+            events,
+        };
+
+        let accepting = server.clone();
+        thread::spawn(move || accepting.accept_loop(listener));
+        Ok((server, receiver))
+    }
+
+    /// The first TCP address listened on; see [`ChatServer::local_addrs`].
+    /// `None` for a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.first().copied()
+    }
+
+    /// Every TCP address listened on, e.g. an IPv6 and an IPv4 one.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Where the server listens, for display.
+    pub fn listening_on(&self) -> &str {
+        &self.listening_on
+    }
+
+    /// Sends a message from the operator to every client.
+    pub fn send(&self, text: &str) {
+        self.relay(&self.username, text);
+    }
+
+    /// Sends a message from the operator to every client, as post `id` so
+    /// they can report reading it.
+    pub fn post(&self, id: u64, text: &str) {
+        self.relay_post(&self.username, id, text);
+    }
+
+    /// Tells `author` that the operator read their post `id`.
+    pub fn read(&self, id: u64, author: &str) {
+        self.send_to(author, &Frame::Read { id, user: self.username.clone() });
+    }
+
+    /// Tells every client whether the operator is typing.
+    pub fn set_typing(&self, typing: bool) {
+        self.broadcast(&Frame::Typing { from: self.username.clone(), typing }, &[]);
+    }
+
+    /// Tells every client whether the operator is active or idle.
+    pub fn set_presence(&self, presence: Presence) {
+        self.update_presence(&self.username, presence);
+    }
+
+    /// Carries out a slash command typed by the operator, e.g. `/kick bob`;
+    /// the error says why it was refused.
+    pub fn command(&self, line: &str) -> Result<(), String> {
+        self.run_command(&self.username, line)
+    }
+
+    /// Stops accepting and disconnects every client. The event channel
+    /// reports [`ServerEvent::Stopped`] once the listener is closed.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for client in self.clients.lock().unwrap().values() {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Everyone in the room, including the operator, sorted by name.
+    pub fn users(&self) -> Vec<String> {
+        let mut users: Vec<String> = self.clients.lock().unwrap().keys().cloned().collect();
+        users.push(self.username.clone());
+        users.sort();
+        users
+    }
+
+    fn accept_loop(&self, listener: net::Listener) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            match listener.try_accept() {
+                Ok(Some((mut stream, peer))) => {
+                    println!("Client connected from: {}", peer);
+                    let connection = match self.connections.admit(peer.address()) {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            println!("Turning away {}: {}", peer, e);
+                            let _ = stream.set_write_timeout(Some(self.limits.send_timeout));
+                            let _ = reject(&mut stream, &e);
+                            continue;
+                        }
+                    };
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        let _connection = connection;
+                        if let Err(e) = server.handle_client(stream, peer) {
+                            println!("Client {} disconnected with error: {}", peer, e);
+                        }
+                    });
+                }
+                Ok(None) => thread::sleep(ACCEPT_POLL),
+                Err(e) => {
+                    // Out of descriptors, say, under a flood of connections.
+                    println!("Error accepting connection: {}", e);
+                    thread::sleep(ACCEPT_POLL);
+                }
+            }
+        }
+        drop(listener);
+        println!("Server stopped");
+        self.notify(ServerEvent::Stopped);
+    }
+
+    fn handle_client(&self, stream: Stream, peer: Peer) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        writer.set_read_timeout(Some(self.handshake_timeout))?;
+        writer.set_write_timeout(Some(self.limits.send_timeout))?;
+        let (username, password) =
+            read_hello(&mut reader, self.limits.max_message).map_err(|e| net::timed_out("login", e))?;
+
+        if let Err(e) = self.auth.authenticate(peer.address(), &username, &password) {
+            println!("Rejected login as {} from {}: {}", username, peer, e);
+            if e == AuthError::BadCredentials {
+                // Slow down password guessing on this connection.
+                thread::sleep(Duration::from_secs(1));
+            }
+            return reject(&mut writer, &e);
+        }
+        if self.moderation.lock().unwrap().bans().is_banned(&username, peer.address()) {
+            println!("Rejected login as {} from {}: banned", username, peer);
+            return reject(&mut writer, &AuthError::Banned);
+        }
+
+        {
+            // Holding the client map while replaying means no message can slip
+            // between the end of the history and the client being registered.
+            let mut clients = self.clients.lock().unwrap();
+            if clients.contains_key(&username) {
+                drop(clients);
+                println!("Rejected login as {} from {}: name in use", username, peer);
+                return reject(&mut writer, &AuthError::NameInUse);
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            self.heartbeat.apply(&writer)?;
+            // Written by the client's own writer, as nothing may wait for a
+            // client while the map is held.
+            let mut greeting = vec![Frame::Welcome.encode()];
+            self.replay_history(&mut greeting);
+            self.send_presence(&mut greeting);
+            self.send_topic(&mut greeting);
+            let (outgoing, queue) = bounded(OUTGOING_QUEUE);
+            clients.insert(username.clone(), Client { stream: writer.try_clone()?, outgoing });
+            let name = username.clone();
+            thread::spawn(move || write_queued(writer, &name, greeting, queue));
+        }
+        self.announce(&format!("{} joined the chat from {}", username, peer));
+        self.broadcast_users();
+
+        let (server, name) = (self.clone(), username.clone());
+        let pinger = self.heartbeat.start(move |frame| {
+            server.send_to(&name, frame);
+            Ok(())
+        });
+        let result = self.receive_from_client(reader, &username).map_err(|e| self.heartbeat.explain(e));
+        drop(pinger);
+
+        self.clients.lock().unwrap().remove(&username);
+        self.idle.lock().unwrap().remove(&username);
+        self.announce(&format!("{} left the chat", username));
+        self.broadcast_users();
+        result
+    }
+
+    /// Handles frames from `username` until the connection ends. Frames
+    /// coming in faster than the limits allow are read more slowly, which
+    /// leaves the flood to back up on the client's side.
+    fn receive_from_client(&self, mut reader: BufReader<Stream>, username: &str) -> io::Result<()> {
+        let mut throttle = self.limits.throttle();
+        let mut warned = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = match limits::read_line_limited(&mut reader, self.limits.max_message, &mut line) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::InvalidData {
+                        self.send_to(username, &Frame::System(format!("Disconnected: {}", e)));
+                    }
+                    return Err(e);
+                }
+            };
+            let wait = throttle.frame(read, Instant::now());
+            if !wait.is_zero() {
+                if !warned {
+                    println!("Slowing down {}", username);
+                    let warning = "You are sending too fast; the server is slowing you down";
+                    self.send_to(username, &Frame::System(warning.to_string()));
+                    warned = true;
+                }
+                thread::sleep(wait);
+            }
+            match Frame::decode(&line) {
+                Ok(Frame::Command(line)) => {
+                    if let Err(e) = self.run_command(username, &line) {
+                        self.send_to(username, &Frame::System(e));
+                    }
+                }
+                Ok(frame @ (Frame::Chat { .. } | Frame::Post { .. } | Frame::Typing { .. }))
+                    if self.is_muted(username) =>
+                {
+                    println!("Dropping {:?} from muted {}", frame, username);
+                    if let Frame::Post { id, .. } = frame {
+                        // Acknowledged all the same, or it would be sent again and again.
+                        self.send_to(username, &Frame::Ack { id });
+                    }
+                    if !matches!(frame, Frame::Typing { .. }) {
+                        self.send_to(username, &Frame::System("You are muted; nobody saw that".to_string()));
+                    }
+                }
+                Ok(Frame::Chat { text, .. }) => {
+                    println!("Receiver got message from {}: {}", username, text);
+                    self.relay(username, &text);
+                    self.notify(ServerEvent::Chat { from: username.to_string(), text });
+                }
+                Ok(Frame::Typing { typing, .. }) => {
+                    let from = username.to_string();
+                    self.broadcast(&Frame::Typing { from: from.clone(), typing }, &[username]);
+                    self.notify(ServerEvent::Typing { from, typing });
+                }
+                Ok(Frame::Presence { presence, .. }) => self.update_presence(username, presence),
+                Ok(Frame::Ping(token)) => self.send_to(username, &Frame::Pong(token)),
+                // Pongs only need to arrive, which the read timeout sees.
+                Ok(Frame::Pong(_)) => {}
+                Ok(Frame::Post { id, .. }) if !self.first_post(username, id) => {
+                    // Sent again after a reconnect: the first copy got through,
+                    // only its acknowledgement was lost.
+                    println!("Ignoring repeated post {} from {}", id, username);
+                    self.send_to(username, &Frame::Ack { id });
+                }
+                Ok(Frame::Post { id, text, .. }) => {
+                    println!("Receiver got post {} from {}: {}", id, username, text);
+                    self.relay_post(username, id, &text);
+                    self.send_to(username, &Frame::Ack { id });
+                    self.notify(ServerEvent::Post { id, from: username.to_string(), text });
+                }
+                Ok(Frame::Read { id, user }) if user == self.username => {
+                    self.notify(ServerEvent::Read { id, by: username.to_string() });
+                }
+                Ok(Frame::Read { id, user }) => self.send_to(&user, &Frame::Read { id, user: username.to_string() }),
+                Ok(other) => println!("Ignoring unexpected frame from {}: {:?}", username, other),
+                Err(e) => println!("Invalid frame from {}: {}", username, e),
+            }
+        }
+    }
+
+    /// See [`ChatServer::command`]; `by` must be the operator or have been
+    /// made an operator.
+    fn run_command(&self, by: &str, line: &str) -> Result<(), String> {
+        let command = Command::parse(line)?;
+        // Looked up first: joining takes the client map, then moderation.
+        let present = command.user().is_some_and(|user| self.clients.lock().unwrap().contains_key(user));
+        let mut moderation = self.moderation.lock().unwrap();
+        if by != self.username && !moderation.is_operator(by) {
+            return Err(format!("only operators can use {}", line.split_whitespace().next().unwrap_or(line)));
+        }
+        if command.user() == Some(self.username.as_str()) {
+            return Err(format!("{} runs the room and cannot be the target of {}", self.username, command));
+        }
+        let saving = |e: io::Error| format!("could not save the ban list: {}", e);
+        let refused = match &command {
+            Command::Kick { .. } => (!present).then_some("is not here"),
+            Command::Ban { target, .. } => {
+                (!moderation.bans_mut().ban(target.clone()).map_err(saving)?).then_some("is already banned")
+            }
+            Command::Unban(target) => {
+                (!moderation.bans_mut().unban(target).map_err(saving)?).then_some("is not banned")
+            }
+            Command::Mute(user) => (!moderation.set_muted(user, true)).then_some("is already muted"),
+            Command::Unmute(user) => (!moderation.set_muted(user, false)).then_some("is not muted"),
+            Command::Op(user) => (!moderation.set_operator(user, true)).then_some("is already an operator"),
+            Command::Deop(user) => (!moderation.set_operator(user, false)).then_some("is not an operator"),
+            Command::Topic(topic) => {
+                moderation.set_topic(topic);
+                None
+            }
+        };
+        if let Some(refused) = refused {
+            let target = match &command {
+                Command::Ban { target, .. } | Command::Unban(target) => target.to_string(),
+                other => other.user().unwrap_or_default().to_string(),
+            };
+            return Err(format!("{} {}", target, refused));
+        }
+        if let Err(e) = moderation.audit().record(by, &command) {
+            println!("Error writing the audit log: {}", e);
+        }
+        drop(moderation);
+
+        let text = command.announcement(by);
+        let kicked = match &command {
+            Command::Kick { user, .. } | Command::Ban { target: Target::User(user), .. } => {
+                self.kick(&text, |name, _| name == user)
+            }
+            Command::Ban { target: Target::Address(ip), .. } => self.kick(&text, |_, stream| stream.peer().address() == Some(*ip)),
+            _ => Vec::new(),
+        };
+        let kicked: Vec<&str> = kicked.iter().map(String::as_str).collect();
+        self.broadcast(&Frame::System(text.clone()), &kicked);
+        self.notify(ServerEvent::Notice(text));
+        Ok(())
+    }
+
+    /// Tells every client `matches` picks why it is thrown out, and
+    /// disconnects it. Returns their names.
+    fn kick(&self, reason: &str, matches: impl Fn(&str, &Stream) -> bool) -> Vec<String> {
+        let mut kicked = Vec::new();
+        let clients = self.clients.lock().unwrap();
+        for (name, client) in clients.iter() {
+            if !matches(name, &client.stream) {
+                continue;
+            }
+            println!("Kicking {}", name);
+            client.send(name, Frame::Kicked(reason.to_string()).encode());
+            // Its receiving thread sees the connection end and announces the
+            // leave; the writer sends what is queued, then closes it.
+            let _ = client.stream.shutdown(Shutdown::Read);
+            kicked.push(name.clone());
+        }
+        kicked
+    }
+
+    fn is_muted(&self, user: &str) -> bool {
+        self.moderation.lock().unwrap().is_muted(user)
+    }
+
+    fn broadcast(&self, frame: &Frame, except: &[&str]) {
+        let line = frame.encode();
+        let clients = self.clients.lock().unwrap();
+        for (name, client) in clients.iter() {
+            if except.contains(&name.as_str()) {
+                continue;
+            }
+            client.send(name, line.clone());
+        }
+    }
+
+    /// Sends `frame` to the client logged in as `name`, if there is one.
+    fn send_to(&self, name: &str, frame: &Frame) {
+        if let Some(client) = self.clients.lock().unwrap().get(name) {
+            client.send(name, frame.encode());
+        }
+    }
+
+    /// Records a chat message in the history and forwards it to every client but its author.
+    fn relay(&self, from: &str, text: &str) {
+        self.record(from, text);
+        let frame = Frame::Chat { from: from.to_string(), text: text.to_string() };
+        self.broadcast(&frame, &[from]);
+    }
+
+    /// Like [`ChatServer::relay`], keeping the author's id on the message.
+    fn relay_post(&self, from: &str, id: u64, text: &str) {
+        self.record(from, text);
+        let frame = Frame::Post { id, from: from.to_string(), text: text.to_string() };
+        self.broadcast(&frame, &[from]);
+    }
+
+    fn record(&self, from: &str, text: &str) {
+        if let Err(e) = self.history.lock().unwrap().push(HistoryEntry::now(from, text)) {
+            println!("Error writing history: {}", e);
+        }
+    }
+
+    /// Remembers post `id` of `user`; false if it was seen before.
+    fn first_post(&self, user: &str, id: u64) -> bool {
+        let mut posts = self.posts.lock().unwrap();
+        let ids = posts.entry(user.to_string()).or_default();
+        if ids.contains(&id) {
+            return false;
+        }
+        if ids.len() == REMEMBERED_POSTS {
+            ids.pop_front();
+        }
+        ids.push_back(id);
+        true
+    }
+
+    /// Records `user`'s presence and passes it on to everyone else.
+    fn update_presence(&self, user: &str, presence: Presence) {
+        {
+            let mut idle = self.idle.lock().unwrap();
+            match presence {
+                Presence::Active => idle.remove(user),
+                other => idle.insert(user.to_string(), other),
+            };
+        }
+        self.broadcast(&Frame::Presence { user: user.to_string(), presence }, &[user]);
+        if user != self.username {
+            self.notify(ServerEvent::Presence { user: user.to_string(), presence });
+        }
+    }
+
+    /// Tells a client that just joined who is idle; everyone else is active.
+    fn send_presence(&self, greeting: &mut Vec<String>) {
+        for (user, presence) in self.idle.lock().unwrap().iter() {
+            greeting.push(Frame::Presence { user: user.clone(), presence: *presence }.encode());
+        }
+    }
+
+    /// Tells a client that just joined what the room is about.
+    fn send_topic(&self, greeting: &mut Vec<String>) {
+        if let Some(topic) = self.moderation.lock().unwrap().topic() {
+            greeting.push(Frame::System(format!("Topic: {}", topic)).encode());
+        }
+    }
+
+    fn announce(&self, text: &str) {
+        self.broadcast(&Frame::System(text.to_string()), &[]);
+        self.notify(ServerEvent::Notice(text.to_string()));
+    }
+
+    fn broadcast_users(&self) {
+        let users = self.users();
+        self.notify(ServerEvent::Users(users.clone()));
+        self.broadcast(&Frame::Users(users), &[]);
+    }
+
+    /// Sends the last `replay` messages to a client that just completed its handshake.
+    fn replay_history(&self, greeting: &mut Vec<String>) {
+        let history = self.history.lock().unwrap();
+        let mut replayed = 0;
+        for entry in history.recent(self.replay) {
+            let frame = Frame::History {
+                timestamp: entry.timestamp,
+                from: entry.from.clone(),
+                text: entry.text.clone(),
+            };
+            greeting.push(frame.encode());
+            replayed += 1;
+        }
+        if replayed > 0 {
+            greeting.push(Frame::System(format!("End of history ({} messages)", replayed)).encode());
+        }
+    }
+
+    fn notify(&self, event: ServerEvent) {
+        // Nobody listening any more only means the window was closed.
+        let _ = self.events.send(event);
+    }
+}
+
+/// Writes the `greeting` of the client `name`, then what is queued for it
+/// until the queue closes or a write fails, and closes the connection.
+fn write_queued(mut stream: Stream, name: &str, greeting: Vec<String>, queue: Receiver<String>) {
+    for line in greeting.into_iter().chain(queue.iter()) {
+        if let Err(e) = writeln!(stream, "{}", line).and_then(|_| stream.flush()) {
+            println!("Error sending to {}: {}", name, e);
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn read_hello(reader: &mut BufReader<Stream>, max: usize) -> io::Result<(String, String)> {
+    println!("Handling initial connection setup");
+    let mut line = String::new();
+    limits::read_line_limited(reader, max, &mut line)?;
+    match Frame::decode(&line) {
+        Ok(Frame::Hello { username, password }) => {
+            println!("Client username (cleaned): {}", username.trim());
+            Ok((username.trim().to_string(), password))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected a HELLO frame")),
+    }
+}
+
+fn reject(stream: &mut Stream, reason: &AuthError) -> io::Result<()> {
+    writeln!(stream, "{}", Frame::Rejected(reason.to_string()).encode())?;
+    stream.flush()
+}
@@ -0,0 +1,141 @@
+// src/settings.rs
+//! Small per-user settings files kept between runs, stored under
+//! `$XDG_CONFIG_HOME/socat_chat` (or `~/.config/socat_chat`).
+
+use crate::protocol::{escape, unescape};
+use std::{
+    env, fs,
+    io,
+    path::PathBuf,
+};
+
+/// Directory holding the settings files, if a home directory is known.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("socat_chat"))
+}
+
+/// Position and size of a top-level window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl WindowGeometry {
+    /// Geometry saved for the window called `name` by a previous run.
+    pub fn load(name: &str) -> Option<Self> {
+        let path = config_dir()?.join(format!("{}.window", name));
+        let text = fs::read_to_string(path).ok()?;
+        let values: Vec<i32> = text
+            .split_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match values[..] {
+            [x, y, w, h] if w > 0 && h > 0 => Some(WindowGeometry { x, y, w, h }),
+            _ => None,
+        }
+    }
+
+    /// The saved geometry, or `default` when none was saved.
+    pub fn load_or(name: &str, default: WindowGeometry) -> Self {
+        WindowGeometry::load(name).unwrap_or(default)
+    }
+
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        let dir = config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(format!("{}.window", name)),
+            format!("{} {} {} {}\n", self.x, self.y, self.w, self.h),
+        )
+    }
+}
+
+/// How many entries [`RecentConnections`] keeps.
+const MAX_RECENT: usize = 10;
+
+/// One way of starting a chat, as picked in the connection dialog.
+/// Passwords are deliberately not part of it, so they are never saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionProfile {
+    pub mode: String,
+    pub address: String,
+    pub username: String,
+    pub e2e: bool,
+}
+
+impl ConnectionProfile {
+    /// One-line description for a list, e.g. `client 10.0.0.2:8080 as bob`.
+    pub fn label(&self) -> String {
+        let mut label = format!("{} {}", self.mode, self.address);
+        if !self.username.is_empty() {
+            label.push_str(&format!(" as {}", self.username));
+        }
+        if self.e2e {
+            label.push_str(" (E2E)");
+        }
+        label
+    }
+
+    fn to_line(&self) -> String {
+        [self.mode.as_str(), &self.address, &self.username, if self.e2e { "e2e" } else { "-" }]
+            .iter()
+            .map(|field| escape(field))
+            .collect::<Vec<_>>()
+            .join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        match &fields[..] {
+            [mode, address, username, e2e] if !mode.is_empty() => Some(ConnectionProfile {
+                mode: mode.clone(),
+                address: address.clone(),
+                username: username.clone(),
+                e2e: e2e == "e2e",
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Most recently used connections of one binary, newest first, stored in
+/// `<config>/<name>.recent`.
+#[derive(Debug, Clone, Default)]
+pub struct RecentConnections {
+    pub entries: Vec<ConnectionProfile>,
+}
+
+impl RecentConnections {
+    /// The saved list, or an empty one when there is none.
+    pub fn load(name: &str) -> Self {
+        let entries = config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(format!("{}.recent", name))).ok())
+            .map(|text| text.lines().filter_map(ConnectionProfile::from_line).collect())
+            .unwrap_or_default();
+        RecentConnections { entries }
+    }
+
+    /// Moves `profile` to the front, dropping the oldest entries beyond the limit.
+    pub fn remember(&mut self, profile: ConnectionProfile) {
+        self.entries.retain(|entry| *entry != profile);
+        self.entries.insert(0, profile);
+        self.entries.truncate(MAX_RECENT);
+    }
+
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        let dir = config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        fs::create_dir_all(&dir)?;
+        let text: String = self.entries.iter().map(|entry| entry.to_line() + "\n").collect();
+        fs::write(dir.join(format!("{}.recent", name)), text)
+    }
+}
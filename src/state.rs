@@ -0,0 +1,74 @@
+// src/state.rs
+//! Connection lifecycle shared by the chat binaries. Connection threads
+//! report a [`ConnectionState`] and the window shows it in its status bar.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Nothing started yet.
+    Idle,
+    /// Opening the listening socket or pipe.
+    Binding { address: String },
+    /// Ready and waiting for a peer.
+    Listening { address: String },
+    /// Reaching out to a peer.
+    Connecting { address: String },
+    /// Transport is up, login or key exchange still in progress.
+    Handshaking,
+    /// Ready to chat. `peer` is who we are talking to, when there is one.
+    Connected { peer: Option<String> },
+    /// The connection dropped and is being re-established.
+    Reconnecting { attempt: u32 },
+    Failed { reason: String },
+    /// A local program we were chatting with ended, e.g. `exited with
+    /// status 1`. It may be restarted.
+    Exited { status: String },
+}
+
+impl ConnectionState {
+    pub fn failed(reason: impl fmt::Display) -> Self {
+        ConnectionState::Failed { reason: reason.to_string() }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, ConnectionState::Connected { .. })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, ConnectionState::Failed { .. })
+    }
+
+    /// Whether something is still in progress, i.e. neither settled nor idle.
+    pub fn is_pending(&self) -> bool {
+        !matches!(
+            self,
+            ConnectionState::Idle
+                | ConnectionState::Connected { .. }
+                | ConnectionState::Failed { .. }
+                | ConnectionState::Exited { .. }
+        )
+    }
+
+    /// Text for a status bar, e.g. `Status: Connected to 10.0.0.2:8080`.
+    pub fn status_line(&self) -> String {
+        format!("Status: {}", self)
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Idle => write!(f, "Idle"),
+            ConnectionState::Binding { address } => write!(f, "Opening {}...", address),
+            ConnectionState::Listening { address } => write!(f, "Waiting for peers on {}", address),
+            ConnectionState::Connecting { address } => write!(f, "Connecting to {}...", address),
+            ConnectionState::Handshaking => write!(f, "Handshaking..."),
+            ConnectionState::Connected { peer: Some(peer) } => write!(f, "Connected to {}", peer),
+            ConnectionState::Connected { peer: None } => write!(f, "Connected"),
+            ConnectionState::Reconnecting { attempt } => write!(f, "Reconnecting (attempt {})...", attempt),
+            ConnectionState::Failed { reason } => write!(f, "Connection failed - {}", reason),
+            ConnectionState::Exited { status } => write!(f, "Program {}", status),
+        }
+    }
+}
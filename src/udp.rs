@@ -0,0 +1,442 @@
+// src/udp.rs
+//! UDP as a chat transport, for `network_chat`'s `udp` mode.
+//!
+//! Every line written becomes one datagram carrying a sequence number, so
+//! the receiving side can drop duplicates. With acknowledgements turned on,
+//! datagrams are resent until the peer confirms them; without, a lost
+//! datagram is simply a lost message. Messages are not reordered.
+//!
+//! A datagram is one line of tab-separated fields, `data <epoch> <seq>
+//! <payload>` or `ack <epoch> <seq>`. The epoch is picked at random when an
+//! endpoint opens, so a restarted peer counting from 1 again is not taken
+//! for a stream of duplicates.
+
+use crate::{cancel::Cancel, cli::Args, direct::Duplex, net};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Ethernet's MTU, right for most LANs.
+pub const DEFAULT_MTU: usize = 1500;
+
+/// Bytes of UDP header in every datagram.
+const UDP_HEADER: usize = 8;
+
+/// How long one read waits before looking at the [`Cancel`] again.
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// First resend delay; it doubles with every try up to [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Sends per message, including the first, before it is reported lost.
+const MAX_TRIES: u32 = 8;
+
+/// How many sequence numbers per epoch are remembered for spotting
+/// duplicates.
+const DEDUP_WINDOW: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpOptions {
+    /// Path MTU towards the peer; messages must fit into one datagram.
+    pub mtu: usize,
+    /// Ask the peer to acknowledge every message, and resend until it does.
+    pub acks: bool,
+}
+
+impl Default for UdpOptions {
+    fn default() -> Self {
+        UdpOptions { mtu: DEFAULT_MTU, acks: false }
+    }
+}
+
+impl UdpOptions {
+    /// Reads `--mtu=BYTES` and `--ack`.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let mtu = args.parse_or("mtu", DEFAULT_MTU)?;
+        if mtu < 576 {
+            return Err(format!("--mtu must be at least 576, got {}", mtu));
+        }
+        Ok(UdpOptions { mtu, acks: args.flag("ack") })
+    }
+}
+
+/// Largest datagram payload that fits into `mtu` without fragmenting.
+pub fn max_datagram(mtu: usize, peer: &SocketAddr) -> usize {
+    let ip_header = if peer.is_ipv4() { 20 } else { 40 };
+    mtu.saturating_sub(ip_header + UDP_HEADER)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data { epoch: u32, seq: u64, payload: String },
+    Ack { epoch: u32, seq: u64 },
+}
+
+impl Packet {
+    pub fn encode(&self) -> String {
+        match self {
+            Packet::Data { epoch, seq, payload } => format!("data\t{:08x}\t{}\t{}", epoch, seq, payload),
+            Packet::Ack { epoch, seq } => format!("ack\t{:08x}\t{}", epoch, seq),
+        }
+    }
+
+    pub fn decode(datagram: &str) -> Option<Packet> {
+        let mut fields = datagram.splitn(4, '\t');
+        let kind = fields.next()?;
+        let epoch = u32::from_str_radix(fields.next()?, 16).ok()?;
+        let seq = fields.next()?.parse().ok()?;
+        match (kind, fields.next()) {
+            ("data", Some(payload)) => Some(Packet::Data { epoch, seq, payload: payload.to_string() }),
+            ("ack", None) => Some(Packet::Ack { epoch, seq }),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `[LOCAL,]PEER` or `LOCAL,`: where to bind and whom to talk to.
+/// Without a peer the first one to send a datagram becomes the peer.
+pub fn parse_spec(spec: &str) -> Result<(Option<String>, Option<String>), String> {
+    let (local, peer) = match spec.split_once(',') {
+        Some((local, peer)) => (local.trim(), peer.trim()),
+        None => ("", spec.trim()),
+    };
+    let local = (!local.is_empty()).then(|| local.to_string());
+    let peer = (!peer.is_empty()).then(|| peer.to_string());
+    for address in local.iter().chain(&peer) {
+        net::validate_address(address)?;
+    }
+    if let Some((host, _)) = peer.as_deref().map(net::split_host_port).transpose()? {
+        if host.is_empty() || host == "*" {
+            return Err(format!("no peer host in {:?}", spec));
+        }
+    }
+    match (&local, &peer) {
+        (None, None) => Err(format!("expected [LOCAL,]PEER or LOCAL, got {:?}", spec)),
+        _ => Ok((local, peer)),
+    }
+}
+
+type LostHook = Box<dyn Fn(u64) + Send>;
+
+/// An open UDP socket and what is known about the peer.
+pub struct Endpoint {
+    shared: Arc<Shared>,
+    cancel: Cancel,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    epoch: u32,
+    options: UdpOptions,
+    outgoing: Mutex<Outgoing>,
+    on_lost: Mutex<Option<LostHook>>,
+}
+
+struct Outgoing {
+    peer: Option<SocketAddr>,
+    next_seq: u64,
+    /// Datagrams written before the peer was known.
+    queued: Vec<Vec<u8>>,
+    unacked: BTreeMap<u64, Unacked>,
+}
+
+struct Unacked {
+    datagram: Vec<u8>,
+    tries: u32,
+    due: Instant,
+}
+
+impl Endpoint {
+    /// Binds the socket described by `spec` (see [`parse_spec`]). Reading
+    /// stops once `cancel` fires.
+    pub fn open(spec: &str, options: UdpOptions, cancel: &Cancel) -> io::Result<Endpoint> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let (local, peer) = parse_spec(spec).map_err(invalid)?;
+        let peer = match peer {
+            Some(peer) => Some(net::resolve(&peer)?[0]),
+            None => None,
+        };
+        let socket = bind(local.as_deref(), peer)?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        println!("UDP socket bound to {}", socket.local_addr()?);
+
+        let mut epoch = [0u8; 4];
+        getrandom::getrandom(&mut epoch).map_err(|e| io::Error::other(e.to_string()))?;
+        let shared = Arc::new(Shared {
+            socket,
+            epoch: u32::from_be_bytes(epoch),
+            options,
+            outgoing: Mutex::new(Outgoing { peer, next_seq: 1, queued: Vec::new(), unacked: BTreeMap::new() }),
+            on_lost: Mutex::new(None),
+        });
+        if options.acks {
+            let weak = Arc::downgrade(&shared);
+            let cancel = cancel.clone();
+            thread::spawn(move || resend_loop(weak, cancel));
+        }
+        Ok(Endpoint { shared, cancel: cancel.clone() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// The peer, once known.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.shared.outgoing.lock().unwrap().peer.map(net::canonical)
+    }
+
+    /// Called with the sequence number of every message the peer never
+    /// acknowledged. Only used with [`UdpOptions::acks`].
+    pub fn on_lost(&self, hook: impl Fn(u64) + Send + 'static) {
+        *self.shared.on_lost.lock().unwrap() = Some(Box::new(hook));
+    }
+
+    /// Line-based reading and writing over the socket.
+    pub fn duplex(&self) -> Duplex {
+        let reader = DatagramReader {
+            shared: Arc::clone(&self.shared),
+            cancel: self.cancel.clone(),
+            dedup: Dedup::default(),
+            buffer: VecDeque::new(),
+        };
+        let writer = DatagramWriter { shared: Arc::clone(&self.shared), line: Vec::new() };
+        Duplex::new(reader, writer)
+    }
+}
+
+/// Binds `local`, or an ephemeral port when not given, in the peer's
+/// address family so datagrams to it can be sent from the socket.
+fn bind(local: Option<&str>, peer: Option<SocketAddr>) -> io::Result<UdpSocket> {
+    let (host, port) = match local {
+        Some(local) => net::split_host_port(local).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => ("", 0),
+    };
+    if !host.is_empty() && host != "*" {
+        let addrs = net::resolve(local.unwrap_or_default())?;
+        let addr = addrs
+            .iter()
+            .find(|addr| peer.is_none_or(|peer| peer.is_ipv4() == addr.is_ipv4()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "local and peer address families differ"))?;
+        return UdpSocket::bind(addr);
+    }
+    let v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    match peer {
+        Some(peer) if peer.is_ipv4() => UdpSocket::bind(v4),
+        // A dual-stack socket answers IPv4 peers as well, where available.
+        _ => UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))
+            .or_else(|_| UdpSocket::bind(v4)),
+    }
+}
+
+impl Shared {
+    fn send(&self, datagram: &[u8], peer: SocketAddr) {
+        if let Err(e) = self.socket.send_to(datagram, peer) {
+            println!("Error sending datagram to {}: {}", peer, e);
+        }
+    }
+}
+
+struct DatagramWriter {
+    shared: Arc<Shared>,
+    line: Vec<u8>,
+}
+
+impl DatagramWriter {
+    fn send_line(&mut self, payload: &str) -> io::Result<()> {
+        let shared = &self.shared;
+        let mut outgoing = shared.outgoing.lock().unwrap();
+        let seq = outgoing.next_seq;
+        let datagram = Packet::Data { epoch: shared.epoch, seq, payload: payload.to_string() }
+            .encode()
+            .into_bytes();
+        // Before the peer is known, assume the larger IPv6 header.
+        let limit_for = outgoing.peer.unwrap_or(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0));
+        let limit = max_datagram(shared.options.mtu, &limit_for);
+        if datagram.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("message too long for one datagram ({} bytes, at most {} fit)", datagram.len(), limit),
+            ));
+        }
+        outgoing.next_seq += 1;
+        if shared.options.acks {
+            let due = Instant::now() + RETRY_DELAY;
+            outgoing.unacked.insert(seq, Unacked { datagram: datagram.clone(), tries: 1, due });
+        }
+        match outgoing.peer {
+            Some(peer) => shared.send(&datagram, peer),
+            None => outgoing.queued.push(datagram),
+        }
+        Ok(())
+    }
+}
+
+impl Write for DatagramWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        while let Some(end) = self.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            self.send_line(line.trim_end_matches('\r'))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sequence numbers seen from the peer's current epoch.
+#[derive(Default)]
+struct Dedup {
+    epoch: Option<u32>,
+    seen: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl Dedup {
+    fn is_new(&mut self, epoch: u32, seq: u64) -> bool {
+        if self.epoch != Some(epoch) {
+            *self = Dedup { epoch: Some(epoch), ..Dedup::default() };
+        }
+        if !self.seen.insert(seq) {
+            return false;
+        }
+        self.order.push_back(seq);
+        if self.order.len() > DEDUP_WINDOW {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+struct DatagramReader {
+    shared: Arc<Shared>,
+    cancel: Cancel,
+    dedup: Dedup,
+    /// Received lines not yet read, newlines included.
+    buffer: VecDeque<u8>,
+}
+
+impl DatagramReader {
+    /// Waits for the next new message and appends it to the buffer.
+    fn receive(&mut self) -> io::Result<()> {
+        let mut datagram = [0u8; 65_536];
+        loop {
+            let (len, from) = match self.shared.socket.recv_from(&mut datagram) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    self.cancel.check()?;
+                    continue;
+                }
+                // An earlier datagram found nobody listening; keep going.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(e) => return Err(e),
+            };
+            let Some(packet) = std::str::from_utf8(&datagram[..len]).ok().and_then(Packet::decode) else {
+                println!("Ignoring malformed datagram from {}", net::canonical(from));
+                continue;
+            };
+            if !self.accept_from(from) {
+                continue;
+            }
+            match packet {
+                Packet::Ack { epoch, seq } => {
+                    if epoch == self.shared.epoch {
+                        self.shared.outgoing.lock().unwrap().unacked.remove(&seq);
+                    }
+                }
+                Packet::Data { epoch, seq, payload } => {
+                    // Duplicates are acknowledged again: the first ack may
+                    // be what got lost.
+                    if self.shared.options.acks {
+                        self.shared.send(Packet::Ack { epoch, seq }.encode().as_bytes(), from);
+                    }
+                    if self.dedup.is_new(epoch, seq) {
+                        self.buffer.extend(payload.as_bytes());
+                        self.buffer.push_back(b'\n');
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether a datagram from `from` is from the peer, learning the peer
+    /// from it if none is known yet.
+    fn accept_from(&self, from: SocketAddr) -> bool {
+        let mut outgoing = self.shared.outgoing.lock().unwrap();
+        match outgoing.peer {
+            Some(peer) if net::canonical(peer) == net::canonical(from) => true,
+            Some(_) => {
+                println!("Ignoring datagram from {}, not the peer", net::canonical(from));
+                false
+            }
+            None => {
+                println!("Peer is {}", net::canonical(from));
+                outgoing.peer = Some(from);
+                for datagram in std::mem::take(&mut outgoing.queued) {
+                    self.shared.send(&datagram, from);
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Read for DatagramReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            self.receive()?;
+        }
+        let len = buf.len().min(self.buffer.len());
+        for (slot, byte) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *slot = byte;
+        }
+        Ok(len)
+    }
+}
+
+/// Resends unacknowledged datagrams until the endpoint is dropped or
+/// cancelled, reporting the ones that run out of tries.
+fn resend_loop(shared: Weak<Shared>, cancel: Cancel) {
+    while !cancel.is_cancelled() {
+        thread::sleep(RETRY_DELAY / 5);
+        let Some(shared) = shared.upgrade() else { return };
+        let now = Instant::now();
+        let mut lost = Vec::new();
+        {
+            let mut outgoing = shared.outgoing.lock().unwrap();
+            let Some(peer) = outgoing.peer else { continue };
+            outgoing.unacked.retain(|&seq, unacked| {
+                if unacked.due > now {
+                    return true;
+                }
+                if unacked.tries >= MAX_TRIES {
+                    lost.push(seq);
+                    return false;
+                }
+                let delay = (RETRY_DELAY * 2u32.pow(unacked.tries)).min(MAX_RETRY_DELAY);
+                unacked.tries += 1;
+                unacked.due = now + delay;
+                shared.send(&unacked.datagram, peer);
+                true
+            });
+        }
+        if !lost.is_empty() {
+            if let Some(hook) = shared.on_lost.lock().unwrap().as_ref() {
+                lost.iter().for_each(|&seq| hook(seq));
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// src/unix.rs
+//! Unix domain sockets as a chat transport, for local chats where file
+//! permissions decide who may connect.
+//!
+//! Addresses look like `unix:/run/chat.sock`, optionally followed by
+//! `,mode=660` to set the permissions of a socket being listened on (it
+//! defaults to 600, owner only). On Linux `unix:@name` names a socket in
+//! the abstract namespace, which has no file and therefore no permissions.
+
+use std::{
+    fmt, fs, io,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process,
+};
+
+pub const PREFIX: &str = "unix:";
+
+/// Permissions of a listening socket unless `mode=` says otherwise.
+pub const DEFAULT_MODE: u32 = 0o600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixAddress {
+    Path(PathBuf),
+    /// A Linux abstract-namespace name, without the leading `@`.
+    Abstract(String),
+}
+
+impl fmt::Display for UnixAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixAddress::Path(path) => write!(f, "{}{}", PREFIX, path.display()),
+            UnixAddress::Abstract(name) => write!(f, "{}@{}", PREFIX, name),
+        }
+    }
+}
+
+/// A parsed `unix:` address and its options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSpec {
+    pub address: UnixAddress,
+    /// Permission bits for a socket file being listened on.
+    pub mode: u32,
+}
+
+/// Whether `address` is meant for this module rather than TCP.
+pub fn is_unix(address: &str) -> bool {
+    address.starts_with(PREFIX)
+}
+
+/// Parses `unix:PATH[,mode=OCTAL]` or `unix:@NAME`.
+pub fn parse(address: &str) -> Result<UnixSpec, String> {
+    let rest = address
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("expected {}PATH, got {:?}", PREFIX, address))?;
+    let mut parts = rest.split(',');
+    let target = parts.next().unwrap_or_default().trim();
+    let address = match target.strip_prefix('@') {
+        Some("") => return Err("missing abstract socket name after '@'".to_string()),
+        Some(name) => UnixAddress::Abstract(name.to_string()),
+        None if target.is_empty() => return Err(format!("missing socket path in {:?}", address)),
+        None => UnixAddress::Path(PathBuf::from(target)),
+    };
+    let mut mode = DEFAULT_MODE;
+    for option in parts {
+        match option.trim().split_once('=') {
+            Some(("mode", value)) => {
+                mode = u32::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| format!("invalid socket mode {:?}, expected octal like 660", value))?;
+            }
+            _ => return Err(format!("unknown socket option {:?}", option)),
+        }
+    }
+    Ok(UnixSpec { address, mode })
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets need Linux"))
+}
+
+/// A listening socket that removes its file when dropped.
+pub struct Bound {
+    pub listener: UnixListener,
+    pub address: UnixAddress,
+}
+
+impl Drop for Bound {
+    fn drop(&mut self) {
+        if let UnixAddress::Path(path) = &self.address {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Listens on `address` (see [`parse`]). A socket file left behind by a
+/// server that is no longer running is replaced; one still in use is not.
+pub fn bind(address: &str) -> io::Result<Bound> {
+    let spec = parse(address).map_err(invalid_input)?;
+    let listener = match &spec.address {
+        UnixAddress::Abstract(name) => UnixListener::bind_addr(&abstract_addr(name)?)?,
+        UnixAddress::Path(path) => {
+            remove_stale(path)?;
+            bind_private(path, spec.mode)?
+        }
+    };
+    Ok(Bound { listener, address: spec.address })
+}
+
+/// Binds in a directory only we can enter and moves the socket to `path`
+/// once it has `mode`, so no one can connect while it still has the
+/// permissions the umask gave it.
+fn bind_private(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| invalid_input(format!("{} is not a file", path.display())))?;
+    let dir = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join(name);
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        // Connecting needs write permission on the socket file.
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&dir);
+    bound
+}
+
+fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => match UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use", path.display()))),
+            Err(_) => {
+                println!("Removing stale socket {}", path.display());
+                fs::remove_file(path)
+            }
+        },
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(_) => Ok(()),
+    }
+}
+
+pub fn connect(address: &str) -> io::Result<UnixStream> {
+    match parse(address).map_err(invalid_input)?.address {
+        UnixAddress::Abstract(name) => UnixStream::connect_addr(&abstract_addr(&name)?),
+        UnixAddress::Path(path) => UnixStream::connect(path),
+    }
+}
+
+/// Who is on the other end of a Unix socket, as the kernel reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    /// Not known on every system.
+    pub pid: Option<i32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match user_name(self.uid) {
+            Some(name) => write!(f, "{} (uid {}", name, self.uid)?,
+            None => write!(f, "uid {} (gid {}", self.uid, self.gid)?,
+        }
+        if let Some(pid) = self.pid {
+            write!(f, ", pid {}", pid)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The peer's credentials, if the system tells.
+#[cfg(target_os = "linux")]
+pub fn peer_credentials(stream: &UnixStream) -> Option<Credentials> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: all-zero is a valid ucred.
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` has room for the `len` bytes the kernel writes.
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut _,
+            &mut len,
+        )
+    };
+    (result == 0).then_some(Credentials { pid: Some(cred.pid), uid: cred.uid, gid: cred.gid })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peer_credentials(stream: &UnixStream) -> Option<Credentials> {
+    use std::os::fd::AsRawFd;
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: both out-pointers are valid for the call.
+    let result = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    (result == 0).then_some(Credentials { pid: None, uid, gid })
+}
+
+/// Looks `uid` up in `/etc/passwd`.
+pub fn user_name(uid: u32) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id: u32 = fields.nth(1)?.parse().ok()?;
+        (id == uid).then(|| name.to_string())
+    })
+}
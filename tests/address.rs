@@ -0,0 +1,171 @@
+// tests/address.rs
+//! socat-style endpoint addresses: parsing, printing them back, and opening
+//! a few kinds over loopback.
+
+mod common;
+
+use common::TIMEOUT;
+use socat_chat::{
+    address::{Address, Opened, Transport},
+    cancel::Cancel,
+    direct,
+    exec::ExecSpec,
+    net::Timeouts,
+    state::ConnectionState,
+    udp::UdpOptions,
+};
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+fn parse(spec: &str) -> Address {
+    Address::parse(spec).unwrap_or_else(|e| panic!("{:?} did not parse: {}", spec, e))
+}
+
+fn open(address: &Address, cancel: &Cancel) -> Opened {
+    address.open(Timeouts::default(), UdpOptions::default(), cancel, &|_| {}).unwrap()
+}
+
+#[test]
+fn every_kind_of_address_parses() {
+    assert_eq!(parse("TCP:192.168.0.108:8080"), Address::Connect("192.168.0.108:8080".into()));
+    assert_eq!(parse("tcp:[::1]:8080"), Address::Connect("[::1]:8080".into()));
+    assert_eq!(parse("TCP-LISTEN:8080"), Address::Listen("*:8080".into()));
+    assert_eq!(parse("TCP-LISTEN:8080,bind=::1"), Address::Listen("[::1]:8080".into()));
+    assert_eq!(
+        parse("PIPE:/tmp/in,/tmp/out"),
+        Address::Pipe { read: PathBuf::from("/tmp/in"), write: PathBuf::from("/tmp/out") }
+    );
+    assert_eq!(
+        parse("FILE:/dev/ttyUSB0,b9600,raw"),
+        Address::Serial { device: "/dev/ttyUSB0".into(), baud: 9600 }
+    );
+    assert_eq!(parse("FILE:/dev/ttyS0"), Address::Serial { device: "/dev/ttyS0".into(), baud: 115_200 });
+    assert_eq!(parse("UDP:10.0.0.2:9000"), Address::Udp("10.0.0.2:9000".into()));
+    assert_eq!(parse("UDP:10.0.0.2:9000,bind=*:9001"), Address::Udp("*:9001,10.0.0.2:9000".into()));
+    assert_eq!(parse("UDP-LISTEN:9000"), Address::Udp("*:9000,".into()));
+    assert_eq!(parse("STDIO"), Address::Stdio);
+    assert_eq!(parse("-"), Address::Stdio);
+    assert_eq!(parse("EXEC:sort -t, -k2"), Address::Exec(ExecSpec { command: "sort -t, -k2".into(), pty: false }));
+    assert_eq!(parse("EXEC:bash -i,pty"), Address::Exec(ExecSpec { command: "bash -i".into(), pty: true }));
+    if cfg!(unix) {
+        assert_eq!(parse("UNIX-CONNECT:/tmp/chat.sock"), Address::Connect("unix:/tmp/chat.sock".into()));
+        assert_eq!(parse("UNIX-LISTEN:/tmp/chat.sock,mode=660"), Address::Listen("unix:/tmp/chat.sock,mode=660".into()));
+        assert_eq!(parse("ABSTRACT-LISTEN:chat"), Address::Listen("unix:@chat".into()));
+    }
+}
+
+#[test]
+fn mistakes_are_explained() {
+    for (spec, complaint) in [
+        ("localhost", "KEYWORD:PARAMETERS"),
+        ("localhost:8080", "unknown address type"),
+        ("SCTP:host:1", "unknown address type"),
+        ("TCP:host", "missing port"),
+        ("TCP:host:1,fork", "does not take option"),
+        ("TCP-LISTEN:http", "invalid port"),
+        ("PIPE:/tmp/only", "two named pipes"),
+        ("FILE:/dev/ttyS0,bfast", "invalid baud rate"),
+        ("EXEC:", "needs a command"),
+        ("TCP:", "missing parameters"),
+    ] {
+        let err = Address::parse(spec).unwrap_err();
+        assert!(err.contains(complaint), "{:?}: {}", spec, err);
+    }
+}
+
+#[test]
+fn addresses_print_in_socat_form_and_read_back() {
+    let mut specs = vec![
+        "TCP:chat.lan:8080",
+        "TCP-LISTEN:8080",
+        "TCP-LISTEN:8080,bind=127.0.0.1",
+        "PIPE:/tmp/in,/tmp/out",
+        "FILE:/dev/ttyUSB0,b115200,raw",
+        "UDP:10.0.0.2:9000",
+        "UDP:10.0.0.2:9000,bind=*:9001",
+        "UDP-LISTEN:9000",
+        "STDIO",
+        "EXEC:cat -n",
+        "EXEC:python3 -i,pty",
+    ];
+    if cfg!(unix) {
+        specs.extend(["UNIX-CONNECT:/tmp/chat.sock", "UNIX-LISTEN:/tmp/chat.sock,mode=660", "ABSTRACT-CONNECT:chat"]);
+    }
+    for spec in specs {
+        let address = parse(spec);
+        assert_eq!(address.to_string(), spec);
+        assert_eq!(parse(&address.to_string()), address);
+    }
+}
+
+#[test]
+fn dialog_modes_map_onto_addresses() {
+    assert_eq!(Address::from_mode("server", "*:8080").unwrap().to_string(), "TCP-LISTEN:8080");
+    assert_eq!(Address::from_mode("client", "10.0.0.2:8080").unwrap().to_string(), "TCP:10.0.0.2:8080");
+    assert_eq!(Address::from_mode("serial", "/dev/ttyS0,9600").unwrap().to_string(), "FILE:/dev/ttyS0,b9600,raw");
+    assert_eq!(Address::from_mode("fifo", "/tmp/a,/tmp/b").unwrap().to_string(), "PIPE:/tmp/a,/tmp/b");
+    assert_eq!(Address::from_mode("udp", "*:9000,").unwrap().to_string(), "UDP-LISTEN:9000");
+    assert!(Address::from_mode("client", "nowhere").is_err());
+    assert!(Address::from_mode("carrier-pigeon", "x").is_err());
+
+    assert!(Address::from_mode("server", "*:8080").unwrap().is_listening());
+    assert!(parse("UDP-LISTEN:9000").is_listening());
+    assert!(!parse("UDP:10.0.0.2:9000").is_listening());
+}
+
+#[test]
+fn tcp_listen_and_connect_addresses_chat() {
+    // Find a free port for the listening side.
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let listen = parse(&format!("TCP-LISTEN:{},bind=127.0.0.1", port));
+    let cancel = Cancel::new();
+
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&states);
+    let waiting = cancel.clone();
+    let server = thread::spawn(move || {
+        let report = move |state: ConnectionState| seen.lock().unwrap().push(state);
+        listen.open(Timeouts::default(), UdpOptions::default(), &waiting, &report).unwrap()
+    });
+    let connect = parse(&format!("TCP:127.0.0.1:{}", port));
+    let client = loop {
+        match connect.open(Timeouts::default(), UdpOptions::default(), &cancel, &|_| {}) {
+            Ok(opened) => break opened,
+            // The listener may not be up yet.
+            Err(_) => thread::sleep(std::time::Duration::from_millis(20)),
+        }
+    };
+    let server = server.join().unwrap();
+    assert!(matches!(server.transport, Transport::Stream(_)));
+    assert!(server.peer.unwrap().starts_with("127.0.0.1:"));
+    assert_eq!(client.peer.unwrap(), format!("127.0.0.1:{}", port));
+    assert!(states.lock().unwrap().iter().any(|state| matches!(state, ConnectionState::Listening { .. })));
+
+    let (mut tx, _) = direct::split_duplex(client.transport.into_duplex().unwrap(), None);
+    let (_, mut rx) = direct::split_duplex(server.transport.into_duplex().unwrap(), None);
+    tx.send("over socat addresses").unwrap();
+    assert_eq!(rx.recv().unwrap().unwrap(), "over socat addresses");
+    cancel.cancel();
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_talks_to_the_command() {
+    let cancel = Cancel::new();
+    let opened = open(&parse("EXEC:sed -u 's/^/echo: /'"), &cancel);
+    assert_eq!(opened.peer.as_deref(), Some("sed -u 's/^/echo: /'"));
+    let (mut tx, rx) = direct::split_duplex(opened.transport.into_duplex().unwrap(), None);
+    let (replies, received) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut rx = rx;
+        while let Some(Ok(line)) = rx.recv() {
+            let _ = replies.send(line);
+        }
+    });
+    tx.send("hello").unwrap();
+    assert_eq!(received.recv_timeout(TIMEOUT).unwrap(), "echo: hello");
+}
@@ -0,0 +1,68 @@
+// tests/bot.rs
+//! Bot rules: parsing the rules file, filling in replies and running
+//! commands.
+
+mod common;
+
+use common::scratch_dir;
+use socat_chat::bot::{Action, Bot, Rule};
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn rules_files_are_parsed_line_by_line() {
+    let bot = Bot::parse("# a comment\n\n(?i)^ping$ => reply pong\n^!build (\\S+)$ => run ./status.sh\n").unwrap();
+    let actions: Vec<_> = bot.rules().iter().map(|rule| rule.action.clone()).collect();
+    assert_eq!(actions, vec![Action::Reply("pong".into()), Action::Run("./status.sh".into())]);
+
+    let err = Bot::parse("ping => reply pong\n\n^(unclosed => reply x\n").unwrap_err();
+    assert!(err.starts_with("line 3: invalid regex"), "{}", err);
+    assert!(Rule::parse("ping -> reply pong").unwrap_err().contains("expected"));
+    assert!(Rule::parse("ping => shout pong").unwrap_err().contains("unknown action"));
+    assert!(Rule::parse("ping => run ").is_err());
+}
+
+#[test]
+fn replies_are_filled_in_from_the_match() {
+    let bot = Bot::parse(concat!(
+        "^!deploy (\\w+) to (?P<env>\\w+)$ => reply $from: deploying $1 to ${env} ($0), costs $$5\n",
+        "(?i)^ping$ => reply pong$2\n",
+        "^ping => reply never, the rule above matched first\n",
+    ))
+    .unwrap();
+    assert_eq!(
+        bot.respond("alice", "!deploy web to prod").as_deref(),
+        Some("alice: deploying web to prod (!deploy web to prod), costs $5")
+    );
+    assert_eq!(bot.respond("bob", "PING").as_deref(), Some("pong"));
+    assert_eq!(bot.respond("bob", "hello"), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn commands_read_the_message_and_their_output_is_sent() {
+    let dir = scratch_dir("bot-run");
+    let marker = dir.join("injected");
+    let bot = Bot::parse("^!status (\\w+) => run echo \"$CHAT_FROM asked about $CHAT_MATCH_1\"; tr a-z A-Z\n").unwrap();
+    assert_eq!(bot.respond("alice", "!status main").as_deref(), Some("alice asked about main\n!STATUS MAIN"));
+
+    // The message is data, never part of the command line.
+    let message = format!("!status x $(touch {})", marker.display());
+    assert!(bot.respond("mallory", &message).is_some());
+    assert!(!marker.exists());
+
+    let silent = Bot::parse("^quiet$ => run cat >/dev/null\n").unwrap();
+    assert_eq!(silent.respond("alice", "quiet"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn slow_commands_are_killed() {
+    let bot = Bot::parse("^slow$ => run sleep 5; echo late\n").unwrap().with_timeout(Duration::from_millis(200));
+    let started = Instant::now();
+    assert_eq!(bot.respond("alice", "slow"), None);
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+}
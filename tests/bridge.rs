@@ -0,0 +1,102 @@
+// tests/bridge.rs
+//! `chat_bridge`'s relay: bytes passed on unchanged both ways, operator
+//! injection, and how the traffic is shown.
+
+mod common;
+
+use common::TIMEOUT;
+use crossbeam_channel::Receiver;
+use socat_chat::{
+    bridge::{Bridge, BridgeEvent, Direction, LineEnding, Mirror},
+    cancel::Cancel,
+    direct::Duplex,
+};
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+};
+
+/// A connected pair of streams: (our end, the bridge's end).
+fn loopback_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ours = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (theirs, _) = listener.accept().unwrap();
+    ours.set_read_timeout(Some(TIMEOUT)).unwrap();
+    (ours, theirs)
+}
+
+fn read_exactly(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+/// Skips events until one `matches` accepts.
+fn wait_for_event(events: &Receiver<BridgeEvent>, matches: impl Fn(&BridgeEvent) -> bool) -> BridgeEvent {
+    loop {
+        let event = events.recv_timeout(TIMEOUT).expect("bridge event");
+        if matches(&event) {
+            return event;
+        }
+    }
+}
+
+#[test]
+fn bytes_pass_both_ways_unchanged() {
+    let (mut a, a_end) = loopback_pair();
+    let (mut b, b_end) = loopback_pair();
+    let cancel = Cancel::new();
+    let (_bridge, events) = Bridge::start(Duplex::tcp(a_end).unwrap(), Duplex::tcp(b_end).unwrap(), &cancel);
+
+    let binary = [0u8, 1, 0x1b, b'\r', b'\n', 0xff];
+    a.write_all(&binary).unwrap();
+    assert_eq!(read_exactly(&mut b, binary.len()), binary);
+    b.write_all(b"login: ").unwrap();
+    assert_eq!(read_exactly(&mut a, 7), b"login: ");
+
+    let relayed = wait_for_event(&events, |e| matches!(e, BridgeEvent::Relayed { direction: Direction::BtoA, .. }));
+    assert_eq!(relayed, BridgeEvent::Relayed { direction: Direction::BtoA, bytes: b"login: ".to_vec() });
+
+    a.shutdown(Shutdown::Both).unwrap();
+    let closed = wait_for_event(&events, |e| matches!(e, BridgeEvent::Closed { .. }));
+    assert_eq!(closed, BridgeEvent::Closed { direction: Direction::AtoB, error: None });
+    cancel.cancel();
+}
+
+#[test]
+fn the_operator_can_send_either_way() {
+    let (mut a, a_end) = loopback_pair();
+    let (mut b, b_end) = loopback_pair();
+    let cancel = Cancel::new();
+    let (bridge, events) = Bridge::start(Duplex::tcp(a_end).unwrap(), Duplex::tcp(b_end).unwrap(), &cancel);
+
+    bridge.inject(Direction::BtoA, b"reboot\r").unwrap();
+    assert_eq!(read_exactly(&mut a, 7), b"reboot\r");
+    bridge.inject(Direction::AtoB, b"note\n").unwrap();
+    assert_eq!(read_exactly(&mut b, 5), b"note\n");
+    assert_eq!(
+        wait_for_event(&events, |_| true),
+        BridgeEvent::Injected { direction: Direction::BtoA, bytes: b"reboot\r".to_vec() }
+    );
+    cancel.cancel();
+}
+
+#[test]
+fn traffic_is_shown_line_by_line_with_its_direction() {
+    let mut mirror = Mirror::default();
+    let mut shown = String::new();
+    shown += &mirror.render(Direction::AtoB, false, b"Welcome\r\nlog");
+    shown += &mirror.render(Direction::AtoB, false, b"in: ");
+    shown += &mirror.render(Direction::BtoA, false, b"root\n");
+    shown += &mirror.render(Direction::BtoA, true, b"\x03");
+    shown += &mirror.render(Direction::AtoB, false, b"\x1b[0m$ ");
+    assert_eq!(shown, "A>B Welcome\nA>B login: \nB>A root\nB>A* \\x03\nA>B \\x1b[0m$ ");
+}
+
+#[test]
+fn line_endings_are_named() {
+    assert_eq!("crlf".parse::<LineEnding>().unwrap().as_str(), "\r\n");
+    assert_eq!("CR".parse::<LineEnding>().unwrap().as_str(), "\r");
+    assert_eq!("none".parse::<LineEnding>().unwrap().as_str(), "");
+    assert!("nl".parse::<LineEnding>().is_err());
+}
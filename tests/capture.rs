@@ -0,0 +1,186 @@
+// tests/capture.rs
+//! Capture files: the text format, recording a connection's traffic, pcap
+//! output and replaying at the recorded pace.
+
+mod common;
+
+use common::{scratch_dir, start_server, TIMEOUT};
+use socat_chat::{
+    auth::Authenticator,
+    cancel::Cancel,
+    capture::{self, Flow, Record, Recorder, Speed},
+    client::{ChatClient, ClientEvent},
+    direct::Duplex,
+    history::History,
+    net::Stream,
+    protocol::Frame,
+};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+fn record(millis: u64, flow: Flow, bytes: &[u8]) -> Record {
+    Record { time: Duration::from_millis(1_718_000_000_000 + millis), flow, bytes: bytes.to_vec() }
+}
+
+#[test]
+fn records_are_tab_separated_lines() {
+    let sent = record(250, Flow::Out, b"hi\n");
+    assert_eq!(sent.encode(), "1718000000.250000\tout\t68690a");
+    assert_eq!(Record::decode(&sent.encode()).unwrap(), sent);
+    // Fewer digits are fractions of a second, not microseconds.
+    assert_eq!(Record::decode("1718000000.5\tin\t00ff").unwrap(), record(500, Flow::In, &[0, 0xff]));
+
+    assert!(Record::decode("1718000000.5\tsideways\t00").is_err());
+    assert!(Record::decode("1718000000.5\tin\t0").is_err());
+    assert!(Record::decode("1718000000.5 in 00").is_err());
+}
+
+#[test]
+fn reading_skips_comments_and_names_bad_lines() {
+    let dir = scratch_dir("capture-read");
+    let path = dir.join("session.cap");
+    fs::write(&path, "# socat_chat capture 1\n1.000000\tout\t61\n\n2.000000\tin\t62\n").unwrap();
+    let records = capture::read(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!((records[1].flow, records[1].bytes.as_slice()), (Flow::In, &b"b"[..]));
+
+    fs::write(&path, "# socat_chat capture 1\n1.000000\tout\tzz\n").unwrap();
+    let err = capture::read(&path).unwrap_err();
+    assert!(err.to_string().contains(":2: invalid hex"), "{}", err);
+}
+
+#[test]
+fn a_tapped_connection_records_both_directions() {
+    let dir = scratch_dir("capture-tap");
+    let path = dir.join("session.cap");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (ours, _) = listener.accept().unwrap();
+    peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    let mut duplex = capture::tap(Duplex::tcp(ours).unwrap(), Recorder::create(&path).unwrap());
+    duplex.write_all(b"ping\n").unwrap();
+    let mut buf = [0u8; 5];
+    peer.read_exact(&mut buf).unwrap();
+    peer.write_all(b"pong\n").unwrap();
+    duplex.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong\n");
+
+    assert!(fs::read_to_string(&path).unwrap().starts_with("# socat_chat capture 1\n"));
+    let records = capture::read(&path).unwrap();
+    let flows: Vec<_> = records.iter().map(|r| (r.flow, r.bytes.clone())).collect();
+    assert_eq!(flows, vec![(Flow::Out, b"ping\n".to_vec()), (Flow::In, b"pong\n".to_vec())]);
+    assert!(records[0].time <= records[1].time);
+}
+
+/// Internet checksum over `data`, which is 0 when a stored checksum is right.
+fn ones_complement_sum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2).map(|p| u32::from(u16::from_be_bytes([p[0], *p.get(1).unwrap_or(&0)]))).sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[test]
+fn pcap_captures_are_one_tcp_connection() {
+    let dir = scratch_dir("capture-pcap");
+    let path = dir.join("session.pcap");
+    let recorder = Recorder::create(&path).unwrap();
+    recorder.set_peer("192.168.1.20:8080".parse().unwrap());
+    recorder.record(Flow::Out, b"hi\n");
+    recorder.record(Flow::In, b"hello\n");
+
+    let data = fs::read(&path).unwrap();
+    assert_eq!(&data[..4], &0xa1b2_c3d4u32.to_le_bytes());
+    assert_eq!(u32::from_le_bytes(data[20..24].try_into().unwrap()), 101, "raw IP link type");
+
+    let first = &data[24 + 16..24 + 16 + 43];
+    assert_eq!(u32::from_le_bytes(data[24 + 8..24 + 12].try_into().unwrap()), 43);
+    assert_eq!(first[9], 6, "TCP");
+    assert_eq!(&first[16..20], &[192, 168, 1, 20], "sent to the peer");
+    assert_eq!(u16::from_be_bytes([first[22], first[23]]), 8080);
+    assert_eq!(&first[40..], b"hi\n");
+    assert_eq!(ones_complement_sum(&first[..20]), 0, "IP checksum");
+
+    let second = &data[24 + 16 + 43 + 16..];
+    assert_eq!(second.len(), 46);
+    assert_eq!(&second[12..16], &[192, 168, 1, 20], "received from the peer");
+    // The reply acknowledges the 3 bytes sent and starts its own numbering.
+    assert_eq!(u32::from_be_bytes(second[24..28].try_into().unwrap()), 1);
+    assert_eq!(u32::from_be_bytes(second[28..32].try_into().unwrap()), 4);
+
+    let mut pseudo = Vec::new();
+    pseudo.extend_from_slice(&second[12..20]);
+    pseudo.extend_from_slice(&[0, 6, 0, 26]);
+    pseudo.extend_from_slice(&second[20..]);
+    assert_eq!(ones_complement_sum(&pseudo), 0, "TCP checksum");
+}
+
+#[test]
+fn replay_keeps_the_recorded_pauses_scaled() {
+    let records = vec![
+        record(0, Flow::Out, b"one\n"),
+        record(100, Flow::In, b"ignored\n"),
+        record(400, Flow::Out, b"two\n"),
+        record(800, Flow::Out, b"three\n"),
+    ];
+    let cancel = Cancel::new();
+    let mut sent = Vec::new();
+    let started = Instant::now();
+    let played = capture::replay(&records, Flow::Out, Speed::Factor(4.0), &mut sent, &cancel).unwrap();
+    let took = started.elapsed();
+    assert_eq!(played, 3);
+    assert_eq!(sent, b"one\ntwo\nthree\n");
+    assert!(took >= Duration::from_millis(190) && took < Duration::from_millis(700), "took {:?}", took);
+
+    let mut received = Vec::new();
+    capture::replay(&records, Flow::In, Speed::Max, &mut received, &cancel).unwrap();
+    assert_eq!(received, b"ignored\n");
+
+    cancel.cancel();
+    let err = capture::replay(&records, Flow::Out, Speed::Max, &mut Vec::new(), &cancel).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn speeds_are_factors_or_max() {
+    assert_eq!("10x".parse::<Speed>().unwrap(), Speed::Factor(10.0));
+    assert_eq!("0.5".parse::<Speed>().unwrap(), Speed::Factor(0.5));
+    assert_eq!("MAX".parse::<Speed>().unwrap(), Speed::Max);
+    assert!("0".parse::<Speed>().is_err());
+    assert!("fast".parse::<Speed>().is_err());
+    assert_eq!("in".parse::<Flow>().unwrap(), Flow::In);
+}
+
+#[test]
+fn room_clients_record_frames_without_the_password() {
+    let dir = scratch_dir("capture-client");
+    let path = dir.join("client.cap");
+    let (server, _events) = start_server(Authenticator::new(), History::new(10), 0);
+    let stream = Stream::from(TcpStream::connect(server.local_addr().unwrap()).unwrap());
+    let recorder = Recorder::create(&path).unwrap();
+    let (client, events) = ChatClient::login_recorded(stream, "alice", "secret", Some(recorder)).unwrap();
+    client.send("hi all").unwrap();
+    loop {
+        match events.recv_timeout(TIMEOUT).expect("user list") {
+            ClientEvent::Users(_) => break,
+            _ => continue,
+        }
+    }
+    client.disconnect();
+
+    let records = capture::read(&path).unwrap();
+    let hello = Frame::Hello { username: "alice".into(), password: String::new() };
+    assert_eq!(records[0].bytes, format!("{}\n", hello.encode()).into_bytes());
+    assert_eq!(records[1].bytes, format!("{}\n", Frame::Welcome.encode()).into_bytes());
+    let sent: Vec<_> = records.iter().filter(|r| r.flow == Flow::Out).collect();
+    let chat = Frame::Chat { from: "alice".into(), text: "hi all".into() };
+    assert_eq!(sent[1].bytes, format!("{}\n", chat.encode()).into_bytes());
+    assert!(records.iter().all(|r| !String::from_utf8_lossy(&r.bytes).contains("secret")));
+    assert!(records.iter().skip(2).any(|r| r.flow == Flow::In), "frames from the server");
+}
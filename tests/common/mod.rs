@@ -0,0 +1,175 @@
+// tests/common/mod.rs
+//! Helpers shared by the integration tests. Everything runs headless over
+//! loopback TCP or FIFOs in a scratch directory; no window is ever opened.
+#![allow(dead_code)]
+
+use crossbeam_channel::Receiver;
+use socat_chat::{
+    auth::Authenticator,
+    heartbeat::Heartbeat,
+    history::History,
+    limits::Limits,
+    moderation::Moderation,
+    protocol::Frame,
+    server::{ChatServer, ServerConfig, ServerEvent},
+};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long any single expectation waits before the test fails.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Login time limit of servers started by [`start_server`].
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Name the server operator uses in the tests.
+pub const HOST: &str = "host";
+
+/// A fresh, empty directory under the system temp dir, unique per call.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "socat_chat-{}-{}-{}",
+        name,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create scratch dir");
+    dir
+}
+
+/// Starts a room server run by [`HOST`] on an ephemeral loopback port.
+pub fn start_server(auth: Authenticator, history: History, replay: usize) -> (ChatServer, Receiver<ServerEvent>) {
+    start_server_with(server_config(auth, history, replay))
+}
+
+/// The configuration [`start_server`] uses, to change before starting.
+pub fn server_config(auth: Authenticator, history: History, replay: usize) -> ServerConfig {
+    ServerConfig {
+        username: HOST.to_string(),
+        history,
+        replay,
+        auth: Arc::new(auth.with_reserved(vec![HOST.to_string()])),
+        handshake_timeout: HANDSHAKE_TIMEOUT,
+        heartbeat: Heartbeat::default(),
+        moderation: Moderation::new(),
+        limits: Limits::default(),
+    }
+}
+
+pub fn start_server_with(config: ServerConfig) -> (ChatServer, Receiver<ServerEvent>) {
+    ChatServer::bind("127.0.0.1:0", config).expect("bind server")
+}
+
+/// Waits for the first item on `events` that `matches` accepts, skipping
+/// everything before it.
+pub fn wait_for<T: std::fmt::Debug>(events: &Receiver<T>, what: &str, matches: impl Fn(&T) -> bool) -> T {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match events.recv_timeout(left) {
+            Ok(event) if matches(&event) => return event,
+            Ok(_) => continue,
+            Err(_) => panic!("timed out waiting for {}", what),
+        }
+    }
+}
+
+/// A scripted client speaking the wire protocol directly, so tests can
+/// check exactly what the server puts on the wire.
+pub struct FakeClient {
+    username: String,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl FakeClient {
+    /// Opens a connection without logging in.
+    pub fn connect(addr: SocketAddr, username: &str) -> FakeClient {
+        let writer = TcpStream::connect(addr).expect("connect to server");
+        writer.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        FakeClient { username: username.to_string(), reader, writer }
+    }
+
+    /// Connects and sends `HELLO`, returning the server's reply.
+    pub fn try_join(addr: SocketAddr, username: &str, password: &str) -> (FakeClient, Frame) {
+        let mut client = FakeClient::connect(addr, username);
+        client.send_frame(&Frame::Hello { username: username.to_string(), password: password.to_string() });
+        let reply = client.recv().expect("server closed the connection during login");
+        (client, reply)
+    }
+
+    /// Connects and logs in, failing the test unless the server welcomes us.
+    pub fn join(addr: SocketAddr, username: &str, password: &str) -> FakeClient {
+        let (client, reply) = FakeClient::try_join(addr, username, password);
+        assert_eq!(reply, Frame::Welcome, "login as {} was not accepted", username);
+        client
+    }
+
+    pub fn send_frame(&mut self, frame: &Frame) {
+        writeln!(self.writer, "{}", frame.encode()).expect("write to server");
+        self.writer.flush().unwrap();
+    }
+
+    /// Sends a chat message as this client.
+    pub fn send(&mut self, text: &str) {
+        let frame = Frame::Chat { from: self.username.clone(), text: text.to_string() };
+        self.send_frame(&frame);
+    }
+
+    /// Next frame from the server, `None` once it closed the connection.
+    /// Fails the test on a timeout or an undecodable line.
+    pub fn recv(&mut self) -> Option<Frame> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Frame::decode(&line).unwrap_or_else(|e| panic!("bad frame {:?}: {}", line, e))),
+            Err(e) => panic!("{} got no frame: {}", self.username, e),
+        }
+    }
+
+    /// Skips frames until one equal to `expected` arrives.
+    pub fn expect(&mut self, expected: &Frame) {
+        self.expect_where(&format!("{:?}", expected), |frame| frame == expected);
+    }
+
+    /// Skips frames until `matches` accepts one, and returns it.
+    pub fn expect_where(&mut self, what: &str, matches: impl Fn(&Frame) -> bool) -> Frame {
+        loop {
+            match self.recv() {
+                Some(frame) if matches(&frame) => return frame,
+                Some(_) => continue,
+                None => panic!("{} was disconnected while waiting for {}", self.username, what),
+            }
+        }
+    }
+
+    /// Expects a chat message with exactly this sender and text.
+    pub fn expect_chat(&mut self, from: &str, text: &str) {
+        self.expect(&Frame::Chat { from: from.to_string(), text: text.to_string() });
+    }
+
+    /// Reads until the server closes the connection.
+    pub fn expect_closed(&mut self) {
+        while let Some(frame) = self.recv() {
+            println!("{} ignoring {:?} before close", self.username, frame);
+        }
+    }
+
+    /// Leaves the room by closing the connection.
+    pub fn disconnect(self) {
+        let _ = self.writer.shutdown(std::net::Shutdown::Both);
+    }
+}
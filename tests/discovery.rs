@@ -0,0 +1,96 @@
+// tests/discovery.rs
+//! Servers announcing themselves and browsers finding them, over loopback
+//! multicast.
+
+use socat_chat::discovery::{Announcement, Announcer, Browser, DiscoveryConfig};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A group of its own per test, so tests running in parallel do not hear
+/// each other.
+fn loopback_config(test: u8) -> DiscoveryConfig {
+    let port = 40000 + (process::id() % 1000) as u16 * 10 + u16::from(test);
+    DiscoveryConfig {
+        group: SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, test), port),
+        interface: Ipv4Addr::LOCALHOST,
+        interval: Duration::from_millis(100),
+    }
+}
+
+fn lobby(users: usize) -> Announcement {
+    Announcement { room: "lobby".to_string(), host: "box".to_string(), port: 8080, users }
+}
+
+/// Polls until `browser` lists `count` servers.
+fn wait_for_servers(browser: &mut Browser, count: usize) -> Vec<socat_chat::discovery::DiscoveredServer> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        browser.poll(Duration::from_millis(50)).unwrap();
+        let servers = browser.servers();
+        if servers.len() == count {
+            return servers;
+        }
+        assert!(Instant::now() < deadline, "expected {} servers, have {:?}", count, servers);
+    }
+}
+
+#[test]
+fn announcements_round_trip() {
+    let announcement = Announcement { room: "tab\there".to_string(), host: "box".to_string(), port: 1, users: 3 };
+    assert_eq!(Announcement::decode(&announcement.encode()), Some(announcement));
+    assert_eq!(Announcement::decode("something else"), None);
+    assert_eq!(Announcement::decode("socat_chat-announce\tlobby\tbox\t8080\tmany"), None);
+}
+
+#[test]
+fn browsers_find_announced_servers_and_forget_stopped_ones() {
+    let config = loopback_config(1);
+    // Two browsers on one machine share the group port.
+    let mut first = Browser::open(config).unwrap();
+    let mut second = Browser::open(config).unwrap();
+
+    let users = Arc::new(AtomicUsize::new(1));
+    let counted = Arc::clone(&users);
+    let announcer = Announcer::start(config, move || lobby(counted.load(Ordering::SeqCst))).unwrap();
+
+    for browser in [&mut first, &mut second] {
+        let servers = wait_for_servers(browser, 1);
+        assert_eq!(servers[0].address.to_string(), "127.0.0.1:8080");
+        assert_eq!(servers[0].label(), "lobby on box (127.0.0.1:8080), 1 user");
+    }
+
+    // Later announcements carry the current user count.
+    users.store(4, Ordering::SeqCst);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while first.servers()[0].announcement.users != 4 {
+        first.poll(Duration::from_millis(50)).unwrap();
+        assert!(Instant::now() < deadline, "user count not updated");
+    }
+
+    announcer.stop();
+    wait_for_servers(&mut first, 0);
+}
+
+#[test]
+fn servers_are_listed_by_room() {
+    let config = loopback_config(2);
+    let mut browser = Browser::open(config).unwrap();
+    let make = |room: &str, port| {
+        let room = room.to_string();
+        Announcer::start(config, move || Announcement { room: room.clone(), port, ..lobby(1) }).unwrap()
+    };
+    let zoo = make("zoo", 9001);
+    let attic = make("attic", 9002);
+    let rooms: Vec<String> =
+        wait_for_servers(&mut browser, 2).into_iter().map(|server| server.announcement.room).collect();
+    assert_eq!(rooms, ["attic", "zoo"]);
+    zoo.stop();
+    attic.stop();
+}
@@ -0,0 +1,144 @@
+// tests/exec.rs
+//! Chatting with local programs: output, errors, exit status, restarts
+//! and pseudo-terminals. Uses `sh` and common tools, so Unix only.
+#![cfg(unix)]
+
+mod common;
+
+use common::TIMEOUT;
+use crossbeam_channel::{unbounded, Receiver};
+use socat_chat::{
+    cancel::Cancel,
+    direct::{self, DirectReceiver},
+    exec::{self, Exec, ExecSpec},
+};
+use std::{process::Command, thread};
+
+fn spec(command: &str) -> ExecSpec {
+    ExecSpec { command: command.to_string(), pty: false }
+}
+
+/// Moves `receiver` to a thread so tests can wait for messages with a
+/// timeout.
+fn incoming(mut receiver: DirectReceiver) -> Receiver<String> {
+    let (sender, messages) = unbounded();
+    thread::spawn(move || {
+        while let Some(Ok(message)) = receiver.recv() {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    messages
+}
+
+fn exits(exec: &Exec) -> Receiver<String> {
+    let (sender, statuses) = unbounded();
+    exec.on_exit(move |status| {
+        let _ = sender.send(exec::describe_exit(status));
+    });
+    statuses
+}
+
+#[test]
+fn specs_take_a_trailing_pty_option() {
+    assert_eq!(ExecSpec::parse("bash -i,pty"), Ok(ExecSpec { command: "bash -i".into(), pty: true }));
+    assert_eq!(ExecSpec::parse("cut -d, -f1"), Ok(spec("cut -d, -f1")));
+    assert_eq!(ExecSpec::parse("python3,pty").unwrap().to_string(), "python3,pty");
+    assert!(ExecSpec::parse(" ").is_err());
+    assert!(ExecSpec::parse(",pty").is_err());
+}
+
+#[test]
+fn lines_go_to_the_program_and_its_answers_come_back() {
+    let cancel = Cancel::new();
+    let exec = Exec::spawn(&spec("while read line; do echo \"got $line\"; done"), &cancel).unwrap();
+    let (mut tx, rx) = direct::split_duplex(exec.duplex(), None);
+    let rx = incoming(rx);
+    tx.send("one").unwrap();
+    tx.send("two").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "got one");
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "got two");
+    assert_eq!(exec.exit_status(), None);
+    cancel.cancel();
+}
+
+#[test]
+fn errors_are_reported_apart_from_output() {
+    let cancel = Cancel::new();
+    let exec = Exec::spawn(&spec("echo out; echo oops >&2; echo again >&2"), &cancel).unwrap();
+    let rx = incoming(direct::split_duplex(exec.duplex(), None).1);
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "out");
+
+    // Lines written before anyone listened are not lost.
+    let statuses = exits(&exec);
+    assert_eq!(statuses.recv_timeout(TIMEOUT).unwrap(), "exited with status 0");
+    let (sender, errors) = unbounded();
+    exec.on_stderr(move |line| sender.send(line).unwrap());
+    assert_eq!(errors.recv_timeout(TIMEOUT).unwrap(), "oops");
+    assert_eq!(errors.recv_timeout(TIMEOUT).unwrap(), "again");
+    cancel.cancel();
+}
+
+#[test]
+fn exits_are_reported_and_programs_can_be_restarted() {
+    let cancel = Cancel::new();
+    let exec = Exec::spawn(&spec("read line; echo \"bye $line\"; exit 3"), &cancel).unwrap();
+    let statuses = exits(&exec);
+    let (mut tx, rx) = direct::split_duplex(exec.duplex(), None);
+    let rx = incoming(rx);
+
+    tx.send("first").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "bye first");
+    assert_eq!(statuses.recv_timeout(TIMEOUT).unwrap(), "exited with status 3");
+    assert_eq!(exec.exit_status().and_then(|status| status.code()), Some(3));
+    let err = tx.send("nobody listens").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+    // The same chat carries on with the new process.
+    exec.restart().unwrap();
+    assert_eq!(exec.exit_status(), None);
+    tx.send("second").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "bye second");
+    assert_eq!(statuses.recv_timeout(TIMEOUT).unwrap(), "exited with status 3");
+    cancel.cancel();
+}
+
+#[test]
+fn restarting_a_running_program_replaces_it_quietly() {
+    let cancel = Cancel::new();
+    let exec = Exec::spawn(&spec("sleep 30"), &cancel).unwrap();
+    let statuses = exits(&exec);
+    let first = exec.id();
+    exec.restart().unwrap();
+    assert_ne!(exec.id(), first);
+    assert!(statuses.recv_timeout(std::time::Duration::from_millis(300)).is_err(), "replaced child reported");
+
+    // Cancelling kills it, which is reported.
+    cancel.cancel();
+    assert_eq!(statuses.recv_timeout(TIMEOUT).unwrap(), "was killed by signal 9");
+}
+
+#[test]
+fn programs_can_run_on_a_pseudo_terminal() {
+    let cancel = Cancel::new();
+    let exec = Exec::spawn(&ExecSpec::parse("tty; cat,pty").unwrap(), &cancel).unwrap();
+    let (mut tx, rx) = direct::split_duplex(exec.duplex(), None);
+    let rx = incoming(rx);
+    let tty = rx.recv_timeout(TIMEOUT).unwrap();
+    assert!(tty.starts_with("/dev/"), "not on a terminal: {}", tty);
+
+    // Echo is off, so each line comes back once, from cat.
+    tx.send("hello").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "hello");
+    assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err(), "line echoed twice");
+    cancel.cancel();
+}
+
+#[test]
+fn exit_descriptions_name_the_code_or_signal() {
+    let status = Command::new("sh").args(["-c", "exit 2"]).status().unwrap();
+    assert_eq!(exec::describe_exit(status), "exited with status 2");
+    let status = Command::new("sh").args(["-c", "kill -TERM $$"]).status().unwrap();
+    assert_eq!(exec::describe_exit(status), "was killed by signal 15");
+}
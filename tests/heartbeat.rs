@@ -0,0 +1,221 @@
+// tests/heartbeat.rs
+//! Noticing vanished peers: the ping frames and control lines, the command
+//! line options, both ends of a room connection giving up on a side that
+//! went silent, and direct chats doing the same.
+
+mod common;
+
+use common::{server_config, start_server_with, wait_for, FakeClient, TIMEOUT};
+use socat_chat::{
+    auth::Authenticator,
+    cli::Args,
+    client::{ChatClient, ClientEvent},
+    direct::{self, Control, DirectReceiver, DirectSender},
+    e2e::{self, E2eError, Session},
+    heartbeat::Heartbeat,
+    history::History,
+    net::{Keepalive, Stream},
+    protocol::{Frame, ProtocolError},
+    server::{ServerConfig, ServerEvent},
+};
+use crossbeam_channel::{unbounded, Receiver};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Pings often and gives up quickly, so tests need not wait long.
+fn brisk() -> Heartbeat {
+    Heartbeat { interval: Duration::from_millis(100), timeout: Duration::from_millis(400), keepalive: None }
+}
+
+fn args(list: &[&str]) -> Args {
+    Args::parse(list.iter().map(|s| s.to_string()))
+}
+
+#[test]
+fn ping_frames_round_trip() {
+    for frame in [Frame::Ping(0), Frame::Pong(1_234_567)] {
+        assert_eq!(Frame::decode(&frame.encode()), Ok(frame));
+    }
+    assert_eq!(Frame::decode("PING\tsoon"), Err(ProtocolError::InvalidField("token")));
+    assert_eq!(Frame::decode("PONG"), Err(ProtocolError::MissingField("token")));
+}
+
+/// A connected pair of sockets: (listening side, calling side).
+fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let calling = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    (listener.accept().unwrap().0, calling)
+}
+
+/// Both ends of a connection after the end-to-end handshake.
+fn sealed_pair() -> ((TcpStream, Session), (TcpStream, Session)) {
+    let (mut a, mut b) = socket_pair();
+    let peer = thread::spawn(move || {
+        let session = e2e::handshake(&mut b).unwrap();
+        (b, session)
+    });
+    let a_session = e2e::handshake(&mut a).unwrap();
+    ((a, a_session), peer.join().unwrap())
+}
+
+/// One end of a direct chat keeping `heartbeat`, with the round trips it
+/// measured.
+fn direct_end(
+    socket: TcpStream,
+    session: Option<Session>,
+    heartbeat: Heartbeat,
+) -> (Arc<Mutex<DirectSender>>, DirectReceiver, Receiver<Duration>) {
+    let (sender, mut receiver) = direct::split(socket.try_clone().unwrap(), session).unwrap();
+    let sender = Arc::new(Mutex::new(sender));
+    let (measured, round_trips) = unbounded();
+    direct::keep_alive(&heartbeat, Stream::Tcp(socket), &sender, &mut receiver, move |rtt| {
+        let _ = measured.send(rtt);
+    }).unwrap();
+    (sender, receiver, round_trips)
+}
+
+#[test]
+fn control_lines_round_trip() {
+    for control in [Control::Ping(0), Control::Pong(98_765)] {
+        assert_eq!(Control::decode(&control.encode()), Some(control));
+    }
+    assert_eq!(Control::encode(&Control::Ping(5)), "\\ping 5");
+    assert_eq!(Control::decode("ping 5"), None);
+    assert_eq!(Control::decode("\\ping soon"), None);
+    assert_eq!(Control::decode("\\\\ping 5"), None);
+}
+
+#[test]
+fn heartbeat_options_are_checked() {
+    assert_eq!(Heartbeat::from_args(&args(&[])), Ok(Heartbeat::default()));
+    let heartbeat = Heartbeat::from_args(&args(&["--ping-interval=5", "--ping-timeout=20", "--keepalive=30,5,3"])).unwrap();
+    assert_eq!(heartbeat.interval, Duration::from_secs(5));
+    assert_eq!(heartbeat.timeout, Duration::from_secs(20));
+    assert_eq!(
+        heartbeat.keepalive,
+        Some(Keepalive { idle: Duration::from_secs(30), interval: Duration::from_secs(5), retries: 3 })
+    );
+
+    let off = Heartbeat::from_args(&args(&["--ping-interval=0", "--ping-timeout=0", "--no-keepalive"])).unwrap();
+    assert!(!off.enabled());
+    assert_eq!(off.keepalive, None);
+
+    assert!(Heartbeat::from_args(&args(&["--ping-interval=30", "--ping-timeout=30"])).is_err());
+    assert!(Keepalive::from_args(&args(&["--keepalive=0"])).is_err());
+    assert!(Keepalive::from_args(&args(&["--keepalive=1,2,3,4"])).is_err());
+    assert_eq!(Keepalive::from_args(&args(&["--keepalive=90"])).unwrap().unwrap().idle, Duration::from_secs(90));
+}
+
+#[test]
+fn keepalive_can_be_set_on_tcp_streams() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = Stream::Tcp(TcpStream::connect(listener.local_addr().unwrap()).unwrap());
+    stream.set_keepalive(Some(Keepalive::default())).unwrap();
+    stream.set_keepalive(None).unwrap();
+}
+
+#[test]
+fn the_server_answers_pings_and_drops_silent_clients() {
+    let config = ServerConfig { heartbeat: brisk(), ..server_config(Authenticator::new(), History::new(10), 0) };
+    let (server, events) = start_server_with(config);
+    let addr = server.local_addr().unwrap();
+
+    let mut alice = FakeClient::join(addr, "alice", "");
+    alice.send_frame(&Frame::Ping(7));
+    alice.expect(&Frame::Pong(7));
+
+    // Alice stops answering the server's pings and is dropped for it.
+    alice.expect_where("a ping", |f| matches!(f, Frame::Ping(_)));
+    alice.expect_closed();
+    wait_for(&events, "alice leaving", |e| *e == ServerEvent::Notice("alice left the chat".into()));
+}
+
+#[test]
+fn a_client_measures_round_trips_and_stays_connected() {
+    let config = ServerConfig { heartbeat: brisk(), ..server_config(Authenticator::new(), History::new(10), 0) };
+    let (server, events) = start_server_with(config);
+    let addr = server.local_addr().unwrap();
+    let stream = Stream::Tcp(TcpStream::connect(addr).unwrap());
+    let (bob, bob_events) = ChatClient::login_with(stream, "bob", "", None, brisk()).unwrap();
+
+    wait_for(&bob_events, "a round trip", |e| matches!(e, ClientEvent::RoundTrip(rtt) if *rtt < TIMEOUT));
+    // Well past the timeout bob is still connected, since the client answers pings.
+    thread::sleep(Duration::from_secs(1));
+    bob.send("still here").unwrap();
+    wait_for(&events, "bob's message", |e| *e == ServerEvent::Chat { from: "bob".into(), text: "still here".into() });
+}
+
+#[test]
+fn a_client_gives_up_on_a_silent_server() {
+    // Logs the client in, then never says another word.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut hello = String::new();
+        BufReader::new(stream.try_clone().unwrap()).read_line(&mut hello).unwrap();
+        writeln!(stream, "{}", Frame::Welcome.encode()).unwrap();
+        thread::sleep(TIMEOUT);
+    });
+
+    let stream = Stream::Tcp(TcpStream::connect(addr).unwrap());
+    let (_client, events) = ChatClient::login_with(stream, "bob", "", None, brisk()).unwrap();
+    let ended = wait_for(&events, "the disconnect", |e| matches!(e, ClientEvent::Disconnected(_)));
+    assert_eq!(ended, ClientEvent::Disconnected(Some("no sign of life for 400ms".into())));
+}
+
+#[test]
+fn direct_chats_measure_round_trips_and_stay_connected() {
+    let ((a, a_session), (b, b_session)) = sealed_pair();
+    let (a_tx, mut a_rx, a_round_trips) = direct_end(a, Some(a_session), brisk());
+    let (_b_tx, mut b_rx, b_round_trips) = direct_end(b, Some(b_session), brisk());
+    // Pings are answered while waiting for messages.
+    thread::spawn(move || while let Some(Ok(_)) = a_rx.recv() {});
+    let (received, b_messages) = unbounded();
+    thread::spawn(move || {
+        while let Some(message) = b_rx.recv() {
+            if received.send(message).is_err() {
+                return;
+            }
+        }
+    });
+
+    // Well past the timeout both ends are still connected.
+    thread::sleep(Duration::from_secs(1));
+    a_tx.lock().unwrap().send("still here").unwrap();
+    assert_eq!(b_messages.recv_timeout(TIMEOUT).unwrap().unwrap(), "still here");
+    for round_trips in [a_round_trips, b_round_trips] {
+        assert!(round_trips.recv_timeout(TIMEOUT).unwrap() < TIMEOUT);
+    }
+}
+
+#[test]
+fn a_direct_chat_gives_up_on_a_silent_peer() {
+    let ((a, a_session), (_b, _)) = sealed_pair();
+    let (_a_tx, mut a_rx, _) = direct_end(a, Some(a_session), brisk());
+
+    match a_rx.recv() {
+        Some(Err(E2eError::Io(e))) => assert_eq!(brisk().explain(e).to_string(), "no sign of life for 400ms"),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn plain_direct_chats_do_not_ping() {
+    let (a, b) = socket_pair();
+    let (_a_tx, mut a_rx, _) = direct_end(a, None, brisk());
+    b.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    // Like socat, the peer knows nothing of pings and gets none.
+    let mut line = String::new();
+    let nothing = BufReader::new(b.try_clone().unwrap()).read_line(&mut line).unwrap_err();
+    assert!(matches!(nothing.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{}", nothing);
+    // And what it types is a message, even if it looks like a ping.
+    writeln!(&b, "\\ping 5").unwrap();
+    assert_eq!(a_rx.recv().unwrap().unwrap(), "\\ping 5");
+}
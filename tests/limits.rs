@@ -0,0 +1,176 @@
+// tests/limits.rs
+//! Flood protection: rate limits, the longest line, connection counts and
+//! clients that stop reading, alone and enforced by the room server.
+
+mod common;
+
+use common::{server_config, start_server_with, wait_for, FakeClient, TIMEOUT};
+use socat_chat::{
+    auth::{AuthError, Authenticator},
+    cli::Args,
+    history::History,
+    limits::{read_line_limited, Connections, Limits, TokenBucket},
+    protocol::Frame,
+    server::{ChatServer, ServerConfig, ServerEvent},
+};
+use crossbeam_channel::Receiver;
+use std::{
+    io::{self, Cursor},
+    net::{IpAddr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A room server with these limits.
+fn start(limits: Limits) -> (ChatServer, Receiver<ServerEvent>) {
+    start_server_with(ServerConfig { limits, ..server_config(Authenticator::new(), History::new(10), 0) })
+}
+
+/// Joins as soon as there is room, which may take the server a moment
+/// after someone left.
+fn join_when_possible(addr: SocketAddr, username: &str) -> FakeClient {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match FakeClient::try_join(addr, username, "") {
+            (client, Frame::Welcome) => return client,
+            (_, reply) if Instant::now() > deadline => panic!("{} was not let in: {:?}", username, reply),
+            _ => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+#[test]
+fn token_buckets_let_bursts_through_and_slow_floods() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(10.0);
+    for _ in 0..20 {
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+    }
+    assert_eq!(bucket.take(1.0, start), Duration::from_millis(100));
+    // A second later ten more went in, one of them owed already.
+    assert_eq!(bucket.take(9.0, start + Duration::from_secs(1)), Duration::ZERO);
+    assert!(bucket.take(1.0, start + Duration::from_secs(1)) > Duration::ZERO);
+
+    let mut unlimited = TokenBucket::new(0.0);
+    assert_eq!(unlimited.take(1e9, start), Duration::ZERO);
+
+    let limits = Limits { messages_per_second: 2, bytes_per_second: 100, ..Limits::default() };
+    let mut throttle = limits.throttle();
+    assert_eq!(throttle.frame(10, start), Duration::ZERO);
+    assert_eq!(throttle.frame(290, start), Duration::from_secs(1));
+}
+
+#[test]
+fn lines_longer_than_the_limit_are_refused() {
+    let mut input = Cursor::new(format!("short\n0123456789\n{}\n", "x".repeat(20)));
+    let mut line = String::new();
+    assert_eq!(read_line_limited(&mut input, 10, &mut line).unwrap(), 6);
+    line.clear();
+    assert_eq!(read_line_limited(&mut input, 10, &mut line).unwrap(), 11);
+    line.clear();
+    let error = read_line_limited(&mut input, 10, &mut line).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "message longer than 10 bytes");
+
+    let mut unterminated = Cursor::new("abc");
+    line.clear();
+    assert_eq!(read_line_limited(&mut unterminated, 10, &mut line).unwrap(), 3);
+}
+
+#[test]
+fn limit_options_are_checked() {
+    let args = |list: &[&str]| Args::parse(list.iter().map(|s| s.to_string()));
+    assert_eq!(Limits::from_args(&args(&[])), Ok(Limits::default()));
+    let limits = Limits::from_args(&args(&["--max-rate=0", "--max-message=100", "--send-timeout=3"])).unwrap();
+    assert_eq!(limits.messages_per_second, 0);
+    assert_eq!(limits.max_message, 100);
+    assert_eq!(limits.send_timeout, Duration::from_secs(3));
+    assert!(Limits::from_args(&args(&["--max-message=0"])).is_err());
+    assert!(Limits::from_args(&args(&["--max-clients=many"])).is_err());
+}
+
+#[test]
+fn connections_are_counted_per_address_and_in_total() {
+    let connections = Connections::new(&Limits { max_per_address: 2, max_connections: 3, ..Limits::default() });
+    let (a, b, c): (IpAddr, IpAddr, IpAddr) =
+        ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap(), "192.0.2.3".parse().unwrap());
+    let first = connections.admit(Some(a)).unwrap();
+    let _second = connections.admit(Some(a)).unwrap();
+    assert_eq!(connections.admit(Some(a)).unwrap_err(), AuthError::TooManyConnections);
+    let _third = connections.admit(Some(b)).unwrap();
+    assert_eq!(connections.admit(Some(c)).unwrap_err(), AuthError::RoomFull);
+    assert_eq!(connections.count(), 3);
+
+    drop(first);
+    assert_eq!(connections.count(), 2);
+    connections.admit(Some(a)).unwrap();
+
+    // Unix socket peers have no address and only count towards the total.
+    let local = Connections::new(&Limits { max_per_address: 2, max_connections: 4, ..Limits::default() });
+    let _locals: Vec<_> = (0..4).map(|_| local.admit(None).unwrap()).collect();
+    assert_eq!(local.admit(None).unwrap_err(), AuthError::RoomFull);
+}
+
+#[test]
+fn the_server_turns_away_connections_over_the_limit() {
+    let (server, events) = start(Limits { max_per_address: 2, ..Limits::default() });
+    let addr = server.local_addr().unwrap();
+    let alice = FakeClient::join(addr, "alice", "");
+    let _bob = FakeClient::join(addr, "bob", "");
+    let (_, reply) = FakeClient::try_join(addr, "carol", "");
+    assert_eq!(reply, Frame::Rejected("too many connections from your address".into()));
+
+    alice.disconnect();
+    wait_for(&events, "alice leaving", |e| *e == ServerEvent::Notice("alice left the chat".into()));
+    join_when_possible(addr, "carol");
+}
+
+#[test]
+fn overlong_lines_end_the_connection() {
+    let (server, events) = start(Limits { max_message: 100, ..Limits::default() });
+    let mut alice = FakeClient::join(server.local_addr().unwrap(), "alice", "");
+    alice.send(&"x".repeat(200));
+    alice.expect(&Frame::System("Disconnected: message longer than 100 bytes".into()));
+    alice.expect_closed();
+    wait_for(&events, "alice leaving", |e| *e == ServerEvent::Notice("alice left the chat".into()));
+}
+
+#[test]
+fn floods_are_slowed_to_the_rate() {
+    let (server, _events) = start(Limits { messages_per_second: 5, ..Limits::default() });
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let mut bob = FakeClient::join(addr, "bob", "");
+
+    // Ten go through at once, the next ten at five a second.
+    let started = Instant::now();
+    for n in 0..20 {
+        alice.send(&format!("spam {}", n));
+    }
+    for n in 0..20 {
+        bob.expect_chat("alice", &format!("spam {}", n));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(1500), "took only {:?}", started.elapsed());
+    alice.expect(&Frame::System("You are sending too fast; the server is slowing you down".into()));
+}
+
+#[test]
+fn a_client_that_stops_reading_holds_up_nobody_else() {
+    let limits = Limits { send_timeout: Duration::from_millis(500), ..Limits::default() };
+    let (server, events) = start(limits);
+    let addr = server.local_addr().unwrap();
+    let _stalled = FakeClient::join(addr, "bob", "");
+    let mut carol = FakeClient::join(addr, "carol", "");
+
+    // Far more than the socket buffers hold; bob reads none of it.
+    let text = "y".repeat(60_000);
+    let started = Instant::now();
+    for _ in 0..300 {
+        server.send(&text);
+    }
+    assert!(started.elapsed() < Duration::from_secs(1), "sending blocked for {:?}", started.elapsed());
+    for _ in 0..300 {
+        carol.expect_where("the host's message", |f| matches!(f, Frame::Chat { text: t, .. } if *t == text));
+    }
+    wait_for(&events, "bob being dropped", |e| *e == ServerEvent::Notice("bob left the chat".into()));
+}
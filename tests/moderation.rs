@@ -0,0 +1,145 @@
+// tests/moderation.rs
+//! Operator commands: parsing them, the ban list and audit log files, and
+//! the room server enforcing kicks, bans, mutes, operator rights and topics.
+
+mod common;
+
+use common::{scratch_dir, server_config, start_server, start_server_with, wait_for, FakeClient, HOST};
+use socat_chat::{
+    auth::Authenticator,
+    client::{ChatClient, ClientEvent},
+    history::History,
+    moderation::{AuditLog, BanList, Command, Moderation, Target},
+    protocol::Frame,
+    server::{ServerConfig, ServerEvent},
+};
+use std::{fs, net::IpAddr};
+
+fn system(text: &str) -> Frame {
+    Frame::System(text.to_string())
+}
+
+#[test]
+fn commands_parse_and_print_as_typed() {
+    let kick = Command::parse("/kick bob  flooding the room ").unwrap();
+    assert_eq!(kick, Command::Kick { user: "bob".into(), reason: Some("flooding the room".into()) });
+    assert_eq!(kick.to_string(), "/kick bob flooding the room");
+    assert_eq!(kick.announcement("alice"), "alice kicked bob (flooding the room)");
+
+    let ip: IpAddr = "10.0.0.5".parse().unwrap();
+    assert_eq!(Command::parse("/ban 10.0.0.5"), Ok(Command::Ban { target: Target::Address(ip), reason: None }));
+    assert_eq!(Command::parse("/unban carol"), Ok(Command::Unban(Target::User("carol".into()))));
+    assert_eq!(Command::parse("/topic  Release day"), Ok(Command::Topic("Release day".into())));
+    assert_eq!(Command::parse("/topic").unwrap().announcement("alice"), "alice cleared the topic");
+    assert_eq!(Command::parse("/deop bob").unwrap().user(), Some("bob"));
+
+    assert_eq!(Command::parse("/op"), Err("usage: /op <user>".into()));
+    assert_eq!(Command::parse("/mute bob now"), Err("usage: /mute <user>".into()));
+    assert_eq!(Command::parse("/dance"), Err("unknown command /dance".into()));
+    assert!(Command::parse("kick bob").is_err());
+}
+
+#[test]
+fn the_ban_list_is_kept_in_its_file() {
+    let dir = scratch_dir("bans");
+    let path = dir.join("bans.txt");
+    let ip: IpAddr = "192.0.2.7".parse().unwrap();
+    let elsewhere: IpAddr = "192.0.2.8".parse().unwrap();
+
+    let mut bans = BanList::open(&path).unwrap();
+    assert!(bans.ban(Target::User("mallory".into())).unwrap());
+    assert!(bans.ban(Target::Address(ip)).unwrap());
+    assert!(!bans.ban(Target::Address(ip)).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "user mallory\nip 192.0.2.7\n");
+
+    let mut reopened = BanList::open(&path).unwrap();
+    assert!(reopened.is_banned("mallory", Some(elsewhere)));
+    assert!(reopened.is_banned("mallory", None));
+    assert!(reopened.is_banned("alice", Some(ip)));
+    assert!(!reopened.is_banned("alice", Some(elsewhere)));
+    assert!(!reopened.is_banned("alice", None));
+    assert!(reopened.unban(&Target::User("mallory".into())).unwrap());
+    assert!(!reopened.unban(&Target::User("mallory".into())).unwrap());
+    assert_eq!(BanList::open(&path).unwrap().bans(), [Target::Address(ip)]);
+
+    let broken = dir.join("broken.txt");
+    fs::write(&broken, "# bans\nuser mallory\nip nowhere\n").unwrap();
+    let error = BanList::open(&broken).unwrap_err().to_string();
+    assert!(error.ends_with("broken.txt:3: malformed ban entry"), "{}", error);
+}
+
+#[test]
+fn operators_kick_and_ban_and_everything_is_audited() {
+    let dir = scratch_dir("moderation");
+    let (bans, audit) = (dir.join("bans.txt"), dir.join("audit.log"));
+    let moderation = Moderation::new()
+        .with_bans(BanList::open(&bans).unwrap())
+        .with_audit(AuditLog::open(&audit).unwrap());
+    let config = ServerConfig { moderation, ..server_config(Authenticator::new(), History::new(10), 0) };
+    let (server, events) = start_server_with(config);
+    let addr = server.local_addr().unwrap();
+
+    let (_alice, alice_events) = ChatClient::connect(&addr.to_string(), "alice", "").unwrap();
+    let mut bob = FakeClient::join(addr, "bob", "");
+    bob.send_frame(&Frame::Command("/kick alice".into()));
+    bob.expect(&system("only operators can use /kick"));
+
+    // Once made an operator, bob can.
+    server.command("/op bob").unwrap();
+    bob.expect(&system("host made bob an operator"));
+    bob.send_frame(&Frame::Command("/kick alice spam".into()));
+    wait_for(&alice_events, "the kick", |e| *e == ClientEvent::Kicked("bob kicked alice (spam)".into()));
+    wait_for(&alice_events, "the disconnect", |e| matches!(e, ClientEvent::Disconnected(_)));
+    bob.expect(&system("bob kicked alice (spam)"));
+    wait_for(&events, "alice leaving", |e| *e == ServerEvent::Notice("alice left the chat".into()));
+
+    // Nobody acts on the host, and commands that change nothing are refused.
+    assert_eq!(server.command("/kick host"), Err("host runs the room and cannot be the target of /kick host".into()));
+    assert_eq!(server.command("/kick alice"), Err("alice is not here".into()));
+
+    // A ban keeps alice out, even after a restart.
+    server.command("/ban alice").unwrap();
+    let (_, reply) = FakeClient::try_join(addr, "alice", "");
+    assert_eq!(reply, Frame::Rejected("banned from this room".into()));
+    assert!(BanList::open(&bans).unwrap().is_banned("alice", "192.0.2.1".parse().ok()));
+
+    // Banning the address throws out everyone connecting from it.
+    server.command("/ban 127.0.0.1").unwrap();
+    bob.expect(&Frame::Kicked("host banned 127.0.0.1".into()));
+    bob.expect_closed();
+
+    let log = fs::read_to_string(&audit).unwrap();
+    let commands: Vec<&str> = log.lines().map(|line| line.split_once('\t').unwrap().1).collect();
+    assert_eq!(commands, ["host\t/op bob", "bob\t/kick alice spam", "host\t/ban alice", "host\t/ban 127.0.0.1"]);
+}
+
+#[test]
+fn muted_users_go_unheard_and_newcomers_learn_the_topic() {
+    let (server, _events) = start_server(Authenticator::new(), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+    server.command("/topic Release day").unwrap();
+
+    let mut alice = FakeClient::join(addr, "alice", "");
+    alice.expect(&system("Topic: Release day"));
+    let mut bob = FakeClient::join(addr, "bob", "");
+    alice.expect_where("bob's join notice", |f| matches!(f, Frame::System(t) if t.starts_with("bob joined")));
+
+    server.command("/mute alice").unwrap();
+    bob.expect(&system("host muted alice"));
+    alice.send("anyone?");
+    alice.expect(&system("You are muted; nobody saw that"));
+    alice.send_frame(&Frame::Post { id: 3, from: "alice".into(), text: "hello?".into() });
+    alice.expect(&Frame::Ack { id: 3 });
+    assert_eq!(server.command("/mute alice"), Err("alice is already muted".into()));
+
+    server.command("/unmute alice").unwrap();
+    alice.send("back");
+    let heard = bob.expect_where("alice's next message", |f| matches!(f, Frame::Chat { .. } | Frame::Post { .. }));
+    assert_eq!(heard, Frame::Chat { from: "alice".into(), text: "back".into() });
+
+    // Only the host may change the topic here.
+    alice.send_frame(&Frame::Command("/topic mine now".into()));
+    alice.expect(&system("only operators can use /topic"));
+    server.command("/topic").unwrap();
+    bob.expect(&system(&format!("{} cleared the topic", HOST)));
+}
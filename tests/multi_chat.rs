@@ -0,0 +1,261 @@
+// tests/multi_chat.rs
+//! The `multi_chat` room server and client, driven over loopback TCP.
+
+mod common;
+
+use common::{start_server, wait_for, FakeClient, HANDSHAKE_TIMEOUT, HOST};
+use crossbeam_channel::Receiver;
+use socat_chat::{
+    auth::{password_from, AuthError, Authenticator, LoginLimiter, UserDb},
+    cli::Args,
+    client::{ChatClient, ClientEvent},
+    history::{History, HistoryEntry},
+    protocol::Frame,
+    server::{ChatServer, ServerEvent},
+};
+use std::{
+    fs,
+    io::Cursor,
+    net::{IpAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// A room without passwords or history on disk.
+fn open_room() -> (ChatServer, Receiver<ServerEvent>) {
+    start_server(Authenticator::new(), History::new(100), 20)
+}
+
+#[test]
+fn messages_reach_everyone_but_the_sender() {
+    let (server, events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let mut bob = FakeClient::join(addr, "bob", "");
+
+    alice.send("hello bob");
+    bob.expect_chat("alice", "hello bob");
+    wait_for(&events, "alice's message", |e| {
+        *e == ServerEvent::Chat { from: "alice".into(), text: "hello bob".into() }
+    });
+
+    server.send("hello both");
+    bob.expect_chat(HOST, "hello both");
+    // Alice's own message must not be echoed back before the operator's.
+    let next = alice.expect_where("a chat message", |f| matches!(f, Frame::Chat { .. }));
+    assert_eq!(next, Frame::Chat { from: HOST.into(), text: "hello both".into() });
+}
+
+#[test]
+fn multi_line_messages_arrive_whole() {
+    let (server, _events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let mut bob = FakeClient::join(addr, "bob", "");
+
+    alice.send("first line\nsecond\tline");
+    bob.expect_chat("alice", "first line\nsecond\tline");
+}
+
+#[test]
+fn joins_and_leaves_are_announced() {
+    let (server, events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    alice.expect(&Frame::Users(vec!["alice".into(), HOST.into()]));
+
+    let bob = FakeClient::join(addr, "bob", "");
+    alice.expect_where("bob's join notice", |f| matches!(f, Frame::System(t) if t.starts_with("bob joined")));
+    alice.expect(&Frame::Users(vec!["alice".into(), "bob".into(), HOST.into()]));
+
+    bob.disconnect();
+    alice.expect(&Frame::System("bob left the chat".into()));
+    alice.expect(&Frame::Users(vec!["alice".into(), HOST.into()]));
+    wait_for(&events, "bob leaving", |e| *e == ServerEvent::Notice("bob left the chat".into()));
+    assert_eq!(server.users(), vec!["alice".to_string(), HOST.to_string()]);
+}
+
+#[test]
+fn new_clients_get_recent_history() {
+    let mut history = History::new(100);
+    for n in 0..5 {
+        history.push(HistoryEntry::now("old", &format!("message {}", n))).unwrap();
+    }
+    let (server, _events) = start_server(Authenticator::new(), history, 3);
+
+    let mut alice = FakeClient::join(server.local_addr().unwrap(), "alice", "");
+    for n in 2..5 {
+        alice.expect_where("history entry", |f| {
+            matches!(f, Frame::History { from, text, .. } if from == "old" && *text == format!("message {}", n))
+        });
+    }
+    alice.expect(&Frame::System("End of history (3 messages)".into()));
+}
+
+#[test]
+fn history_files_keep_only_the_recent_entries() {
+    let path = History::room_path(&common::scratch_dir("history"), "lobby");
+    let line_count = || fs::read_to_string(&path).unwrap().lines().count();
+    let mut history = History::open(3, &path).unwrap();
+    for n in 0..5 {
+        history.push(HistoryEntry::now("alice", &format!("message {}", n))).unwrap();
+    }
+    // Cut back once it reached twice the capacity.
+    assert_eq!(line_count(), 5);
+    history.push(HistoryEntry::now("alice", "message 5")).unwrap();
+    assert_eq!(line_count(), 3);
+    history.push(HistoryEntry::now("alice", "message 6")).unwrap();
+    assert_eq!(line_count(), 4);
+
+    // Reopening keeps the last three and drops the rest from the file.
+    let reopened = History::open(3, &path).unwrap();
+    let texts: Vec<&str> = reopened.recent(3).map(|entry| entry.text.as_str()).collect();
+    assert_eq!(texts, ["message 4", "message 5", "message 6"]);
+    assert_eq!(line_count(), 3);
+}
+
+#[test]
+fn messages_sent_in_the_room_are_replayed_later() {
+    let (server, events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    alice.send("remember me");
+    // Once the operator saw it, it is in the history.
+    wait_for(&events, "alice's message", |e| matches!(e, ServerEvent::Chat { .. }));
+    let mut bob = FakeClient::join(addr, "bob", "");
+    bob.expect_where("replayed message", |f| matches!(f, Frame::History { text, .. } if text == "remember me"));
+}
+
+#[test]
+fn wrong_room_password_is_rejected() {
+    let (server, _events) = start_server(Authenticator::new().with_room_password("secret"), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+
+    let (mut intruder, reply) = FakeClient::try_join(addr, "mallory", "guess");
+    assert!(matches!(reply, Frame::Rejected(_)), "got {:?}", reply);
+    intruder.expect_closed();
+
+    FakeClient::join(addr, "alice", "secret");
+}
+
+#[test]
+fn passwords_come_from_the_option_a_prompt_or_the_environment() {
+    let args = |list: &[&str]| Args::parse(list.iter().map(|s| s.to_string()));
+    let env = || Some("from env".to_string());
+    let mut typed = Cursor::new("typed\r\nignored\n");
+    assert_eq!(password_from(&args(&["--password=given"]), env(), &mut typed).unwrap(), "given");
+    assert_eq!(password_from(&args(&[]), env(), &mut typed).unwrap(), "from env");
+    assert_eq!(password_from(&args(&[]), None, &mut typed).unwrap(), "");
+    assert_eq!(password_from(&args(&["--password"]), env(), &mut typed).unwrap(), "typed");
+}
+
+#[test]
+fn only_wrong_passwords_lock_an_address_out() {
+    let ip = Some("192.0.2.1".parse().unwrap());
+    let limiter = || LoginLimiter::new(2, Duration::from_secs(60), Duration::from_secs(60));
+    let auth = Authenticator::new().with_reserved(vec!["host".to_string()]).with_limiter(limiter());
+    for _ in 0..5 {
+        assert_eq!(auth.authenticate(ip, "al ice", ""), Err(AuthError::InvalidName));
+        assert_eq!(auth.authenticate(ip, "Host", ""), Err(AuthError::ReservedName));
+    }
+    auth.authenticate(ip, "alice", "").unwrap();
+
+    let auth = Authenticator::new().with_room_password("secret").with_limiter(limiter());
+    for _ in 0..2 {
+        assert_eq!(auth.authenticate(ip, "alice", "guess"), Err(AuthError::BadCredentials));
+    }
+    assert_eq!(auth.authenticate(ip, "alice", "secret"), Err(AuthError::TooManyAttempts));
+}
+
+#[test]
+fn failures_are_forgotten_once_over() {
+    let mut limiter = LoginLimiter::new(5, Duration::from_millis(100), Duration::from_millis(100));
+    for n in 0..=255u8 {
+        limiter.record_failure(Some(IpAddr::from([192, 0, 2, n])));
+    }
+    assert_eq!(limiter.tracked(), 256);
+    thread::sleep(Duration::from_millis(200));
+    limiter.record_failure(Some("198.51.100.1".parse().unwrap()));
+    assert_eq!(limiter.tracked(), 1);
+}
+
+#[test]
+fn users_file_logins_are_checked() {
+    let dir = common::scratch_dir("users");
+    let path = dir.join("users");
+    UserDb::add_user(&path, "alice", "wonderland").unwrap();
+    let auth = Authenticator::new().with_users_file(&path).unwrap();
+    let (server, _events) = start_server(auth, History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+
+    let (_, reply) = FakeClient::try_join(addr, "bob", "wonderland");
+    assert!(matches!(reply, Frame::Rejected(_)), "got {:?}", reply);
+    FakeClient::join(addr, "alice", "wonderland");
+}
+
+#[test]
+fn taken_and_reserved_names_are_rejected() {
+    let (server, _events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let _alice = FakeClient::join(addr, "alice", "");
+
+    let (_, reply) = FakeClient::try_join(addr, "alice", "");
+    assert!(matches!(reply, Frame::Rejected(_)), "duplicate name got {:?}", reply);
+
+    let (_, reply) = FakeClient::try_join(addr, HOST, "");
+    assert!(matches!(reply, Frame::Rejected(_)), "operator's name got {:?}", reply);
+}
+
+#[test]
+fn chat_client_talks_to_the_server() {
+    let (server, _events) = open_room();
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+
+    let (client, client_events) = ChatClient::connect(&addr.to_string(), "bob", "").unwrap();
+    alice.expect_where("bob's join notice", |f| matches!(f, Frame::System(t) if t.starts_with("bob joined")));
+
+    client.send("hi from bob").unwrap();
+    alice.expect_chat("bob", "hi from bob");
+
+    alice.send("hi from alice");
+    wait_for(&client_events, "alice's message", |e| {
+        *e == ClientEvent::Chat { from: "alice".into(), text: "hi from alice".into() }
+    });
+
+    client.disconnect();
+    wait_for(&client_events, "disconnect", |e| matches!(e, ClientEvent::Disconnected(_)));
+}
+
+#[test]
+fn chat_client_reports_rejected_login() {
+    let (server, _events) = start_server(Authenticator::new().with_room_password("secret"), History::new(10), 0);
+    let err = ChatClient::connect(&server.local_addr().unwrap().to_string(), "bob", "nope")
+        .err()
+        .expect("login should fail");
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn silent_connections_are_dropped_after_the_handshake_timeout() {
+    let (server, _events) = open_room();
+    let mut lurker = FakeClient::connect(server.local_addr().unwrap(), "lurker");
+    // Never says HELLO; the server hangs up instead of waiting forever.
+    lurker.expect_closed();
+
+    // Logged-in clients may stay quiet for longer than that.
+    let mut alice = FakeClient::join(server.local_addr().unwrap(), "alice", "");
+    thread::sleep(HANDSHAKE_TIMEOUT * 2);
+    server.send("still there?");
+    alice.expect_chat(HOST, "still there?");
+}
+
+#[test]
+fn shutdown_disconnects_everyone() {
+    let (server, events) = open_room();
+    let mut alice = FakeClient::join(server.local_addr().unwrap(), "alice", "");
+    server.shutdown();
+    alice.expect_closed();
+    wait_for(&events, "stop", |e| *e == ServerEvent::Stopped);
+    assert!(TcpStream::connect(server.local_addr().unwrap()).is_err(), "listener still open");
+}
@@ -0,0 +1,153 @@
+// tests/net.rs
+//! Connecting, accepting and cancelling over loopback.
+
+use socat_chat::{
+    cancel::Cancel,
+    net::{self, Peer, Stream},
+};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn ipv6_available() -> bool {
+    TcpListener::bind("[::1]:0").is_ok()
+}
+
+#[test]
+fn addresses_need_host_and_port() {
+    assert!(net::validate_address("127.0.0.1:8080").is_ok());
+    assert!(net::validate_address("localhost:0").is_ok());
+    assert!(net::validate_address("0.0.0.0").is_err());
+    assert!(net::validate_address("host:http").is_err());
+    assert!(net::validate_address("host:70000").is_err());
+}
+
+#[test]
+fn host_and_port_are_split() {
+    assert_eq!(net::split_host_port("example.org:80"), Ok(("example.org", 80)));
+    assert_eq!(net::split_host_port("[::1]:8080"), Ok(("::1", 8080)));
+    assert_eq!(net::split_host_port(":8080"), Ok(("", 8080)));
+    assert_eq!(net::split_host_port("*:8080"), Ok(("*", 8080)));
+    assert!(net::split_host_port("::1:8080").unwrap_err().contains("brackets"));
+    assert!(net::split_host_port("[::1]").is_err());
+    assert!(net::split_host_port("[::1:8080").is_err());
+}
+
+#[test]
+fn families_alternate_starting_with_the_first() {
+    let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "10.0.0.1:1", "10.0.0.2:1", "10.0.0.3:1"]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+    let ordered: Vec<String> = net::interleave_families(addrs).iter().map(|a| a.to_string()).collect();
+    assert_eq!(ordered, ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "10.0.0.3:1"]);
+}
+
+#[test]
+fn connecting_falls_back_to_the_next_address() {
+    // A closed port first, then the real one: the first refusal must not
+    // end the attempt.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = TcpListener::bind("127.0.0.2:0").map(|l| l.local_addr().unwrap());
+    let Ok(closed) = closed else { return };
+    let addrs = vec![closed, listener.local_addr().unwrap()];
+    let started = Instant::now();
+    let stream = net::connect_to(addrs, Duration::from_secs(5)).unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn connect_resolves_host_names() {
+    let listener = net::Listener::bind("localhost:0").unwrap();
+    let port = listener.local_addrs()[0].port();
+    let client = thread::spawn(move || net::connect(&format!("localhost:{}", port), Duration::from_secs(5), &Cancel::new()));
+    listener.accept(&Cancel::new()).unwrap();
+    client.join().unwrap().unwrap();
+    assert!(net::connect(&format!(":{}", port), Duration::from_secs(5), &Cancel::new()).is_err());
+}
+
+#[test]
+fn ipv6_peers_connect_and_display_in_brackets() {
+    if !ipv6_available() {
+        return;
+    }
+    let listener = net::Listener::bind("[::1]:0").unwrap();
+    let address = listener.describe();
+    assert!(address.starts_with("[::1]:"), "{}", address);
+    let client = thread::spawn(move || net::connect(&address, Duration::from_secs(5), &Cancel::new()));
+    let (_, peer) = listener.accept(&Cancel::new()).unwrap();
+    client.join().unwrap().unwrap();
+    assert!(peer.to_string().starts_with("[::1]:"), "{}", peer);
+}
+
+#[test]
+fn wildcard_listens_on_both_families() {
+    let listener = net::Listener::bind("*:0").unwrap();
+    let port = listener.local_addrs()[0].port();
+    let mut targets = vec![format!("127.0.0.1:{}", port)];
+    if ipv6_available() {
+        targets.push(format!("[::1]:{}", port));
+    }
+    for target in targets {
+        let _client = TcpStream::connect(&target).unwrap();
+        let (_, peer) = listener.accept(&Cancel::new()).unwrap();
+        // IPv4 peers of a dual-stack socket are not shown as ::ffff:a.b.c.d.
+        assert_eq!(peer.address().unwrap().is_ipv4(), target.starts_with("127."), "{} from {}", peer, target);
+    }
+}
+
+#[test]
+fn accept_gives_up_when_cancelled() {
+    let listener = net::Listener::bind("127.0.0.1:0").unwrap();
+    let cancel = Cancel::new();
+    let canceller = cancel.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+    let err = listener.accept(&cancel).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn accept_and_connect_meet() {
+    let listener = net::Listener::bind("127.0.0.1:0").unwrap();
+    let address = listener.describe();
+    let client = thread::spawn(move || net::connect(&address, Duration::from_secs(5), &Cancel::new()));
+    let (_, peer) = listener.accept(&Cancel::new()).unwrap();
+    assert_eq!(Peer::Tcp(client.join().unwrap().unwrap().local_addr().unwrap()), peer);
+}
+
+#[test]
+fn cancelling_does_not_wait_for_the_connect_timeout() {
+    let cancel = Cancel::new();
+    cancel.cancel();
+    let started = Instant::now();
+    // A non-routable address would otherwise hang for the whole timeout.
+    let err = net::connect("10.255.255.1:9", Duration::from_secs(30), &cancel).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn shutdown_waits_for_workers_and_wakes_watched_sockets() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let _server_side = listener.accept().unwrap();
+
+    let cancel = Cancel::new();
+    cancel.watch(&Stream::from(stream.try_clone().unwrap())).unwrap();
+    let worker = cancel.worker();
+    let reader = thread::spawn(move || {
+        let _worker = worker;
+        // Blocks until the socket is shut down.
+        io::Read::read(&mut &stream, &mut [0u8; 16])
+    });
+
+    assert!(cancel.shutdown(Duration::from_secs(5)), "worker did not finish");
+    assert_eq!(reader.join().unwrap().unwrap(), 0);
+}
@@ -0,0 +1,131 @@
+// tests/network_chat.rs
+//! One-to-one chat as used by `network_chat`, plain and end-to-end
+//! encrypted, over a loopback connection.
+
+mod common;
+
+use common::TIMEOUT;
+use socat_chat::{
+    direct::{self, DirectReceiver, DirectSender},
+    e2e::{self, E2eError},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+/// A connected pair of streams: (server side, client side).
+fn loopback_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    for stream in [&server, &client] {
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    }
+    (server, client)
+}
+
+/// Runs the key exchange on both ends at once and splits the streams.
+fn encrypted_pair() -> ((DirectSender, DirectReceiver), (DirectSender, DirectReceiver), String, String) {
+    let (mut server, mut client) = loopback_pair();
+    let peer = thread::spawn(move || {
+        let session = e2e::handshake(&mut client).unwrap();
+        (client, session)
+    });
+    let server_session = e2e::handshake(&mut server).unwrap();
+    let (client, client_session) = peer.join().unwrap();
+
+    let server_fp = server_session.fingerprint.clone();
+    let client_fp = client_session.fingerprint.clone();
+    (
+        direct::split(server, Some(server_session)).unwrap(),
+        direct::split(client, Some(client_session)).unwrap(),
+        server_fp,
+        client_fp,
+    )
+}
+
+fn expect_message(receiver: &mut DirectReceiver, expected: &str) {
+    match receiver.recv() {
+        Some(Ok(message)) => assert_eq!(message, expected),
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
+
+#[test]
+fn plain_messages_both_ways() {
+    let (server, client) = loopback_pair();
+    let (mut server_tx, mut server_rx) = direct::split(server, None).unwrap();
+    let (mut client_tx, mut client_rx) = direct::split(client, None).unwrap();
+
+    client_tx.send("hello server").unwrap();
+    expect_message(&mut server_rx, "hello server");
+
+    // Unsealed, every line is a message of its own.
+    server_tx.send("two\nlines").unwrap();
+    expect_message(&mut client_rx, "two");
+    expect_message(&mut client_rx, "lines");
+}
+
+#[test]
+fn plain_lines_go_over_as_they_are() {
+    // The peer is plain socat or netcat, reading and writing lines.
+    let (server, client) = loopback_pair();
+    let (mut tx, mut rx) = direct::split(server, None).unwrap();
+    let mut peer = BufReader::new(client.try_clone().unwrap());
+
+    tx.send("C:\\temp\tand\nmore").unwrap();
+    let mut received = String::new();
+    for _ in 0..2 {
+        peer.read_line(&mut received).unwrap();
+    }
+    assert_eq!(received, "C:\\temp\tand\nmore\n");
+
+    let mut raw = client;
+    raw.write_all(b"a \\n is not a newline\r\n   \n\nlast\n").unwrap();
+    for expected in ["a \\n is not a newline", "   ", "", "last"] {
+        expect_message(&mut rx, expected);
+    }
+}
+
+#[test]
+fn receiver_ends_when_peer_closes() {
+    let (server, client) = loopback_pair();
+    let (_, mut server_rx) = direct::split(server, None).unwrap();
+    let (mut client_tx, _) = direct::split(client, None).unwrap();
+
+    client_tx.send("bye").unwrap();
+    drop(client_tx);
+    expect_message(&mut server_rx, "bye");
+    assert!(server_rx.recv().is_none());
+}
+
+#[test]
+fn encrypted_messages_both_ways() {
+    let ((mut server_tx, mut server_rx), (mut client_tx, mut client_rx), server_fp, client_fp) = encrypted_pair();
+    assert_eq!(server_fp, client_fp, "both sides must show the same fingerprint");
+
+    for n in 0..3 {
+        client_tx.send(&format!("secret {}", n)).unwrap();
+        expect_message(&mut server_rx, &format!("secret {}", n));
+    }
+    server_tx.send("multi\nline secret").unwrap();
+    expect_message(&mut client_rx, "multi\nline secret");
+}
+
+#[test]
+fn tampered_ciphertext_is_dropped() {
+    let (mut server, mut client) = loopback_pair();
+    let mut raw = client.try_clone().unwrap();
+    let peer = thread::spawn(move || e2e::handshake(&mut client).map(|_| ()));
+    let session = e2e::handshake(&mut server).unwrap();
+    peer.join().unwrap().unwrap();
+
+    let (_, mut server_rx) = direct::split(server, Some(session)).unwrap();
+    writeln!(raw, "{}", "00".repeat(40)).unwrap();
+    match server_rx.recv() {
+        Some(Err(E2eError::Decrypt)) | Some(Err(E2eError::Malformed)) => {}
+        other => panic!("forged message was accepted: {:?}", other),
+    }
+}
@@ -0,0 +1,123 @@
+// tests/outbox.rs
+//! Queueing messages while offline: the outbox and its file, logging in
+//! again after a dropped connection, and the server ignoring posts sent twice.
+
+mod common;
+
+use common::{scratch_dir, start_server, wait_for, FakeClient, TIMEOUT};
+use socat_chat::{
+    auth::Authenticator,
+    cancel::Cancel,
+    client::{reconnect_delay, ChatClient, ClientEvent},
+    history::History,
+    net,
+    outbox::{Outbox, Queued},
+    protocol::Frame,
+    receipts::Receipts,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn the_outbox_keeps_messages_until_acknowledged() {
+    let path = scratch_dir("outbox").join("outbox.txt");
+    let mut outbox = Outbox::open(&path).unwrap();
+    assert!(outbox.is_empty());
+    outbox.push(1, "first\tline\nsecond").unwrap();
+    outbox.push(2, "two").unwrap();
+    outbox.push(3, "three").unwrap();
+    assert!(outbox.acknowledged(2).unwrap());
+    assert!(!outbox.acknowledged(2).unwrap());
+
+    // Another run picks up where this one stopped, in order.
+    let reopened = Outbox::open(&path).unwrap();
+    assert_eq!(
+        reopened.queued(),
+        [Queued { id: 1, text: "first\tline\nsecond".into() }, Queued { id: 3, text: "three".into() }]
+    );
+
+    let mut memory = Outbox::new();
+    memory.push(7, "x").unwrap();
+    assert_eq!(memory.len(), 1);
+}
+
+#[test]
+fn queued_messages_show_as_such_until_sent() {
+    let mut receipts = Receipts::new();
+    let (first, second) = (receipts.next_id(), receipts.next_id());
+    let mut log = receipts.track(first, "Me: a\n", 0);
+    let at = log.len();
+    log += &receipts.track_queued(second, "Me: b\n", at);
+    assert_eq!(log, "Me: a …\nMe: b … queued\n");
+
+    // The connection drops and comes back: both go out again.
+    let mut edits = receipts.disconnected();
+    edits.extend(receipts.resent(first));
+    edits.extend(receipts.resent(second));
+    assert_eq!(receipts.resent(second), None);
+    for edit in edits {
+        log.replace_range(edit.start..edit.end, &edit.text);
+    }
+    assert_eq!(log, "Me: a …\nMe: b …\n");
+}
+
+#[test]
+fn reconnection_backs_off_and_can_be_cancelled() {
+    let delays: Vec<u64> = (1..=8).map(|attempt| reconnect_delay(attempt).as_secs()).collect();
+    assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30]);
+
+    let cancel = Cancel::new();
+    let started = Instant::now();
+    cancel.sleep(Duration::from_millis(60)).unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(60));
+
+    let sleeping = cancel.clone();
+    let sleeper = thread::spawn(move || sleeping.sleep(Duration::from_secs(30)));
+    cancel.cancel();
+    assert!(sleeper.join().unwrap().is_err());
+}
+
+#[test]
+fn a_client_resumes_over_a_new_connection() {
+    let (server, _events) = start_server(Authenticator::new(), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let (bob, bob_events) = ChatClient::connect(&addr.to_string(), "bob", "").unwrap();
+
+    bob.disconnect();
+    wait_for(&bob_events, "the disconnect", |e| matches!(e, ClientEvent::Disconnected(_)));
+    alice.expect_where("bob leaving", |f| matches!(f, Frame::System(t) if t == "bob left the chat"));
+
+    let stream = net::dial(&addr.to_string(), TIMEOUT, &Cancel::new()).unwrap();
+    let events = bob.resume(stream, "").unwrap();
+    bob.post(5, "back again").unwrap();
+    wait_for(&events, "the acknowledgement", |e| *e == ClientEvent::Delivered(5));
+    alice.expect(&Frame::Post { id: 5, from: "bob".into(), text: "back again".into() });
+}
+
+#[test]
+fn posts_sent_again_are_only_acknowledged() {
+    let (server, _events) = start_server(Authenticator::new(), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+    let mut bob = FakeClient::join(addr, "bob", "");
+
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let post = |id: u64, text: &str| Frame::Post { id, from: "alice".into(), text: text.into() };
+    alice.send_frame(&post(1, "one"));
+    alice.expect(&Frame::Ack { id: 1 });
+    alice.disconnect();
+    bob.expect(&post(1, "one"));
+    bob.expect_where("alice leaving", |f| matches!(f, Frame::System(t) if t == "alice left the chat"));
+
+    // After logging in again, the unacknowledged tail is sent once more.
+    let mut alice = FakeClient::join(addr, "alice", "");
+    alice.send_frame(&post(1, "one"));
+    alice.send_frame(&post(2, "two"));
+    alice.expect(&Frame::Ack { id: 1 });
+    alice.expect(&Frame::Ack { id: 2 });
+
+    let next = bob.expect_where("the next post", |f| matches!(f, Frame::Post { .. }));
+    assert_eq!(next, post(2, "two"));
+}
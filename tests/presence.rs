@@ -0,0 +1,132 @@
+// tests/presence.rs
+//! Typing notifications and idle status: the frames, the bookkeeping the
+//! window does, and the room server passing them on.
+
+mod common;
+
+use common::{start_server, wait_for, FakeClient, HOST};
+use socat_chat::{
+    auth::Authenticator,
+    history::History,
+    presence::{IdleTimer, Roster, TypingNotifier, TYPING_EXPIRY, TYPING_PAUSE, TYPING_REFRESH},
+    protocol::{Frame, Presence, ProtocolError},
+    server::ServerEvent,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn typing_and_presence_frames_round_trip() {
+    for frame in [
+        Frame::Typing { from: "al\tice".into(), typing: true },
+        Frame::Typing { from: "bob".into(), typing: false },
+        Frame::Presence { user: "bob".into(), presence: Presence::Idle },
+        Frame::Presence { user: "bob".into(), presence: Presence::Active },
+    ] {
+        assert_eq!(Frame::decode(&frame.encode()), Ok(frame));
+    }
+    assert_eq!(Frame::decode("TYPING\tbob\tyes"), Err(ProtocolError::InvalidField("typing")));
+    assert_eq!(Frame::decode("PRESENCE\tbob\taway"), Err(ProtocolError::InvalidField("presence")));
+}
+
+#[test]
+fn typing_is_announced_sparingly() {
+    let start = Instant::now();
+    let mut notifier = TypingNotifier::default();
+    assert_eq!(notifier.edited(false, start), Some(true));
+    assert_eq!(notifier.edited(false, start + Duration::from_secs(1)), None);
+    assert_eq!(notifier.edited(false, start + Duration::from_secs(4)), None);
+    // Still typing: a reminder once the refresh interval passed.
+    let later = start + TYPING_REFRESH;
+    assert_eq!(notifier.tick(later), None);
+    assert_eq!(notifier.edited(false, later), Some(true));
+
+    // A pause ends it, once.
+    assert_eq!(notifier.tick(later + TYPING_PAUSE), Some(false));
+    assert_eq!(notifier.tick(later + TYPING_PAUSE * 2), None);
+
+    // So do clearing the input and sending, but only when typing was said.
+    assert_eq!(notifier.edited(false, later), Some(true));
+    assert_eq!(notifier.edited(true, later), Some(false));
+    assert_eq!(notifier.edited(false, later), Some(true));
+    assert_eq!(notifier.sent(), Some(false));
+    assert_eq!(notifier.sent(), None);
+}
+
+#[test]
+fn idleness_changes_are_reported_once() {
+    let start = Instant::now();
+    let mut idle = IdleTimer::new(Duration::from_secs(60));
+    assert_eq!(idle.update(start, start + Duration::from_secs(59)), None);
+    assert_eq!(idle.update(start, start + Duration::from_secs(60)), Some(Presence::Idle));
+    assert_eq!(idle.update(start, start + Duration::from_secs(90)), None);
+    let back = start + Duration::from_secs(100);
+    assert_eq!(idle.update(back, back), Some(Presence::Active));
+}
+
+#[test]
+fn the_roster_labels_users_and_sums_up_typing() {
+    let now = Instant::now();
+    let mut roster = Roster::default();
+    roster.set_users(vec!["alice".into(), "bob".into(), "carol".into(), "dave".into()]);
+    assert_eq!(roster.typing_line(), "");
+
+    roster.set_presence("bob", Presence::Idle);
+    roster.set_typing("carol", true, now);
+    assert_eq!(roster.label("alice"), "alice");
+    assert_eq!(roster.label("bob"), "bob (idle)");
+    assert_eq!(roster.label("carol"), "carol (typing…)");
+    assert_eq!(roster.typing_line(), "carol is typing…");
+
+    roster.set_typing("alice", true, now);
+    assert_eq!(roster.typing_line(), "alice and carol are typing…");
+    roster.set_typing("bob", true, now);
+    assert_eq!(roster.typing_line(), "alice, bob and carol are typing…");
+    roster.set_typing("dave", true, now + TYPING_EXPIRY);
+    assert_eq!(roster.typing_line(), "Several people are typing…");
+
+    // Speaking, leaving and silence all end typing.
+    roster.spoke("bob");
+    roster.set_users(vec!["alice".into(), "bob".into(), "dave".into()]);
+    assert_eq!(roster.typing_line(), "alice and dave are typing…");
+    assert!(roster.expire(now + TYPING_EXPIRY));
+    assert!(!roster.expire(now + TYPING_EXPIRY));
+    assert_eq!(roster.typing_line(), "dave is typing…");
+    roster.set_presence("bob", Presence::Active);
+    assert_eq!(roster.label("bob"), "bob");
+}
+
+#[test]
+fn the_server_passes_typing_and_presence_on() {
+    let (server, events) = start_server(Authenticator::new(), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let mut bob = FakeClient::join(addr, "bob", "");
+
+    // Whatever name a client claims, it is relayed under its login.
+    alice.send_frame(&Frame::Typing { from: "mallory".into(), typing: true });
+    bob.expect_where("alice typing", |f| *f == Frame::Typing { from: "alice".into(), typing: true });
+    wait_for(&events, "alice typing", |e| *e == ServerEvent::Typing { from: "alice".into(), typing: true });
+
+    alice.send_frame(&Frame::Presence { user: "alice".into(), presence: Presence::Idle });
+    bob.expect_where("alice idle", |f| *f == Frame::Presence { user: "alice".into(), presence: Presence::Idle });
+    wait_for(&events, "alice idle", |e| {
+        *e == ServerEvent::Presence { user: "alice".into(), presence: Presence::Idle }
+    });
+
+    server.set_typing(true);
+    alice.expect_where("host typing", |f| *f == Frame::Typing { from: HOST.into(), typing: true });
+    server.set_presence(Presence::Idle);
+    bob.expect_where("host idle", |f| *f == Frame::Presence { user: HOST.into(), presence: Presence::Idle });
+
+    // A newcomer learns who is idle right away.
+    let mut carol = FakeClient::join(addr, "carol", "");
+    let mut idle = Vec::new();
+    for _ in 0..2 {
+        match carol.recv() {
+            Some(Frame::Presence { user, presence: Presence::Idle }) => idle.push(user),
+            other => panic!("expected a presence frame, got {:?}", other),
+        }
+    }
+    idle.sort();
+    assert_eq!(idle, vec!["alice".to_string(), HOST.to_string()]);
+}
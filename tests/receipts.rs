@@ -0,0 +1,124 @@
+// tests/receipts.rs
+//! Delivery and read receipts: the frames, the markers kept in the log and
+//! the room server routing acknowledgements back to authors.
+
+mod common;
+
+use common::{start_server, wait_for, FakeClient, HOST};
+use socat_chat::{
+    auth::Authenticator,
+    client::{ChatClient, ClientEvent},
+    history::History,
+    protocol::{Frame, ProtocolError},
+    receipts::{Delivery, Edit, Receipts},
+    server::ServerEvent,
+};
+use std::time::{Duration, Instant};
+
+/// Applies `edit` to `log` the way the window applies it to its buffer.
+fn apply(log: &mut String, edit: Option<Edit>) {
+    let edit = edit.expect("an edit");
+    log.replace_range(edit.start..edit.end, &edit.text);
+}
+
+#[test]
+fn receipt_frames_round_trip() {
+    for frame in [
+        Frame::Post { id: 1_700_000_000_001, from: "alice".into(), text: "hi\tthere".into() },
+        Frame::Ack { id: 7 },
+        Frame::Read { id: 7, user: "bob".into() },
+    ] {
+        assert_eq!(Frame::decode(&frame.encode()), Ok(frame));
+    }
+    assert_eq!(Frame::decode("ACK\tseven"), Err(ProtocolError::InvalidField("id")));
+    assert_eq!(Frame::decode("READ\t7"), Err(ProtocolError::MissingField("user")));
+}
+
+#[test]
+fn markers_follow_their_messages_through_the_log() {
+    let mut receipts = Receipts::new();
+    let mut log = String::from("*** Connected\n");
+    let first = receipts.next_id();
+    let second = receipts.next_id();
+    assert!(second > first);
+
+    let at = log.len();
+    log += &receipts.track(first, "Me: one\n    two\n", at);
+    log += "bob: hello\n";
+    let at = log.len();
+    log += &receipts.track(second, "Me: three\n", at);
+    assert_eq!(log, "*** Connected\nMe: one\n    two …\nbob: hello\nMe: three …\n");
+
+    // A longer marker on the first message moves the second one along.
+    apply(&mut log, receipts.read(first, "bob"));
+    apply(&mut log, receipts.delivered(second));
+    assert_eq!(log, "*** Connected\nMe: one\n    two ✓✓ bob\nbob: hello\nMe: three ✓\n");
+
+    // A late acknowledgement does not undo a read, nor does a repeated read.
+    assert_eq!(receipts.delivered(first), None);
+    assert_eq!(receipts.read(first, "bob"), None);
+    apply(&mut log, receipts.read(first, "carol"));
+    apply(&mut log, receipts.read(second, "carol"));
+    assert_eq!(log, "*** Connected\nMe: one\n    two ✓✓ bob, carol\nbob: hello\nMe: three ✓✓ carol\n");
+    assert_eq!(receipts.delivery(first), Some(&Delivery::Read(vec!["bob".into(), "carol".into()])));
+    assert_eq!(receipts.read(99, "bob"), None);
+}
+
+#[test]
+fn unacknowledged_messages_are_flagged_when_the_connection_drops() {
+    let mut receipts = Receipts::new();
+    let mut log = String::new();
+    let (first, second) = (receipts.next_id(), receipts.next_id());
+    log += &receipts.track(first, "Me: a\n", 0);
+    let at = log.len();
+    log += &receipts.track(second, "Me: b\n", at);
+    apply(&mut log, receipts.delivered(first));
+
+    for edit in receipts.disconnected() {
+        apply(&mut log, Some(edit));
+    }
+    assert_eq!(log, "Me: a ✓\nMe: b ✗ not delivered\n");
+    assert!(receipts.disconnected().is_empty());
+
+    // The acknowledgement may still turn up.
+    apply(&mut log, receipts.delivered(second));
+    assert_eq!(log, "Me: a ✓\nMe: b ✓\n");
+}
+
+#[test]
+fn posts_count_as_seen_after_later_input() {
+    let start = Instant::now();
+    let mut receipts = Receipts::new();
+    receipts.received(1, "alice", start);
+    receipts.received(2, "bob", start + Duration::from_secs(10));
+    assert!(receipts.seen(start).is_empty());
+    assert_eq!(receipts.seen(start + Duration::from_secs(5)), vec![(1, "alice".to_string())]);
+    assert_eq!(receipts.seen(start + Duration::from_secs(20)), vec![(2, "bob".to_string())]);
+    assert!(receipts.seen(start + Duration::from_secs(30)).is_empty());
+}
+
+#[test]
+fn the_server_acknowledges_posts_and_routes_reads_to_authors() {
+    let (server, events) = start_server(Authenticator::new(), History::new(10), 0);
+    let addr = server.local_addr().unwrap();
+    let mut alice = FakeClient::join(addr, "alice", "");
+    let (bob, bob_events) = ChatClient::connect(&addr.to_string(), "bob", "").unwrap();
+    alice.expect_where("bob's join notice", |f| matches!(f, Frame::System(t) if t.starts_with("bob joined")));
+
+    bob.post(41, "anyone?").unwrap();
+    wait_for(&bob_events, "the acknowledgement", |e| *e == ClientEvent::Delivered(41));
+    alice.expect(&Frame::Post { id: 41, from: "bob".into(), text: "anyone?".into() });
+    wait_for(&events, "bob's post", |e| *e == ServerEvent::Post { id: 41, from: "bob".into(), text: "anyone?".into() });
+
+    // Alice reads it, then so does the operator.
+    alice.send_frame(&Frame::Read { id: 41, user: "bob".into() });
+    wait_for(&bob_events, "alice's receipt", |e| *e == ClientEvent::Read { id: 41, by: "alice".into() });
+    server.read(41, "bob");
+    wait_for(&bob_events, "the host's receipt", |e| *e == ClientEvent::Read { id: 41, by: HOST.into() });
+
+    // The operator's posts need no acknowledgement, only reading.
+    server.post(9, "welcome");
+    wait_for(&bob_events, "the host's post", |e| *e == ClientEvent::Post { id: 9, from: HOST.into(), text: "welcome".into() });
+    bob.read(9, HOST).unwrap();
+    wait_for(&events, "bob's receipt", |e| *e == ServerEvent::Read { id: 9, by: "bob".into() });
+}
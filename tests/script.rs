@@ -0,0 +1,133 @@
+// tests/script.rs
+//! Expect-style scripts: the language, and running one against a fake
+//! device over loopback TCP.
+
+mod common;
+
+use socat_chat::{
+    cancel::Cancel,
+    script::{parse_duration, Runner, Script, ScriptError, ScriptEvent, Step},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// A device answering each line it gets with `answer(line)`; `None` hangs up.
+fn device(answer: impl Fn(&str) -> Option<String> + Send + 'static) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ours = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (theirs, _) = listener.accept().unwrap();
+    thread::spawn(move || {
+        let mut writer = theirs.try_clone().unwrap();
+        for line in BufReader::new(theirs).split(b'\r') {
+            let line = String::from_utf8(line.unwrap()).unwrap();
+            match answer(line.trim()) {
+                Some(reply) => writer.write_all(reply.as_bytes()).unwrap(),
+                None => return,
+            }
+        }
+    });
+    ours
+}
+
+fn modem(line: &str) -> Option<String> {
+    match line {
+        "AT" => Some("AT\r\nOK\r\n".to_string()),
+        "ATI" => Some("Model: X200\r\nOK\r\n".to_string()),
+        "ATH" => None,
+        _ => Some("ERROR\r\n".to_string()),
+    }
+}
+
+fn run(script: &str, stream: TcpStream) -> (Result<(), ScriptError>, Vec<ScriptEvent>) {
+    let script = Script::parse(script).unwrap();
+    let cancel = Cancel::new();
+    let mut runner = Runner::new(Box::new(stream.try_clone().unwrap()), Box::new(stream), &cancel);
+    let mut events = Vec::new();
+    let result = runner.run(&script, &mut |event| events.push(event));
+    cancel.cancel();
+    (result, events)
+}
+
+const MODEM_SCRIPT: &str = r#"
+# Wake the modem up and check it answers.
+eol cr
+timeout 2s
+loop 3
+    send "AT"
+    expect "OK" fail "ERROR"   # comments may follow commands
+end
+send "ATI"
+expect /Model: (\w+)/ timeout 5s
+sleep 10ms
+"#;
+
+#[test]
+fn scripts_parse_into_steps() {
+    let script = Script::parse(MODEM_SCRIPT).unwrap();
+    assert_eq!(script.lines.len(), 6);
+    let Step::Loop { count: Some(3), body } = &script.lines[2].step else { panic!("{:?}", script.lines[2]) };
+    assert_eq!(script.lines[2].number, 5);
+    assert_eq!(body.len(), 2);
+    assert!(matches!(&body[0].step, Step::Send(text) if text == b"AT"));
+    assert!(matches!(&body[1].step, Step::Expect { timeout: None, fail: Some(_), .. }));
+    assert!(matches!(&script.lines[4].step, Step::Expect { timeout: Some(t), .. } if *t == Duration::from_secs(5)));
+
+    let escaped = Script::parse(r#"send "a\tb\x00\xff\"\\""#).unwrap();
+    assert!(matches!(&escaped.lines[0].step, Step::Send(text) if text == b"a\tb\x00\xff\"\\"));
+}
+
+#[test]
+fn mistakes_name_their_line() {
+    let error = |script: &str| Script::parse(script).unwrap_err();
+    assert_eq!(error("send \"a\"\nshout \"b\""), "line 2: unknown command \"shout\"");
+    assert_eq!(error("loop 2\nsend \"a\"\nend\nend"), "line 4: end without loop");
+    assert_eq!(error("send \"a\"\nloop\nloop 2\nend"), "line 2: loop without end");
+    assert!(error("send \"open").contains("unterminated string"));
+    assert!(error("expect \"x\" timeout soon").contains("invalid duration"));
+    assert!(error("expect /(/").starts_with("line 1: invalid regex"));
+    assert!(error("send \"a\" \"b\"").contains("unexpected"));
+    assert!(error("eol lf-ish").contains("unknown line ending"));
+}
+
+#[test]
+fn durations_take_units() {
+    assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+    assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+    assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+    assert!(parse_duration("5").is_err());
+    assert!(parse_duration("-1s").is_err());
+}
+
+#[test]
+fn a_script_drives_the_device() {
+    let (result, events) = run(MODEM_SCRIPT, device(modem));
+    result.unwrap();
+    let sent: Vec<_> = events.iter().filter_map(|e| if let ScriptEvent::Sent(b) = e { Some(b.clone()) } else { None }).collect();
+    assert_eq!(sent, vec![b"AT\r".to_vec(), b"AT\r".to_vec(), b"AT\r".to_vec(), b"ATI\r".to_vec()]);
+    let matched = events.iter().filter(|e| matches!(e, ScriptEvent::Matched { .. })).count();
+    assert_eq!(matched, 4);
+}
+
+#[test]
+fn timeouts_fail_with_what_was_received() {
+    let (result, _) = run("eol cr\nsend \"ATI\"\nexpect \"Model: Z9\" timeout 200ms", device(modem));
+    let error = result.unwrap_err();
+    assert_eq!(error.exit_code(), 2);
+    assert!(matches!(&error, ScriptError::Timeout { line: 3, received, .. } if received.contains("Model: X200")), "{}", error);
+}
+
+#[test]
+fn fail_patterns_and_hangups_are_mismatches() {
+    let (result, _) = run("eol cr\nsend \"ATX\"\nexpect \"OK\" fail /ERR\\w+/", device(modem));
+    let error = result.unwrap_err();
+    assert_eq!(error.exit_code(), 3);
+    assert_eq!(error.to_string(), "line 3: got /ERR\\w+/ instead, received \"ERROR\\r\\n\"");
+
+    let (result, _) = run("eol cr\nsend \"ATH\"\nexpect \"NO CARRIER\"", device(modem));
+    let error = result.unwrap_err();
+    assert!(matches!(error, ScriptError::Closed { line: 3, .. }), "{}", error);
+}
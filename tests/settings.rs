@@ -0,0 +1,44 @@
+// tests/settings.rs
+//! Settings files, written to a scratch config directory.
+
+mod common;
+
+use socat_chat::{
+    serial,
+    settings::{ConnectionProfile, RecentConnections},
+};
+use std::env;
+
+fn profile(mode: &str, address: &str) -> ConnectionProfile {
+    ConnectionProfile { mode: mode.into(), address: address.into(), username: "alice\tsmith".into(), e2e: true }
+}
+
+// Everything touching the environment lives in this one test, so nothing
+// else in this binary races with it.
+#[test]
+fn recent_connections_round_trip() {
+    env::set_var("XDG_CONFIG_HOME", common::scratch_dir("config"));
+    assert!(RecentConnections::load("test").entries.is_empty());
+
+    let mut recent = RecentConnections::default();
+    for n in 0..12 {
+        recent.remember(profile("client", &format!("10.0.0.{}:8080", n)));
+    }
+    recent.remember(profile("client", "10.0.0.5:8080"));
+    recent.save("test").unwrap();
+
+    let loaded = RecentConnections::load("test");
+    assert_eq!(loaded.entries.len(), 10);
+    assert_eq!(loaded.entries[0], profile("client", "10.0.0.5:8080"));
+    assert_eq!(loaded.entries[1], profile("client", "10.0.0.11:8080"));
+    assert_eq!(loaded.entries.iter().filter(|e| e.address == "10.0.0.5:8080").count(), 1);
+}
+
+#[test]
+fn serial_specs_take_an_optional_baud_rate() {
+    assert_eq!(serial::parse_spec("/dev/ttyUSB0").unwrap(), ("/dev/ttyUSB0".to_string(), serial::DEFAULT_BAUD));
+    assert_eq!(serial::parse_spec("/dev/ttyUSB0,9600").unwrap(), ("/dev/ttyUSB0".to_string(), 9600));
+    assert_eq!(serial::parse_spec("COM3,b115200").unwrap(), ("COM3".to_string(), 115_200));
+    assert!(serial::parse_spec("/dev/ttyUSB0,fast").is_err());
+    assert!(serial::parse_spec(",9600").is_err());
+}
@@ -0,0 +1,82 @@
+// tests/simple_chat.rs
+//! FIFO chat as used by `simple_chat` and `network_chat`'s fifo mode, over
+//! pipes in a scratch directory.
+
+mod common;
+
+use socat_chat::{direct, pipe};
+use std::{path::Path, process::Command, thread};
+
+fn mkfifo(path: &Path) {
+    let status = Command::new("mkfifo").arg(path).status().expect("run mkfifo");
+    assert!(status.success(), "mkfifo {} failed", path.display());
+}
+
+#[test]
+fn messages_cross_a_fifo() {
+    let dir = common::scratch_dir("fifo");
+    let path = dir.join("pipe1");
+    mkfifo(&path);
+
+    let reader_path = path.clone();
+    let reader = thread::spawn(move || pipe::receive(&reader_path).unwrap());
+    pipe::send(&path, "hello over the pipe").unwrap();
+    assert_eq!(reader.join().unwrap(), vec!["hello over the pipe".to_string()]);
+}
+
+#[test]
+fn two_fifos_carry_a_conversation() {
+    let dir = common::scratch_dir("fifo");
+    let (a_to_b, b_to_a) = (dir.join("a_to_b"), dir.join("b_to_a"));
+    mkfifo(&a_to_b);
+    mkfifo(&b_to_a);
+
+    // "b" answers each message it reads on one pipe through the other.
+    let (b_in, b_out) = (a_to_b.clone(), b_to_a.clone());
+    let b = thread::spawn(move || {
+        for _ in 0..2 {
+            for message in pipe::receive(&b_in).unwrap() {
+                pipe::send(&b_out, &format!("re: {}", message)).unwrap();
+            }
+        }
+    });
+
+    for question in ["one", "two"] {
+        let out = a_to_b.clone();
+        let sender = thread::spawn(move || pipe::send(&out, question).unwrap());
+        let reply = pipe::receive(&b_to_a).unwrap();
+        sender.join().unwrap();
+        assert_eq!(reply, vec![format!("re: {}", question)]);
+    }
+    b.join().unwrap();
+}
+
+#[test]
+fn network_chat_fifo_mode_talks_to_simple_chat() {
+    let dir = common::scratch_dir("fifo");
+    let (a_to_b, b_to_a) = (dir.join("a_to_b"), dir.join("b_to_a"));
+    mkfifo(&a_to_b);
+    mkfifo(&b_to_a);
+
+    // "a" streams over the pair, "b" is a simple_chat-style peer.
+    let (mut tx, mut rx) = direct::split_duplex(pipe::open_pair(&b_to_a, &a_to_b), None);
+    let b_in = a_to_b.clone();
+    let b = thread::spawn(move || pipe::receive(&b_in).unwrap());
+    tx.send("from the stream").unwrap();
+    assert_eq!(b.join().unwrap(), vec!["from the stream".to_string()]);
+
+    // Each simple_chat message closes the pipe; the stream keeps reading.
+    for text in ["one", "two"] {
+        let out = b_to_a.clone();
+        let sender = thread::spawn(move || pipe::send(&out, text).unwrap());
+        assert_eq!(rx.recv().unwrap().unwrap(), text);
+        sender.join().unwrap();
+    }
+}
+
+#[test]
+fn fifo_pairs_are_read_then_write() {
+    let (read, write) = pipe::parse_pair("/tmp/pipe1, /tmp/pipe2").unwrap();
+    assert_eq!((read.to_str().unwrap(), write.to_str().unwrap()), ("/tmp/pipe1", "/tmp/pipe2"));
+    assert!(pipe::parse_pair("/tmp/pipe1").is_err());
+}
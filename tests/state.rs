@@ -0,0 +1,30 @@
+// tests/state.rs
+//! Status bar text for each connection state.
+
+use socat_chat::state::ConnectionState;
+
+#[test]
+fn addresses_and_errors_with_colons_are_shown_whole() {
+    let connected = ConnectionState::Connected { peer: Some("[::1]:8080".to_string()) };
+    assert_eq!(connected.status_line(), "Status: Connected to [::1]:8080");
+
+    let failed = ConnectionState::failed("tls: handshake: bad certificate");
+    assert_eq!(failed.status_line(), "Status: Connection failed - tls: handshake: bad certificate");
+}
+
+#[test]
+fn only_in_between_states_are_pending() {
+    assert!(!ConnectionState::Idle.is_pending());
+    assert!(ConnectionState::Handshaking.is_pending());
+    assert!(ConnectionState::Reconnecting { attempt: 2 }.is_pending());
+    assert!(ConnectionState::Listening { address: "0.0.0.0:8080".into() }.is_pending());
+    assert!(!ConnectionState::Connected { peer: None }.is_pending());
+    assert!(ConnectionState::failed("refused").is_failed());
+    assert!(!ConnectionState::Exited { status: "exited with status 0".into() }.is_pending());
+}
+
+#[test]
+fn programs_that_ended_say_how() {
+    let exited = ConnectionState::Exited { status: "exited with status 1".to_string() };
+    assert_eq!(exited.status_line(), "Status: Program exited with status 1");
+}
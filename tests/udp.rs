@@ -0,0 +1,186 @@
+// tests/udp.rs
+//! `network_chat`'s UDP mode over loopback: peers learning each other,
+//! duplicate suppression, acknowledgements and the size limit.
+
+mod common;
+
+use common::TIMEOUT;
+use crossbeam_channel::{unbounded, Receiver};
+use socat_chat::{
+    cancel::Cancel,
+    direct::{self, DirectReceiver},
+    e2e,
+    udp::{self, Endpoint, Packet, UdpOptions},
+};
+use std::{io::Write, net::UdpSocket, thread, time::Duration};
+
+const ACKS: UdpOptions = UdpOptions { mtu: udp::DEFAULT_MTU, acks: true };
+
+/// Moves `receiver` to a thread so tests can wait for messages with a
+/// timeout.
+fn incoming(mut receiver: DirectReceiver) -> Receiver<String> {
+    let (sender, messages) = unbounded();
+    thread::spawn(move || {
+        while let Some(Ok(message)) = receiver.recv() {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    messages
+}
+
+/// A raw socket standing in for the peer of `endpoint`.
+fn raw_peer(endpoint: &Endpoint) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(endpoint.local_addr().unwrap()).unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    socket
+}
+
+fn recv_packet(socket: &UdpSocket) -> Packet {
+    let mut datagram = [0u8; 2048];
+    let len = socket.recv(&mut datagram).unwrap();
+    Packet::decode(std::str::from_utf8(&datagram[..len]).unwrap()).unwrap()
+}
+
+#[test]
+fn specs_name_a_peer_or_a_local_address() {
+    assert_eq!(udp::parse_spec("10.0.0.2:9000"), Ok((None, Some("10.0.0.2:9000".to_string()))));
+    assert_eq!(udp::parse_spec("*:9000,"), Ok((Some("*:9000".to_string()), None)));
+    assert_eq!(
+        udp::parse_spec(":9000, [::1]:9001"),
+        Ok((Some(":9000".to_string()), Some("[::1]:9001".to_string())))
+    );
+    assert!(udp::parse_spec(",").is_err());
+    assert!(udp::parse_spec("*:9000").is_err(), "a wildcard is no peer");
+    assert!(udp::parse_spec("host").is_err());
+}
+
+#[test]
+fn packets_round_trip() {
+    for packet in [
+        Packet::Data { epoch: 0xdeadbeef, seq: 7, payload: "with\ttab".to_string() },
+        Packet::Ack { epoch: 1, seq: 7 },
+    ] {
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+    assert_eq!(Packet::decode("ack\tzz\t1"), None);
+    assert_eq!(Packet::decode("data\t1\t1"), None);
+}
+
+#[test]
+fn listening_side_learns_the_peer_and_answers() {
+    for acks in [false, true] {
+        let options = UdpOptions { acks, ..UdpOptions::default() };
+        let cancel = Cancel::new();
+        let listening = Endpoint::open("127.0.0.1:0,", options, &cancel).unwrap();
+        assert_eq!(listening.peer(), None);
+        let address = listening.local_addr().unwrap().to_string();
+        let calling = Endpoint::open(&address, options, &cancel).unwrap();
+
+        let (mut a_tx, a_rx) = direct::split_duplex(listening.duplex(), None);
+        let (mut b_tx, b_rx) = direct::split_duplex(calling.duplex(), None);
+        let (a_rx, b_rx) = (incoming(a_rx), incoming(b_rx));
+
+        // Written before the peer is known, so held back until it is.
+        a_tx.send("early").unwrap();
+        b_tx.send("hello\nover two lines").unwrap();
+        assert_eq!(a_rx.recv_timeout(TIMEOUT).unwrap(), "hello");
+        assert_eq!(a_rx.recv_timeout(TIMEOUT).unwrap(), "over two lines");
+        assert_eq!(listening.peer().unwrap().port(), calling.local_addr().unwrap().port());
+        assert_eq!(b_rx.recv_timeout(TIMEOUT).unwrap(), "early");
+        a_tx.send("hi").unwrap();
+        assert_eq!(b_rx.recv_timeout(TIMEOUT).unwrap(), "hi");
+        cancel.cancel();
+    }
+}
+
+#[test]
+fn duplicates_are_dropped_until_the_peer_restarts() {
+    let cancel = Cancel::new();
+    let endpoint = Endpoint::open("127.0.0.1:0,", UdpOptions::default(), &cancel).unwrap();
+    let (_, receiver) = direct::split_duplex(endpoint.duplex(), None);
+    let messages = incoming(receiver);
+    let peer = raw_peer(&endpoint);
+
+    let data = |epoch, seq, payload: &str| Packet::Data { epoch, seq, payload: payload.to_string() }.encode();
+    for datagram in [data(1, 1, "one"), data(1, 1, "one"), data(1, 2, "two"), data(1, 1, "one"), data(2, 1, "again")] {
+        peer.send(datagram.as_bytes()).unwrap();
+    }
+    let received: Vec<String> = (0..3).map(|_| messages.recv_timeout(TIMEOUT).unwrap()).collect();
+    assert_eq!(received, ["one", "two", "again"]);
+    assert!(messages.recv_timeout(Duration::from_millis(200)).is_err(), "duplicate delivered");
+    cancel.cancel();
+}
+
+#[test]
+fn unacknowledged_messages_are_resent() {
+    let cancel = Cancel::new();
+    let endpoint = Endpoint::open("127.0.0.1:0,", ACKS, &cancel).unwrap();
+    let (mut sender, receiver) = direct::split_duplex(endpoint.duplex(), None);
+    let messages = incoming(receiver);
+    let peer = raw_peer(&endpoint);
+
+    // The peer's first message is acknowledged, even when repeated.
+    let hello = Packet::Data { epoch: 5, seq: 1, payload: "hello".to_string() }.encode();
+    peer.send(hello.as_bytes()).unwrap();
+    assert_eq!(recv_packet(&peer), Packet::Ack { epoch: 5, seq: 1 });
+    peer.send(hello.as_bytes()).unwrap();
+    assert_eq!(recv_packet(&peer), Packet::Ack { epoch: 5, seq: 1 });
+    assert_eq!(messages.recv_timeout(TIMEOUT).unwrap(), "hello");
+
+    // Ours comes again until acknowledged, then stops.
+    sender.send("reply").unwrap();
+    let Packet::Data { epoch, seq, payload } = recv_packet(&peer) else { panic!("expected data") };
+    assert_eq!(payload, "reply");
+    assert_eq!(recv_packet(&peer), Packet::Data { epoch, seq, payload });
+    peer.send(Packet::Ack { epoch, seq }.encode().as_bytes()).unwrap();
+    peer.set_read_timeout(Some(Duration::from_millis(1500))).unwrap();
+    let mut datagram = [0u8; 2048];
+    let mut resent = 0;
+    // One resend may already have been under way.
+    while peer.recv(&mut datagram).is_ok() {
+        resent += 1;
+    }
+    assert!(resent <= 1, "still resending after the ack");
+    cancel.cancel();
+}
+
+#[test]
+fn encrypted_chat_over_acknowledged_udp() {
+    let cancel = Cancel::new();
+    let listening = Endpoint::open("127.0.0.1:0,", ACKS, &cancel).unwrap();
+    let calling = Endpoint::open(&listening.local_addr().unwrap().to_string(), ACKS, &cancel).unwrap();
+    let mut a = listening.duplex();
+    let mut b = calling.duplex();
+
+    let peer = thread::spawn(move || {
+        let session = e2e::handshake(&mut b).unwrap();
+        (b, session)
+    });
+    let a_session = e2e::handshake(&mut a).unwrap();
+    let (b, b_session) = peer.join().unwrap();
+    assert_eq!(a_session.fingerprint, b_session.fingerprint);
+
+    let (mut a_tx, _) = direct::split_duplex(a, Some(a_session));
+    let (_, b_rx) = direct::split_duplex(b, Some(b_session));
+    a_tx.send("secret").unwrap();
+    assert_eq!(incoming(b_rx).recv_timeout(TIMEOUT).unwrap(), "secret");
+    cancel.cancel();
+}
+
+#[test]
+fn messages_must_fit_into_one_datagram() {
+    let cancel = Cancel::new();
+    let options = UdpOptions { mtu: 576, acks: false };
+    let endpoint = Endpoint::open("127.0.0.1:9", options, &cancel).unwrap();
+    let limit = udp::max_datagram(576, &"127.0.0.1:9".parse().unwrap());
+    assert_eq!(limit, 548);
+
+    let mut duplex = endpoint.duplex();
+    assert!(writeln!(duplex, "{}", "x".repeat(400)).is_ok());
+    let err = writeln!(duplex, "{}", "x".repeat(600)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("too long"), "{}", err);
+}
@@ -0,0 +1,229 @@
+// tests/unix.rs
+//! Chatting over Unix domain sockets: address parsing, socket file
+//! permissions, stale sockets and peer credentials.
+#![cfg(unix)]
+
+mod common;
+
+use common::{
+    scratch_dir, server_config, start_server_with, wait_for, FakeClient, HANDSHAKE_TIMEOUT, HOST, TIMEOUT,
+};
+use crossbeam_channel::Receiver;
+use socat_chat::{
+    auth::{Authenticator, LoginLimiter},
+    cancel::Cancel,
+    client::{ChatClient, ClientEvent},
+    heartbeat::Heartbeat,
+    history::History,
+    limits::Limits,
+    moderation::Moderation,
+    net::{self, Listener, Peer},
+    server::{ChatServer, ServerConfig, ServerEvent},
+    unix::{self, UnixAddress, UnixSpec},
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::MetadataExt,
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    process,
+    sync::Arc,
+    time::Duration,
+};
+
+/// The uid this test runs as, read off a file it creates.
+fn own_uid(dir: &std::path::Path) -> u32 {
+    let probe = dir.join("probe");
+    fs::write(&probe, "").unwrap();
+    fs::metadata(&probe).unwrap().uid()
+}
+
+fn mode_of(path: &PathBuf) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn addresses_name_a_path_or_an_abstract_socket() {
+    assert_eq!(
+        unix::parse("unix:/tmp/chat.sock"),
+        Ok(UnixSpec { address: UnixAddress::Path("/tmp/chat.sock".into()), mode: 0o600 })
+    );
+    assert_eq!(
+        unix::parse("unix:/tmp/chat.sock,mode=660"),
+        Ok(UnixSpec { address: UnixAddress::Path("/tmp/chat.sock".into()), mode: 0o660 })
+    );
+    assert_eq!(unix::parse("unix:@chat").unwrap().address, UnixAddress::Abstract("chat".into()));
+    assert_eq!(unix::parse("unix:@chat").unwrap().address.to_string(), "unix:@chat");
+    assert!(unix::parse("unix:").is_err());
+    assert!(unix::parse("unix:@").is_err());
+    assert!(unix::parse("unix:/tmp/x,mode=999").is_err());
+    assert!(unix::parse("unix:/tmp/x,owner=me").is_err());
+    assert!(net::validate_address("unix:/tmp/chat.sock").is_ok());
+}
+
+#[test]
+fn lines_pass_both_ways_and_the_peer_is_known() {
+    let dir = scratch_dir("unix-echo");
+    let address = format!("unix:{}", dir.join("chat.sock").display());
+    let listener = Listener::bind(&address).unwrap();
+    assert_eq!(listener.describe(), address);
+
+    let cancel = Cancel::new();
+    let mut calling = net::dial(&address, TIMEOUT, &cancel).unwrap();
+    let (accepted, peer) = listener.accept(&cancel).unwrap();
+    let Peer::Local(Some(credentials)) = peer else { panic!("expected local credentials, got {:?}", peer) };
+    assert_eq!(credentials.uid, own_uid(&dir));
+    if cfg!(target_os = "linux") {
+        assert_eq!(credentials.pid, Some(process::id() as i32));
+    }
+    assert!(peer.to_string().starts_with("local user "), "{}", peer);
+    assert_eq!(peer.address(), None);
+
+    writeln!(calling, "hello").unwrap();
+    let mut reader = BufReader::new(accepted.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "hello\n");
+
+    let mut accepted = accepted;
+    writeln!(accepted, "hi").unwrap();
+    let mut reader = BufReader::new(calling);
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "hi\n");
+}
+
+#[test]
+fn socket_files_are_private_unless_asked_and_removed_afterwards() {
+    let dir = scratch_dir("unix-mode");
+    let path = dir.join("private.sock");
+    let listener = Listener::bind(&format!("unix:{}", path.display())).unwrap();
+    assert_eq!(mode_of(&path), 0o600);
+    drop(listener);
+    assert!(!path.exists(), "socket file left behind");
+
+    let shared = dir.join("shared.sock");
+    let _listener = Listener::bind(&format!("unix:{},mode=660", shared.display())).unwrap();
+    assert_eq!(mode_of(&shared), 0o660);
+    UnixStream::connect(&shared).unwrap();
+
+    // The socket is bound elsewhere first; nothing of that is left over.
+    let names: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(names, ["shared.sock"]);
+}
+
+#[test]
+fn stale_sockets_are_replaced_but_live_ones_are_not() {
+    let dir = scratch_dir("unix-stale");
+    let path = dir.join("chat.sock");
+    let address = format!("unix:{}", path.display());
+
+    // A listener that went away without cleaning up.
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let listener = Listener::bind(&address).unwrap();
+
+    let err = Listener::bind(&address).err().expect("bound a socket in use");
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    drop(listener);
+
+    let file = dir.join("file");
+    fs::write(&file, "not a socket").unwrap();
+    assert!(Listener::bind(&format!("unix:{}", file.display())).is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "not a socket");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn abstract_sockets_need_no_file() {
+    let address = format!("unix:@socat_chat-test-{}", process::id());
+    let listener = Listener::bind(&address).unwrap();
+    let cancel = Cancel::new();
+    let _calling = net::dial(&address, TIMEOUT, &cancel).unwrap();
+    let (_, peer) = listener.accept(&cancel).unwrap();
+    assert!(matches!(peer, Peer::Local(Some(_))), "{:?}", peer);
+}
+
+/// A room on a Unix socket in `dir`, with its address.
+fn unix_room(dir: &std::path::Path) -> (ChatServer, Receiver<ServerEvent>, String) {
+    unix_room_with(dir, Arc::new(Authenticator::new().with_reserved(vec![HOST.to_string()])))
+}
+
+fn unix_room_with(dir: &std::path::Path, auth: Arc<Authenticator>) -> (ChatServer, Receiver<ServerEvent>, String) {
+    let address = format!("unix:{}", dir.join("room.sock").display());
+    let config = ServerConfig {
+        username: HOST.to_string(),
+        history: History::new(10),
+        replay: 0,
+        auth,
+        handshake_timeout: HANDSHAKE_TIMEOUT,
+        heartbeat: Heartbeat::default(),
+        moderation: Moderation::new(),
+        limits: Limits::default(),
+    };
+    let (server, events) = ChatServer::bind(&address, config).unwrap();
+    (server, events, address)
+}
+
+#[test]
+fn rooms_can_be_joined_over_a_unix_socket() {
+    let (server, events, address) = unix_room(&scratch_dir("unix-room"));
+    assert_eq!(server.local_addr(), None);
+    assert_eq!(server.listening_on(), address);
+
+    let (alice, messages) = ChatClient::connect(&address, "alice", "").unwrap();
+    wait_for(&events, "alice's join notice", |e| {
+        matches!(e, ServerEvent::Notice(text) if text.starts_with("alice joined the chat from local user "))
+    });
+    server.send("welcome");
+    wait_for(&messages, "the operator's message", |e| {
+        *e == ClientEvent::Chat { from: HOST.into(), text: "welcome".into() }
+    });
+    alice.send("thanks").unwrap();
+    wait_for(&events, "alice's message", |e| *e == ServerEvent::Chat { from: "alice".into(), text: "thanks".into() });
+    server.shutdown();
+}
+
+#[test]
+fn local_users_are_not_held_to_the_per_address_limits() {
+    let (server, events, address) = unix_room(&scratch_dir("unix-crowd"));
+    let crowd = Limits::default().max_per_address + 2;
+    let clients: Vec<_> = (0..crowd)
+        .map(|n| ChatClient::connect(&address, &format!("user{}", n), "").unwrap())
+        .collect();
+    assert_eq!(server.users().len(), crowd + 1);
+
+    // Banning the loopback address leaves them be.
+    server.command("/ban 127.0.0.1").unwrap();
+    let (last, _) = &clients[crowd - 1];
+    last.send("still in").unwrap();
+    let from = format!("user{}", crowd - 1);
+    let said = ServerEvent::Chat { from, text: "still in".into() };
+    wait_for(&events, "the last user's message", |e| *e == said);
+    assert_eq!(server.users().len(), crowd + 1);
+    server.shutdown();
+}
+
+#[test]
+fn locked_out_local_users_leave_the_loopback_address_be() {
+    let limiter = LoginLimiter::new(2, Duration::from_secs(60), Duration::from_secs(60));
+    let auth = Authenticator::new().with_room_password("secret").with_limiter(limiter);
+    let auth = Arc::new(auth.with_reserved(vec![HOST.to_string()]));
+    let (unix_server, _, address) = unix_room_with(&scratch_dir("unix-lockout"), Arc::clone(&auth));
+    let config = ServerConfig { auth, ..server_config(Authenticator::new(), History::new(10), 0) };
+    let (tcp_server, _) = start_server_with(config);
+
+    for _ in 0..2 {
+        assert!(ChatClient::connect(&address, "alice", "guess").is_err());
+    }
+    assert!(ChatClient::connect(&address, "alice", "secret").is_err(), "local users were not locked out");
+
+    // The same limiter still lets in TCP clients from 127.0.0.1.
+    let _bob = FakeClient::join(tcp_server.local_addr().unwrap(), "bob", "secret");
+    unix_server.shutdown();
+    tcp_server.shutdown();
+}